@@ -0,0 +1,48 @@
+use std::fmt::Write;
+use std::{fs, path::PathBuf};
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+
+use rw_rs::col::ColModel;
+
+// Each model becomes an object named "name:model_id", faces are grouped by surface material
+#[derive(Parser)]
+struct Args {
+    input: PathBuf,
+    output: Option<PathBuf>,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let data = fs::read(&args.input)?;
+    let (_, models) =
+        ColModel::parse_file(&data).map_err(|e| anyhow!("Invalid collision file: {:?}", e))?;
+
+    let mut obj = String::new();
+    let mut base = 1;
+    for model in &models {
+        writeln!(obj, "o {}:{}", model.name, model.model_id)?;
+        for [x, y, z] in &model.vertices {
+            writeln!(obj, "v {} {} {}", x, y, z)?;
+        }
+        let mut materials: Vec<u8> = model.faces.iter().map(|f| f.surface.material).collect();
+        materials.sort();
+        materials.dedup();
+        for material in materials {
+            writeln!(obj, "g surface_{}", material)?;
+            for face in model
+                .faces
+                .iter()
+                .filter(|f| f.surface.material == material)
+            {
+                let [a, b, c] = face.indices.map(|i| i as usize + base);
+                writeln!(obj, "f {} {} {}", a, b, c)?;
+            }
+        }
+        base += model.vertices.len();
+    }
+
+    fs::write(args.output.unwrap_or(args.input.with_extension("obj")), obj)?;
+    Ok(())
+}
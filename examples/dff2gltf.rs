@@ -0,0 +1,29 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+
+use rw_rs::bsf::Chunk;
+use rw_rs::export::gltf;
+use rw_rs::scene::IntoSceneGraph;
+
+#[derive(Parser)]
+struct Args {
+    dff: PathBuf,
+    // Written as binary glTF if the extension is .glb
+    output: PathBuf,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let data = fs::read(&args.dff)?;
+    let (_, clump) = Chunk::parse(&data).map_err(|e| anyhow!("{}", e))?;
+    let gltf = gltf::export(&clump.to_scene_graph());
+    if args.output.extension().is_some_and(|e| e == "glb") {
+        fs::write(&args.output, gltf.to_glb())?;
+    } else {
+        fs::write(&args.output, gltf.to_gltf())?;
+    }
+    Ok(())
+}
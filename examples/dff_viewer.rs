@@ -1,8 +1,10 @@
 use std::fs;
 
-use bevy::{prelude::*, render::render_resource::PrimitiveTopology};
+use bevy::prelude::*;
 
+use rw_rs::bevy::to_bevy_mesh;
 use rw_rs::bsf::*;
+use rw_rs::scene::IntoSceneGraph;
 
 #[derive(Component)]
 struct TheMesh;
@@ -28,40 +30,8 @@ fn main() {
 }
 
 fn load_meshes(bsf: &Chunk) -> Vec<Mesh> {
-    let mut mesh_vec = Vec::new();
-
-    for geometry_chunk in bsf
-        .get_children()
-        .iter()
-        .find(|e| matches!(e.content, ChunkContent::GeometryList))
-        .unwrap()
-        .get_children()
-    {
-        if let ChunkContent::Geometry(geo) = &geometry_chunk.content {
-            let topo = if geo.is_tristrip() {
-                PrimitiveTopology::TriangleStrip
-            } else {
-                PrimitiveTopology::TriangleList
-            };
-            let mut mesh = Mesh::new(topo);
-            mesh.set_indices(Some(bevy::render::mesh::Indices::U16(
-                geo.triangles
-                    .iter()
-                    .flat_map(|t| t.as_arr())
-                    .collect::<Vec<_>>(),
-            )));
-            mesh.insert_attribute(
-                Mesh::ATTRIBUTE_POSITION,
-                geo.vertices.iter().map(|t| t.as_arr()).collect::<Vec<_>>(),
-            );
-            mesh.insert_attribute(
-                Mesh::ATTRIBUTE_NORMAL,
-                geo.normals.iter().map(|t| t.as_arr()).collect::<Vec<_>>(),
-            );
-            mesh_vec.push(mesh);
-        }
-    }
-    mesh_vec
+    let scene = bsf.to_scene_graph();
+    scene.meshes.iter().map(|m| to_bevy_mesh(m, None)).collect()
 }
 
 fn setup(
@@ -0,0 +1,20 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+
+use rw_rs::bsf::Chunk;
+
+#[derive(Parser)]
+struct Args {
+    file: PathBuf,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let data = fs::read(&args.file)?;
+    let (_, chunk) = Chunk::parse(&data).map_err(|e| anyhow!("{}", e))?;
+    println!("{}", serde_json::to_string_pretty(&chunk.to_json())?);
+    Ok(())
+}
@@ -0,0 +1,41 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::Parser;
+
+use rw_rs::diagnostics::Diagnostics;
+use rw_rs::img::*;
+
+#[derive(Parser)]
+struct Args {
+    old: PathBuf,
+    new: PathBuf,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let diagnostics = Diagnostics::new();
+    let old = Img::new_with(&args.old, &diagnostics)?;
+    let new = Img::new_with(&args.new, &diagnostics)?;
+    let res = diff(&old, &new)?;
+    for diagnostic in diagnostics.take() {
+        eprintln!("{}", diagnostic);
+    }
+
+    for entry in &res.added {
+        println!("+ {} ({} bytes)", entry.name, entry.size);
+    }
+    for entry in &res.removed {
+        println!("- {} ({} bytes)", entry.name, entry.size);
+    }
+    for (old, new) in &res.changed {
+        println!(
+            "~ {} ({} -> {} bytes, {:08x} -> {:08x})",
+            old.name, old.size, new.size, old.checksum, new.checksum
+        );
+    }
+    if res.is_empty() {
+        println!("No differences");
+    }
+    Ok(())
+}
@@ -1,6 +1,6 @@
 use std::{
     fs::{self},
-    path::PathBuf,
+    path::{Component, Path, PathBuf},
 };
 
 use anyhow::{bail, Result};
@@ -11,17 +11,75 @@ use rw_rs::img::*;
 #[derive(Parser)]
 struct Args {
     input: PathBuf,
-    name: String,
-    output: Option<PathBuf>,
+    // Entry names, * and ? match any run of characters and any single character
+    #[arg(required_unless_present_any = ["list", "all"])]
+    patterns: Vec<String>,
+    #[arg(short, long, default_value = ".")]
+    output: PathBuf,
+    // Extract every entry
+    #[arg(long)]
+    all: bool,
+    // Print the entries with their offset and size in sectors instead
+    #[arg(long)]
+    list: bool,
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
-    let mut img = Img::new(&args.input)?;
-    if let Some(file) = img.get_file(&args.name) {
-        fs::write(args.output.unwrap_or(args.name.into()), file)?;
-        Ok(())
-    } else {
-        bail!("File not found in img");
+    let img = Img::new(&args.input)?;
+    if args.list {
+        for (name, offset, size) in img.entries() {
+            println!("{:<24} {:>8} {:>6}", name, offset, size);
+        }
+        return Ok(());
+    }
+
+    let patterns: Vec<String> = args.patterns.iter().map(|p| p.to_lowercase()).collect();
+    let names: Vec<String> = img
+        .entries()
+        .map(|(name, _, _)| name.to_owned())
+        .filter(|name| args.all || patterns.iter().any(|p| matches(p, name)))
+        .collect();
+    if names.is_empty() {
+        bail!("No matching files in img");
+    }
+    fs::create_dir_all(&args.output)?;
+    for name in names {
+        // Keep the case the archive stores the name in
+        let original = img.get_entry(&name).unwrap().name;
+        let original = original.to_string_lossy();
+        if !is_file_name(&original) {
+            eprintln!("Skipping {:?}, not a plain file name", original);
+            continue;
+        }
+        let Some(file) = img.get_file(&name) else {
+            bail!("{} could not be read", name);
+        };
+        fs::write(args.output.join(original.as_ref()), file)?;
+    }
+    Ok(())
+}
+
+// Names come from the archive, so they must not lead outside the output directory
+fn is_file_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    matches!(components.next(), Some(Component::Normal(_)))
+        && components.next().is_none()
+        && !name.contains(['/', '\\', ':'])
+}
+
+fn matches(pattern: &str, name: &str) -> bool {
+    match pattern.chars().next() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len())
+            .filter(|&i| name.is_char_boundary(i))
+            .any(|i| matches(&pattern[1..], &name[i..])),
+        Some(c) => {
+            let mut chars = name.chars();
+            match chars.next() {
+                Some(n) if c == '?' || c == n => matches(&pattern[c.len_utf8()..], chars.as_str()),
+                _ => false,
+            }
+        }
     }
 }
@@ -0,0 +1,26 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::Parser;
+
+use rw_rs::img::*;
+
+#[derive(Parser)]
+struct Args {
+    output: PathBuf,
+    files: Vec<PathBuf>,
+    // Write a San Andreas archive instead of a .img/.dir pair
+    #[arg(long)]
+    v2: bool,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let version = if args.v2 {
+        ImgVersion::V2
+    } else {
+        ImgVersion::V1
+    };
+    let files: Vec<_> = args.files.iter().map(PathBuf::as_path).collect();
+    create_from_files(&args.output, version, &files)
+}
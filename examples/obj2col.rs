@@ -0,0 +1,105 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{bail, Result};
+use clap::Parser;
+
+use rw_rs::col::{ColFace, ColModel, ColSurface};
+
+// Reads objects named "name:model_id" and groups named "surface_<material>" as written by col2obj
+// Indices into all vertices of the file and the surface material
+type ObjFace = ([usize; 3], u8);
+
+#[derive(Parser)]
+struct Args {
+    input: PathBuf,
+    output: Option<PathBuf>,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let text = fs::read_to_string(&args.input)?;
+
+    let mut vertices: Vec<[f32; 3]> = Vec::new();
+    let mut models: Vec<(ColModel, Vec<ObjFace>)> = Vec::new();
+    let mut material = 0;
+    for line in text.lines() {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("v") => {
+                let coords: Vec<f32> = words.take(3).map(str::parse).collect::<Result<_, _>>()?;
+                let [x, y, z] = coords[..] else {
+                    bail!("Vertex with fewer than 3 coordinates: {}", line);
+                };
+                vertices.push([x, y, z]);
+            }
+            Some("o") => {
+                let name = words.next().unwrap_or("");
+                let (name, id) = name.rsplit_once(':').unwrap_or((name, "0"));
+                let model = ColModel {
+                    version: 1,
+                    name: name.to_owned(),
+                    model_id: id.parse().unwrap_or(0),
+                    ..Default::default()
+                };
+                models.push((model, Vec::new()));
+            }
+            Some("g") => {
+                material = words
+                    .next()
+                    .and_then(|g| g.strip_prefix("surface_"))
+                    .and_then(|m| m.parse().ok())
+                    .unwrap_or(0);
+            }
+            Some("f") => {
+                let mut indices = Vec::new();
+                for word in words {
+                    let index: i64 = word.split('/').next().unwrap_or("").parse()?;
+                    let index = if index < 0 {
+                        vertices.len() as i64 + index
+                    } else {
+                        index - 1
+                    };
+                    if index < 0 || index as usize >= vertices.len() {
+                        bail!("Face refers to a missing vertex: {}", line);
+                    }
+                    indices.push(index as usize);
+                }
+                if models.is_empty() {
+                    models.push((ColModel::default(), Vec::new()));
+                }
+                let faces = &mut models.last_mut().unwrap().1;
+                // Polygons are split into a fan of triangles
+                for k in 1..indices.len().saturating_sub(1) {
+                    faces.push(([indices[0], indices[k], indices[k + 1]], material));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut col = Vec::new();
+    for (mut model, faces) in models {
+        model.version = 1;
+        // Only keep the vertices this model uses
+        let mut remap = vec![None; vertices.len()];
+        for (indices, material) in faces {
+            let indices = indices.map(|i| {
+                *remap[i].get_or_insert_with(|| {
+                    model.vertices.push(vertices[i]);
+                    model.vertices.len() as u32 - 1
+                })
+            });
+            model.faces.push(ColFace {
+                indices,
+                surface: ColSurface {
+                    material,
+                    ..Default::default()
+                },
+            });
+        }
+        col.extend(model.write_col1());
+    }
+
+    fs::write(args.output.unwrap_or(args.input.with_extension("col")), col)?;
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+
+use rw_rs::bsf::tex::TextureAddressingMode;
+use rw_rs::bsf::Chunk;
+use rw_rs::scene::BORDER_COLOR;
+
+#[derive(Parser)]
+struct Args {
+    input: PathBuf,
+    output: Option<PathBuf>,
+    // Write DXT compressed PC textures as .dds files holding the stored blocks
+    #[arg(long)]
+    dds: bool,
+    // Surround textures addressed with a border by a pixel of the border color
+    #[arg(long)]
+    border: bool,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let data = fs::read(&args.input)?;
+    let (_, txd) = Chunk::parse(&data).map_err(|e| anyhow!("{}", e))?;
+    let output = args.output.unwrap_or_else(|| PathBuf::from("."));
+    fs::create_dir_all(&output)?;
+
+    for texture in txd.textures() {
+        let Some(name) = texture.texture_name() else {
+            eprintln!("Skipping raster of unsupported platform");
+            continue;
+        };
+        let name = file_name(name);
+        let dds = texture.content.as_raster().and_then(|r| r.to_dds());
+        if let Some(dds) = dds.filter(|_| args.dds) {
+            fs::write(output.join(format!("{name}.dds")), dds)?;
+            continue;
+        }
+        let bordered = texture.texture_addressing().is_some_and(|(u, v)| {
+            [u, v]
+                .iter()
+                .any(|m| matches!(m, TextureAddressingMode::TEXTUREADDRESSBORDER))
+        });
+        let mut image = texture.decode_texture();
+        if bordered && args.border {
+            let color = BORDER_COLOR.map(|c| (c * 255.0).round() as u8);
+            image = image.map(|image| image.with_border(color));
+        }
+        match image {
+            Some(image) => image.to_image().save(output.join(format!("{name}.png")))?,
+            None => eprintln!("Skipping {name}, unsupported raster format"),
+        }
+    }
+    Ok(())
+}
+
+// Raster names come from the file, so separators are replaced to stay in the output directory
+fn file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
@@ -0,0 +1,391 @@
+use std::f32::consts::FRAC_PI_2;
+
+use anyhow::anyhow;
+use bevy::asset::{AssetLoader, AssetPath, BoxedFuture, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+use bevy::render::render_resource::{
+    AddressMode, Extent3d, FilterMode, SamplerDescriptor, TextureDimension, TextureFormat,
+};
+use bevy::render::texture::{CompressedImageFormats, ImageSampler, ImageType};
+use wgpu_types::SamplerBorderColor;
+
+use crate::bsf::dxt::DxtFormat;
+use crate::bsf::tex::{RpRasterPC, TextureFilteringMode};
+use crate::bsf::Chunk;
+use crate::scene::{
+    IntoSceneGraph, SceneAddressMode, SceneGraph, SceneMesh, SceneSampler, SceneTexture,
+};
+
+// Registers DffAssetLoader, so that asset_server.load("player.dff") gives a Scene
+pub struct DffPlugin;
+
+impl Plugin for DffPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset_loader(DffAssetLoader);
+    }
+}
+
+// Loads a .dff as a Scene, with its meshes labeled "Mesh{m}/Primitive{p}" and materials
+// "Material{m}". Textures are read from <name>.png next to the .dff and labeled "Texture{t}",
+// with the addressing their material asks for. Border addressing needs a device with
+// Features::ADDRESS_MODE_CLAMP_TO_BORDER.
+#[derive(Default)]
+pub struct DffAssetLoader;
+
+impl AssetLoader for DffAssetLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let (_, chunk) = Chunk::parse(bytes).map_err(|e| anyhow!("{}", e))?;
+            let scene = chunk.to_scene_graph();
+            // Textures that can't be read or decoded are left to the asset server to report
+            let mut images = Vec::new();
+            for texture in &scene.textures {
+                let bytes = load_context
+                    .read_asset_bytes(texture_path(load_context, texture))
+                    .await;
+                images.push(bytes.ok().and_then(|bytes| {
+                    let png = ImageType::Extension("png");
+                    Image::from_buffer(&bytes, png, CompressedImageFormats::NONE, true).ok()
+                }));
+            }
+            load_scene(&scene, images, load_context);
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["dff"]
+    }
+}
+
+// Bevy meshes have a single material, so every primitive becomes its own mesh sharing the
+// vertices. None gives a mesh with the indices of all primitives.
+pub fn to_bevy_mesh(mesh: &SceneMesh, primitive: Option<usize>) -> Mesh {
+    let indices = match primitive {
+        Some(p) => mesh.primitives[p].indices.clone(),
+        None => mesh
+            .primitives
+            .iter()
+            .flat_map(|p| p.indices.iter().copied())
+            .collect(),
+    };
+    let mut bevy_mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    bevy_mesh.set_indices(Some(Indices::U32(indices)));
+    bevy_mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, mesh.positions.clone());
+    if !mesh.normals.is_empty() {
+        bevy_mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, mesh.normals.clone());
+    }
+    if !mesh.uvs.is_empty() {
+        bevy_mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, mesh.uvs.clone());
+    }
+    if !mesh.colors.is_empty() {
+        bevy_mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, mesh.colors.clone());
+    }
+    bevy_mesh
+}
+
+impl RpRasterPC {
+    // GPU format DXT block data can be uploaded as, None for rasters that have to be decoded
+    pub fn wgpu_format(&self) -> Option<TextureFormat> {
+        match self.dxt_format()? {
+            DxtFormat::Dxt1 => Some(TextureFormat::Bc1RgbaUnormSrgb),
+            DxtFormat::Dxt3 => Some(TextureFormat::Bc2RgbaUnormSrgb),
+            DxtFormat::Dxt5 => Some(TextureFormat::Bc3RgbaUnormSrgb),
+        }
+    }
+
+    // Image with all mip levels and the sampler the raster asks for. DXT data is passed through
+    // when the device supports BC compression and the base level is whole blocks, otherwise
+    // levels are decoded to RGBA.
+    pub fn to_bevy_image(&self, bc_supported: bool) -> Option<Image> {
+        let compressed = self
+            .wgpu_format()
+            .filter(|_| bc_supported && self.width % 4 == 0 && self.height % 4 == 0);
+        let mut data = Vec::new();
+        let mut num_levels = 0;
+        for (level, (width, height, bytes)) in self.mip_levels().enumerate() {
+            match compressed {
+                Some(_) => data.extend(bytes.get(..self.level_size(width, height))?),
+                None => data.extend(self.decode_level(level)?.data),
+            }
+            num_levels += 1;
+        }
+
+        let mut image = Image {
+            data,
+            ..Default::default()
+        };
+        let descriptor = &mut image.texture_descriptor;
+        descriptor.size = Extent3d {
+            width: self.width as u32,
+            height: self.height as u32,
+            depth_or_array_layers: 1,
+        };
+        descriptor.dimension = TextureDimension::D2;
+        descriptor.format = compressed.unwrap_or(TextureFormat::Rgba8UnormSrgb);
+        descriptor.mip_level_count = num_levels;
+
+        let sampler = SceneSampler::from_addressing(self.address_u, self.address_v);
+        image.sampler_descriptor =
+            ImageSampler::Descriptor(sampler_descriptor(&sampler, self.filtering));
+        Some(image)
+    }
+}
+
+pub fn sampler_descriptor(
+    sampler: &SceneSampler,
+    filtering: TextureFilteringMode,
+) -> SamplerDescriptor<'static> {
+    let (filter, mipmap_filter) = filter_modes(filtering);
+    let modes = [sampler.address_u, sampler.address_v];
+    // The only border color streams use, see scene::BORDER_COLOR
+    let border_color = modes
+        .contains(&SceneAddressMode::ClampToBorder)
+        .then_some(SamplerBorderColor::TransparentBlack);
+    SamplerDescriptor {
+        address_mode_u: address_mode(sampler.address_u),
+        address_mode_v: address_mode(sampler.address_v),
+        mag_filter: filter,
+        min_filter: filter,
+        mipmap_filter,
+        border_color,
+        ..Default::default()
+    }
+}
+
+fn address_mode(mode: SceneAddressMode) -> AddressMode {
+    match mode {
+        SceneAddressMode::Repeat => AddressMode::Repeat,
+        SceneAddressMode::Mirror => AddressMode::MirrorRepeat,
+        SceneAddressMode::ClampToEdge => AddressMode::ClampToEdge,
+        SceneAddressMode::ClampToBorder => AddressMode::ClampToBorder,
+    }
+}
+
+// Texel and mipmap filters
+fn filter_modes(mode: TextureFilteringMode) -> (FilterMode, FilterMode) {
+    match mode {
+        TextureFilteringMode::FILTERNEAREST | TextureFilteringMode::FILTERMIPNEAREST => {
+            (FilterMode::Nearest, FilterMode::Nearest)
+        }
+        TextureFilteringMode::FILTERMIPLINEAR => (FilterMode::Nearest, FilterMode::Linear),
+        TextureFilteringMode::FILTERLINEARMIPLINEAR => (FilterMode::Linear, FilterMode::Linear),
+        _ => (FilterMode::Linear, FilterMode::Nearest),
+    }
+}
+
+fn texture_path(load_context: &LoadContext, texture: &SceneTexture) -> std::path::PathBuf {
+    load_context
+        .path()
+        .with_file_name(format!("{}.png", texture.name))
+}
+
+fn load_scene(scene: &SceneGraph, images: Vec<Option<Image>>, load_context: &mut LoadContext) {
+    // Images that weren't decoded are loaded by path, and the materials depend on them
+    let textures: Vec<(Handle<Image>, Option<AssetPath>)> = scene
+        .textures
+        .iter()
+        .zip(images)
+        .enumerate()
+        .map(|(t, (texture, image))| match image {
+            Some(mut image) => {
+                let sampler =
+                    sampler_descriptor(&texture.sampler, TextureFilteringMode::FILTERLINEAR);
+                image.sampler_descriptor = ImageSampler::Descriptor(sampler);
+                let label = format!("Texture{}", t);
+                (
+                    load_context.set_labeled_asset(&label, LoadedAsset::new(image)),
+                    None,
+                )
+            }
+            None => {
+                let path = AssetPath::new(texture_path(load_context, texture), None);
+                (load_context.get_handle(path.clone()), Some(path))
+            }
+        })
+        .collect();
+
+    let materials: Vec<Handle<StandardMaterial>> = scene
+        .materials
+        .iter()
+        .enumerate()
+        .map(|(m, material)| {
+            let [r, g, b, a] = material.color;
+            let mut dependencies = Vec::new();
+            let base_color_texture = material.texture.map(|t| {
+                let (handle, path) = &textures[t];
+                dependencies.extend(path.clone());
+                handle.clone()
+            });
+            let alpha_mode = if scene.is_transparent(material) {
+                AlphaMode::Blend
+            } else {
+                AlphaMode::Opaque
+            };
+            let standard = StandardMaterial {
+                base_color: Color::rgba(r, g, b, a),
+                base_color_texture,
+                perceptual_roughness: 1.0,
+                metallic: 0.0,
+                alpha_mode,
+                ..default()
+            };
+            load_context.set_labeled_asset(
+                &format!("Material{}", m),
+                LoadedAsset::new(standard).with_dependencies(dependencies),
+            )
+        })
+        .collect();
+    let default_material = load_context.set_labeled_asset(
+        "MaterialDefault",
+        LoadedAsset::new(StandardMaterial::default()),
+    );
+
+    // (mesh, material) per primitive of every mesh
+    let meshes: Vec<Vec<(Handle<Mesh>, Handle<StandardMaterial>)>> = scene
+        .meshes
+        .iter()
+        .enumerate()
+        .map(|(m, mesh)| {
+            (0..mesh.primitives.len())
+                .map(|p| {
+                    let handle = load_context.set_labeled_asset(
+                        &format!("Mesh{}/Primitive{}", m, p),
+                        LoadedAsset::new(to_bevy_mesh(mesh, Some(p))),
+                    );
+                    let material = mesh.primitives[p]
+                        .material
+                        .map_or(default_material.clone(), |m| materials[m].clone());
+                    (handle, material)
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut world = World::default();
+    // RenderWare is Z-up, Bevy is Y-up
+    world
+        .spawn(SpatialBundle::from_transform(Transform::from_rotation(
+            Quat::from_rotation_x(-FRAC_PI_2),
+        )))
+        .with_children(|parent| {
+            for root in scene.roots() {
+                spawn_node(parent, scene, root, &meshes);
+            }
+        });
+    load_context.set_default_asset(LoadedAsset::new(Scene::new(world)));
+}
+
+fn spawn_node(
+    parent: &mut WorldChildBuilder,
+    scene: &SceneGraph,
+    index: usize,
+    meshes: &[Vec<(Handle<Mesh>, Handle<StandardMaterial>)>],
+) {
+    let node = &scene.nodes[index];
+    let transform = Transform::from_matrix(Mat4::from_cols_array_2d(&node.transform));
+    let mut entity = parent.spawn(SpatialBundle::from_transform(transform));
+    if let Some(name) = &node.name {
+        entity.insert(Name::new(name.clone()));
+    }
+    entity.with_children(|parent| {
+        for (mesh, material) in node.mesh.iter().flat_map(|m| &meshes[*m]) {
+            parent.spawn(PbrBundle {
+                mesh: mesh.clone(),
+                material: material.clone(),
+                ..default()
+            });
+        }
+        for &child in &node.children {
+            spawn_node(parent, scene, child, meshes);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsf::tex::{RasterEncoding, RgbaImage};
+    use crate::scene::ScenePrimitive;
+
+    #[test]
+    fn split_primitives() {
+        let mesh = SceneMesh {
+            positions: vec![[0.0; 3]; 4],
+            primitives: vec![
+                ScenePrimitive {
+                    indices: vec![0, 1, 2],
+                    material: Some(0),
+                },
+                ScenePrimitive {
+                    indices: vec![1, 3, 2],
+                    material: Some(1),
+                },
+            ],
+            ..Default::default()
+        };
+        let second = to_bevy_mesh(&mesh, Some(1));
+        assert_eq!(second.count_vertices(), 4);
+        assert_eq!(second.indices().unwrap().len(), 3);
+        assert_eq!(to_bevy_mesh(&mesh, None).indices().unwrap().len(), 6);
+        assert!(second.attribute(Mesh::ATTRIBUTE_NORMAL).is_none());
+    }
+
+    #[test]
+    fn raster_images() {
+        let image = RgbaImage {
+            width: 8,
+            height: 8,
+            data: vec![255; 256],
+        };
+        let raster = RpRasterPC::encode(
+            "tex",
+            &image,
+            RasterEncoding::Dxt1,
+            true,
+            crate::bsf::RwVersion::SA_PC,
+        );
+
+        let compressed = raster.to_bevy_image(true).unwrap();
+        let descriptor = &compressed.texture_descriptor;
+        assert_eq!(descriptor.format, TextureFormat::Bc1RgbaUnormSrgb);
+        assert_eq!(descriptor.mip_level_count, 4);
+        assert_eq!(compressed.data.len(), 32 + 8 * 3);
+
+        let decoded = raster.to_bevy_image(false).unwrap();
+        assert_eq!(
+            decoded.texture_descriptor.format,
+            TextureFormat::Rgba8UnormSrgb
+        );
+        assert_eq!(decoded.data.len(), (64 + 16 + 4 + 1) * 4);
+        let ImageSampler::Descriptor(sampler) = &decoded.sampler_descriptor else {
+            panic!("Default sampler");
+        };
+        assert_eq!(sampler.address_mode_u, AddressMode::Repeat);
+        assert_eq!(sampler.mipmap_filter, FilterMode::Linear);
+    }
+
+    #[test]
+    fn border_sampler() {
+        let sampler = SceneSampler {
+            address_u: SceneAddressMode::ClampToEdge,
+            address_v: SceneAddressMode::ClampToBorder,
+            ..Default::default()
+        };
+        let descriptor = sampler_descriptor(&sampler, TextureFilteringMode::FILTERLINEAR);
+        assert_eq!(descriptor.address_mode_u, AddressMode::ClampToEdge);
+        assert_eq!(descriptor.address_mode_v, AddressMode::ClampToBorder);
+        assert_eq!(
+            descriptor.border_color,
+            Some(SamplerBorderColor::TransparentBlack)
+        );
+        let descriptor =
+            sampler_descriptor(&SceneSampler::default(), TextureFilteringMode::FILTERLINEAR);
+        assert_eq!(descriptor.border_color, None);
+    }
+}
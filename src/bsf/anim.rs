@@ -0,0 +1,336 @@
+use nom::bytes::complete::take;
+use nom::multi::count;
+use nom::number::complete::{le_f32, le_u32};
+use nom::IResult;
+use nom_derive::{Nom, Parse};
+
+use super::RwVersion;
+
+pub const HANIM_STD_KEYFRAME: u32 = 0x1;
+pub const UV_ANIM_LINEAR: u32 = 0x1C0;
+pub const UV_ANIM_PARAM: u32 = 0x1C1;
+
+#[derive(Clone, Copy, Debug, Nom)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UVAnimKeyFrame {
+    pub time: f32,
+    pub uv: [f32; 6], // 2x3 matrix for linear, (rotation, scale u/v, skew, translation u/v) for param
+    pub prev: i32,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UVAnim {
+    pub name: String,
+    pub node_to_uv_channel: [f32; 8],
+    pub keyframes: Vec<UVAnimKeyFrame>,
+}
+
+impl UVAnim {
+    // Keyframe values at time, interpolated linearly. The animation loops over duration like
+    // in the games.
+    pub fn sample(&self, time: f32, duration: f32) -> Option<[f32; 6]> {
+        let time = if duration > 0.0 {
+            time.rem_euclid(duration)
+        } else {
+            time
+        };
+        let next = self.keyframes.iter().position(|k| k.time > time);
+        let (prev, next) = match next {
+            Some(0) => return self.keyframes.first().map(|k| k.uv),
+            Some(n) => (&self.keyframes[n - 1], &self.keyframes[n]),
+            None => return self.keyframes.last().map(|k| k.uv),
+        };
+        let t = (time - prev.time) / (next.time - prev.time);
+        Some([0, 1, 2, 3, 4, 5].map(|c| prev.uv[c] + (next.uv[c] - prev.uv[c]) * t))
+    }
+}
+
+// Bone keyframe of a skeletal animation. The first two keyframes of every node come first, in
+// node order, the rest is sorted by time.
+#[derive(Clone, Copy, Debug, Nom)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HAnimKeyFrame {
+    pub time: f32,
+    pub rotation: [f32; 4], // Quaternion, imaginary part first
+    pub translation: [f32; 3],
+    // Index of the previous keyframe of the same node, stored as a byte offset into the keyframes
+    pub prev: i32,
+}
+
+impl HAnimKeyFrame {
+    pub const SIZE: i32 = 36;
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AnimData {
+    HAnim(Vec<HAnimKeyFrame>),
+    UV(UVAnim),
+    Unknown(Vec<u8>), // Keyframes of an interpolator type we don't know
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RtAnimAnimation {
+    pub version: u32,
+    pub type_id: u32,
+    pub num_frames: u32,
+    pub flags: u32,
+    pub duration: f32,
+    pub data: AnimData,
+}
+
+impl RtAnimAnimation {
+    pub fn parse(i: &[u8], _version: RwVersion) -> IResult<&[u8], Self> {
+        let (i, version) = le_u32(i)?;
+        let (i, type_id) = le_u32(i)?;
+        let (i, num_frames) = le_u32(i)?;
+        let (i, flags) = le_u32(i)?;
+        let (i, duration) = le_f32(i)?;
+
+        let (i, data) = match type_id {
+            HANIM_STD_KEYFRAME => {
+                let (i, mut keyframes) = count(HAnimKeyFrame::parse_le, num_frames as usize)(i)?;
+                for k in keyframes.iter_mut().filter(|k| k.prev > 0) {
+                    k.prev /= HAnimKeyFrame::SIZE;
+                }
+                (i, AnimData::HAnim(keyframes))
+            }
+            UV_ANIM_LINEAR | UV_ANIM_PARAM => {
+                let (i, _unused) = le_u32(i)?;
+                let (i, name) = take(32usize)(i)?;
+                let name = String::from_utf8_lossy(name).trim_matches('\0').to_string();
+                let (i, node_to_uv_channel) = <[f32; 8]>::parse_le(i)?;
+                let (i, keyframes) = count(UVAnimKeyFrame::parse_le, num_frames as usize)(i)?;
+                (
+                    i,
+                    AnimData::UV(UVAnim {
+                        name,
+                        node_to_uv_channel,
+                        keyframes,
+                    }),
+                )
+            }
+            _ => (&[] as &[u8], AnimData::Unknown(i.to_vec())),
+        };
+
+        Ok((
+            i,
+            Self {
+                version,
+                type_id,
+                num_frames,
+                flags,
+                duration,
+                data,
+            },
+        ))
+    }
+
+    // Keyframes of each of the num_nodes bones in time order, following the prev links. Empty
+    // for other animation types.
+    pub fn node_keyframes(&self, num_nodes: usize) -> Vec<Vec<&HAnimKeyFrame>> {
+        let AnimData::HAnim(keyframes) = &self.data else {
+            return Vec::new();
+        };
+        let mut node_of = Vec::with_capacity(keyframes.len());
+        let mut tracks = vec![Vec::new(); num_nodes];
+        for (index, keyframe) in keyframes.iter().enumerate() {
+            let node = if index < num_nodes {
+                Some(index)
+            } else {
+                usize::try_from(keyframe.prev)
+                    .ok()
+                    .and_then(|prev| node_of.get(prev).copied().flatten())
+            };
+            node_of.push(node);
+            if let Some(track) = node.and_then(|node| tracks.get_mut(node)) {
+                track.push(keyframe);
+            }
+        }
+        tracks
+    }
+
+    pub fn write(&self, _version: RwVersion) -> Vec<u8> {
+        let mut data = Vec::new();
+        for word in [self.version, self.type_id, self.num_frames, self.flags] {
+            data.extend(word.to_le_bytes());
+        }
+        data.extend(self.duration.to_le_bytes());
+        match &self.data {
+            AnimData::HAnim(keyframes) => {
+                for k in keyframes {
+                    data.extend(k.time.to_le_bytes());
+                    for f in k.rotation.iter().chain(&k.translation) {
+                        data.extend(f.to_le_bytes());
+                    }
+                    let prev = if k.prev > 0 {
+                        k.prev * HAnimKeyFrame::SIZE
+                    } else {
+                        k.prev
+                    };
+                    data.extend(prev.to_le_bytes());
+                }
+            }
+            AnimData::UV(uv) => {
+                data.extend(0u32.to_le_bytes());
+                let mut name = uv.name.as_bytes().to_vec();
+                name.resize(32, 0);
+                data.extend(name);
+                data.extend(uv.node_to_uv_channel.iter().flat_map(|f| f.to_le_bytes()));
+                for k in &uv.keyframes {
+                    data.extend(k.time.to_le_bytes());
+                    data.extend(k.uv.iter().flat_map(|f| f.to_le_bytes()));
+                    data.extend(k.prev.to_le_bytes());
+                }
+            }
+            AnimData::Unknown(raw) => data.extend(raw),
+        }
+        data
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UVAnimDictionary {
+    pub num_anims: u32,
+}
+
+impl UVAnimDictionary {
+    pub fn parse(i: &[u8], _version: RwVersion) -> IResult<&[u8], Self> {
+        let (i, num_anims) = le_u32(i)?;
+        Ok((i, Self { num_anims }))
+    }
+
+    pub fn write(&self, _version: RwVersion) -> Vec<u8> {
+        self.num_anims.to_le_bytes().to_vec()
+    }
+}
+
+// Material plugin naming the UV animations applied to each texture slot
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RpUVAnimMaterial {
+    pub anims: Vec<(u32, String)>, // (slot, animation name)
+}
+
+impl RpUVAnimMaterial {
+    pub fn parse(i: &[u8], _version: RwVersion) -> IResult<&[u8], Self> {
+        let (mut i, mask) = le_u32(i)?;
+        let mut anims = Vec::new();
+        for slot in 0..8 {
+            if mask & (1 << slot) != 0 {
+                let name;
+                (i, name) = take(32usize)(i)?;
+                let name = String::from_utf8_lossy(name).trim_matches('\0').to_string();
+                anims.push((slot, name));
+            }
+        }
+        Ok((i, Self { anims }))
+    }
+
+    pub fn write(&self, _version: RwVersion) -> Vec<u8> {
+        let mask = self
+            .anims
+            .iter()
+            .fold(0u32, |mask, (slot, _)| mask | 1 << slot);
+        let mut data = mask.to_le_bytes().to_vec();
+        let mut anims: Vec<_> = self.anims.iter().collect();
+        anims.sort_by_key(|(slot, _)| *slot);
+        for (_, name) in anims {
+            let mut name = name.as_bytes().to_vec();
+            name.resize(32, 0);
+            data.extend(name);
+        }
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsf::fixtures::{chunk, floats};
+    use crate::bsf::{Chunk, ChunkContent};
+
+    #[test]
+    fn uv_anim_dictionary() {
+        let mut anim = Vec::new();
+        for word in [0x100, 0x1C0, 2, 0] {
+            anim.extend(u32::to_le_bytes(word));
+        }
+        anim.extend(floats(&[1.0]));
+        anim.extend(0u32.to_le_bytes());
+        let mut name = b"waterfall".to_vec();
+        name.resize(32, 0);
+        anim.extend(name);
+        anim.extend(floats(&[0.0; 8]));
+        for (time, prev) in [(0.0, 0i32), (1.0, 0)] {
+            anim.extend(floats(&[time, 1.0, 0.0, 0.0, 1.0, 0.0, time]));
+            anim.extend(prev.to_le_bytes());
+        }
+        let mut payload = chunk(0x01, &1u32.to_le_bytes());
+        payload.extend(chunk(0x1B, &anim));
+        let file = chunk(0x2B, &payload);
+
+        let (_, dict) = Chunk::parse(&file).unwrap();
+        assert!(matches!(
+            dict.content,
+            ChunkContent::UVAnimDictionary(d) if d.num_anims == 1
+        ));
+        let ChunkContent::Animation(animation) = &dict.get_children()[0].content else {
+            panic!("not an animation");
+        };
+        let AnimData::UV(uv) = &animation.data else {
+            panic!("not a uv animation");
+        };
+        assert_eq!(uv.name, "waterfall");
+        assert_eq!(uv.keyframes[1].uv[5], 1.0);
+        let found = dict.uv_anim("WATERFALL").unwrap();
+        assert_eq!(uv.sample(0.5, found.duration).unwrap()[5], 0.5);
+        // Loops over the duration
+        assert_eq!(uv.sample(1.25, found.duration).unwrap()[5], 0.25);
+        assert!(dict.uv_anim("lava").is_none());
+        assert_eq!(
+            dict.get_children()[0].to_bytes().unwrap(),
+            chunk(0x1B, &anim)
+        );
+    }
+
+    #[test]
+    fn hanim_animation() {
+        let mut anim = Vec::new();
+        for word in [0x100u32, 1, 5, 0] {
+            anim.extend(word.to_le_bytes());
+        }
+        anim.extend(2f32.to_le_bytes());
+        // Two nodes, the second one gets an extra keyframe. prev is a byte offset.
+        for (time, x, prev) in [
+            (0f32, 0.0, -1i32),
+            (0.0, 1.0, -1),
+            (2.0, 0.0, 0),
+            (1.0, 2.0, 36),
+            (2.0, 3.0, 3 * 36),
+        ] {
+            anim.extend(time.to_le_bytes());
+            anim.extend(floats(&[0.0, 0.0, 0.0, 1.0, x, 0.0, 0.0]));
+            anim.extend(prev.to_le_bytes());
+        }
+        let file = chunk(0x1B, &anim);
+
+        let (_, chunk) = Chunk::parse(&file).unwrap();
+        let anim = chunk.content.as_animation().unwrap();
+        assert_eq!(anim.duration, 2.0);
+        let tracks = anim.node_keyframes(2);
+        let x = |track: &Vec<&HAnimKeyFrame>| -> Vec<f32> {
+            track.iter().map(|k| k.translation[0]).collect()
+        };
+        assert_eq!(x(&tracks[0]), [0.0, 0.0]);
+        assert_eq!(x(&tracks[1]), [1.0, 2.0, 3.0]);
+        let AnimData::HAnim(keyframes) = &anim.data else {
+            panic!()
+        };
+        assert_eq!(keyframes[4].prev, 3);
+        assert_eq!(chunk.to_bytes().unwrap(), file);
+    }
+}
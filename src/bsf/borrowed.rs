@@ -0,0 +1,177 @@
+use nom::bytes::complete::take;
+use nom::error::ErrorKind;
+use nom::multi::many0;
+use nom::IResult;
+
+use super::endian::Endian;
+use super::error::RwError;
+use super::options::ParseOptions;
+use super::tex::{RpRasterPC, PLATFORM_D3D8, PLATFORM_D3D9};
+use super::{Chunk, ChunkHeader, ChunkType, ChunkTypeId};
+
+// A chunk whose payload is borrowed from the input instead of parsed, so large files can be
+// walked without copying. Only the chunks of interest need to be turned into a Chunk.
+#[derive(Clone, Copy, Debug)]
+pub struct ChunkRef<'a> {
+    pub ty: ChunkTypeId,
+    pub header: ChunkHeader,
+    pub data: &'a [u8], // Payload after the 12 byte header
+    raw: &'a [u8],
+}
+
+impl<'a> ChunkRef<'a> {
+    pub fn parse(i: &'a [u8]) -> IResult<&'a [u8], Self, RwError> {
+        let start = i;
+        let endian = Endian::detect(i);
+        let (i, ty) = endian.u32(i)?;
+        let (i, size) = endian.u32(i)?;
+        let (i, header) = ChunkHeader::parse(i, endian)?;
+        let (rest, data) = take(size)(i)?;
+        Ok((
+            rest,
+            Self {
+                ty: ChunkTypeId(ty),
+                header,
+                data,
+                raw: &start[..start.len() - rest.len()],
+            },
+        ))
+    }
+
+    // Splits the payload into chunks, which is only meaningful for sections made of children such
+    // as a Clump or Texture Dictionary, see ChunkTypeId::has_children
+    pub fn children(&self) -> Result<Vec<ChunkRef<'a>>, nom::Err<RwError>> {
+        let (rest, children) = many0(Self::parse)(self.data)?;
+        if !rest.is_empty() {
+            return Err(nom::Err::Error(RwError::Parse(ErrorKind::Eof)));
+        }
+        Ok(children)
+    }
+
+    // Content with the data left in the input. Only rasters are decoded, they hold most of the
+    // data of a file, other chunks are handed out as their payload or children.
+    pub fn content(&self) -> Result<ContentRef<'a>, nom::Err<RwError>> {
+        match self.ty.known() {
+            Some(ChunkType::Struct) => Ok(ContentRef::Struct(self.data)),
+            Some(ChunkType::Raster) => {
+                let children = self.children()?;
+                let raster = children
+                    .iter()
+                    .find(|c| c.ty == ChunkType::Struct)
+                    .map(|s| s.data)
+                    .filter(|s| s.len() >= 4)
+                    .filter(|s| {
+                        let platform = u32::from_le_bytes(s[..4].try_into().unwrap());
+                        matches!(platform, PLATFORM_D3D8 | PLATFORM_D3D9)
+                    });
+                match raster {
+                    Some(data) => {
+                        let (data, info) = RpRasterPC::parse_header(data, self.header.version)?;
+                        Ok(ContentRef::Raster(RasterRef { info, data }))
+                    }
+                    // Other platforms aren't decoded without copying
+                    None => Ok(ContentRef::Children(children)),
+                }
+            }
+            _ if self.ty.has_children() => Ok(ContentRef::Children(self.children()?)),
+            _ => Ok(ContentRef::Section(self.data)),
+        }
+    }
+
+    pub fn to_chunk(&self) -> Result<Chunk, nom::Err<RwError>> {
+        self.to_chunk_with(&ParseOptions::default())
+    }
+
+    pub fn to_chunk_with(&self, opts: &ParseOptions) -> Result<Chunk, nom::Err<RwError>> {
+        Chunk::parse_with(self.raw, opts).map(|(_, chunk)| chunk)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ContentRef<'a> {
+    Struct(&'a [u8]),
+    Raster(RasterRef<'a>),
+    Children(Vec<ChunkRef<'a>>),
+    Section(&'a [u8]), // Any other leaf, as stored
+}
+
+// PC raster whose levels are borrowed from the input. info holds everything but the data.
+#[derive(Clone, Debug)]
+pub struct RasterRef<'a> {
+    pub info: RpRasterPC,
+    pub data: &'a [u8],
+}
+
+impl<'a> RasterRef<'a> {
+    pub fn palette(&self) -> Option<&'a [u8]> {
+        self.info.palette_in(self.data)
+    }
+
+    pub fn mip_levels(&self) -> impl Iterator<Item = (u32, u32, &'a [u8])> + 'a {
+        self.info.mip_levels_in(self.data)
+    }
+
+    // Owned raster, for decoding or writing
+    pub fn to_raster(&self) -> RpRasterPC {
+        RpRasterPC {
+            data: self.data.to_vec(),
+            ..self.info.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsf::fixtures::{chunk, texture};
+    use crate::bsf::tex::{self, RgbaImage};
+    use crate::bsf::RwVersion;
+
+    #[test]
+    fn borrowed_chunks() {
+        let mut payload = chunk(0x01, &[1, 0, 0, 0]);
+        payload.extend(texture("a"));
+        payload.extend(texture("b"));
+        let file = chunk(0x16, &payload);
+
+        let (rest, txd) = ChunkRef::parse(&file).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(txd.ty, ChunkTypeId(0x16));
+        let children = txd.children().unwrap();
+        assert_eq!(children.len(), 3);
+        // Payloads point into the input
+        assert_eq!(
+            children[2].data.as_ptr(),
+            file[file.len() - children[2].data.len()..].as_ptr()
+        );
+
+        assert!(txd.ty.has_children());
+        assert!(!children[0].ty.has_children());
+        let texture = children[1].to_chunk().unwrap();
+        assert_eq!(texture.content.chunk_type(), ChunkType::Texture);
+        assert!(ChunkRef::parse(&file[..20]).is_err());
+        assert!(matches!(
+            children[0].content(),
+            Ok(ContentRef::Struct(data)) if data == [1, 0, 0, 0]
+        ));
+
+        // Raster levels point into the input too
+        let image = RgbaImage {
+            width: 4,
+            height: 4,
+            data: vec![255; 64],
+        };
+        let encoding = tex::RasterEncoding::Format8888;
+        let raster = RpRasterPC::encode("a", &image, encoding, true, RwVersion::VC_PC);
+        let raster = chunk(0x15, &chunk(0x01, &raster.write(RwVersion::VC_PC)));
+        let (_, raster_ref) = ChunkRef::parse(&raster).unwrap();
+        let Ok(ContentRef::Raster(raster_ref)) = raster_ref.content() else {
+            panic!("not a raster");
+        };
+        assert_eq!(raster_ref.info.name, "a");
+        let levels: Vec<_> = raster_ref.mip_levels().collect();
+        assert_eq!(levels.len(), 3);
+        assert_eq!(levels[2].2.as_ptr(), raster[raster.len() - 4..].as_ptr());
+        assert_eq!(raster_ref.to_raster().decode().unwrap().data, image.data);
+    }
+}
@@ -0,0 +1,192 @@
+use nom::bytes::complete::take;
+use nom::multi::count;
+use nom::number::complete::{le_u16, le_u32};
+use nom::IResult;
+use nom_derive::Parse;
+
+use super::geo::RwV3d;
+use super::tex::{RwRGBA, RwTexCoords};
+use super::RwVersion;
+
+// San Andreas geometry extension describing the pieces an object shatters into. Objects that
+// can't break store only a zero magic word.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RpBreakable {
+    pub magic: u32,
+    pub position_rule: u32,
+    pub vertices: Vec<RwV3d>,
+    pub tex_coords: Vec<RwTexCoords>,
+    pub prelit: Vec<RwRGBA>,
+    pub triangles: Vec<[u16; 3]>,
+    pub material_ids: Vec<u16>, // One per triangle
+    pub materials: Vec<BreakableMaterial>,
+    pub offsets: [u32; 9], // Pointers the game fills in when loading, kept as stored
+}
+
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BreakableMaterial {
+    pub texture: String,
+    pub mask: String,
+    pub ambient: [f32; 3],
+}
+
+impl RpBreakable {
+    pub fn parse(i: &[u8], _version: RwVersion) -> IResult<&[u8], Self> {
+        let (i, magic) = le_u32(i)?;
+        if magic == 0 {
+            return Ok((i, Self::default()));
+        }
+        let (i, position_rule) = le_u32(i)?;
+        let (i, num_vertices) = le_u32(i)?;
+        let (i, [vertices, tex_coords, prelit]) = <[u32; 3]>::parse_le(i)?;
+        let (i, num_triangles) = le_u32(i)?;
+        let (i, [triangles, material_ids]) = <[u32; 2]>::parse_le(i)?;
+        let (i, num_materials) = le_u32(i)?;
+        let (i, [textures, texture_names, mask_names, ambient]) = <[u32; 4]>::parse_le(i)?;
+        let offsets = [
+            vertices,
+            tex_coords,
+            prelit,
+            triangles,
+            material_ids,
+            textures,
+            texture_names,
+            mask_names,
+            ambient,
+        ];
+
+        let num_vertices = num_vertices as usize;
+        let (i, vertices) = count(RwV3d::parse_le, num_vertices)(i)?;
+        let (i, tex_coords) = count(RwTexCoords::parse_le, num_vertices)(i)?;
+        let (i, prelit) = count(RwRGBA::parse_le, num_vertices)(i)?;
+        let (i, triangles) = count(<[u16; 3]>::parse_le, num_triangles as usize)(i)?;
+        let (i, material_ids) = count(le_u16, num_triangles as usize)(i)?;
+        let (i, textures) = count(name, num_materials as usize)(i)?;
+        let (i, masks) = count(name, num_materials as usize)(i)?;
+        let (i, ambient) = count(<[f32; 3]>::parse_le, num_materials as usize)(i)?;
+        let materials = textures
+            .into_iter()
+            .zip(masks)
+            .zip(ambient)
+            .map(|((texture, mask), ambient)| BreakableMaterial {
+                texture,
+                mask,
+                ambient,
+            })
+            .collect();
+
+        Ok((
+            i,
+            Self {
+                magic,
+                position_rule,
+                vertices,
+                tex_coords,
+                prelit,
+                triangles,
+                material_ids,
+                materials,
+                offsets,
+            },
+        ))
+    }
+
+    pub fn write(&self, _version: RwVersion) -> Vec<u8> {
+        let mut data = self.magic.to_le_bytes().to_vec();
+        if self.magic == 0 {
+            return data;
+        }
+        let o = &self.offsets;
+        for word in [
+            self.position_rule,
+            self.vertices.len() as u32,
+            o[0],
+            o[1],
+            o[2],
+            self.triangles.len() as u32,
+            o[3],
+            o[4],
+            self.materials.len() as u32,
+            o[5],
+            o[6],
+            o[7],
+            o[8],
+        ] {
+            data.extend(word.to_le_bytes());
+        }
+        for v in &self.vertices {
+            data.extend(v.to_bytes());
+        }
+        for t in &self.tex_coords {
+            data.extend(t.u.to_le_bytes());
+            data.extend(t.v.to_le_bytes());
+        }
+        for c in &self.prelit {
+            data.extend([c.r, c.g, c.b, c.a]);
+        }
+        for t in self.triangles.iter().flatten().chain(&self.material_ids) {
+            data.extend(t.to_le_bytes());
+        }
+        for m in &self.materials {
+            let mut texture = m.texture.as_bytes().to_vec();
+            texture.resize(32, 0);
+            data.extend(texture);
+        }
+        for m in &self.materials {
+            let mut mask = m.mask.as_bytes().to_vec();
+            mask.resize(32, 0);
+            data.extend(mask);
+        }
+        for m in &self.materials {
+            data.extend(m.ambient.iter().flat_map(|f| f.to_le_bytes()));
+        }
+        data
+    }
+}
+
+// Null padded to 32 bytes
+fn name(i: &[u8]) -> IResult<&[u8], String> {
+    let (i, name) = take(32usize)(i)?;
+    let name = String::from_utf8_lossy(name.split(|c| *c == 0).next().unwrap_or(&[])).to_string();
+    Ok((i, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bsf::fixtures::{chunk, floats};
+    use crate::bsf::Chunk;
+
+    #[test]
+    fn breakable() {
+        let mut data = Vec::new();
+        for word in [1u32, 2, 3, 0, 0, 0, 1, 0, 0, 1, 0, 0, 0, 0] {
+            data.extend(word.to_le_bytes());
+        }
+        data.extend(floats(&[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]));
+        data.extend(floats(&[0.0; 6]));
+        data.extend([255; 12]);
+        data.extend([0u16, 1, 2, 0].iter().flat_map(|w| w.to_le_bytes()));
+        for name in [&b"glass"[..], b"glassmask"] {
+            let mut name = name.to_vec();
+            name.resize(32, 0);
+            data.extend(name);
+        }
+        data.extend(floats(&[0.5, 0.5, 0.5]));
+        let mut ext = chunk(0x0253F2FD, &data);
+        ext.extend(chunk(0x0253F2FD, &[0; 4]));
+        let file = chunk(0x03, &ext);
+        let (_, ext) = Chunk::parse(&file).unwrap();
+
+        let breakable = ext.get_children()[0].content.as_breakable().unwrap();
+        assert_eq!(breakable.position_rule, 2);
+        assert_eq!(breakable.vertices[2].as_arr(), [0.0, 1.0, 0.0]);
+        assert_eq!(breakable.triangles, [[0, 1, 2]]);
+        assert_eq!(breakable.materials[0].texture, "glass");
+        assert_eq!(breakable.materials[0].mask, "glassmask");
+        let empty = ext.get_children()[1].content.as_breakable().unwrap();
+        assert!(empty.vertices.is_empty());
+        assert_eq!(ext.to_bytes().unwrap(), file);
+    }
+}
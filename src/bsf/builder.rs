@@ -0,0 +1,602 @@
+use anyhow::{bail, Result};
+
+use super::clump::{RpAtomic, RwFrame, RwFrameList};
+use super::endian::Endian;
+use super::geo::{GeometryFlags, MorphTarget, RpGeometry, RpTriangle, RwSphere, RwV3d};
+use super::skin::{HAnimNode, RpHAnim, RpSkin, HANIM_POP, HANIM_PUSH};
+use super::tex::{
+    RasterEncoding, RgbaImage, RpMaterial, RpMaterialList, RpRasterPC, RpTexture, RwRGBA,
+    RwTexCoords, RwTextureDictionary, TextureAddressingMode, TextureFilteringMode,
+    DEFAULT_SURFACE_PROP,
+};
+use super::{Chunk, ChunkContent, ChunkHeader, RwBuild, RwVersion};
+
+fn node(version: RwVersion, content: ChunkContent, children: Vec<Chunk>) -> Chunk {
+    Chunk {
+        header: ChunkHeader {
+            version,
+            build: RwBuild::UNSPECIFIED,
+            endian: Endian::Little,
+            size: None,
+        },
+        content,
+        children: Some(children),
+    }
+}
+
+fn leaf(version: RwVersion, content: ChunkContent) -> Chunk {
+    Chunk {
+        children: None,
+        ..node(version, content, Vec::new())
+    }
+}
+
+fn extension(version: RwVersion, plugins: Vec<Chunk>) -> Chunk {
+    node(version, ChunkContent::Extension, plugins)
+}
+
+fn raw_struct(version: RwVersion, words: &[u32]) -> Chunk {
+    let data = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+    leaf(version, ChunkContent::Struct(data))
+}
+
+// TextureDictionary of the images encoded as PC rasters. The images are split between one thread
+// per core, the dictionary keeps them in the order given, so the output doesn't depend on the
+// number of threads.
+pub fn texture_dictionary(
+    version: RwVersion,
+    textures: &[(&str, &RgbaImage)],
+    encoding: RasterEncoding,
+    mipmaps: bool,
+) -> Chunk {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let per_thread = textures.len().div_ceil(threads).max(1);
+    let rasters: Vec<RpRasterPC> = std::thread::scope(|scope| {
+        let handles: Vec<_> = textures
+            .chunks(per_thread)
+            .map(|textures| {
+                scope.spawn(move || {
+                    textures
+                        .iter()
+                        .map(|(name, image)| {
+                            RpRasterPC::encode(name, image, encoding, mipmaps, version)
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    });
+
+    let mut children: Vec<Chunk> = rasters
+        .into_iter()
+        .map(|raster| {
+            let raster = ChunkContent::Raster(raster);
+            node(version, raster, vec![extension(version, Vec::new())])
+        })
+        .collect();
+    children.push(extension(version, Vec::new()));
+    let dict = RwTextureDictionary {
+        num_textures: textures.len() as u16,
+        device_id: if version >= RwVersion::SA_PC {
+            RwTextureDictionary::DEVICE_D3D9
+        } else {
+            RwTextureDictionary::DEVICE_D3D8
+        },
+    };
+    node(version, ChunkContent::TextureDictionary(dict), children)
+}
+
+#[derive(Clone, Debug)]
+struct BuilderMaterial {
+    color: [u8; 4],
+    texture: Option<String>,
+}
+
+// Collects the data of a single geometry. Per-vertex lists that are left empty aren't stored.
+#[derive(Clone, Debug, Default)]
+pub struct GeometryBuilder {
+    vertices: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    tex_coords: Vec<[f32; 2]>,
+    colors: Vec<[u8; 4]>,
+    triangles: Vec<RpTriangle>,
+    materials: Vec<BuilderMaterial>,
+    skin: Option<RpSkin>,
+}
+
+impl GeometryBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn vertices(&mut self, vertices: Vec<[f32; 3]>) -> &mut Self {
+        self.vertices = vertices;
+        self
+    }
+
+    pub fn normals(&mut self, normals: Vec<[f32; 3]>) -> &mut Self {
+        self.normals = normals;
+        self
+    }
+
+    pub fn tex_coords(&mut self, tex_coords: Vec<[f32; 2]>) -> &mut Self {
+        self.tex_coords = tex_coords;
+        self
+    }
+
+    pub fn colors(&mut self, colors: Vec<[u8; 4]>) -> &mut Self {
+        self.colors = colors;
+        self
+    }
+
+    // Bone indices refer to the order returned by ClumpBuilder::skeleton, inverse matrices are
+    // column-major
+    pub fn skin(
+        &mut self,
+        bone_indices: Vec<[u8; 4]>,
+        weights: Vec<[f32; 4]>,
+        inverse_matrices: Vec<[[f32; 4]; 4]>,
+    ) -> &mut Self {
+        let mut used_bones = Vec::new();
+        let mut max_weights = 0;
+        for (bones, weights) in bone_indices.iter().zip(&weights) {
+            let used = weights.iter().filter(|w| **w > 0.0).count();
+            max_weights = max_weights.max(used as u8);
+            for (bone, _) in bones.iter().zip(weights).filter(|(_, w)| **w > 0.0) {
+                used_bones.push(*bone);
+            }
+        }
+        used_bones.sort_unstable();
+        used_bones.dedup();
+        self.skin = Some(RpSkin {
+            num_bones: inverse_matrices.len() as u8,
+            max_weights,
+            used_bones,
+            bone_indices,
+            weights,
+            inverse_matrices,
+            matrix_flags: Vec::new(),
+            // Bone limit, mesh and RLE counts of the split data, which isn't used
+            split_data: vec![0; 12],
+        });
+        self
+    }
+
+    // Returns the material id to use for triangles
+    pub fn material(&mut self, color: [u8; 4], texture: Option<&str>) -> u16 {
+        let id = self.materials.len() as u16;
+        self.materials.push(BuilderMaterial {
+            color,
+            texture: texture.map(str::to_owned),
+        });
+        id
+    }
+
+    pub fn triangle(&mut self, indices: [u16; 3], material_id: u16) -> &mut Self {
+        let [vertex1, vertex2, vertex3] = indices;
+        self.triangles.push(RpTriangle {
+            vertex1,
+            vertex2,
+            vertex3,
+            material_id,
+        });
+        self
+    }
+
+    // Fails if a per-vertex list doesn't match the vertices, or a triangle refers to a vertex or
+    // material that wasn't added
+    pub fn build(self, version: RwVersion) -> Result<Chunk> {
+        let num_vertices = self.vertices.len();
+        if num_vertices > 0x10000 {
+            bail!("{} vertices, at most 65536 can be indexed", num_vertices);
+        }
+        if self.materials.len() > 0x10000 {
+            bail!(
+                "{} materials, at most 65536 can be used",
+                self.materials.len()
+            );
+        }
+        let skin = self.skin.as_ref();
+        for (name, len) in [
+            ("normals", self.normals.len()),
+            ("tex_coords", self.tex_coords.len()),
+            ("colors", self.colors.len()),
+            ("bone indices", skin.map_or(0, |s| s.bone_indices.len())),
+            ("weights", skin.map_or(0, |s| s.weights.len())),
+        ] {
+            if len != 0 && len != num_vertices {
+                bail!("{} {}, but {} vertices", len, name, num_vertices);
+            }
+        }
+        for (i, t) in self.triangles.iter().enumerate() {
+            if let Some(v) = t.as_arr().into_iter().find(|&v| v as usize >= num_vertices) {
+                bail!("Triangle {} uses vertex {} of {}", i, v, num_vertices);
+            }
+            if t.material_id as usize >= self.materials.len() {
+                bail!(
+                    "Triangle {} uses material {} of {}",
+                    i,
+                    t.material_id,
+                    self.materials.len()
+                );
+            }
+        }
+
+        let mut flags = GeometryFlags::POSITIONS | GeometryFlags::LIGHT;
+        if !self.normals.is_empty() {
+            flags |= GeometryFlags::NORMALS;
+        }
+        if !self.colors.is_empty() {
+            flags |= GeometryFlags::PRELIT;
+        }
+        if !self.tex_coords.is_empty() {
+            flags |= GeometryFlags::TEXTURED;
+        }
+
+        let mut geo = RpGeometry {
+            flags,
+            num_triangles: self.triangles.len() as u32,
+            num_vertices: self.vertices.len() as u32,
+            num_morphs: 1,
+            surface_prop: (version < RwVersion(0x34000)).then_some(DEFAULT_SURFACE_PROP),
+            prelit: self
+                .colors
+                .iter()
+                .map(|&[r, g, b, a]| RwRGBA { r, g, b, a })
+                .collect(),
+            night_prelit: Vec::new(),
+            tex_coords: if self.tex_coords.is_empty() {
+                Vec::new()
+            } else {
+                vec![self
+                    .tex_coords
+                    .iter()
+                    .map(|&[u, v]| RwTexCoords { u, v })
+                    .collect()]
+            },
+            triangles: self.triangles,
+            morph_targets: vec![MorphTarget {
+                sphere: RwSphere::default(),
+                vertices: self.vertices.iter().copied().map(RwV3d::from_arr).collect(),
+                normals: self.normals.iter().copied().map(RwV3d::from_arr).collect(),
+            }],
+        };
+        geo.update_bounds();
+
+        let materials: Vec<_> = self
+            .materials
+            .into_iter()
+            .map(|m| build_material(version, m))
+            .collect();
+        let mut list = RpMaterialList::new(materials.len() as u32);
+        list.resolve(&materials);
+        let material_list = node(version, ChunkContent::MaterialList(list), materials);
+        let plugins = self
+            .skin
+            .map(|skin| leaf(version, ChunkContent::Skin(skin)))
+            .into_iter()
+            .collect();
+        Ok(node(
+            version,
+            ChunkContent::Geometry(geo),
+            vec![material_list, extension(version, plugins)],
+        ))
+    }
+}
+
+fn build_material(version: RwVersion, material: BuilderMaterial) -> Chunk {
+    let [r, g, b, a] = material.color;
+    let mut children = Vec::new();
+    if let Some(name) = material.texture {
+        let texture = RpTexture {
+            filtering: TextureFilteringMode::FILTERLINEAR,
+            address_u: TextureAddressingMode::TEXTUREADDRESSWRAP,
+            address_v: TextureAddressingMode::TEXTUREADDRESSWRAP,
+            has_mip: false,
+        };
+        children.push(node(
+            version,
+            ChunkContent::Texture(texture),
+            vec![
+                leaf(version, ChunkContent::String(name)),
+                leaf(version, ChunkContent::String(String::new())),
+                extension(version, Vec::new()),
+            ],
+        ));
+    }
+    children.push(extension(version, Vec::new()));
+    let material = RpMaterial {
+        flags: 0,
+        color: RwRGBA { r, g, b, a },
+        unused: 0,
+        surface_prop: (version > RwVersion(0x30400)).then_some(DEFAULT_SURFACE_PROP),
+    };
+    node(version, ChunkContent::Material(material), children)
+}
+
+// Assembles frames, geometries and the atomics pairing them into a Clump
+#[derive(Clone, Debug)]
+pub struct ClumpBuilder {
+    version: RwVersion,
+    frames: Vec<RwFrame>,
+    geometries: Vec<Chunk>,
+    atomics: Vec<RpAtomic>,
+    hanims: Vec<(usize, RpHAnim)>, // (frame index, plugin)
+}
+
+impl ClumpBuilder {
+    pub fn new(version: RwVersion) -> Self {
+        Self {
+            version,
+            frames: Vec::new(),
+            geometries: Vec::new(),
+            atomics: Vec::new(),
+            hanims: Vec::new(),
+        }
+    }
+
+    // Adds a frame with an identity rotation and returns its index
+    pub fn frame(
+        &mut self,
+        name: Option<&str>,
+        parent: Option<usize>,
+        position: [f32; 3],
+    ) -> usize {
+        let axis = |x, y, z| RwV3d { x, y, z };
+        self.frames.push(RwFrame {
+            rotation: [
+                axis(1.0, 0.0, 0.0),
+                axis(0.0, 1.0, 0.0),
+                axis(0.0, 0.0, 1.0),
+            ],
+            position: axis(position[0], position[1], position[2]),
+            parent: parent.map_or(-1, |p| p as i32),
+            flags: 0,
+            name: name.map(str::to_owned),
+        });
+        self.frames.len() - 1
+    }
+
+    // Rotation as the right, up and at vectors of the frame
+    pub fn rotate(&mut self, frame_index: usize, rotation: [[f32; 3]; 3]) -> &mut Self {
+        let frame = &mut self.frames[frame_index];
+        frame.rotation = rotation.map(|[x, y, z]| RwV3d { x, y, z });
+        self
+    }
+
+    // Adds an HAnim hierarchy over the given frames, which must already be added along with
+    // their parents. Returns the bone index of every joint, bones are ordered depth first.
+    pub fn skeleton(&mut self, joints: &[usize]) -> Vec<usize> {
+        // Nearest ancestor that's also a joint
+        let joint_parent = |frame: usize| {
+            let mut parent = self.frames[frame].parent;
+            while parent >= 0 && !joints.contains(&(parent as usize)) {
+                parent = self.frames[parent as usize].parent;
+            }
+            (parent >= 0).then_some(parent as usize)
+        };
+        let parents: Vec<Option<usize>> = joints.iter().map(|&j| joint_parent(j)).collect();
+        let children = |parent: Option<usize>| -> Vec<usize> {
+            (0..joints.len())
+                .filter(|&j| parents[j] == parent)
+                .collect()
+        };
+
+        let mut order = Vec::with_capacity(joints.len());
+        let mut flags = vec![0; joints.len()];
+        let mut stack: Vec<usize> = children(None).into_iter().rev().collect();
+        while let Some(j) = stack.pop() {
+            let siblings = children(parents[j]);
+            if siblings.last() != Some(&j) {
+                flags[j] |= HANIM_PUSH;
+            }
+            let kids = children(Some(joints[j]));
+            if kids.is_empty() {
+                flags[j] |= HANIM_POP;
+            }
+            order.push(j);
+            stack.extend(kids.into_iter().rev());
+        }
+
+        let mut bone_indices = vec![0; joints.len()];
+        let nodes: Vec<HAnimNode> = order
+            .iter()
+            .enumerate()
+            .map(|(index, &j)| {
+                bone_indices[j] = index;
+                HAnimNode {
+                    id: index as i32,
+                    index: index as u32,
+                    flags: flags[j],
+                }
+            })
+            .collect();
+        for (index, &j) in order.iter().enumerate() {
+            self.hanims.push((
+                joints[j],
+                RpHAnim {
+                    version: 0x100,
+                    id: index as i32,
+                    flags: 0,
+                    key_frame_size: if index == 0 { 36 } else { 0 },
+                    nodes: if index == 0 {
+                        nodes.clone()
+                    } else {
+                        Vec::new()
+                    },
+                },
+            ));
+        }
+        bone_indices
+    }
+
+    pub fn geometry(&mut self, geometry: GeometryBuilder) -> Result<usize> {
+        self.geometries.push(geometry.build(self.version)?);
+        Ok(self.geometries.len() - 1)
+    }
+
+    pub fn atomic(&mut self, frame_index: usize, geometry_index: usize) -> &mut Self {
+        self.atomics.push(RpAtomic {
+            frame_index: frame_index as u32,
+            geometry_index: geometry_index as u32,
+            flags: 5, // Collision test and render
+        });
+        self
+    }
+
+    pub fn build(self) -> Chunk {
+        let version = self.version;
+        let mut clump_struct = vec![self.atomics.len() as u32];
+        if version > RwVersion(0x33000) {
+            clump_struct.extend([0, 0]); // Lights and cameras
+        }
+
+        // Every frame is followed by an Extension holding its name
+        let mut frame_children = Vec::new();
+        for (i, frame) in self.frames.iter().enumerate() {
+            let mut plugins: Vec<Chunk> = frame
+                .name
+                .iter()
+                .map(|n| leaf(version, ChunkContent::FrameName(n.clone())))
+                .collect();
+            plugins.extend(
+                self.hanims
+                    .iter()
+                    .filter(|(f, _)| *f == i)
+                    .map(|(_, h)| leaf(version, ChunkContent::HAnim(h.clone()))),
+            );
+            frame_children.push(extension(version, plugins));
+        }
+        let frame_list = RwFrameList {
+            frames: self.frames,
+        };
+
+        let mut geometry_list = vec![raw_struct(version, &[self.geometries.len() as u32])];
+        geometry_list.extend(self.geometries);
+
+        let mut children = vec![
+            raw_struct(version, &clump_struct),
+            node(version, ChunkContent::FrameList(frame_list), frame_children),
+            node(version, ChunkContent::GeometryList, geometry_list),
+        ];
+        for atomic in self.atomics {
+            children.push(node(
+                version,
+                ChunkContent::Atomic(atomic),
+                vec![extension(version, Vec::new())],
+            ));
+        }
+        children.push(extension(version, Vec::new()));
+        node(version, ChunkContent::Clump, children)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsf::tex;
+    use crate::query::Query;
+
+    #[test]
+    fn build_clump() {
+        let mut geometry = GeometryBuilder::new();
+        geometry
+            .vertices(vec![[0.0, 0.0, 0.0], [2.0, 0.0, 0.0], [0.0, 2.0, 0.0]])
+            .tex_coords(vec![[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]]);
+        let material = geometry.material([255, 0, 0, 255], Some("crate"));
+        geometry.triangle([0, 1, 2], material);
+
+        let mut clump = ClumpBuilder::new(RwVersion::VC_PC);
+        let root = clump.frame(Some("crate_root"), None, [0.0; 3]);
+        let frame = clump.frame(Some("crate_dummy"), Some(root), [0.0, 0.0, 1.0]);
+        let geometry = clump.geometry(geometry).unwrap();
+        clump.atomic(frame, geometry);
+        let clump = clump.build();
+
+        let frames = clump.get_children()[1].content.as_frame_list().unwrap();
+        assert_eq!(frames.find("CRATE_DUMMY"), Some(1));
+        let geo_chunk = &clump.get_children()[2].get_children()[1];
+        let geo = geo_chunk.content.as_geometry().unwrap();
+        assert!(geo.is_textured() && !geo.is_prelit());
+        assert_eq!(geo.morph_targets[0].sphere.radius, 2f32.sqrt());
+        assert!(Query::ReferencesTexture("crate".into()).matches(&clump));
+        let atomic = clump.get_children()[3].content.as_atomic().unwrap();
+        assert_eq!((atomic.frame_index, atomic.geometry_index), (1, 0));
+    }
+
+    #[test]
+    fn invalid_geometry() {
+        let mut geometry = GeometryBuilder::new();
+        geometry.vertices(vec![[0.0; 3]; 3]);
+        // No material added yet
+        geometry.triangle([0, 1, 2], 0);
+        assert!(geometry.clone().build(RwVersion::VC_PC).is_err());
+        let material = geometry.material([255; 4], None);
+        assert_eq!(material, 0);
+        assert!(geometry.clone().build(RwVersion::VC_PC).is_ok());
+
+        geometry.colors(vec![[255; 4]; 2]);
+        assert!(geometry.clone().build(RwVersion::VC_PC).is_err());
+        geometry
+            .colors(vec![[255; 4]; 3])
+            .triangle([0, 1, 3], material);
+        let mut clump = ClumpBuilder::new(RwVersion::VC_PC);
+        assert!(clump.geometry(geometry).is_err());
+    }
+
+    #[test]
+    fn write_built_clump() {
+        let mut geometry = GeometryBuilder::new();
+        geometry
+            .vertices(vec![[0.0; 3], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]])
+            .colors(vec![[255; 4]; 3]);
+        let material = geometry.material([255; 4], Some("wall"));
+        geometry.triangle([0, 1, 2], material);
+        let mut clump = ClumpBuilder::new(RwVersion::SA_PC);
+        let frame = clump.frame(Some("wall"), None, [0.0; 3]);
+        let geometry = clump.geometry(geometry).unwrap();
+        clump.atomic(frame, geometry);
+        let data = clump.build().to_bytes().unwrap();
+
+        let (rest, clump) = Chunk::parse(&data).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(clump.to_bytes().unwrap(), data);
+        assert!(Query::ReferencesTexture("wall".into()).matches(&clump));
+        let frames = clump.get_children()[1].content.as_frame_list().unwrap();
+        assert_eq!(frames.frames[0].name.as_deref(), Some("wall"));
+    }
+
+    #[test]
+    fn build_texture_dictionary() {
+        let images: Vec<_> = (0..9u8)
+            .map(|n| tex::RgbaImage {
+                width: 8,
+                height: 8,
+                data: [n * 20, 255 - n * 20, n, 255].repeat(64),
+            })
+            .collect();
+        let names: Vec<String> = (0..9).map(|n| format!("tex{n}")).collect();
+        let textures: Vec<_> = names.iter().map(|n| n.as_str()).zip(&images).collect();
+        let txd = texture_dictionary(RwVersion::SA_PC, &textures, tex::RasterEncoding::Dxt1, true);
+        let data = txd.to_bytes().unwrap();
+
+        let (_, parsed) = Chunk::parse(&data).unwrap();
+        let dict = parsed.content.as_texture_dictionary().unwrap();
+        assert_eq!((dict.num_textures, dict.device_id), (9, 2));
+        for ((texture, name), image) in parsed.textures().zip(&names).zip(&images) {
+            assert_eq!(texture.texture_name(), Some(name.as_str()));
+            let expected = RpRasterPC::encode(
+                name,
+                image,
+                tex::RasterEncoding::Dxt1,
+                true,
+                RwVersion::SA_PC,
+            );
+            assert_eq!(texture.content.as_raster().unwrap().data, expected.data);
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use nom::bytes::complete::take;
+use nom::multi::count;
+use nom::number::complete::{le_u16, le_u32};
+use nom::IResult;
+use nom_derive::Nom;
+
+use super::endian::Endian;
+use super::geo::RwV3d;
+use super::RwVersion;
+use crate::col::ColModel;
+
+#[derive(Clone, Debug, Nom)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RwFrame {
+    pub rotation: [RwV3d; 3], // right, up, at
+    pub position: RwV3d,
+    pub parent: i32,
+    pub flags: u32,
+    #[nom(Ignore)]
+    pub name: Option<String>, // From the Frame extension, e.g. "wheel_lf_dummy"
+}
+
+impl RwFrame {
+    pub fn parent_index(&self) -> Option<usize> {
+        (self.parent >= 0).then_some(self.parent as usize)
+    }
+
+    // Column-major 4x4 matrix
+    pub fn matrix(&self) -> [[f32; 4]; 4] {
+        let [right, up, at] = &self.rotation;
+        [
+            [right.x, right.y, right.z, 0.0],
+            [up.x, up.y, up.z, 0.0],
+            [at.x, at.y, at.z, 0.0],
+            [self.position.x, self.position.y, self.position.z, 1.0],
+        ]
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RwFrameList {
+    pub frames: Vec<RwFrame>,
+}
+
+impl RwFrameList {
+    pub fn parse(i: &[u8], _version: RwVersion, endian: Endian) -> IResult<&[u8], Self> {
+        let (i, num_frames) = endian.u32(i)?;
+        let (i, frames) = count(|i| endian.parse(i), num_frames as usize)(i)?;
+
+        Ok((i, Self { frames }))
+    }
+
+    pub fn write(&self, _version: RwVersion) -> Vec<u8> {
+        let mut data = (self.frames.len() as u32).to_le_bytes().to_vec();
+        for frame in &self.frames {
+            for v in frame.rotation.iter().chain([&frame.position]) {
+                data.extend(v.to_bytes());
+            }
+            data.extend(frame.parent.to_le_bytes());
+            data.extend(frame.flags.to_le_bytes());
+        }
+        data
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.frames.iter().position(|f| {
+            f.name
+                .as_deref()
+                .is_some_and(|n| n.eq_ignore_ascii_case(name))
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RpAtomic {
+    pub frame_index: u32,
+    pub geometry_index: u32,
+    pub flags: u32,
+}
+
+impl RpAtomic {
+    pub fn parse(i: &[u8], _version: RwVersion, endian: Endian) -> IResult<&[u8], Self> {
+        let (i, frame_index) = endian.u32(i)?;
+        let (i, geometry_index) = endian.u32(i)?;
+        let (i, flags) = endian.u32(i)?;
+        let (i, _unused) = endian.u32(i)?;
+
+        Ok((
+            i,
+            Self {
+                frame_index,
+                geometry_index,
+                flags,
+            },
+        ))
+    }
+
+    pub fn write(&self, _version: RwVersion) -> Vec<u8> {
+        [self.frame_index, self.geometry_index, self.flags, 0]
+            .iter()
+            .flat_map(|w| w.to_le_bytes())
+            .collect()
+    }
+}
+
+// Plugin that should render the atomic or material, e.g. Skin or MatFX
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RpRightToRender {
+    pub plugin_id: u32,
+    pub extra_data: u32,
+}
+
+impl RpRightToRender {
+    pub fn parse(i: &[u8], _version: RwVersion) -> IResult<&[u8], Self> {
+        let (i, plugin_id) = le_u32(i)?;
+        let (i, extra_data) = le_u32(i)?;
+        Ok((
+            i,
+            Self {
+                plugin_id,
+                extra_data,
+            },
+        ))
+    }
+
+    pub fn write(&self, _version: RwVersion) -> Vec<u8> {
+        let mut data = self.plugin_id.to_le_bytes().to_vec();
+        data.extend(self.extra_data.to_le_bytes());
+        data
+    }
+}
+
+// Rockstar extension on atomics selecting the render pipeline
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RpPipelineSet {
+    pub pipeline_id: u32,
+}
+
+impl RpPipelineSet {
+    // IDs of the SA pipelines, as librw's gtaplg and the game use them
+    pub const BUILDING: u32 = 0x53F20098;
+    pub const BUILDING_DN: u32 = 0x53F2009A; // Buildings with day/night vertex colors
+    pub const VEHICLE: u32 = 0x53F2009C; // Vehicles with env map/specular materials
+
+    pub fn parse(i: &[u8], _version: RwVersion) -> IResult<&[u8], Self> {
+        let (i, pipeline_id) = le_u32(i)?;
+        Ok((i, Self { pipeline_id }))
+    }
+
+    pub fn write(&self, _version: RwVersion) -> Vec<u8> {
+        self.pipeline_id.to_le_bytes().to_vec()
+    }
+}
+
+// Collision file embedded in SA clumps. Only the header common to all COL
+// versions is decoded, data holds the complete COL file for ColModel::parse.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RpCollisionModel {
+    pub version: u8, // 1 for COLL, otherwise the digit of COL2/COL3/COL4
+    pub name: String,
+    pub model_id: u16,
+    pub data: Vec<u8>,
+}
+
+impl RpCollisionModel {
+    pub fn parse(i: &[u8], _version: RwVersion) -> IResult<&[u8], Self> {
+        let data = i.to_vec();
+        let (i, fourcc) = take(4usize)(i)?;
+        let version = match fourcc {
+            b"COLL" => 1,
+            [b'C', b'O', b'L', v @ b'2'..=b'4'] => v - b'0',
+            _ => {
+                return Err(nom::Err::Error(nom::error::Error::new(
+                    i,
+                    nom::error::ErrorKind::Tag,
+                )))
+            }
+        };
+        let (i, _size) = le_u32(i)?;
+        let (i, name) = take(22usize)(i)?;
+        let name =
+            String::from_utf8_lossy(name.split(|c| *c == 0).next().unwrap_or(&[])).to_string();
+        let (i, model_id) = le_u16(i)?;
+
+        Ok((
+            i,
+            Self {
+                version,
+                name,
+                model_id,
+                data,
+            },
+        ))
+    }
+
+    pub fn model(&self) -> Option<ColModel> {
+        ColModel::parse(&self.data).ok().map(|(_, model)| model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsf::fixtures::{chunk, floats};
+    use crate::bsf::{Chunk, ChunkContent, ChunkTypeId};
+
+    #[test]
+    fn frame_list() {
+        let mut data = 2u32.to_le_bytes().to_vec();
+        for (parent, pos) in [(-1i32, 0.0), (0, 1.5)] {
+            data.extend(floats(&[1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0]));
+            data.extend(floats(&[0.0, 0.0, pos]));
+            data.extend(parent.to_le_bytes());
+            data.extend(0u32.to_le_bytes());
+        }
+        let mut payload = chunk(0x01, &data);
+        payload.extend(chunk(0x03, &[]));
+        payload.extend(chunk(0x03, &chunk(0x0253F2FE, b"wheel_lf_dummy")));
+        let file = chunk(0x0E, &payload);
+
+        let (_, frame_list) = Chunk::parse(&file).unwrap();
+        let ChunkContent::FrameList(list) = frame_list.content else {
+            panic!("not a frame list");
+        };
+        assert_eq!(list.frames.len(), 2);
+        assert_eq!(list.frames[0].parent_index(), None);
+        assert_eq!(list.frames[1].parent_index(), Some(0));
+        assert_eq!(list.frames[1].matrix()[3], [0.0, 0.0, 1.5, 1.0]);
+        assert_eq!(list.frames[0].name, None);
+        assert_eq!(list.find("WHEEL_LF_DUMMY"), Some(1));
+    }
+
+    #[test]
+    fn right_to_render() {
+        // Skin pipeline plugin id with extra data
+        let data: Vec<u8> = [0x116u32, 1].iter().flat_map(|w| w.to_le_bytes()).collect();
+        let file = chunk(0x03, &chunk(0x1F, &data));
+        let (_, ext) = Chunk::parse(&file).unwrap();
+        let rights = ext.get_children()[0].content.as_right_to_render().unwrap();
+        assert_eq!((rights.plugin_id, rights.extra_data), (0x116, 1));
+        assert_eq!(ext.to_bytes().unwrap(), file);
+    }
+
+    #[test]
+    fn pipeline_set() {
+        let file = chunk(0x0253F2F3, &0x53F2009Cu32.to_le_bytes());
+        let (_, chunk) = Chunk::parse(&file).unwrap();
+        let pipeline = chunk.content.as_pipeline_set().unwrap();
+        assert_eq!(pipeline.pipeline_id, RpPipelineSet::VEHICLE);
+        assert_eq!(chunk.to_bytes().unwrap(), file);
+        assert_eq!(RpPipelineSet::BUILDING, 0x53F20098);
+        assert_eq!(RpPipelineSet::BUILDING_DN, 0x53F2009A);
+    }
+
+    #[test]
+    fn embedded_collision() {
+        let mut col = b"COL3".to_vec();
+        col.extend(24u32.to_le_bytes());
+        let mut name = b"infernus".to_vec();
+        name.resize(22, 0);
+        col.extend(name);
+        col.extend(411u16.to_le_bytes());
+        let file = chunk(0x10, &chunk(0x03, &chunk(0x0253F2FA, &col)));
+
+        let (_, clump) = Chunk::parse(&file).unwrap();
+        let col = clump.collision_model().unwrap();
+        assert_eq!((col.version, col.model_id), (3, 411));
+        assert_eq!(col.name, "infernus");
+        assert_eq!(col.data.len(), 32);
+
+        let mut unknown = b"COLX".to_vec();
+        unknown.extend([0; 28]);
+        let file = chunk(0x10, &chunk(0x03, &chunk(0x0253F2FA, &unknown)));
+        let (_, clump) = Chunk::parse(&file).unwrap();
+        assert!(clump.collision_model().is_none());
+        assert!(matches!(
+            &clump.get_extension()[0].content,
+            ChunkContent::Section((ChunkTypeId(0x0253F2FA), data)) if *data == unknown
+        ));
+        assert_eq!(clump.to_bytes().unwrap(), file);
+    }
+}
@@ -0,0 +1,172 @@
+use nom::bytes::complete::take;
+use nom::multi::count;
+use nom::number::complete::le_u32;
+use nom::IResult;
+use nom_derive::Parse;
+
+use super::geo::{GeometryFlags, RwSphere, RwV3d};
+use super::tex::{RwRGBA, RwTexCoords};
+use super::RwVersion;
+
+// Delta Morph PLG of a geometry, used for the facial expressions of San Andreas heads
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RpDeltaMorph {
+    pub targets: Vec<DeltaMorphTarget>,
+}
+
+// Offsets for the subset of vertices a target moves. The lists that are present, as given by
+// flags, hold one entry per affected vertex.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeltaMorphTarget {
+    pub name: String,
+    pub flags: GeometryFlags,
+    pub rle: Vec<u8>, // Pairs of (vertices skipped, vertices affected) counts
+    pub positions: Vec<RwV3d>,
+    pub normals: Vec<RwV3d>,
+    pub prelit: Vec<RwRGBA>,
+    pub tex_coords: Vec<RwTexCoords>,
+    pub sphere: RwSphere,
+}
+
+impl RpDeltaMorph {
+    pub fn parse(i: &[u8], _version: RwVersion) -> IResult<&[u8], Self> {
+        let (i, num_targets) = le_u32(i)?;
+        let (i, targets) = count(DeltaMorphTarget::parse, num_targets as usize)(i)?;
+        Ok((i, Self { targets }))
+    }
+
+    pub fn write(&self, _version: RwVersion) -> Vec<u8> {
+        let mut data = (self.targets.len() as u32).to_le_bytes().to_vec();
+        for target in &self.targets {
+            target.write(&mut data);
+        }
+        data
+    }
+}
+
+impl DeltaMorphTarget {
+    fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        // Length includes the null terminator
+        let (i, name_len) = le_u32(i)?;
+        let (i, name) = take(name_len)(i)?;
+        let name = String::from_utf8_lossy(name).trim_matches('\0').to_string();
+        let (i, flags) = le_u32(i)?;
+        let flags = GeometryFlags::from_bits_retain(flags);
+        let (i, rle_size) = le_u32(i)?;
+        let (mut i, rle) = take(rle_size)(i)?;
+        let num_points = rle.iter().skip(1).step_by(2).map(|&n| n as usize).sum();
+
+        let mut positions = Vec::new();
+        if flags.contains(GeometryFlags::POSITIONS) {
+            (i, positions) = count(RwV3d::parse_le, num_points)(i)?;
+        }
+        let mut normals = Vec::new();
+        if flags.contains(GeometryFlags::NORMALS) {
+            (i, normals) = count(RwV3d::parse_le, num_points)(i)?;
+        }
+        let mut prelit = Vec::new();
+        if flags.contains(GeometryFlags::PRELIT) {
+            (i, prelit) = count(RwRGBA::parse_le, num_points)(i)?;
+        }
+        let mut tex_coords = Vec::new();
+        if flags.contains(GeometryFlags::TEXTURED) {
+            (i, tex_coords) = count(RwTexCoords::parse_le, num_points)(i)?;
+        }
+        let (i, sphere) = RwSphere::parse_le(i)?;
+
+        Ok((
+            i,
+            Self {
+                name,
+                flags,
+                rle: rle.to_vec(),
+                positions,
+                normals,
+                prelit,
+                tex_coords,
+                sphere,
+            },
+        ))
+    }
+
+    // Indices of the affected vertices, in the order of the delta lists
+    pub fn vertex_indices(&self) -> Vec<u32> {
+        let mut indices = Vec::new();
+        let mut vertex = 0;
+        for pair in self.rle.chunks(2) {
+            vertex += pair[0] as u32;
+            if let Some(&affected) = pair.get(1) {
+                indices.extend(vertex..vertex + affected as u32);
+                vertex += affected as u32;
+            }
+        }
+        indices
+    }
+
+    // Adds the position deltas scaled by weight, 1.0 for the full expression
+    pub fn apply(&self, vertices: &mut [RwV3d], weight: f32) {
+        for (index, delta) in self.vertex_indices().into_iter().zip(&self.positions) {
+            if let Some(v) = vertices.get_mut(index as usize) {
+                v.x += delta.x * weight;
+                v.y += delta.y * weight;
+                v.z += delta.z * weight;
+            }
+        }
+    }
+
+    fn write(&self, data: &mut Vec<u8>) {
+        data.extend((self.name.len() as u32 + 1).to_le_bytes());
+        data.extend(self.name.as_bytes());
+        data.push(0);
+        data.extend(self.flags.bits().to_le_bytes());
+        data.extend((self.rle.len() as u32).to_le_bytes());
+        data.extend(&self.rle);
+        for v in self.positions.iter().chain(&self.normals) {
+            data.extend(v.to_bytes());
+        }
+        for c in &self.prelit {
+            data.extend([c.r, c.g, c.b, c.a]);
+        }
+        for t in &self.tex_coords {
+            data.extend(t.u.to_le_bytes());
+            data.extend(t.v.to_le_bytes());
+        }
+        data.extend(self.sphere.pos.to_bytes());
+        data.extend(self.sphere.radius.to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bsf::fixtures::{chunk, floats, geometry};
+    use crate::bsf::Chunk;
+
+    #[test]
+    fn delta_morph() {
+        let mut data = 1u32.to_le_bytes().to_vec();
+        data.extend(6u32.to_le_bytes());
+        data.extend(b"smile\0");
+        data.extend(0x12u32.to_le_bytes());
+        // Skip vertex 0, move 1, skip 2, move 3
+        data.extend(4u32.to_le_bytes());
+        data.extend([1, 1, 1, 1]);
+        data.extend(floats(&[0.0, 0.0, 1.0, 0.0, 0.0, -1.0]));
+        data.extend(floats(&[0.0, 1.0, 0.0, 0.0, 1.0, 0.0]));
+        data.extend(floats(&[0.0, 0.0, 0.0, 1.0]));
+        let file = geometry(&[[0.0; 3]; 4], &chunk(0x122, &data));
+
+        let (_, geo) = Chunk::parse(&file).unwrap();
+        let dmorph = geo.get_extension()[0].content.as_delta_morph().unwrap();
+        let target = &dmorph.targets[0];
+        assert_eq!(target.name, "smile");
+        assert_eq!(target.vertex_indices(), [1, 3]);
+        assert_eq!(target.normals.len(), 2);
+        let mut vertices = geo.content.as_geometry().unwrap().vertices().to_vec();
+        target.apply(&mut vertices, 0.5);
+        let z: Vec<_> = vertices.iter().map(|v| v.z).collect();
+        assert_eq!(z, [0.0, 0.5, 0.0, -0.5]);
+        assert_eq!(geo.to_bytes().unwrap(), file);
+    }
+}
@@ -0,0 +1,73 @@
+use num_traits::FromPrimitive;
+use serde_json::{json, Map, Value};
+
+use super::{Chunk, ChunkContent, ChunkType};
+
+impl Chunk {
+    // Tree of objects holding each chunk's type, version, size and decoded contents, for debugging
+    // and diffing files. Raw payloads are written as hex strings.
+    pub fn to_json(&self) -> Value {
+        let id = self.content.chunk_type().0;
+        let mut node = Map::new();
+        let name = ChunkType::from_u32(id).map_or("Unknown".to_owned(), |ty| format!("{:?}", ty));
+        node.insert("type".into(), name.into());
+        node.insert("id".into(), format!("{:#010x}", id).into());
+        node.insert("version".into(), self.header.version.to_string().into());
+        node.insert(
+            "build".into(),
+            format!("{:#06x}", self.header.build.0).into(),
+        );
+        // As declared in the file, built chunks don't have one
+        if let Some(size) = self.header.size {
+            node.insert("size".into(), size.into());
+        }
+        node.insert("content".into(), content_json(&self.content));
+        if let Some(children) = &self.children {
+            let children = children.iter().map(Chunk::to_json).collect();
+            node.insert("children".into(), Value::Array(children));
+        }
+        Value::Object(node)
+    }
+}
+
+fn content_json(content: &ChunkContent) -> Value {
+    match content {
+        ChunkContent::Section((_, data)) | ChunkContent::Struct(data) => json!(hex(data)),
+        content => match serde_json::to_value(content) {
+            // Drop the variant name, it's already in the type field
+            Ok(Value::Object(map)) if map.len() == 1 => map.into_iter().next().unwrap().1,
+            Ok(Value::String(_)) => Value::Null,
+            Ok(value) => value,
+            Err(e) => json!({ "error": e.to_string() }),
+        },
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsf::fixtures::chunk;
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_dump() {
+        let mut payload = chunk(0x01, &[0, 0x11, 0, 0]);
+        payload.extend(chunk(0x02, b"wheel\0\0\0"));
+        payload.extend(chunk(0x0BADF00D, &[0xAB, 0xCD]));
+        let (_, texture) = Chunk::parse(&chunk(0x06, &payload)).unwrap();
+
+        let json = texture.to_json();
+        assert_eq!(json["type"], "Texture");
+        assert_eq!(json["version"], "3.1.0.1");
+        assert_eq!(json["size"], payload.len());
+        assert_eq!(json["content"]["has_mip"], false);
+        assert_eq!(json["children"][0]["content"], "wheel");
+        assert_eq!(json["children"][1]["type"], "Unknown");
+        assert_eq!(json["children"][1]["content"], "abcd");
+        assert_eq!(json["children"][1]["size"], 2);
+    }
+}
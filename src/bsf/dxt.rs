@@ -0,0 +1,307 @@
+// Software decoder for the S3TC block compressed formats used by D3D and Xbox rasters
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DxtFormat {
+    Dxt1,
+    Dxt3, // Also used for premultiplied DXT2
+    Dxt5, // Also used for premultiplied DXT4
+}
+
+impl DxtFormat {
+    pub fn block_size(self) -> usize {
+        match self {
+            Self::Dxt1 => 8,
+            Self::Dxt3 | Self::Dxt5 => 16,
+        }
+    }
+
+    // Bytes taken by a level, levels smaller than a block still use a whole one
+    pub fn level_size(self, width: u32, height: u32) -> usize {
+        let blocks = |n: u32| n.div_ceil(4).max(1) as usize;
+        blocks(width) * blocks(height) * self.block_size()
+    }
+}
+
+// Magic and header of a .dds file holding levels of the format, followed by the levels largest
+// first. fourcc is kept separate so premultiplied DXT2 and DXT4 can be written as such.
+pub fn dds_header(fourcc: u32, width: u32, height: u32, num_levels: u32, top_size: u32) -> Vec<u8> {
+    const DDSD_CAPS: u32 = 0x1;
+    const DDSD_HEIGHT: u32 = 0x2;
+    const DDSD_WIDTH: u32 = 0x4;
+    const DDSD_PIXELFORMAT: u32 = 0x1000;
+    const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+    const DDSD_LINEARSIZE: u32 = 0x80000;
+    const DDPF_FOURCC: u32 = 0x4;
+    const DDSCAPS_COMPLEX: u32 = 0x8;
+    const DDSCAPS_TEXTURE: u32 = 0x1000;
+    const DDSCAPS_MIPMAP: u32 = 0x400000;
+
+    let mipmapped = num_levels > 1;
+    let mut flags = DDSD_CAPS | DDSD_HEIGHT | DDSD_WIDTH | DDSD_PIXELFORMAT | DDSD_LINEARSIZE;
+    let mut caps = DDSCAPS_TEXTURE;
+    if mipmapped {
+        flags |= DDSD_MIPMAPCOUNT;
+        caps |= DDSCAPS_COMPLEX | DDSCAPS_MIPMAP;
+    }
+    let mut words = vec![124, flags, height, width, top_size, 0, num_levels];
+    words.extend([0; 11]);
+    words.extend([32, DDPF_FOURCC, fourcc, 0, 0, 0, 0, 0]);
+    words.extend([caps, 0, 0, 0, 0]);
+
+    let mut data = b"DDS ".to_vec();
+    data.extend(words.iter().flat_map(|w| w.to_le_bytes()));
+    data
+}
+
+// Expands a level to RGBA, None if data is too short
+pub fn decode(format: DxtFormat, width: u32, height: u32, data: &[u8]) -> Option<Vec<u8>> {
+    let (w, h) = (width as usize, height as usize);
+    let blocks_x = w.div_ceil(4).max(1);
+    let data = data.get(..format.level_size(width, height))?;
+    let mut rgba = vec![0; w * h * 4];
+
+    for (b, block) in data.chunks_exact(format.block_size()).enumerate() {
+        let (bx, by) = ((b % blocks_x) * 4, (b / blocks_x) * 4);
+        let (alpha, color) = match format {
+            DxtFormat::Dxt1 => (None, block),
+            DxtFormat::Dxt3 => (Some(explicit_alpha(&block[..8])), &block[8..]),
+            DxtFormat::Dxt5 => (Some(interpolated_alpha(&block[..8])), &block[8..]),
+        };
+        let colors = color_block(color, format == DxtFormat::Dxt1);
+        for p in 0..16 {
+            let (x, y) = (bx + p % 4, by + p / 4);
+            if x >= w || y >= h {
+                continue;
+            }
+            let mut texel = colors[p];
+            if let Some(alpha) = alpha {
+                texel[3] = alpha[p];
+            }
+            let o = (y * w + x) * 4;
+            rgba[o..o + 4].copy_from_slice(&texel);
+        }
+    }
+    Some(rgba)
+}
+
+fn rgb565(c: u16) -> [u8; 4] {
+    let (r, g, b) = (
+        (c >> 11) as u8 & 0x1F,
+        (c >> 5) as u8 & 0x3F,
+        c as u8 & 0x1F,
+    );
+    [r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2, 255]
+}
+
+// Colors of the 16 texels in row order
+fn color_block(block: &[u8], dxt1: bool) -> [[u8; 4]; 16] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let palette = color_palette(c0, c1, dxt1);
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    std::array::from_fn(|p| palette[(indices >> (p * 2)) as usize & 3])
+}
+
+fn color_palette(c0: u16, c1: u16, dxt1: bool) -> [[u8; 4]; 4] {
+    let (a, b) = (rgb565(c0), rgb565(c1));
+    let mix = |wa: u16, wb: u16| {
+        let mut c = [0, 0, 0, 255];
+        for i in 0..3 {
+            c[i] = ((a[i] as u16 * wa + b[i] as u16 * wb) / (wa + wb)) as u8;
+        }
+        c
+    };
+    // DXT1 switches to three colors and transparent black when the endpoints are in order
+    if c0 > c1 || !dxt1 {
+        [a, b, mix(2, 1), mix(1, 2)]
+    } else {
+        [a, b, mix(1, 1), [0, 0, 0, 0]]
+    }
+}
+
+// DXT3, 4 bits per texel, low nibble first
+fn explicit_alpha(block: &[u8]) -> [u8; 16] {
+    std::array::from_fn(|p| {
+        let a = (block[p / 2] >> ((p % 2) * 4)) & 0xF;
+        a << 4 | a
+    })
+}
+
+// DXT5, two endpoints and 3 bit indices
+fn interpolated_alpha(block: &[u8]) -> [u8; 16] {
+    let palette = alpha_palette(block[0], block[1]);
+    let mut bits = [0; 8];
+    bits[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(bits);
+    std::array::from_fn(|p| palette[(indices >> (p * 3)) as usize & 7])
+}
+
+fn alpha_palette(a0: u8, a1: u8) -> [u8; 8] {
+    let (a0, a1) = (a0 as u32, a1 as u32);
+    let mut palette = [a0, a1, 0, 0, 0, 0, 0, 255];
+    if a0 > a1 {
+        for i in 1..7 {
+            palette[i as usize + 1] = ((7 - i) * a0 + i * a1) / 7;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i as usize + 1] = ((5 - i) * a0 + i * a1) / 5;
+        }
+    }
+    palette.map(|a| a as u8)
+}
+
+// Compresses RGBA pixels, fitting each block between the corners of its color bounding box.
+// Transparent texels of DXT1 use the punch-through color when alpha is below 128.
+pub fn encode(format: DxtFormat, width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    let (w, h) = (width as usize, height as usize);
+    let mut data = Vec::with_capacity(format.level_size(width, height));
+    for by in (0..h.max(1)).step_by(4) {
+        for bx in (0..w.max(1)).step_by(4) {
+            // Edge texels are repeated to fill partial blocks
+            let texels: [[u8; 4]; 16] = std::array::from_fn(|p| {
+                let (x, y) = ((bx + p % 4).min(w - 1), (by + p / 4).min(h - 1));
+                let o = (y * w + x) * 4;
+                [rgba[o], rgba[o + 1], rgba[o + 2], rgba[o + 3]]
+            });
+            match format {
+                DxtFormat::Dxt1 => {}
+                DxtFormat::Dxt3 => {
+                    for pair in texels.chunks(2) {
+                        data.push(pair[0][3] >> 4 | (pair[1][3] >> 4) << 4);
+                    }
+                }
+                DxtFormat::Dxt5 => data.extend(encode_alpha(&texels)),
+            }
+            data.extend(encode_colors(&texels, format == DxtFormat::Dxt1));
+        }
+    }
+    data
+}
+
+fn to_565(c: [u8; 4]) -> u16 {
+    (c[0] as u16 >> 3) << 11 | (c[1] as u16 >> 2) << 5 | c[2] as u16 >> 3
+}
+
+fn distance(a: [u8; 4], b: [u8; 4]) -> u32 {
+    (0..3)
+        .map(|i| (a[i] as i32 - b[i] as i32).pow(2) as u32)
+        .sum()
+}
+
+fn encode_colors(texels: &[[u8; 4]; 16], dxt1: bool) -> [u8; 8] {
+    let punch_through = dxt1 && texels.iter().any(|t| t[3] < 128);
+    let mut min = [255, 255, 255, 255];
+    let mut max = [0, 0, 0, 255];
+    for t in texels.iter().filter(|t| !punch_through || t[3] >= 128) {
+        for i in 0..3 {
+            min[i] = min[i].min(t[i]);
+            max[i] = max[i].max(t[i]);
+        }
+    }
+    let (mut c0, mut c1) = (to_565(max), to_565(min));
+    // Four color blocks need c0 > c1, three color blocks the opposite
+    if punch_through == (c0 > c1) {
+        (c0, c1) = (c1, c0);
+    }
+    let palette = color_palette(c0, c1, dxt1);
+
+    let mut indices = 0u32;
+    for (p, t) in texels.iter().enumerate() {
+        let index = if punch_through && t[3] < 128 {
+            3
+        } else {
+            // Equal endpoints also decode as a three color block
+            let candidates = if dxt1 && c0 <= c1 { 0..3 } else { 0..4 };
+            candidates
+                .min_by_key(|&i| distance(palette[i], *t))
+                .unwrap()
+        };
+        indices |= (index as u32) << (p * 2);
+    }
+    let mut block = [0; 8];
+    block[..2].copy_from_slice(&c0.to_le_bytes());
+    block[2..4].copy_from_slice(&c1.to_le_bytes());
+    block[4..].copy_from_slice(&indices.to_le_bytes());
+    block
+}
+
+fn encode_alpha(texels: &[[u8; 4]; 16]) -> [u8; 8] {
+    let a0 = texels.iter().map(|t| t[3]).max().unwrap();
+    let a1 = texels.iter().map(|t| t[3]).min().unwrap();
+    let palette = alpha_palette(a0, a1);
+    let mut indices = 0u64;
+    for (p, t) in texels.iter().enumerate() {
+        let index = (0..8)
+            .min_by_key(|&i| (palette[i] as i32 - t[3] as i32).abs())
+            .unwrap();
+        indices |= (index as u64) << (p * 3);
+    }
+    let mut block = [a0, a1, 0, 0, 0, 0, 0, 0];
+    block[2..].copy_from_slice(&indices.to_le_bytes()[..6]);
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsf::fixtures::chunk;
+    use crate::bsf::tex::PLATFORM_D3D8;
+    use crate::bsf::Chunk;
+
+    #[test]
+    fn dxt_pc_raster() {
+        let mut data = PLATFORM_D3D8.to_le_bytes().to_vec();
+        data.extend(0x1102u32.to_le_bytes());
+        data.extend([0; 64]);
+        data.extend(0x8200u32.to_le_bytes());
+        data.extend(0u32.to_le_bytes());
+        data.extend(4u16.to_le_bytes());
+        data.extend(4u16.to_le_bytes());
+        data.extend([16, 2, 4, 1]);
+        // Red and blue endpoints, the first row uses all four colors
+        let block = [0x00, 0xF8, 0x1F, 0x00, 0b11_10_01_00, 0, 0, 0];
+        for _ in 0..2 {
+            data.extend(8u32.to_le_bytes());
+            data.extend(block);
+        }
+        let (_, chunk) = Chunk::parse(&chunk(0x15, &chunk(0x01, &data))).unwrap();
+
+        let raster = chunk.content.as_raster().unwrap();
+        assert_eq!(raster.dxt_format(), Some(DxtFormat::Dxt1));
+        let image = raster.decode().unwrap();
+        assert_eq!(image.data.len(), 4 * 4 * 4);
+        assert_eq!(
+            image.data[..16],
+            [255, 0, 0, 255, 0, 0, 255, 255, 170, 0, 85, 255, 85, 0, 170, 255]
+        );
+        assert_eq!(image.data[16..20], [255, 0, 0, 255]);
+        let levels: Vec<_> = raster.mip_levels().collect();
+        assert_eq!(levels, vec![(4, 4, &block[..]), (2, 2, &block[..])]);
+        assert_eq!(raster.level_size(2, 2), 8);
+        let mip = raster.decode_level(1).unwrap();
+        assert_eq!((mip.width, mip.height), (2, 2));
+        assert_eq!(mip.data[..8], image.data[..8]);
+        assert!(raster.decode_level(2).is_none());
+        // Corrupt level counts past what a u32 can be shifted by
+        let mut corrupt = raster.clone();
+        corrupt.num_levels = 40;
+        for _ in 2..40 {
+            corrupt.data.extend(8u32.to_le_bytes());
+            corrupt.data.extend(block);
+        }
+        assert_eq!(corrupt.mip_levels().last().unwrap().0, 1);
+        assert!(corrupt.decode_level(39).is_some());
+        assert!(corrupt.to_dds().is_some());
+
+        // Alpha of the first two texels, colors are black
+        let mut block = [0; 16];
+        block[0] = 0xF0;
+        let dxt3 = decode(DxtFormat::Dxt3, 4, 4, &block).unwrap();
+        assert_eq!([dxt3[3], dxt3[7]], [0, 255]);
+        block[..3].copy_from_slice(&[255, 0, 0b111_001]);
+        let dxt5 = decode(DxtFormat::Dxt5, 4, 4, &block).unwrap();
+        assert_eq!([dxt5[3], dxt5[7]], [0, 36]);
+        assert!(decode(DxtFormat::Dxt5, 4, 4, &block[..8]).is_none());
+    }
+}
@@ -0,0 +1,75 @@
+use nom::error::ParseError;
+use nom::number::complete::{f32, i32, u32};
+use nom::number::Endianness;
+use nom::IResult;
+use nom_derive::Parse;
+
+use super::get_chunk_version;
+
+// Byte order of a stream. PC, PS2 and Xbox files are little-endian, GameCube files big-endian.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Endian {
+    #[default]
+    Little,
+    Big,
+}
+
+impl Endian {
+    // Guesses the byte order from the library id at offset 8 of a chunk header, falling back to
+    // little-endian
+    pub fn detect(header: &[u8]) -> Endian {
+        let Some(lib_id) = header.get(8..12) else {
+            return Endian::Little;
+        };
+        let lib_id: [u8; 4] = lib_id.try_into().unwrap();
+        if !plausible_lib_id(u32::from_le_bytes(lib_id))
+            && plausible_lib_id(u32::from_be_bytes(lib_id))
+        {
+            Endian::Big
+        } else {
+            Endian::Little
+        }
+    }
+
+    pub(crate) fn u32<'a, E: ParseError<&'a [u8]>>(self, i: &'a [u8]) -> IResult<&'a [u8], u32, E> {
+        u32(self.into())(i)
+    }
+
+    pub(crate) fn i32<'a, E: ParseError<&'a [u8]>>(self, i: &'a [u8]) -> IResult<&'a [u8], i32, E> {
+        i32(self.into())(i)
+    }
+
+    pub(crate) fn f32<'a, E: ParseError<&'a [u8]>>(self, i: &'a [u8]) -> IResult<&'a [u8], f32, E> {
+        f32(self.into())(i)
+    }
+
+    // Structs deriving Nom
+    pub(crate) fn parse<'a, T, E>(self, i: &'a [u8]) -> IResult<&'a [u8], T, E>
+    where
+        T: Parse<&'a [u8], E>,
+        E: ParseError<&'a [u8]>,
+    {
+        match self {
+            Endian::Little => T::parse_le(i),
+            Endian::Big => T::parse_be(i),
+        }
+    }
+}
+
+impl From<Endian> for Endianness {
+    fn from(endian: Endian) -> Self {
+        match endian {
+            Endian::Little => Endianness::Little,
+            Endian::Big => Endianness::Big,
+        }
+    }
+}
+
+// Old format ids hold versions up to 3.1.0, newer ones have to decode to a later version
+fn plausible_lib_id(lib_id: u32) -> bool {
+    if lib_id & 0xFFFF0000 == 0 {
+        return (0x300..=0x310).contains(&lib_id);
+    }
+    (0x31001..0x38000).contains(&get_chunk_version(lib_id))
+}
@@ -0,0 +1,42 @@
+use std::fmt;
+
+use nom::error::{ErrorKind, ParseError};
+
+use super::ChunkTypeId;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RwError {
+    // A known section had no Struct child its parser could read
+    MissingStruct(ChunkTypeId),
+    InvalidValue { field: &'static str, value: u32 },
+    // Anything reported by the underlying nom parsers, usually truncated data
+    Parse(ErrorKind),
+}
+
+impl fmt::Display for RwError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingStruct(ty) => write!(f, "Chunk {:#x} has no valid Struct", ty.0),
+            Self::InvalidValue { field, value } => write!(f, "Invalid {} {:#x}", field, value),
+            Self::Parse(kind) => write!(f, "Parse error: {}", kind.description()),
+        }
+    }
+}
+
+impl std::error::Error for RwError {}
+
+impl<I> ParseError<I> for RwError {
+    fn from_error_kind(_input: I, kind: ErrorKind) -> Self {
+        Self::Parse(kind)
+    }
+
+    fn append(_input: I, _kind: ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+impl<I> From<nom::error::Error<I>> for RwError {
+    fn from(e: nom::error::Error<I>) -> Self {
+        Self::Parse(e.code)
+    }
+}
@@ -0,0 +1,40 @@
+//! Chunk fixtures shared by the bsf tests
+
+// Library id of GTA3 PC streams (3.1.0.1)
+pub(crate) const LIB_ID: u32 = 0x0401FFFF;
+
+pub(crate) fn chunk(ty: u32, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend(ty.to_le_bytes());
+    out.extend((payload.len() as u32).to_le_bytes());
+    out.extend(LIB_ID.to_le_bytes());
+    out.extend(payload);
+    out
+}
+
+pub(crate) fn floats(values: &[f32]) -> Vec<u8> {
+    values.iter().flat_map(|f| f.to_le_bytes()).collect()
+}
+
+// Untextured geometry with positions only and the given extension plugins
+pub(crate) fn geometry(vertices: &[[f32; 3]], plugins: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for word in [0, 0, vertices.len() as u32, 1] {
+        data.extend(word.to_le_bytes());
+    }
+    data.extend(floats(&[1.0, 1.0, 1.0, 0.0, 0.0, 0.0, 1.0]));
+    data.extend(1u32.to_le_bytes());
+    data.extend(0u32.to_le_bytes());
+    data.extend(floats(&vertices.concat()));
+
+    let mut payload = chunk(0x01, &data);
+    payload.extend(chunk(0x03, plugins));
+    chunk(0x0F, &payload)
+}
+
+pub(crate) fn texture(name: &str) -> Vec<u8> {
+    let mut payload = chunk(0x01, &[2, 0x11, 1, 0]);
+    payload.extend(chunk(0x02, format!("{name}\0").as_bytes()));
+    payload.extend(chunk(0x02, b"\0"));
+    chunk(0x06, &payload)
+}
@@ -1,12 +1,19 @@
+use std::collections::HashMap;
+
+use bitflags::bitflags;
+use nom::bytes::complete::take;
 use nom::multi::count;
 use nom::number::complete::le_u32;
 use nom::IResult;
 use nom_derive::{Nom, Parse};
 
-use super::tex::{RpSurfProp, RwRGBA};
+use super::endian::Endian;
+use super::tex::{RpSurfProp, RwRGBA, DEFAULT_SURFACE_PROP};
+use super::RwVersion;
 use crate::bsf::tex::RwTexCoords;
 
 #[derive(Clone, Copy, Debug, Nom)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RpTriangle {
     pub vertex2: u16,
     pub vertex1: u16,
@@ -20,7 +27,8 @@ impl RpTriangle {
     }
 }
 
-#[derive(Clone, Debug, Nom)]
+#[derive(Clone, Debug, Default, Nom)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RwV3d {
     pub x: f32,
     pub y: f32,
@@ -31,55 +39,141 @@ impl RwV3d {
     pub fn as_arr(&self) -> [f32; 3] {
         [self.x, self.y, self.z]
     }
+
+    pub fn from_arr([x, y, z]: [f32; 3]) -> Self {
+        Self { x, y, z }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.as_arr().iter().flat_map(|f| f.to_le_bytes()).collect()
+    }
 }
 
-#[derive(Clone, Debug, Nom)]
+#[derive(Clone, Debug, Default, Nom)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RwSphere {
     pub pos: RwV3d,
     pub radius: f32,
 }
 
+// Either list may be empty if the target doesn't store it
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MorphTarget {
+    pub sphere: RwSphere,
+    pub vertices: Vec<RwV3d>,
+    pub normals: Vec<RwV3d>,
+}
+
+impl MorphTarget {
+    pub fn parse(i: &[u8], endian: Endian, num_vertices: u32) -> IResult<&[u8], Self> {
+        let (i, sphere) = endian.parse(i)?;
+        let (i, has_vertices) = endian.u32(i)?;
+        let (mut i, has_normals) = endian.u32(i)?;
+
+        let mut vertices = Vec::new();
+        if has_vertices > 0 {
+            (i, vertices) = count(|i| endian.parse(i), num_vertices as usize)(i)?;
+        }
+
+        let mut normals = Vec::new();
+        if has_normals > 0 {
+            (i, normals) = count(|i| endian.parse(i), num_vertices as usize)(i)?;
+        }
+
+        Ok((
+            i,
+            Self {
+                sphere,
+                vertices,
+                normals,
+            },
+        ))
+    }
+
+    // Minimum and maximum corners of the vertices, None if the target has no vertices
+    pub fn compute_aabb(&self) -> Option<(RwV3d, RwV3d)> {
+        let first = self.vertices.first()?.as_arr();
+        let (min, max) = self.vertices.iter().fold((first, first), |(min, max), v| {
+            let v = v.as_arr();
+            (
+                [0, 1, 2].map(|c| min[c].min(v[c])),
+                [0, 1, 2].map(|c| max[c].max(v[c])),
+            )
+        });
+        Some((RwV3d::from_arr(min), RwV3d::from_arr(max)))
+    }
+
+    // Centered on the bounding box, which is good enough for culling
+    pub fn compute_bounding_sphere(&self) -> RwSphere {
+        let Some((min, max)) = self.compute_aabb() else {
+            return RwSphere::default();
+        };
+        let (min, max) = (min.as_arr(), max.as_arr());
+        let center = [0, 1, 2].map(|c| (min[c] + max[c]) / 2.0);
+        let radius = self
+            .vertices
+            .iter()
+            .map(|v| {
+                let v = v.as_arr();
+                (0..3)
+                    .map(|c| (v[c] - center[c]).powi(2))
+                    .sum::<f32>()
+                    .sqrt()
+            })
+            .fold(0.0, f32::max);
+        RwSphere {
+            pos: RwV3d::from_arr(center),
+            radius,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RpGeometry {
-    format: u32,
+    pub flags: GeometryFlags,
     pub num_triangles: u32,
     pub num_vertices: u32,
     pub num_morphs: u32,
     pub surface_prop: Option<RpSurfProp>,
     pub prelit: Vec<RwRGBA>,
+    pub night_prelit: Vec<RwRGBA>, // From the Extra Vert Colour extension
     pub tex_coords: Vec<Vec<RwTexCoords>>,
     pub triangles: Vec<RpTriangle>,
-    pub vertices: Vec<RwV3d>,
-    pub normals: Vec<RwV3d>,
+    pub morph_targets: Vec<MorphTarget>, // The first one is the base mesh
 }
 
-const RP_GEOMETRYTRISTRIP: u32 = 0x00000001;
-const RP_GEOMETRYTEXTURED: u32 = 0x00000004;
-const RP_GEOMETRYPRELIT: u32 = 0x00000008;
-const RP_GEOMETRYTEXTURED2: u32 = 0x00000080;
-const RP_GEOMETRYNATIVE: u32 = 0x01000000;
+bitflags! {
+    // Format word of a geometry. Bits 16-23 hold the number of texture coordinate sets,
+    // they are kept when parsing but have no flag of their own.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct GeometryFlags: u32 {
+        const TRISTRIP = 0x00000001;
+        const POSITIONS = 0x00000002;
+        const TEXTURED = 0x00000004;
+        const PRELIT = 0x00000008;
+        const NORMALS = 0x00000010;
+        const LIGHT = 0x00000020;
+        const MODULATE_MATERIAL_COLOR = 0x00000040;
+        const TEXTURED2 = 0x00000080;
+        const NATIVE = 0x01000000;
+    }
+}
 
 impl RpGeometry {
-    pub fn parse(i: &[u8], version: u32) -> IResult<&[u8], Self> {
-        let (i, format) = le_u32(i)?;
-        let (i, num_triangles) = le_u32(i)?;
-        let (i, num_vertices) = le_u32(i)?;
-        let (mut i, num_morphs) = le_u32(i)?;
-
-        let mut num_tex_sets = (format & 0x00FF0000) << 16;
-        if num_tex_sets == 0 {
-            if format & RP_GEOMETRYTEXTURED != 0 {
-                num_tex_sets = 1;
-            }
-            if format & RP_GEOMETRYTEXTURED2 != 0 {
-                num_tex_sets = 2;
-            }
-        }
+    pub fn parse(i: &[u8], version: RwVersion, endian: Endian) -> IResult<&[u8], Self> {
+        let (i, format) = endian.u32(i)?;
+        let flags = GeometryFlags::from_bits_retain(format);
+        let (i, num_triangles) = endian.u32(i)?;
+        let (i, num_vertices) = endian.u32(i)?;
+        let (mut i, num_morphs) = endian.u32(i)?;
 
         let mut surface_prop = None;
-        if version < 0x34000 {
+        if version < RwVersion(0x34000) {
             let s;
-            (i, s) = RpSurfProp::parse_le(i)?;
+            (i, s) = endian.parse::<RpSurfProp, _>(i)?;
             surface_prop = Some(s);
         }
 
@@ -87,51 +181,706 @@ impl RpGeometry {
         let mut tex_coords = Vec::new();
         let mut triangles = Vec::new();
 
-        if format & RP_GEOMETRYNATIVE == 0 {
-            if format & RP_GEOMETRYPRELIT != 0 {
+        if !flags.contains(GeometryFlags::NATIVE) {
+            if flags.contains(GeometryFlags::PRELIT) {
                 (i, prelit) = count(RwRGBA::parse_le, num_vertices as usize)(i)?;
             }
             (i, tex_coords) = count(
-                count(RwTexCoords::parse_le, num_vertices as usize),
-                num_tex_sets as usize,
+                count(|i| endian.parse::<RwTexCoords, _>(i), num_vertices as usize),
+                num_tex_sets(flags) as usize,
             )(i)?;
-            (i, triangles) = count(RpTriangle::parse_le, num_triangles as usize)(i)?;
-        }
-
-        // TODO: Multiple Morph sets
-
-        let (i, _) = RwSphere::parse_le(i)?;
-        let (i, has_vertices) = le_u32(i)?;
-        let (mut i, has_normals) = le_u32(i)?;
-
-        let mut vertices = Vec::new();
-        if has_vertices > 0 {
-            (i, vertices) = count(RwV3d::parse_le, num_vertices as usize)(i)?;
+            (i, triangles) = count(|i| endian.parse(i), num_triangles as usize)(i)?;
         }
 
-        let mut normals = Vec::new();
-        if has_normals > 0 {
-            (i, normals) = count(RwV3d::parse_le, num_vertices as usize)(i)?;
-        }
+        let (i, morph_targets) = count(
+            |i| MorphTarget::parse(i, endian, num_vertices),
+            num_morphs as usize,
+        )(i)?;
 
         Ok((
             i,
             Self {
-                format,
+                flags,
                 num_triangles,
                 num_vertices,
                 num_morphs,
                 surface_prop,
                 prelit,
+                night_prelit: Vec::new(),
                 tex_coords,
                 triangles,
-                vertices,
-                normals,
+                morph_targets,
             },
         ))
     }
 
     pub fn is_tristrip(&self) -> bool {
-        self.format & RP_GEOMETRYTRISTRIP > 0
+        self.flags.contains(GeometryFlags::TRISTRIP)
+    }
+
+    pub fn is_prelit(&self) -> bool {
+        self.flags.contains(GeometryFlags::PRELIT)
+    }
+
+    pub fn is_textured(&self) -> bool {
+        self.num_tex_sets() > 0
+    }
+
+    pub fn num_tex_sets(&self) -> u32 {
+        num_tex_sets(self.flags)
+    }
+
+    // Triangles described by the bin mesh with strips unrolled, so their material ids come from
+    // the splits. Falls back to the stored triangles when the splits carry no indices.
+    pub fn to_triangle_list(&self, bin_mesh: &BinMesh) -> Vec<RpTriangle> {
+        if bin_mesh
+            .splits
+            .iter()
+            .all(|(_, indices)| indices.is_empty())
+        {
+            return self.triangles.clone();
+        }
+        let vertices = self.vertices();
+        let mut triangles = Vec::new();
+        for (material_id, indices) in &bin_mesh.splits {
+            let tris = if bin_mesh.is_tristrip() {
+                strip_triangles(0, indices.len())
+            } else {
+                (0..indices.len() / 3)
+                    .map(|t| [t * 3, t * 3 + 1, t * 3 + 2])
+                    .collect()
+            };
+            for tri in tris {
+                let [a, b, c] = tri.map(|k| indices[k]);
+                // Strips are joined with degenerate triangles, either by index or by position
+                let pos = |v: u32| vertices.get(v as usize).map(RwV3d::as_arr);
+                if a == b
+                    || b == c
+                    || a == c
+                    || pos(a) == pos(b)
+                    || pos(b) == pos(c)
+                    || pos(a) == pos(c)
+                {
+                    continue;
+                }
+                triangles.push(RpTriangle {
+                    vertex1: a as u16,
+                    vertex2: b as u16,
+                    vertex3: c as u16,
+                    material_id: *material_id as u16,
+                });
+            }
+        }
+        triangles
+    }
+
+    // Triangle list indices grouped by material id, in id order, ready to upload as index
+    // buffers. The bin mesh is preferred when given since some exporters only fill its strips.
+    pub fn index_buffers(&self, bin_mesh: Option<&BinMesh>) -> Vec<(u32, Vec<u32>)> {
+        let triangles = match bin_mesh {
+            Some(bin_mesh) => self.to_triangle_list(bin_mesh),
+            None => self.triangles.clone(),
+        };
+        let mut buffers: Vec<(u32, Vec<u32>)> = Vec::new();
+        for tri in triangles {
+            let material_id = tri.material_id as u32;
+            let buffer = match buffers.iter().position(|(id, _)| *id == material_id) {
+                Some(b) => &mut buffers[b].1,
+                None => {
+                    buffers.push((material_id, Vec::new()));
+                    &mut buffers.last_mut().unwrap().1
+                }
+            };
+            buffer.extend(tri.as_arr().map(u32::from));
+        }
+        buffers.sort_by_key(|(id, _)| *id);
+        buffers
+    }
+
+    // Merges vertices whose positions, normals and UVs in every set and morph target are within
+    // epsilon and whose colors match, dropping triangles that collapse. Returns the new index of
+    // every old vertex, for remapping per-vertex plugin data such as skin weights.
+    pub fn weld_vertices(&mut self, epsilon: f32) -> Vec<u32> {
+        let n = self.num_vertices as usize;
+        let attributes = |v: usize| -> Vec<f32> {
+            let targets = self.morph_targets.iter().flat_map(|m| {
+                let pos = m.vertices.get(v).map(RwV3d::as_arr);
+                let normal = m.normals.get(v).map(RwV3d::as_arr);
+                pos.into_iter().chain(normal).flatten()
+            });
+            let uvs = self
+                .tex_coords
+                .iter()
+                .filter_map(|set| set.get(v).map(RwTexCoords::as_arr))
+                .flatten();
+            targets.chain(uvs).collect()
+        };
+        let colors = |v: usize| {
+            [&self.prelit, &self.night_prelit].map(|c| c.get(v).map(|c| [c.r, c.g, c.b, c.a]))
+        };
+        // Vertices are bucketed by position so only neighbouring cells need comparing. Cells of
+        // huge or non-finite coordinates saturate, their vertices are still compared exactly.
+        let cell_size = epsilon.max(f32::MIN_POSITIVE);
+        let cell = |v: usize| {
+            let pos = self.vertices().get(v).map_or([0.0; 3], RwV3d::as_arr);
+            pos.map(|c| (c / cell_size).floor() as i64)
+        };
+
+        let mut grid: HashMap<[i64; 3], Vec<usize>> = HashMap::new();
+        let mut kept: Vec<usize> = Vec::new(); // Old index of every new vertex
+        let mut remap = Vec::with_capacity(n);
+        for v in 0..n {
+            let attrs = attributes(v);
+            let [x, y, z] = cell(v);
+            let neighbours = (-1..=1).flat_map(|dx| {
+                (-1..=1).flat_map(move |dy| {
+                    (-1..=1).map(move |dz| {
+                        [
+                            x.saturating_add(dx),
+                            y.saturating_add(dy),
+                            z.saturating_add(dz),
+                        ]
+                    })
+                })
+            });
+            let found = neighbours
+                .filter_map(|c| grid.get(&c))
+                .flatten()
+                .find(|&&new| {
+                    let old = kept[new];
+                    colors(old) == colors(v)
+                        && attributes(old)
+                            .iter()
+                            .zip(&attrs)
+                            .all(|(a, b)| (a - b).abs() <= epsilon)
+                })
+                .copied();
+            let new = found.unwrap_or_else(|| {
+                kept.push(v);
+                grid.entry([x, y, z]).or_default().push(kept.len() - 1);
+                kept.len() - 1
+            });
+            remap.push(new as u32);
+        }
+
+        fn select<T: Clone>(values: &mut Vec<T>, kept: &[usize], n: usize) {
+            if values.len() == n {
+                *values = kept.iter().map(|&v| values[v].clone()).collect();
+            }
+        }
+        for target in &mut self.morph_targets {
+            select(&mut target.vertices, &kept, n);
+            select(&mut target.normals, &kept, n);
+        }
+        for set in &mut self.tex_coords {
+            select(set, &kept, n);
+        }
+        select(&mut self.prelit, &kept, n);
+        select(&mut self.night_prelit, &kept, n);
+
+        let map = |v: u16| remap.get(v as usize).map_or(v, |&v| v as u16);
+        self.triangles = self
+            .triangles
+            .iter()
+            .map(|t| RpTriangle {
+                vertex1: map(t.vertex1),
+                vertex2: map(t.vertex2),
+                vertex3: map(t.vertex3),
+                material_id: t.material_id,
+            })
+            .filter(|t| t.vertex1 != t.vertex2 && t.vertex2 != t.vertex3 && t.vertex1 != t.vertex3)
+            .collect();
+        self.num_vertices = kept.len() as u32;
+        self.num_triangles = self.triangles.len() as u32;
+        remap
+    }
+
+    // Positions of the base morph target
+    pub fn vertices(&self) -> &[RwV3d] {
+        self.morph_targets
+            .first()
+            .map_or(&[], |m| m.vertices.as_slice())
+    }
+
+    pub fn normals(&self) -> &[RwV3d] {
+        self.morph_targets
+            .first()
+            .map_or(&[], |m| m.normals.as_slice())
+    }
+
+    // Bounds of the base morph target
+    pub fn compute_aabb(&self) -> Option<(RwV3d, RwV3d)> {
+        self.morph_targets.first()?.compute_aabb()
+    }
+
+    pub fn compute_bounding_sphere(&self) -> RwSphere {
+        self.morph_targets
+            .first()
+            .map_or(RwSphere::default(), MorphTarget::compute_bounding_sphere)
+    }
+
+    // Recomputes the sphere of every morph target after its vertices were changed. Targets without
+    // vertices keep theirs.
+    pub fn update_bounds(&mut self) {
+        for target in &mut self.morph_targets {
+            if !target.vertices.is_empty() {
+                target.sphere = target.compute_bounding_sphere();
+            }
+        }
+    }
+
+    // Vertex data is stored in a platform specific format in the Native Data PLG
+    pub fn is_native(&self) -> bool {
+        self.flags.contains(GeometryFlags::NATIVE)
+    }
+
+    pub fn write(&self, version: RwVersion) -> Vec<u8> {
+        let mut data = Vec::new();
+        for word in [
+            self.flags.bits(),
+            if self.is_native() {
+                self.num_triangles
+            } else {
+                self.triangles.len() as u32
+            },
+            self.num_vertices,
+            self.morph_targets.len() as u32,
+        ] {
+            data.extend(word.to_le_bytes());
+        }
+        if version < RwVersion(0x34000) {
+            data.extend(self.surface_prop.unwrap_or(DEFAULT_SURFACE_PROP).to_bytes());
+        }
+        if !self.is_native() {
+            for c in &self.prelit {
+                data.extend([c.r, c.g, c.b, c.a]);
+            }
+            for t in self.tex_coords.iter().flatten() {
+                data.extend(t.u.to_le_bytes());
+                data.extend(t.v.to_le_bytes());
+            }
+            for t in &self.triangles {
+                for word in [t.vertex2, t.vertex1, t.material_id, t.vertex3] {
+                    data.extend(word.to_le_bytes());
+                }
+            }
+        }
+        for target in &self.morph_targets {
+            data.extend(target.sphere.pos.to_bytes());
+            data.extend(target.sphere.radius.to_le_bytes());
+            data.extend((!target.vertices.is_empty() as u32).to_le_bytes());
+            data.extend((!target.normals.is_empty() as u32).to_le_bytes());
+            for v in target.vertices.iter().chain(&target.normals) {
+                data.extend(v.to_bytes());
+            }
+        }
+        data
+    }
+
+    pub fn write_night_prelit(&self) -> Vec<u8> {
+        let mut data = (!self.night_prelit.is_empty() as u32)
+            .to_le_bytes()
+            .to_vec();
+        for c in &self.night_prelit {
+            data.extend([c.r, c.g, c.b, c.a]);
+        }
+        data
+    }
+
+    // Extra Vert Colour extension payload
+    pub fn parse_night_prelit<'a>(&mut self, i: &'a [u8], endian: Endian) -> IResult<&'a [u8], ()> {
+        let (i, has_colors) = endian.u32(i)?;
+        if has_colors == 0 {
+            return Ok((i, ()));
+        }
+        let (i, colors) = count(RwRGBA::parse_le, self.num_vertices as usize)(i)?;
+        self.night_prelit = colors;
+        Ok((i, ()))
+    }
+}
+
+// The explicit count takes precedence over the textured flags
+pub(crate) fn num_tex_sets(flags: GeometryFlags) -> u32 {
+    match (flags.bits() >> 16) & 0xFF {
+        0 if flags.contains(GeometryFlags::TEXTURED2) => 2,
+        0 if flags.contains(GeometryFlags::TEXTURED) => 1,
+        n => n,
+    }
+}
+
+// Vertex triples of a strip of count vertices starting at start, every other one flipped to keep
+// the winding consistent
+pub(crate) fn strip_triangles(start: usize, count: usize) -> Vec<[usize; 3]> {
+    (0..count.saturating_sub(2))
+        .map(|k| {
+            let v = start + k;
+            if k % 2 == 0 {
+                [v, v + 1, v + 2]
+            } else {
+                [v + 1, v, v + 2]
+            }
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, Nom)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MorphInterpolator {
+    pub flags: i32,
+    pub start_morph_target: i16,
+    pub end_morph_target: i16,
+    pub time: f32,
+    pub next: i32, // Index of the interpolator to continue with
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RpMorph {
+    pub interpolators: Vec<MorphInterpolator>,
+}
+
+impl RpMorph {
+    pub fn parse(i: &[u8], _version: RwVersion, endian: Endian) -> IResult<&[u8], Self> {
+        let (i, num_interpolators) = endian.u32(i)?;
+        let (i, interpolators) = count(|i| endian.parse(i), num_interpolators as usize)(i)?;
+
+        Ok((i, Self { interpolators }))
+    }
+
+    pub fn write(&self, _version: RwVersion) -> Vec<u8> {
+        let mut data = (self.interpolators.len() as u32).to_le_bytes().to_vec();
+        for m in &self.interpolators {
+            data.extend(m.flags.to_le_bytes());
+            data.extend(m.start_morph_target.to_le_bytes());
+            data.extend(m.end_morph_target.to_le_bytes());
+            data.extend(m.time.to_le_bytes());
+            data.extend(m.next.to_le_bytes());
+        }
+        data
+    }
+}
+
+// PS2 address control flags, one per tristrip vertex. Flagged vertices don't start a triangle,
+// which joins strips without degenerate triangles.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RpAdc {
+    pub flags: Vec<u8>,
+}
+
+impl RpAdc {
+    pub fn parse(i: &[u8], _version: RwVersion) -> IResult<&[u8], Self> {
+        let (i, num_flags) = le_u32(i)?;
+        let (i, flags) = take(num_flags)(i)?;
+        Ok((
+            i,
+            Self {
+                flags: flags.to_vec(),
+            },
+        ))
+    }
+
+    pub fn write(&self, _version: RwVersion) -> Vec<u8> {
+        let mut data = (self.flags.len() as u32).to_le_bytes().to_vec();
+        data.extend(&self.flags);
+        // Padded to 4 bytes
+        data.resize(data.len().next_multiple_of(4), 0);
+        data
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BinMesh {
+    pub flags: u32,
+    pub total_indices: u32,
+    pub splits: Vec<(u32, Vec<u32>)>, // (material_id, indices)
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub native_counts: Vec<u32>, // Index count of every split, for native geometry only
+}
+
+const BINMESH_TRISTRIP: u32 = 0x00000001;
+
+impl BinMesh {
+    pub fn parse(i: &[u8], _version: RwVersion, endian: Endian) -> IResult<&[u8], Self> {
+        let (i, flags) = endian.u32(i)?;
+        let (i, num_splits) = endian.u32(i)?;
+        let (mut i, total_indices) = endian.u32(i)?;
+
+        // Native geometry keeps its indices in the native data, only the split headers are stored
+        let native = i.len() as u64 == num_splits as u64 * 8;
+
+        // The count isn't trusted, every split takes at least 8 bytes
+        let mut splits = Vec::with_capacity((num_splits as usize).min(i.len() / 8));
+        let mut native_counts = Vec::new();
+        for _ in 0..num_splits {
+            let (rest, num_indices) = endian.u32(i)?;
+            let (rest, material_id) = endian.u32(rest)?;
+            let mut indices = Vec::new();
+            i = rest;
+            if native {
+                native_counts.push(num_indices);
+            } else {
+                (i, indices) = count(|i| endian.u32(i), num_indices as usize)(i)?;
+            }
+            splits.push((material_id, indices));
+        }
+
+        Ok((
+            i,
+            Self {
+                flags,
+                total_indices,
+                splits,
+                native_counts,
+            },
+        ))
+    }
+
+    pub fn is_tristrip(&self) -> bool {
+        self.flags & BINMESH_TRISTRIP > 0
+    }
+
+    pub fn write(&self, _version: RwVersion) -> Vec<u8> {
+        let mut data = Vec::new();
+        for word in [self.flags, self.splits.len() as u32, self.total_indices] {
+            data.extend(word.to_le_bytes());
+        }
+        for (split, (material_id, indices)) in self.splits.iter().enumerate() {
+            let num_indices = match self.native_counts.get(split) {
+                Some(&n) => n,
+                None => indices.len() as u32,
+            };
+            data.extend(num_indices.to_le_bytes());
+            data.extend(material_id.to_le_bytes());
+            data.extend(indices.iter().flat_map(|i| i.to_le_bytes()));
+        }
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsf::fixtures::{chunk, floats, geometry};
+    use crate::bsf::{Chunk, ChunkContent};
+
+    #[test]
+    fn bin_mesh() {
+        let words: [u32; 12] = [1, 2, 5, 3, 0, 0, 1, 2, 2, 1, 3, 2];
+        let data: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+        let file = chunk(0x03, &chunk(0x50E, &data));
+
+        let (_, ext) = Chunk::parse(&file).unwrap();
+        let ChunkContent::BinMesh(mesh) = &ext.get_children()[0].content else {
+            panic!("not a bin mesh");
+        };
+        assert!(mesh.is_tristrip());
+        assert_eq!(mesh.total_indices, 5);
+        assert_eq!(mesh.splits, vec![(0, vec![0, 1, 2]), (1, vec![3, 2])]);
+
+        // A split count far beyond the data fails without allocating for it
+        let data: Vec<u8> = [0u32, u32::MAX, 3, 3, 0, 0]
+            .iter()
+            .flat_map(|w| w.to_le_bytes())
+            .collect();
+        let file = chunk(0x03, &chunk(0x50E, &data));
+        let (_, ext) = Chunk::parse(&file).unwrap();
+        assert!(matches!(
+            ext.get_children()[0].content,
+            ChunkContent::Section(_)
+        ));
+    }
+
+    #[test]
+    fn strip_to_triangle_list() {
+        let words: [u32; 9] = [1, 1, 6, 6, 2, 0, 1, 2, 2];
+        let mut data: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+        data.extend([3u32, 4].iter().flat_map(|w| w.to_le_bytes()));
+        let vertices: Vec<_> = (0..5).map(|v| [v as f32, (v % 2) as f32, 0.0]).collect();
+        let file = geometry(&vertices, &chunk(0x50E, &data));
+
+        let (_, geo) = Chunk::parse(&file).unwrap();
+        let bin_mesh = geo.get_extension()[0].content.as_bin_mesh().unwrap();
+        let geo = geo.content.as_geometry().unwrap();
+        let triangles: Vec<_> = geo
+            .to_triangle_list(bin_mesh)
+            .iter()
+            .map(|t| (t.as_arr(), t.material_id))
+            .collect();
+        assert_eq!(triangles, vec![([0, 1, 2], 2), ([3, 2, 4], 2)]);
+        assert_eq!(
+            geo.index_buffers(Some(bin_mesh)),
+            vec![(2, vec![0, 1, 2, 3, 2, 4])]
+        );
+        // The geometry itself stores no triangles
+        assert!(geo.index_buffers(None).is_empty());
+    }
+
+    #[test]
+    fn bounds() {
+        let (_, geo) = Chunk::parse(&geometry(&[[0.0; 3], [2.0, 4.0, -4.0]], &[])).unwrap();
+        let mut geo = geo.content.as_geometry().unwrap().clone();
+        // The parsed sphere is kept
+        assert_eq!(geo.morph_targets[0].sphere.radius, 1.0);
+
+        let (min, max) = geo.compute_aabb().unwrap();
+        assert_eq!(
+            (min.as_arr(), max.as_arr()),
+            ([0.0, 0.0, -4.0], [2.0, 4.0, 0.0])
+        );
+        geo.update_bounds();
+        let sphere = &geo.morph_targets[0].sphere;
+        assert_eq!(
+            (sphere.pos.as_arr(), sphere.radius),
+            ([1.0, 2.0, -2.0], 3.0)
+        );
+
+        geo.morph_targets[0].vertices.clear();
+        assert!(geo.compute_aabb().is_none());
+        assert_eq!(geo.compute_bounding_sphere().radius, 0.0);
+    }
+
+    #[test]
+    fn weld_vertices() {
+        // Two quads sharing an edge, stored with separate vertices
+        let vertices = [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [2.0, 0.0, 0.0],
+            [1.00001, 1.0, 0.0],
+        ];
+        let (_, geo) = Chunk::parse(&geometry(&vertices, &[])).unwrap();
+        let mut geo = geo.content.as_geometry().unwrap().clone();
+        geo.triangles = [[0, 1, 2], [3, 4, 5], [1, 3, 2]]
+            .iter()
+            .map(|&[vertex1, vertex2, vertex3]| RpTriangle {
+                vertex1,
+                vertex2,
+                vertex3,
+                material_id: 0,
+            })
+            .collect();
+
+        assert_eq!(geo.weld_vertices(0.001), vec![0, 1, 2, 1, 3, 2]);
+        assert_eq!(geo.num_vertices, 4);
+        assert_eq!(geo.vertices()[3].as_arr(), [2.0, 0.0, 0.0]);
+        let triangles: Vec<_> = geo.triangles.iter().map(|t| t.as_arr()).collect();
+        assert_eq!(triangles, vec![[0, 1, 2], [1, 3, 2]]);
+
+        // Only identical vertices are merged without a tolerance
+        let quad = [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [1.0, 0.0, 0.0],
+        ];
+        let (_, geo) = Chunk::parse(&geometry(&quad, &[])).unwrap();
+        let mut geo = geo.content.as_geometry().unwrap().clone();
+        assert_eq!(geo.weld_vertices(0.0), vec![0, 1, 2, 1]);
+
+        // Huge and non-finite positions aren't merged with anything else
+        let far = [
+            [f32::MAX, 0.0, 0.0],
+            [f32::MAX, 0.0, 0.0],
+            [f32::INFINITY, 0.0, 0.0],
+            [f32::NAN, f32::NEG_INFINITY, 0.0],
+            [f32::NAN, f32::NEG_INFINITY, 0.0],
+        ];
+        let (_, geo) = Chunk::parse(&geometry(&far, &[])).unwrap();
+        let mut geo = geo.content.as_geometry().unwrap().clone();
+        assert_eq!(geo.weld_vertices(0.5), vec![0, 0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn geometry_flags() {
+        let mut file = geometry(&[[0.0; 3]], &[]);
+        // Two UV sets given by the count in the format word rather than the textured flags
+        file[24..28].copy_from_slice(&0x00020004u32.to_le_bytes());
+        file.splice(52..52, floats(&[0.25, 0.5, 0.75, 1.0]));
+        for offset in [4, 16] {
+            let size = u32::from_le_bytes(file[offset..offset + 4].try_into().unwrap());
+            file[offset..offset + 4].copy_from_slice(&(size + 16).to_le_bytes());
+        }
+
+        let (_, geo) = Chunk::parse(&file).unwrap();
+        let geo = geo.content.as_geometry().unwrap();
+        assert!(geo.flags.contains(GeometryFlags::TEXTURED));
+        assert!(geo.is_textured() && !geo.is_prelit() && !geo.is_native());
+        assert_eq!(geo.num_tex_sets(), 2);
+        assert_eq!(geo.tex_coords[1][0].as_arr(), [0.75, 1.0]);
+        assert_eq!(geo.vertices().len(), 1);
+    }
+
+    #[test]
+    fn morph_targets() {
+        let mut file = geometry(
+            &[[0.0; 3], [1.0; 3]],
+            &chunk(0x0253F2F9, &0u32.to_le_bytes()),
+        );
+        // Append a second target with positions and normals to the Struct
+        let mut target = floats(&[0.0, 0.0, 0.0, 2.0]);
+        target.extend(1u32.to_le_bytes());
+        target.extend(1u32.to_le_bytes());
+        target.extend(floats(&[2.0, 2.0, 2.0, 3.0, 3.0, 3.0]));
+        target.extend(floats(&[0.0, 0.0, 1.0, 0.0, 0.0, 1.0]));
+        file.splice(100..100, target.iter().copied());
+        file[36..40].copy_from_slice(&2u32.to_le_bytes());
+        for (offset, grow) in [(4, target.len()), (16, target.len())] {
+            let size = u32::from_le_bytes(file[offset..offset + 4].try_into().unwrap());
+            file[offset..offset + 4].copy_from_slice(&(size + grow as u32).to_le_bytes());
+        }
+
+        let (_, geo) = Chunk::parse(&file).unwrap();
+        assert_eq!(geo.get_extension().len(), 1);
+        let geo = geo.content.as_geometry().unwrap();
+        assert_eq!(geo.morph_targets.len(), 2);
+        assert_eq!(geo.vertices()[1].as_arr(), [1.0; 3]);
+        assert!(geo.normals().is_empty());
+        assert_eq!(geo.morph_targets[1].sphere.radius, 2.0);
+        assert_eq!(geo.morph_targets[1].vertices[1].as_arr(), [3.0; 3]);
+        assert_eq!(geo.morph_targets[1].normals[0].as_arr(), [0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn morph_plugin() {
+        // Two interpolators blending between the targets and back
+        let mut data = 2u32.to_le_bytes().to_vec();
+        for (start, end, time, next) in [(0i16, 1i16, 0.5f32, 1i32), (1, 0, 1.5, 0)] {
+            data.extend(0i32.to_le_bytes());
+            data.extend(start.to_le_bytes());
+            data.extend(end.to_le_bytes());
+            data.extend(time.to_le_bytes());
+            data.extend(next.to_le_bytes());
+        }
+        let file = geometry(&[[0.0; 3]], &chunk(0x105, &data));
+
+        let (_, geo) = Chunk::parse(&file).unwrap();
+        let morph = geo.get_extension()[0].content.as_morph().unwrap();
+        assert_eq!(morph.interpolators.len(), 2);
+        let second = &morph.interpolators[1];
+        assert_eq!((second.start_morph_target, second.end_morph_target), (1, 0));
+        assert_eq!((second.time, second.next), (1.5, 0));
+        assert_eq!(geo.to_bytes().unwrap(), file);
+    }
+
+    #[test]
+    fn night_vertex_colors() {
+        let mut data = 1u32.to_le_bytes().to_vec();
+        data.extend([10, 20, 30, 255, 40, 50, 60, 255]);
+        let file = geometry(&[[0.0; 3], [1.0; 3]], &chunk(0x0253F2F9, &data));
+
+        let (_, geo) = Chunk::parse(&file).unwrap();
+        let ChunkContent::Geometry(geometry) = &geo.content else {
+            panic!("not a geometry");
+        };
+        assert_eq!(geometry.night_prelit.len(), 2);
+        assert_eq!(geometry.night_prelit[1].as_rgba_arr()[3], 1.0);
+        assert!(matches!(
+            geo.get_extension()[0].content,
+            ChunkContent::NightVertexColors
+        ));
     }
 }
@@ -0,0 +1,191 @@
+// Writer for KTX2 containers, so rasters can be handed to GPU texture pipelines without going
+// through DDS. Only BC and RGBA8 levels are written as they are: there's no UASTC/ETC1S
+// re-encoding, and the Bevy loader doesn't use these containers.
+
+use super::dxt::DxtFormat;
+
+const IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+const HEADER_SIZE: usize = 80;
+const LEVEL_INDEX_SIZE: usize = 24;
+
+// Data format descriptor values, see the Khronos Data Format Specification
+const MODEL_RGBSDA: u8 = 1;
+const MODEL_BC1A: u8 = 128;
+const MODEL_BC2: u8 = 129;
+const MODEL_BC3: u8 = 130;
+const PRIMARIES_BT709: u8 = 1;
+const TRANSFER_SRGB: u8 = 2;
+const CHANNEL_LINEAR: u8 = 0x10;
+const CHANNEL_ALPHA: u8 = 15;
+
+// Bit offset, bit length, channel and upper value of a descriptor sample
+type Sample = (u16, u8, u8, u32);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ktx2Format {
+    Bc(DxtFormat),
+    Rgba8,
+}
+
+impl Ktx2Format {
+    pub fn vk_format(self) -> u32 {
+        match self {
+            Self::Bc(DxtFormat::Dxt1) => 134, // VK_FORMAT_BC1_RGBA_SRGB_BLOCK
+            Self::Bc(DxtFormat::Dxt3) => 136, // VK_FORMAT_BC2_SRGB_BLOCK
+            Self::Bc(DxtFormat::Dxt5) => 138, // VK_FORMAT_BC3_SRGB_BLOCK
+            Self::Rgba8 => 43,                // VK_FORMAT_R8G8B8A8_SRGB
+        }
+    }
+
+    fn block_size(self) -> usize {
+        match self {
+            Self::Bc(format) => format.block_size(),
+            Self::Rgba8 => 4,
+        }
+    }
+
+    // Basic descriptor block
+    fn descriptor(self) -> Vec<u8> {
+        let (model, dims, samples): (u8, u8, &[Sample]) = match self {
+            Self::Bc(DxtFormat::Dxt1) => (MODEL_BC1A, 3, &[(0, 64, 1, u32::MAX)]),
+            Self::Bc(DxtFormat::Dxt3) => (
+                MODEL_BC2,
+                3,
+                &[
+                    (0, 64, CHANNEL_ALPHA | CHANNEL_LINEAR, u32::MAX),
+                    (64, 64, 0, u32::MAX),
+                ],
+            ),
+            Self::Bc(DxtFormat::Dxt5) => (
+                MODEL_BC3,
+                3,
+                &[
+                    (0, 64, CHANNEL_ALPHA | CHANNEL_LINEAR, u32::MAX),
+                    (64, 64, 0, u32::MAX),
+                ],
+            ),
+            Self::Rgba8 => (
+                MODEL_RGBSDA,
+                0,
+                &[
+                    (0, 8, 0, 255),
+                    (8, 8, 1, 255),
+                    (16, 8, 2, 255),
+                    (24, 8, CHANNEL_ALPHA | CHANNEL_LINEAR, 255),
+                ],
+            ),
+        };
+        let size = 24 + 16 * samples.len() as u32;
+        let mut dfd = Vec::new();
+        dfd.extend(0u32.to_le_bytes()); // Khronos vendor, basic descriptor type
+        dfd.extend((2 | size << 16).to_le_bytes());
+        dfd.extend([model, PRIMARIES_BT709, TRANSFER_SRGB, 0]);
+        dfd.extend([dims, dims, 0, 0]);
+        dfd.extend([self.block_size() as u8, 0, 0, 0, 0, 0, 0, 0]);
+        for &(offset, length, channel, upper) in samples {
+            dfd.extend(offset.to_le_bytes());
+            dfd.extend([length - 1, channel]);
+            dfd.extend(0u32.to_le_bytes()); // Sample position
+            dfd.extend(0u32.to_le_bytes());
+            dfd.extend(upper.to_le_bytes());
+        }
+        dfd
+    }
+}
+
+// Complete .ktx2 file of a 2D texture from its levels, largest first. The levels are stored
+// smallest first like the specification recommends.
+pub fn write(format: Ktx2Format, width: u32, height: u32, levels: &[&[u8]]) -> Vec<u8> {
+    let dfd = format.descriptor();
+    let dfd_offset = HEADER_SIZE + levels.len() * LEVEL_INDEX_SIZE;
+    let dfd_len = 4 + dfd.len();
+
+    let mut data = Vec::new();
+    let mut index = vec![(0, 0); levels.len()];
+    let mut offset = dfd_offset + dfd_len;
+    for (level, bytes) in levels.iter().enumerate().rev() {
+        let padding = offset.next_multiple_of(format.block_size()) - offset;
+        data.resize(data.len() + padding, 0);
+        offset += padding;
+        index[level] = (offset, bytes.len());
+        data.extend_from_slice(bytes);
+        offset += bytes.len();
+    }
+
+    let mut ktx = IDENTIFIER.to_vec();
+    let words = [
+        format.vk_format(),
+        1, // typeSize
+        width,
+        height,
+        0, // pixelDepth
+        0, // layerCount
+        1, // faceCount
+        levels.len() as u32,
+        0, // supercompressionScheme
+        dfd_offset as u32,
+        dfd_len as u32,
+        0, // kvdByteOffset
+        0, // kvdByteLength
+    ];
+    ktx.extend(words.iter().flat_map(|w| w.to_le_bytes()));
+    ktx.extend([0; 16]); // sgdByteOffset, sgdByteLength
+    for (offset, len) in index {
+        ktx.extend((offset as u64).to_le_bytes());
+        ktx.extend((len as u64).to_le_bytes());
+        ktx.extend((len as u64).to_le_bytes());
+    }
+    ktx.extend((dfd_len as u32).to_le_bytes());
+    ktx.extend(dfd);
+    ktx.extend(data);
+    ktx
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bsf::tex::{self, RgbaImage};
+    use crate::bsf::RwVersion;
+
+    #[cfg(feature = "ktx2")]
+    #[test]
+    fn ktx2_raster() {
+        let image = RgbaImage {
+            width: 8,
+            height: 4,
+            data: [255, 0, 0, 255].repeat(32),
+        };
+        let encode =
+            |encoding| tex::RpRasterPC::encode("red", &image, encoding, true, RwVersion::SA_PC);
+
+        let raster = encode(tex::RasterEncoding::Dxt1);
+        let file = raster.to_ktx2().unwrap();
+        let reader = ::ktx2::Reader::new(&file[..]).unwrap();
+        let header = reader.header();
+        assert_eq!(header.format, Some(::ktx2::Format::BC1_RGBA_SRGB_BLOCK));
+        assert_eq!((header.pixel_width, header.pixel_height), (8, 4));
+        assert_eq!(header.level_count, 4);
+        let levels: Vec<_> = reader.levels().collect();
+        let expected: Vec<_> = raster.mip_levels().map(|(_, _, data)| data).collect();
+        assert_eq!(levels, expected);
+        let dfd = reader.data_format_descriptors().next().unwrap();
+        let basic = ::ktx2::BasicDataFormatDescriptor::parse(dfd.data).unwrap();
+        assert_eq!(basic.color_model, Some(::ktx2::ColorModel::BC1A));
+        assert_eq!(basic.texel_block_dimensions, [4, 4, 1, 1]);
+        assert_eq!(basic.sample_information().count(), 1);
+
+        let raster = encode(tex::RasterEncoding::Format565);
+        let file = raster.to_ktx2().unwrap();
+        let reader = ::ktx2::Reader::new(&file[..]).unwrap();
+        assert_eq!(reader.header().format, Some(::ktx2::Format::R8G8B8A8_SRGB));
+        let levels: Vec<_> = reader.levels().collect();
+        assert_eq!(levels.len(), 4);
+        assert_eq!(levels[0], &image.data[..]);
+        assert_eq!(levels[3], [255, 0, 0, 255]);
+        let dfd = reader.data_format_descriptors().next().unwrap();
+        let basic = ::ktx2::BasicDataFormatDescriptor::parse(dfd.data).unwrap();
+        assert_eq!(basic.color_model, Some(::ktx2::ColorModel::RGBSDA));
+        assert_eq!(basic.sample_information().count(), 4);
+    }
+}
@@ -0,0 +1,266 @@
+use anyhow::{bail, Result};
+use nom::number::complete::{le_f32, le_u32};
+use nom::IResult;
+
+use super::error::RwError;
+use super::options::ParseOptions;
+use super::{Chunk, RwVersion};
+
+const MATFX_NULL: u32 = 0;
+const MATFX_BUMPMAP: u32 = 1;
+const MATFX_ENVMAP: u32 = 2;
+const MATFX_DUAL: u32 = 4;
+const MATFX_UVTRANSFORM: u32 = 5;
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MatFXEffect {
+    None,
+    BumpMap {
+        intensity: f32,
+        bumped_texture: Option<Box<Chunk>>,
+        bump_texture: Option<Box<Chunk>>,
+    },
+    EnvMap {
+        coefficient: f32,
+        frame_buffer_alpha: bool,
+        texture: Option<Box<Chunk>>,
+    },
+    DualTexture {
+        src_blend: u32,
+        dst_blend: u32,
+        texture: Option<Box<Chunk>>,
+    },
+    UvTransform,
+    Unknown(u32),
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RpMatFX {
+    // Attached to atomics, only says whether the atomic uses material effects
+    Atomic {
+        enabled: bool,
+    },
+    Material {
+        ty: u32, // BUMPMAP = 1, ENVMAP = 2, BUMPENVMAP = 3, DUAL = 4, UVTRANSFORM = 5, DUALUVTRANSFORM = 6
+        effects: [MatFXEffect; 2],
+    },
+}
+
+impl RpMatFX {
+    pub fn parse<'a>(
+        i: &'a [u8],
+        _version: RwVersion,
+        opts: &ParseOptions,
+    ) -> IResult<&'a [u8], Self, RwError> {
+        if i.len() == 4 {
+            let (i, enabled) = le_u32(i)?;
+            return Ok((
+                i,
+                Self::Atomic {
+                    enabled: enabled != 0,
+                },
+            ));
+        }
+
+        let (i, ty) = le_u32(i)?;
+        let (i, first) = MatFXEffect::parse(i, opts)?;
+        let (i, second) = MatFXEffect::parse(i, opts)?;
+
+        Ok((
+            i,
+            Self::Material {
+                ty,
+                effects: [first, second],
+            },
+        ))
+    }
+
+    pub fn write(&self, _version: RwVersion) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        match self {
+            Self::Atomic { enabled } => data.extend((*enabled as u32).to_le_bytes()),
+            Self::Material { ty, effects } => {
+                data.extend(ty.to_le_bytes());
+                for effect in effects {
+                    effect.write(&mut data)?;
+                }
+            }
+        }
+        Ok(data)
+    }
+
+    pub fn effects(&self) -> &[MatFXEffect] {
+        match self {
+            Self::Atomic { .. } => &[],
+            Self::Material { effects, .. } => effects,
+        }
+    }
+}
+
+impl MatFXEffect {
+    fn parse<'a>(i: &'a [u8], opts: &ParseOptions) -> IResult<&'a [u8], Self, RwError> {
+        let (i, ty) = le_u32(i)?;
+        match ty {
+            MATFX_NULL => Ok((i, Self::None)),
+            MATFX_BUMPMAP => {
+                let (i, intensity) = le_f32(i)?;
+                let (i, bumped_texture) = parse_texture(i, opts)?;
+                let (i, bump_texture) = parse_texture(i, opts)?;
+                Ok((
+                    i,
+                    Self::BumpMap {
+                        intensity,
+                        bumped_texture,
+                        bump_texture,
+                    },
+                ))
+            }
+            MATFX_ENVMAP => {
+                let (i, coefficient) = le_f32(i)?;
+                let (i, frame_buffer_alpha) = le_u32(i)?;
+                let (i, texture) = parse_texture(i, opts)?;
+                Ok((
+                    i,
+                    Self::EnvMap {
+                        coefficient,
+                        frame_buffer_alpha: frame_buffer_alpha != 0,
+                        texture,
+                    },
+                ))
+            }
+            MATFX_DUAL => {
+                let (i, src_blend) = le_u32(i)?;
+                let (i, dst_blend) = le_u32(i)?;
+                let (i, texture) = parse_texture(i, opts)?;
+                Ok((
+                    i,
+                    Self::DualTexture {
+                        src_blend,
+                        dst_blend,
+                        texture,
+                    },
+                ))
+            }
+            MATFX_UVTRANSFORM => Ok((i, Self::UvTransform)),
+            _ => Ok((i, Self::Unknown(ty))),
+        }
+    }
+
+    fn write(&self, out: &mut Vec<u8>) -> Result<()> {
+        let mut u32 = |v: u32| out.extend(v.to_le_bytes());
+        match self {
+            Self::None => u32(MATFX_NULL),
+            Self::BumpMap {
+                intensity,
+                bumped_texture,
+                bump_texture,
+            } => {
+                u32(MATFX_BUMPMAP);
+                u32(intensity.to_bits());
+                write_texture(out, bumped_texture)?;
+                write_texture(out, bump_texture)?;
+            }
+            Self::EnvMap {
+                coefficient,
+                frame_buffer_alpha,
+                texture,
+            } => {
+                u32(MATFX_ENVMAP);
+                u32(coefficient.to_bits());
+                u32(*frame_buffer_alpha as u32);
+                write_texture(out, texture)?;
+            }
+            Self::DualTexture {
+                src_blend,
+                dst_blend,
+                texture,
+            } => {
+                u32(MATFX_DUAL);
+                u32(*src_blend);
+                u32(*dst_blend);
+                write_texture(out, texture)?;
+            }
+            Self::UvTransform => u32(MATFX_UVTRANSFORM),
+            // The size of unknown effects isn't known, so neither is where the second one starts
+            Self::Unknown(ty) => bail!("Writing material effect {ty} isn't supported"),
+        }
+        Ok(())
+    }
+
+    pub fn textures(&self) -> impl Iterator<Item = &Chunk> {
+        let textures = match self {
+            Self::BumpMap {
+                bumped_texture,
+                bump_texture,
+                ..
+            } => [bumped_texture.as_deref(), bump_texture.as_deref()],
+            Self::EnvMap { texture, .. } | Self::DualTexture { texture, .. } => {
+                [texture.as_deref(), None]
+            }
+            _ => [None, None],
+        };
+        textures.into_iter().flatten()
+    }
+}
+
+// A u32 presence flag followed by a Texture chunk if set
+fn parse_texture<'a>(
+    i: &'a [u8],
+    opts: &ParseOptions,
+) -> IResult<&'a [u8], Option<Box<Chunk>>, RwError> {
+    let (i, has_texture) = le_u32(i)?;
+    if has_texture == 0 {
+        return Ok((i, None));
+    }
+    let (i, texture) = Chunk::parse_with(i, opts)?;
+    Ok((i, Some(Box::new(texture))))
+}
+
+fn write_texture(out: &mut Vec<u8>, texture: &Option<Box<Chunk>>) -> Result<()> {
+    match texture {
+        Some(texture) => {
+            out.extend(1u32.to_le_bytes());
+            out.extend(texture.to_bytes()?);
+        }
+        None => out.extend(0u32.to_le_bytes()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsf::fixtures::{chunk, texture};
+    use crate::bsf::ChunkContent;
+
+    #[test]
+    fn material_effects() {
+        let mut data = 2u32.to_le_bytes().to_vec();
+        data.extend(2u32.to_le_bytes());
+        data.extend(0.5f32.to_le_bytes());
+        data.extend(0u32.to_le_bytes());
+        data.extend(1u32.to_le_bytes());
+        data.extend(texture("vehicleenvmap128"));
+        data.extend(0u32.to_le_bytes());
+        let file = chunk(0x120, &data);
+
+        let (_, matfx) = Chunk::parse(&file).unwrap();
+        let ChunkContent::MatFX(matfx) = &matfx.content else {
+            panic!("not material effects");
+        };
+        let [MatFXEffect::EnvMap {
+            coefficient,
+            texture: Some(tex),
+            ..
+        }, MatFXEffect::None] = matfx.effects()
+        else {
+            panic!("unexpected effects {:?}", matfx.effects());
+        };
+        assert_eq!(*coefficient, 0.5);
+        assert!(
+            matches!(&tex.get_children()[0].content, ChunkContent::String(s) if s == "vehicleenvmap128")
+        );
+    }
+}
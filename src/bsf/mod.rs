@@ -1,46 +1,321 @@
+pub mod anim;
+pub mod borrowed;
+pub mod breakable;
+pub mod builder;
+pub mod clump;
+pub mod dmorph;
+#[cfg(feature = "serde")]
+mod dump;
+pub mod dxt;
+pub mod endian;
+pub mod error;
+#[cfg(test)]
+pub(crate) mod fixtures;
 pub mod geo;
+#[cfg(feature = "ktx2")]
+pub mod ktx2;
+pub mod matfx;
+pub mod options;
+pub mod plugin;
+pub mod ps2;
+pub mod skin;
+pub mod stream;
 pub mod tex;
+pub mod world;
+mod write;
+pub mod xbox;
+
+use std::fmt;
 
+use anyhow::{bail, Result};
 use nom::bytes::complete::take;
 use nom::multi::many0;
 use nom::number::complete::le_u32;
 use nom::IResult;
 use nom_derive::*;
+use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
 
-use self::geo::RpGeometry;
-use self::tex::{RpMaterial, RpMaterialList, RpRasterPC, RpTexture};
+use self::anim::{AnimData, RpUVAnimMaterial, RtAnimAnimation, UVAnimDictionary};
+use self::breakable::RpBreakable;
+use self::clump::{RpAtomic, RpCollisionModel, RpPipelineSet, RpRightToRender, RwFrameList};
+use self::dmorph::RpDeltaMorph;
+use self::endian::Endian;
+use self::error::RwError;
+use self::geo::{BinMesh, RpAdc, RpGeometry, RpMorph};
+use self::matfx::RpMatFX;
+use self::options::ParseOptions;
+use self::plugin::CustomChunk;
+use self::ps2::{RpNativeDataPS2, RpRasterPS2, PLATFORM_PS2_FOURCC};
+use self::skin::{RpHAnim, RpSkin};
+use self::tex::{
+    RgbaImage, RpMaterial, RpMaterialList, RpRasterPC, RpReflectionMaterial, RpSpecularMaterial,
+    RpTexture, RwTextureDictionary, TextureAddressingMode, TextureConflict, PLATFORM_D3D8,
+    PLATFORM_D3D9,
+};
+use self::world::{decode_sectors, RpAtomicSector, RpPlaneSector, RpWorld};
+use self::xbox::{RpRasterXbox, PLATFORM_XBOX};
+use crate::ide::Game;
 
 macro_rules! parse_children {
-    ($i:ident, $enum:path) => {{
-        let (i, children) = many0(Chunk::parse)($i)?;
+    ($i:ident, $opts:ident, $enum:path) => {{
+        let (i, children) = many0(|i| Chunk::parse_child(i, $opts, false))($i)?;
         Ok((i, ($enum, Some(children))))
     }};
 }
 
-macro_rules! parse_struct_and_children {
+macro_rules! parse_plugin {
     ($i:ident, $version:ident, $enum:path, $struc:ty) => {{
-        let (i, mut children) = many0(Chunk::parse)($i)?;
+        let (_, plugin) = <$struc>::parse($i, $version).map_err(nom::Err::convert)?;
+        Ok((&[] as &[u8], ($enum(plugin), None)))
+    }};
+    // For parsers that also handle big-endian data
+    ($i:ident, $version:ident, $endian:ident, $enum:path, $struc:ty) => {{
+        let (_, plugin) = <$struc>::parse($i, $version, $endian).map_err(nom::Err::convert)?;
+        Ok((&[] as &[u8], ($enum(plugin), None)))
+    }};
+}
+
+macro_rules! parse_struct_and_children {
+    ($i:ident, $ty:ident, $version:ident, $opts:ident, $enum:path, $struc:ty) => {
+        parse_struct_and_children!($i, $ty, $opts, $enum, |s| <$struc>::parse(s, $version))
+    };
+    // For parsers that also handle big-endian data
+    ($i:ident, $ty:ident, $version:ident, $endian:ident, $opts:ident, $enum:path, $struc:ty) => {
+        parse_struct_and_children!($i, $ty, $opts, $enum, |s| <$struc>::parse(
+            s, $version, $endian
+        ))
+    };
+    ($i:ident, $ty:ident, $opts:ident, $enum:path, $parse:expr) => {{
+        let (i, mut children) = many0(|i| Chunk::parse_child(i, $opts, false))($i)?;
         let mut struc = None;
+        let mut error: Option<nom::Err<RwError>> = None;
         children.retain(|e| match &e.content {
             Self::Struct(vec) => {
-                if let Ok(s) = <$struc>::parse(&vec[..], $version) {
-                    struc = Some(s.1);
-                    return false;
+                match $parse(&vec[..]) {
+                    Ok(s) => {
+                        struc = Some(s.1);
+                        return false;
+                    }
+                    Err(e) => error = Some(nom::Err::convert(e)),
                 }
                 true
             }
             _ => true,
         });
 
-        // TODO: proper error handling if struc is None
-        Ok((i, ($enum(struc.unwrap()), Some(children))))
+        // Report why the Struct was rejected, if there was one
+        let Some(struc) = struc else {
+            return Err(error.unwrap_or(nom::Err::Error(RwError::MissingStruct(ChunkTypeId($ty)))));
+        };
+        Ok((i, ($enum(struc), Some(children))))
     }};
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, FromPrimitive)]
+#[repr(u32)]
+#[non_exhaustive]
+pub enum ChunkType {
+    Struct = 0x00000001,
+    String = 0x00000002,
+    Extension = 0x00000003,
+    Camera = 0x00000005,
+    Texture = 0x00000006,
+    Material = 0x00000007,
+    MaterialList = 0x00000008,
+    AtomicSection = 0x00000009,
+    PlaneSection = 0x0000000A,
+    World = 0x0000000B,
+    Spline = 0x0000000C,
+    Matrix = 0x0000000D,
+    FrameList = 0x0000000E,
+    Geometry = 0x0000000F,
+    Clump = 0x00000010,
+    Light = 0x00000012,
+    UnicodeString = 0x00000013,
+    Atomic = 0x00000014,
+    Raster = 0x00000015, // Texture Native
+    TextureDictionary = 0x00000016,
+    AnimationDatabase = 0x00000017,
+    Image = 0x00000018,
+    SkinAnimation = 0x00000019,
+    GeometryList = 0x0000001A,
+    AnimAnimation = 0x0000001B,
+    Team = 0x0000001C,
+    Crowd = 0x0000001D,
+    DeltaMorphAnimation = 0x0000001E,
+    RightToRender = 0x0000001F,
+    MultiTextureEffectNative = 0x00000020,
+    MultiTextureEffectDictionary = 0x00000021,
+    TeamDictionary = 0x00000022,
+    PITextureDictionary = 0x00000023, // Platform independent
+    TableOfContents = 0x00000024,
+    ParticleStandardGlobalData = 0x00000025,
+    AltPipe = 0x00000026,
+    PIPeds = 0x00000027,
+    PatchMesh = 0x00000028,
+    ChunkGroupStart = 0x00000029,
+    ChunkGroupEnd = 0x0000002A,
+    UVAnimDictionary = 0x0000002B,
+    CollTree = 0x0000002C,
+    // Toolkit and plugin sections
+    MetricsPLG = 0x00000101,
+    SplinePLG = 0x00000102,
+    StereoPLG = 0x00000103,
+    VrmlPLG = 0x00000104,
+    MorphPLG = 0x00000105,
+    PvsPLG = 0x00000106,
+    MemoryLeakPLG = 0x00000107,
+    AnimationPLG = 0x00000108,
+    GlossPLG = 0x00000109,
+    LogoPLG = 0x0000010A,
+    MemoryInfoPLG = 0x0000010B,
+    RandomPLG = 0x0000010C,
+    PngImagePLG = 0x0000010D,
+    BonePLG = 0x0000010E,
+    VrmlAnimPLG = 0x0000010F,
+    SkyMipmapVal = 0x00000110,
+    MrmPLG = 0x00000111,
+    LodAtomicPLG = 0x00000112,
+    MePLG = 0x00000113,
+    LightmapPLG = 0x00000114,
+    RefinePLG = 0x00000115,
+    SkinPLG = 0x00000116,
+    LabelPLG = 0x00000117,
+    ParticlesPLG = 0x00000118,
+    GeomTxPLG = 0x00000119,
+    SynthCorePLG = 0x0000011A,
+    StqppPLG = 0x0000011B,
+    PartPpPLG = 0x0000011C,
+    CollisionPLG = 0x0000011D,
+    HAnimPLG = 0x0000011E,
+    UserDataPLG = 0x0000011F,
+    MaterialEffectsPLG = 0x00000120,
+    ParticleSystemPLG = 0x00000121,
+    DeltaMorphPLG = 0x00000122,
+    PatchPLG = 0x00000123,
+    TeamPLG = 0x00000124,
+    CrowdPpPLG = 0x00000125,
+    MipSplitPLG = 0x00000126,
+    AnisotropyPLG = 0x00000127,
+    GcnMaterialPLG = 0x00000129,
+    GeometricPvsPLG = 0x0000012A,
+    XboxMaterialPLG = 0x0000012B,
+    MultiTexturePLG = 0x0000012C,
+    ChainPLG = 0x0000012D,
+    ToonPLG = 0x0000012E,
+    PTankPLG = 0x0000012F,
+    ParticleStandardPLG = 0x00000130,
+    PdsPLG = 0x00000131, // PS2 pipeline delivery system, selects a predefined pipeline
+    PrtAdvPLG = 0x00000132,
+    NormalMapPLG = 0x00000133,
+    AdcPLG = 0x00000134,
+    UVAnimPLG = 0x00000135,
+    BinMeshPLG = 0x0000050E,
+    NativeDataPLG = 0x00000510,
+    VertexFormatPLG = 0x00000511,
+    // Rockstar sections
+    PipelineSet = 0x0253F2F3,
+    SpecularMaterial = 0x0253F2F6,
+    Effect2d = 0x0253F2F8,
+    NightVertexColors = 0x0253F2F9,
+    CollisionModel = 0x0253F2FA,
+    ReflectionMaterial = 0x0253F2FC,
+    Breakable = 0x0253F2FD,
+    FrameName = 0x0253F2FE,
+}
+
+impl ChunkType {
+    // Whether the payload is made of child chunks, usually starting with a Struct
+    pub fn has_children(self) -> bool {
+        matches!(
+            self,
+            Self::Extension
+                | Self::Camera
+                | Self::Texture
+                | Self::Material
+                | Self::MaterialList
+                | Self::AtomicSection
+                | Self::PlaneSection
+                | Self::World
+                | Self::FrameList
+                | Self::Geometry
+                | Self::Clump
+                | Self::Light
+                | Self::Atomic
+                | Self::Raster
+                | Self::TextureDictionary
+                | Self::GeometryList
+                | Self::UVAnimDictionary
+                | Self::UVAnimPLG
+        )
+    }
+
+    // Whether the chunk can be parsed from a big-endian stream. Geometry plugins decoded along
+    // with their geometry, like Skin, are handled by parse_geometry_plugins.
+    pub fn supports_big_endian(self) -> bool {
+        matches!(
+            self,
+            Self::Struct
+                | Self::String
+                | Self::Extension
+                | Self::Camera
+                | Self::Texture
+                | Self::Material
+                | Self::MaterialList
+                | Self::FrameList
+                | Self::Geometry
+                | Self::Clump
+                | Self::Atomic
+                | Self::TextureDictionary
+                | Self::GeometryList
+                | Self::MorphPLG
+                | Self::HAnimPLG
+                | Self::BinMeshPLG
+                | Self::FrameName
+        )
+    }
+}
+
+// Raw section id as found in the stream, which may or may not be a known ChunkType
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChunkTypeId(pub u32);
+
+impl ChunkTypeId {
+    pub fn known(self) -> Option<ChunkType> {
+        ChunkType::from_u32(self.0)
+    }
+
+    // Unknown sections are assumed to be leaves
+    pub fn has_children(self) -> bool {
+        self.known().is_some_and(ChunkType::has_children)
+    }
+}
+
+impl From<ChunkType> for ChunkTypeId {
+    fn from(ty: ChunkType) -> Self {
+        ChunkTypeId(ty as u32)
+    }
+}
+
+impl PartialEq<ChunkType> for ChunkTypeId {
+    fn eq(&self, other: &ChunkType) -> bool {
+        self.0 == *other as u32
+    }
+}
+
+// Content of a chunk and its child chunks, if it has any
+type ParsedContent = (ChunkContent, Option<Vec<Chunk>>);
+
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u32)]
+#[non_exhaustive]
 pub enum ChunkContent {
-    Section((u32, Vec<u8>)), // For sections we can't yet parse
+    Section((ChunkTypeId, Vec<u8>)), // For sections we can't yet parse
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Custom(CustomChunk), // Parsed by a parser from ParseOptions::plugins
     Struct(Vec<u8>), // The contents of a known section will be in that enum variant, this is only for child Struct sections of unknown sections
     String(String),
     Extension,
@@ -48,23 +323,96 @@ pub enum ChunkContent {
     Texture(RpTexture),
     Material(RpMaterial),
     MaterialList(RpMaterialList),
-    FrameList,
+    FrameList(RwFrameList),
     Geometry(RpGeometry),
     Clump,
-    Atomic,
+    Atomic(RpAtomic),
     Raster(RpRasterPC),
-    TextureDictionary,
+    RasterPS2(RpRasterPS2),
+    RasterXbox(RpRasterXbox),
+    TextureDictionary(RwTextureDictionary),
     GeometryList,
+    BinMesh(BinMesh),
+    Skin(RpSkin),
+    HAnim(RpHAnim),
+    MatFX(RpMatFX),
+    ReflectionMaterial(RpReflectionMaterial),
+    SpecularMaterial(RpSpecularMaterial),
+    NightVertexColors, // The colors are stored in RpGeometry::night_prelit
+    Morph(RpMorph),
+    Animation(RtAnimAnimation),
+    UVAnimDictionary(UVAnimDictionary),
+    UVAnim(RpUVAnimMaterial),
+    CollisionModel(RpCollisionModel),
+    PipelineSet(RpPipelineSet),
+    RightToRender(RpRightToRender),
+    FrameName(String),              // Also copied into RwFrame::name
+    NativeDataPS2(RpNativeDataPS2), // The vertices are also unpacked into RpGeometry
+    Adc(RpAdc),
+    World(RpWorld),
+    PlaneSection(RpPlaneSector),
+    AtomicSection(RpAtomicSector),
+    DeltaMorph(RpDeltaMorph),
+    Breakable(RpBreakable),
 }
 impl ChunkContent {
-    fn parse(
-        i: &[u8],
+    pub fn chunk_type(&self) -> ChunkTypeId {
+        let ty = match self {
+            Self::Section((ty, _)) => return *ty,
+            Self::Custom(custom) => return custom.ty,
+            Self::Struct(_) => ChunkType::Struct,
+            Self::String(_) => ChunkType::String,
+            Self::Extension => ChunkType::Extension,
+            Self::Camera => ChunkType::Camera,
+            Self::Texture(_) => ChunkType::Texture,
+            Self::Material(_) => ChunkType::Material,
+            Self::MaterialList(_) => ChunkType::MaterialList,
+            Self::FrameList(_) => ChunkType::FrameList,
+            Self::Geometry(_) => ChunkType::Geometry,
+            Self::Clump => ChunkType::Clump,
+            Self::Atomic(_) => ChunkType::Atomic,
+            Self::Raster(_) | Self::RasterPS2(_) | Self::RasterXbox(_) => ChunkType::Raster,
+            Self::TextureDictionary(_) => ChunkType::TextureDictionary,
+            Self::GeometryList => ChunkType::GeometryList,
+            Self::BinMesh(_) => ChunkType::BinMeshPLG,
+            Self::Skin(_) => ChunkType::SkinPLG,
+            Self::HAnim(_) => ChunkType::HAnimPLG,
+            Self::MatFX(_) => ChunkType::MaterialEffectsPLG,
+            Self::ReflectionMaterial(_) => ChunkType::ReflectionMaterial,
+            Self::SpecularMaterial(_) => ChunkType::SpecularMaterial,
+            Self::NightVertexColors => ChunkType::NightVertexColors,
+            Self::Morph(_) => ChunkType::MorphPLG,
+            Self::Animation(_) => ChunkType::AnimAnimation,
+            Self::UVAnimDictionary(_) => ChunkType::UVAnimDictionary,
+            Self::UVAnim(_) => ChunkType::UVAnimPLG,
+            Self::CollisionModel(_) => ChunkType::CollisionModel,
+            Self::PipelineSet(_) => ChunkType::PipelineSet,
+            Self::RightToRender(_) => ChunkType::RightToRender,
+            Self::FrameName(_) => ChunkType::FrameName,
+            Self::NativeDataPS2(_) => ChunkType::NativeDataPLG,
+            Self::Adc(_) => ChunkType::AdcPLG,
+            Self::World(_) => ChunkType::World,
+            Self::PlaneSection(_) => ChunkType::PlaneSection,
+            Self::AtomicSection(_) => ChunkType::AtomicSection,
+            Self::DeltaMorph(_) => ChunkType::DeltaMorphPLG,
+            Self::Breakable(_) => ChunkType::Breakable,
+        };
+        ty.into()
+    }
+
+    fn parse<'a>(
+        i: &'a [u8],
         ty: u32,
-        version: u32,
-    ) -> IResult<&[u8], (ChunkContent, Option<Vec<Chunk>>)> {
-        match ty {
-            0x00000001 => Ok((&[] as &[u8], (Self::Struct(i.to_vec()), None))),
-            0x00000002 => Ok((
+        version: RwVersion,
+        endian: Endian,
+        opts: &ParseOptions,
+    ) -> IResult<&'a [u8], ParsedContent, RwError> {
+        // Big-endian chunks without a parser for them stay raw sections
+        let known =
+            ChunkType::from_u32(ty).filter(|t| endian == Endian::Little || t.supports_big_endian());
+        match known {
+            Some(ChunkType::Struct) => Ok((&[] as &[u8], (Self::Struct(i.to_vec()), None))),
+            Some(ChunkType::String) => Ok((
                 &[] as &[u8],
                 (
                     Self::String(
@@ -76,60 +424,485 @@ impl ChunkContent {
                     None,
                 ),
             )),
-            0x00000003 => parse_children!(i, Self::Extension),
-            0x00000005 => parse_children!(i, Self::Camera),
-            0x00000006 => parse_struct_and_children!(i, version, Self::Texture, RpTexture),
-            0x00000007 => parse_struct_and_children!(i, version, Self::Material, RpMaterial),
-            0x00000008 => {
-                parse_struct_and_children!(i, version, Self::MaterialList, RpMaterialList)
+            Some(ChunkType::Extension) => {
+                let (i, children) =
+                    many0(|i| Chunk::parse_child(i, opts, opts.clamp_truncated_plugins))(i)?;
+                Ok((i, (Self::Extension, Some(children))))
+            }
+            Some(ChunkType::Camera) => parse_children!(i, opts, Self::Camera),
+            Some(ChunkType::Texture) => {
+                warn_invalid_modes(i, 12, endian, opts);
+                parse_struct_and_children!(i, ty, version, endian, opts, Self::Texture, RpTexture)
+            }
+            Some(ChunkType::Material) => {
+                parse_struct_and_children!(i, ty, version, endian, opts, Self::Material, RpMaterial)
+            }
+            Some(ChunkType::MaterialList) => {
+                let (i, (mut content, children)) = parse_struct_and_children!(
+                    i,
+                    ty,
+                    version,
+                    endian,
+                    opts,
+                    Self::MaterialList,
+                    RpMaterialList
+                )?;
+                if let (Self::MaterialList(list), Some(children)) = (&mut content, &children) {
+                    list.resolve(children);
+                }
+                Ok((i, (content, children)))
+            }
+            Some(ChunkType::FrameList) => {
+                let (i, (mut content, children)) = parse_struct_and_children!(
+                    i,
+                    ty,
+                    version,
+                    endian,
+                    opts,
+                    Self::FrameList,
+                    RwFrameList
+                )?;
+                if let (Self::FrameList(list), Some(children)) = (&mut content, &children) {
+                    // Each frame is followed by its own Extension, in frame order
+                    let extensions = children
+                        .iter()
+                        .filter(|e| matches!(e.content, ChunkContent::Extension));
+                    for (frame, ext) in list.frames.iter_mut().zip(extensions) {
+                        frame.name = ext
+                            .get_children()
+                            .iter()
+                            .find_map(|e| e.content.as_frame_name())
+                            .cloned();
+                    }
+                }
+                Ok((i, (content, children)))
+            }
+            Some(ChunkType::Geometry) => {
+                let (i, (mut content, mut children)) = parse_struct_and_children!(
+                    i,
+                    ty,
+                    version,
+                    endian,
+                    opts,
+                    Self::Geometry,
+                    RpGeometry
+                )?;
+                if let (Self::Geometry(geo), Some(children)) = (&mut content, &mut children) {
+                    parse_geometry_plugins(children, geo, version, endian);
+                }
+                Ok((i, (content, children)))
+            }
+            Some(ChunkType::Clump) => parse_children!(i, opts, Self::Clump),
+            Some(ChunkType::Atomic) => {
+                parse_struct_and_children!(i, ty, version, endian, opts, Self::Atomic, RpAtomic)
+            }
+            Some(ChunkType::World) => {
+                let (i, (content, mut children)) =
+                    parse_struct_and_children!(i, ty, version, opts, Self::World, RpWorld)?;
+                if let (Self::World(world), Some(children)) = (&content, &mut children) {
+                    decode_sectors(world, children);
+                }
+                Ok((i, (content, children)))
+            }
+            Some(ChunkType::PlaneSection) => {
+                parse_struct_and_children!(i, ty, version, opts, Self::PlaneSection, RpPlaneSector)
+            }
+            Some(ChunkType::AtomicSection) => parse_struct_and_children!(
+                i,
+                ty,
+                version,
+                opts,
+                Self::AtomicSection,
+                RpAtomicSector
+            ),
+            Some(ChunkType::Raster) if raster_platform(i) == Some(PLATFORM_PS2_FOURCC) => {
+                let (i, raster) = RpRasterPS2::parse(i, version).map_err(nom::Err::convert)?;
+                let (i, children) = many0(|i| Chunk::parse_child(i, opts, false))(i)?;
+                Ok((i, (Self::RasterPS2(raster), Some(children))))
             }
-            0x0000000E => parse_children!(i, Self::FrameList),
-            0x0000000F => parse_struct_and_children!(i, version, Self::Geometry, RpGeometry),
-            0x00000010 => parse_children!(i, Self::Clump),
-            0x00000014 => parse_children!(i, Self::Atomic),
-            0x00000015 => parse_struct_and_children!(i, version, Self::Raster, RpRasterPC),
-            0x00000016 => parse_children!(i, Self::TextureDictionary),
-            0x0000001A => parse_children!(i, Self::GeometryList),
+            Some(ChunkType::Raster) if raster_platform(i) == Some(PLATFORM_XBOX) => {
+                parse_struct_and_children!(i, ty, version, opts, Self::RasterXbox, RpRasterXbox)
+            }
+            // Other platforms, like the War Drum OpenGL rasters of the mobile ports, stay raw sections
+            Some(ChunkType::Raster)
+                if matches!(raster_platform(i), Some(PLATFORM_D3D8 | PLATFORM_D3D9)) =>
+            {
+                warn_invalid_modes(i, 16, Endian::Little, opts);
+                parse_struct_and_children!(i, ty, version, opts, Self::Raster, RpRasterPC)
+            }
+            Some(ChunkType::TextureDictionary) => parse_struct_and_children!(
+                i,
+                ty,
+                version,
+                endian,
+                opts,
+                Self::TextureDictionary,
+                RwTextureDictionary
+            ),
+            Some(ChunkType::GeometryList) => parse_children!(i, opts, Self::GeometryList),
+            Some(ChunkType::AnimAnimation) => {
+                parse_plugin!(i, version, Self::Animation, RtAnimAnimation)
+            }
+            Some(ChunkType::UVAnimDictionary) => parse_struct_and_children!(
+                i,
+                ty,
+                version,
+                opts,
+                Self::UVAnimDictionary,
+                UVAnimDictionary
+            ),
+            Some(ChunkType::UVAnimPLG) => {
+                parse_struct_and_children!(i, ty, version, opts, Self::UVAnim, RpUVAnimMaterial)
+            }
+            Some(ChunkType::MorphPLG) => parse_plugin!(i, version, endian, Self::Morph, RpMorph),
+            Some(ChunkType::HAnimPLG) => parse_plugin!(i, version, endian, Self::HAnim, RpHAnim),
+            Some(ChunkType::MaterialEffectsPLG) => {
+                let (_, matfx) = RpMatFX::parse(i, version, opts)?;
+                Ok((&[] as &[u8], (Self::MatFX(matfx), None)))
+            }
+            Some(ChunkType::BinMeshPLG) => {
+                parse_plugin!(i, version, endian, Self::BinMesh, BinMesh)
+            }
+            Some(ChunkType::AdcPLG) => parse_plugin!(i, version, Self::Adc, RpAdc),
+            Some(ChunkType::DeltaMorphPLG) => {
+                parse_plugin!(i, version, Self::DeltaMorph, RpDeltaMorph)
+            }
+            Some(ChunkType::ReflectionMaterial) => {
+                parse_plugin!(i, version, Self::ReflectionMaterial, RpReflectionMaterial)
+            }
+            Some(ChunkType::SpecularMaterial) => {
+                parse_plugin!(i, version, Self::SpecularMaterial, RpSpecularMaterial)
+            }
+            Some(ChunkType::RightToRender) => {
+                parse_plugin!(i, version, Self::RightToRender, RpRightToRender)
+            }
+            Some(ChunkType::PipelineSet) => {
+                parse_plugin!(i, version, Self::PipelineSet, RpPipelineSet)
+            }
+            Some(ChunkType::Breakable) => parse_plugin!(i, version, Self::Breakable, RpBreakable),
+            // Kept as is when the FourCC isn't one of the known COL versions
+            Some(ChunkType::CollisionModel) => match RpCollisionModel::parse(i, version) {
+                Ok((_, model)) => Ok((&[] as &[u8], (Self::CollisionModel(model), None))),
+                Err(_) => Ok((
+                    &[] as &[u8],
+                    (Self::Section((ChunkTypeId(ty), i.to_vec())), None),
+                )),
+            },
+            // Not null terminated, the name takes up the whole section
+            Some(ChunkType::FrameName) => Ok((
+                &[] as &[u8],
+                (
+                    Self::FrameName(String::from_utf8_lossy(i).trim_matches('\0').to_owned()),
+                    None,
+                ),
+            )),
+
+            _ => {
+                let content = match opts.plugins.parse(ty, i, version) {
+                    Some(custom) => Self::Custom(custom),
+                    None => Self::Section((ChunkTypeId(ty), i.to_vec())),
+                };
+                Ok((&[] as &[u8], (content, None)))
+            }
+        }
+    }
+}
+
+macro_rules! content_accessors {
+    ($($fn:ident => $variant:ident($ty:ty)),* $(,)?) => {
+        impl ChunkContent {
+            $(
+                pub fn $fn(&self) -> Option<&$ty> {
+                    match self {
+                        Self::$variant(v) => Some(v),
+                        _ => None,
+                    }
+                }
+            )*
+        }
+    };
+}
+
+content_accessors! {
+    as_struct => Struct(Vec<u8>),
+    as_string => String(String),
+    as_texture => Texture(RpTexture),
+    as_material => Material(RpMaterial),
+    as_material_list => MaterialList(RpMaterialList),
+    as_frame_list => FrameList(RwFrameList),
+    as_geometry => Geometry(RpGeometry),
+    as_atomic => Atomic(RpAtomic),
+    as_raster => Raster(RpRasterPC),
+    as_raster_ps2 => RasterPS2(RpRasterPS2),
+    as_raster_xbox => RasterXbox(RpRasterXbox),
+    as_bin_mesh => BinMesh(BinMesh),
+    as_skin => Skin(RpSkin),
+    as_hanim => HAnim(RpHAnim),
+    as_matfx => MatFX(RpMatFX),
+    as_reflection_material => ReflectionMaterial(RpReflectionMaterial),
+    as_specular_material => SpecularMaterial(RpSpecularMaterial),
+    as_morph => Morph(RpMorph),
+    as_animation => Animation(RtAnimAnimation),
+    as_uv_anim_dictionary => UVAnimDictionary(UVAnimDictionary),
+    as_uv_anim => UVAnim(RpUVAnimMaterial),
+    as_collision_model => CollisionModel(RpCollisionModel),
+    as_pipeline_set => PipelineSet(RpPipelineSet),
+    as_right_to_render => RightToRender(RpRightToRender),
+    as_frame_name => FrameName(String),
+    as_native_data_ps2 => NativeDataPS2(RpNativeDataPS2),
+    as_texture_dictionary => TextureDictionary(RwTextureDictionary),
+    as_adc => Adc(RpAdc),
+    as_world => World(RpWorld),
+    as_plane_section => PlaneSection(RpPlaneSector),
+    as_atomic_section => AtomicSection(RpAtomicSector),
+    as_delta_morph => DeltaMorph(RpDeltaMorph),
+    as_breakable => Breakable(RpBreakable),
+}
+
+impl ChunkContent {
+    pub fn as_section(&self) -> Option<(ChunkTypeId, &[u8])> {
+        match self {
+            Self::Section((ty, data)) => Some((*ty, data)),
+            _ => None,
+        }
+    }
+}
+
+// Flags word at offset of a Texture or Texture Native payload
+fn warn_invalid_modes(i: &[u8], offset: usize, endian: Endian, opts: &ParseOptions) {
+    let Some(word) = i.get(offset..) else {
+        return;
+    };
+    let Ok((_, flags)) = endian.u32::<()>(word) else {
+        return;
+    };
+    if let Some(message) = tex::invalid_modes(flags) {
+        opts.diagnostics
+            .warn("bsf", opts.diagnostics.offset_of(word), message);
+    }
+}
+
+// The platform id is the first field of the Struct child of a Texture Native
+fn raster_platform(i: &[u8]) -> Option<u32> {
+    let (_, platform) = le_u32::<_, nom::error::Error<_>>(i.get(12..)?).ok()?;
+    Some(platform)
+}
 
-            _ => Ok((&[] as &[u8], (Self::Section((ty, i.to_vec())), None))),
+// Plugins whose layout depends on the geometry they're attached to are
+// left as raw sections by the generic parser and decoded here
+fn parse_geometry_plugins(
+    children: &mut [Chunk],
+    geo: &mut RpGeometry,
+    version: RwVersion,
+    endian: Endian,
+) {
+    let Some(ext) = children
+        .iter_mut()
+        .find(|e| matches!(e.content, ChunkContent::Extension))
+    else {
+        return;
+    };
+    let bin_mesh = ext
+        .get_children()
+        .iter()
+        .find_map(|e| e.content.as_bin_mesh())
+        .cloned();
+    for plugin in ext.children.iter_mut().flatten() {
+        let ChunkContent::Section((ty, data)) = &plugin.content else {
+            continue;
+        };
+        match ty.known() {
+            Some(ChunkType::SkinPLG) => {
+                if let Ok((_, skin)) = RpSkin::parse(data, version, endian, geo.num_vertices) {
+                    plugin.content = ChunkContent::Skin(skin);
+                }
+            }
+            Some(ChunkType::NightVertexColors) if geo.parse_night_prelit(data, endian).is_ok() => {
+                plugin.content = ChunkContent::NightVertexColors;
+            }
+            Some(ChunkType::NativeDataPLG) if geo.is_native() && endian == Endian::Little => {
+                let Some(bin_mesh) = &bin_mesh else {
+                    continue;
+                };
+                if let Ok((_, native)) =
+                    RpNativeDataPS2::parse(data, version, bin_mesh.splits.len())
+                {
+                    native.apply_to(geo, bin_mesh);
+                    plugin.content = ChunkContent::NativeDataPS2(native);
+                }
+            }
+            _ => {}
         }
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Nom)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RwBuild(pub u32);
+
+impl RwBuild {
+    // Written by most released games, where no specific build was stamped into the stream
+    pub const UNSPECIFIED: RwBuild = RwBuild(0xFFFF);
+
+    pub fn is_specified(self) -> bool {
+        self != Self::UNSPECIFIED && self.0 != 0
+    }
+}
+
+// Library version in its unpacked form, 0x34003 for 3.4.0.3
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Nom)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RwVersion(pub u32);
+
+impl RwVersion {
+    pub const GTA3_PC: RwVersion = RwVersion(0x31001);
+    pub const VC_PC: RwVersion = RwVersion(0x34003);
+    pub const SA_PC: RwVersion = RwVersion(0x36003);
+
+    pub fn from_lib_id(lib_id: u32) -> Self {
+        Self(get_chunk_version(lib_id))
+    }
+
+    // Versions up to 3.1.0.0 can't hold a build and are written in the old format
+    pub fn to_lib_id(self, build: RwBuild) -> u32 {
+        if self.0 <= 0x31000 {
+            return self.0 >> 8;
+        }
+        let v = self.0 - 0x30000;
+        (v & 0x3FF00) << 14 | (v & 0x3F) << 16 | build.0 & 0xFFFF
+    }
+
+    pub fn major(self) -> u32 {
+        self.0 >> 16
+    }
+
+    pub fn minor(self) -> u32 {
+        self.0 >> 12 & 0xF
+    }
+
+    pub fn revision(self) -> u32 {
+        self.0 >> 8 & 0xF
+    }
+
+    pub fn binary(self) -> u32 {
+        self.0 & 0xFF
+    }
+
+    // Games known to have shipped files of this version, on any platform
+    pub fn games(self) -> &'static [Game] {
+        match self.0 >> 12 {
+            0x31 | 0x32 => &[Game::GTA3],
+            0x33 => &[Game::GTA3, Game::VC],
+            0x34 | 0x35 => &[Game::VC],
+            0x36 => &[Game::SA],
+            _ => &[],
+        }
+    }
+}
+
+impl fmt::Display for RwVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{}.{}.{}",
+            self.major(),
+            self.minor(),
+            self.revision(),
+            self.binary()
+        )
+    }
+}
+
 #[derive(Copy, Clone, Debug, Nom)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ChunkHeader {
-    pub version: u32,
-    pub build: u32,
+    pub version: RwVersion,
+    pub build: RwBuild,
+    // Byte order of the stream the chunk was read from, only little-endian chunks can be written
+    #[nom(Ignore)]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub endian: Endian,
+    // Payload size declared in the stream, None for chunks that weren't parsed. It isn't updated
+    // when the chunk is changed, the writer always recomputes it.
+    #[nom(Ignore)]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub size: Option<u32>,
 }
 
 impl ChunkHeader {
-    pub fn parse(i: &[u8]) -> IResult<&[u8], ChunkHeader> {
-        let (i, lib_id) = le_u32(i)?;
+    pub fn parse(i: &[u8], endian: Endian) -> IResult<&[u8], ChunkHeader, RwError> {
+        let (i, lib_id) = endian.u32(i)?;
 
         Ok((
             i,
             ChunkHeader {
-                version: get_chunk_version(lib_id),
-                build: get_chunk_build(lib_id),
+                version: RwVersion::from_lib_id(lib_id),
+                build: RwBuild(get_chunk_build(lib_id)),
+                endian,
+                size: None,
             },
         ))
     }
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Chunk {
     pub header: ChunkHeader,
     pub content: ChunkContent,
     pub children: Option<Vec<Chunk>>,
 }
 
+pub struct ChunkIter<'a> {
+    stack: Vec<&'a Chunk>,
+}
+
+impl<'a> Iterator for ChunkIter<'a> {
+    type Item = &'a Chunk;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let chunk = self.stack.pop()?;
+        self.stack.extend(chunk.get_children().iter().rev());
+        Some(chunk)
+    }
+}
+
 impl Chunk {
-    pub fn parse(i: &[u8]) -> IResult<&[u8], Chunk> {
-        let (i, ty) = le_u32(i)?;
-        let (i, size) = le_u32(i)?;
-        let (i, header) = ChunkHeader::parse(i)?;
+    pub fn parse(i: &[u8]) -> IResult<&[u8], Chunk, RwError> {
+        Self::parse_with(i, &ParseOptions::default())
+    }
+
+    pub fn parse_with<'a>(i: &'a [u8], opts: &ParseOptions) -> IResult<&'a [u8], Chunk, RwError> {
+        opts.diagnostics
+            .with_origin(i, || Self::parse_inner(i, opts, false))
+    }
+
+    fn parse_inner<'a>(
+        i: &'a [u8],
+        opts: &ParseOptions,
+        clamp: bool,
+    ) -> IResult<&'a [u8], Chunk, RwError> {
+        let start = i;
+        let endian = opts.endian.unwrap_or_else(|| Endian::detect(i));
+        let (i, ty) = endian.u32(i)?;
+        let (i, mut size) = endian.u32(i)?;
+        let (i, mut header) = ChunkHeader::parse(i, endian)?;
+        header.size = Some(size);
+        if clamp && size as usize > i.len() {
+            opts.diagnostics.warn(
+                "bsf",
+                opts.diagnostics.offset_of(start),
+                format!(
+                    "Chunk {:#x} declares {} bytes but only {} are left, truncating",
+                    ty,
+                    size,
+                    i.len()
+                ),
+            );
+            size = i.len() as u32;
+        }
         let (i, data) = take(size)(i)?;
-        let (_, (content, children)) = ChunkContent::parse(data, ty, header.version)?;
+        let (_, (content, children)) = ChunkContent::parse(data, ty, header.version, endian, opts)?;
 
         Ok((
             i,
@@ -141,6 +914,51 @@ impl Chunk {
         ))
     }
 
+    // Children whose content fails to parse are kept as a Section with the raw payload, so one
+    // broken plugin doesn't take its siblings down with it. Only a header that can't be read or a
+    // size past the parent stops the list.
+    fn parse_child<'a>(
+        i: &'a [u8],
+        opts: &ParseOptions,
+        clamp: bool,
+    ) -> IResult<&'a [u8], Chunk, RwError> {
+        let error = match Self::parse_inner(i, opts, clamp) {
+            Ok(result) => return Ok(result),
+            Err(error) => error,
+        };
+        let endian = opts.endian.unwrap_or_else(|| Endian::detect(i));
+        let header = endian.u32(i).and_then(|(rest, ty)| {
+            let (rest, size) = endian.u32(rest)?;
+            let (rest, header) = ChunkHeader::parse(rest, endian)?;
+            Ok((rest, ty, size, header))
+        });
+        let Ok((rest, ty, size, mut header)) = header else {
+            return Err(error);
+        };
+        let len = if clamp {
+            (size as usize).min(rest.len())
+        } else {
+            size as usize
+        };
+        let Some(data) = rest.get(..len) else {
+            return Err(error);
+        };
+        opts.diagnostics.warn(
+            "bsf",
+            opts.diagnostics.offset_of(i),
+            format!("Chunk {:#x} could not be parsed, keeping it as is", ty),
+        );
+        header.size = Some(size);
+        Ok((
+            &rest[len..],
+            Chunk {
+                header,
+                content: ChunkContent::Section((ChunkTypeId(ty), data.to_vec())),
+                children: None,
+            },
+        ))
+    }
+
     pub fn get_children(&self) -> &[Chunk] {
         if let Some(children) = &self.children {
             children
@@ -148,6 +966,216 @@ impl Chunk {
             &[]
         }
     }
+
+    // This chunk followed by all of its descendants, depth-first in file order
+    pub fn iter(&self) -> ChunkIter<'_> {
+        ChunkIter { stack: vec![self] }
+    }
+
+    // Calls f on the same chunks as iter, with this chunk at depth 0
+    pub fn visit(&self, f: &mut impl FnMut(&Chunk, usize)) {
+        self.visit_at(f, 0);
+    }
+
+    fn visit_at(&self, f: &mut impl FnMut(&Chunk, usize), depth: usize) {
+        f(self, depth);
+        for child in self.get_children() {
+            child.visit_at(f, depth + 1);
+        }
+    }
+
+    // Plugin chunks stored in the Extension child of this chunk
+    pub fn get_extension(&self) -> &[Chunk] {
+        self.get_children()
+            .iter()
+            .find(|e| matches!(e.content, ChunkContent::Extension))
+            .map_or(&[], |e| e.get_children())
+    }
+
+    // Texture Natives of a TextureDictionary, in order. Rasters of platforms that can't be parsed
+    // are raw sections.
+    pub fn textures(&self) -> impl Iterator<Item = &Chunk> {
+        self.get_children()
+            .iter()
+            .filter(|c| c.content.chunk_type() == ChunkType::Raster)
+    }
+
+    // Name of a Texture Native, None for rasters that weren't parsed
+    pub fn texture_name(&self) -> Option<&str> {
+        match &self.content {
+            ChunkContent::Raster(raster) => Some(&raster.name),
+            ChunkContent::RasterPS2(raster) => Some(&raster.name),
+            ChunkContent::RasterXbox(raster) => Some(&raster.name),
+            _ => None,
+        }
+    }
+
+    // Name of the texture a Texture Native takes its alpha from, if it has one
+    pub fn mask_name(&self) -> Option<&str> {
+        let name = match &self.content {
+            ChunkContent::Raster(raster) => &raster.mask_name,
+            ChunkContent::RasterPS2(raster) => &raster.mask_name,
+            ChunkContent::RasterXbox(raster) => &raster.mask_name,
+            _ => return None,
+        };
+        (!name.is_empty()).then_some(name.as_str())
+    }
+
+    // U and V addressing modes of a Texture Native, None for rasters that weren't parsed
+    pub fn texture_addressing(&self) -> Option<(TextureAddressingMode, TextureAddressingMode)> {
+        match &self.content {
+            ChunkContent::Raster(raster) => Some((raster.address_u, raster.address_v)),
+            ChunkContent::RasterPS2(raster) => Some((raster.address_u, raster.address_v)),
+            ChunkContent::RasterXbox(raster) => Some((raster.address_u, raster.address_v)),
+            _ => None,
+        }
+    }
+
+    // Base level of a Texture Native of any parsed platform as RGBA
+    pub fn decode_texture(&self) -> Option<RgbaImage> {
+        match &self.content {
+            ChunkContent::Raster(raster) => raster.decode(),
+            ChunkContent::RasterPS2(raster) => raster.to_image(),
+            ChunkContent::RasterXbox(raster) => raster.to_image(),
+            _ => None,
+        }
+    }
+
+    // Combines the textures of several TextureDictionaries into one with the version, device and
+    // extension of the first. Names are compared case-insensitively like the game does, rasters
+    // without a name are always kept. PC rasters are converted to the D3D8 or D3D9 layout the
+    // version of the first uses, which fails for formats the other layout can't describe.
+    pub fn merge_texture_dictionaries(
+        dictionaries: &[Chunk],
+        conflict: TextureConflict,
+    ) -> Result<Chunk> {
+        let Some(first) = dictionaries.first() else {
+            bail!("No texture dictionaries to merge");
+        };
+        let Some(&dict) = first.content.as_texture_dictionary() else {
+            bail!(
+                "Chunk {:#x} is not a texture dictionary",
+                first.content.chunk_type().0
+            );
+        };
+        let mut textures: Vec<Chunk> = Vec::new();
+        for txd in dictionaries {
+            if txd.content.as_texture_dictionary().is_none() {
+                bail!(
+                    "Chunk {:#x} is not a texture dictionary",
+                    txd.content.chunk_type().0
+                );
+            }
+            for texture in txd.textures() {
+                let mut texture = texture.clone();
+                if let ChunkContent::Raster(raster) = &texture.content {
+                    texture.content = ChunkContent::Raster(raster.convert(first.header.version)?);
+                }
+                texture.set_version(first.header.version);
+                let existing = texture.texture_name().and_then(|name| {
+                    textures.iter().position(|t| {
+                        t.texture_name()
+                            .is_some_and(|n| n.eq_ignore_ascii_case(name))
+                    })
+                });
+                match (existing, conflict) {
+                    (None, _) => textures.push(texture),
+                    (Some(_), TextureConflict::KeepFirst) => {}
+                    (Some(i), TextureConflict::KeepLast) => textures[i] = texture,
+                    (Some(_), TextureConflict::Fail) => bail!(
+                        "Texture {} is in more than one dictionary",
+                        texture.texture_name().unwrap_or_default()
+                    ),
+                }
+            }
+        }
+
+        let extension = first
+            .get_children()
+            .iter()
+            .find(|c| matches!(c.content, ChunkContent::Extension))
+            .cloned()
+            .unwrap_or_else(|| Chunk {
+                header: first.header,
+                content: ChunkContent::Extension,
+                children: Some(Vec::new()),
+            });
+        let dict = RwTextureDictionary {
+            num_textures: textures.len() as u16,
+            ..dict
+        };
+        textures.push(extension);
+        Ok(Chunk {
+            header: first.header,
+            content: ChunkContent::TextureDictionary(dict),
+            children: Some(textures),
+        })
+    }
+
+    // Animation of a UVAnimDictionary by name, as referenced by Material::uv_anims
+    pub fn uv_anim(&self, name: &str) -> Option<&RtAnimAnimation> {
+        self.get_children()
+            .iter()
+            .filter_map(|c| c.content.as_animation())
+            .find(|a| matches!(&a.data, AnimData::UV(uv) if uv.name.eq_ignore_ascii_case(name)))
+    }
+
+    // Collision embedded in the extension of a Clump
+    pub fn collision_model(&self) -> Option<&RpCollisionModel> {
+        self.get_extension()
+            .iter()
+            .find_map(|e| e.content.as_collision_model())
+    }
+
+    // Paths are child indices from this chunk downwards, an empty path is the chunk itself
+    pub fn get_path(&self, path: &[usize]) -> Option<&Chunk> {
+        match path.split_first() {
+            None => Some(self),
+            Some((first, rest)) => self.get_children().get(*first)?.get_path(rest),
+        }
+    }
+
+    pub fn get_path_mut(&mut self, path: &[usize]) -> Option<&mut Chunk> {
+        match path.split_first() {
+            None => Some(self),
+            Some((first, rest)) => self.children.as_mut()?.get_mut(*first)?.get_path_mut(rest),
+        }
+    }
+
+    pub fn extract(&self, path: &[usize]) -> Option<Chunk> {
+        self.get_path(path).cloned()
+    }
+
+    // Inserts chunk so that it ends up at path, its version is changed to match the new parent.
+    // Sizes are recomputed whenever the tree is written, header.size keeps the one from the file.
+    pub fn splice(&mut self, path: &[usize], mut chunk: Chunk) -> Result<()> {
+        let Some((index, parent_path)) = path.split_last() else {
+            bail!("Cannot splice at an empty path");
+        };
+        let Some(parent) = self.get_path_mut(parent_path) else {
+            bail!("Path {:?} does not exist", parent_path);
+        };
+        let children = parent.children.get_or_insert_with(Vec::new);
+        if *index > children.len() {
+            bail!("Index {} is past the end of {:?}", index, parent_path);
+        }
+        chunk.set_version(parent.header.version);
+        children.insert(*index, chunk);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, path: &[usize]) -> Option<Chunk> {
+        let (index, parent_path) = path.split_last()?;
+        let children = self.get_path_mut(parent_path)?.children.as_mut()?;
+        (*index < children.len()).then(|| children.remove(*index))
+    }
+
+    pub fn set_version(&mut self, version: RwVersion) {
+        self.header.version = version;
+        for child in self.children.iter_mut().flatten() {
+            child.set_version(version);
+        }
+    }
 }
 
 pub fn get_chunk_version(lib_id: u32) -> u32 {
@@ -171,6 +1199,7 @@ mod tests {
     use anyhow::Result;
 
     use super::*;
+    use crate::bsf::fixtures::{chunk, geometry, LIB_ID};
 
     #[test]
     fn it_works() -> Result<()> {
@@ -179,4 +1208,279 @@ mod tests {
         dbg!(dff);
         Ok(())
     }
+
+    #[test]
+    fn iter_and_visit() {
+        let mut payload = chunk(0x01, &[0, 0x11, 0, 0]);
+        payload.extend(chunk(0x02, b"wheel\0\0\0"));
+        payload.extend(chunk(0x03, &chunk(0x02, b"\0\0\0\0")));
+        let (_, texture) = Chunk::parse(&chunk(0x06, &payload)).unwrap();
+
+        let types: Vec<_> = texture.iter().map(|c| c.content.chunk_type().0).collect();
+        // The Struct is parsed into the Texture itself
+        assert_eq!(types, vec![0x06, 0x02, 0x03, 0x02]);
+        let mut depths = Vec::new();
+        texture.visit(&mut |_, depth| depths.push(depth));
+        assert_eq!(depths, vec![0, 1, 1, 2]);
+    }
+
+    #[test]
+    fn versions() {
+        for lib_id in [0x0401FFFF, 0x0C02FFFF, 0x1803FFFF, 0x310] {
+            let version = RwVersion::from_lib_id(lib_id);
+            assert_eq!(version.to_lib_id(RwBuild(get_chunk_build(lib_id))), lib_id);
+        }
+        assert_eq!(RwVersion::from_lib_id(0x1803FFFF), RwVersion::SA_PC);
+        assert_eq!(RwVersion::SA_PC.to_string(), "3.6.0.3");
+        assert!(RwVersion::GTA3_PC < RwVersion::VC_PC);
+        assert_eq!(RwVersion::VC_PC.games(), &[Game::VC]);
+    }
+
+    #[test]
+    fn big_endian() {
+        let be_chunk = |ty: u32, payload: &[u8]| {
+            let mut out = Vec::new();
+            for word in [ty, payload.len() as u32, LIB_ID] {
+                out.extend(word.to_be_bytes());
+            }
+            out.extend(payload);
+            out
+        };
+        let words =
+            |words: &[u32]| -> Vec<u8> { words.iter().flat_map(|w| w.to_be_bytes()).collect() };
+        let be_floats =
+            |values: &[f32]| -> Vec<u8> { values.iter().flat_map(|f| f.to_be_bytes()).collect() };
+
+        // One vertex, surface properties and a morph target with vertices
+        let mut data = words(&[0x02, 0, 1, 1]);
+        data.extend(be_floats(&[1.0, 0.5, 1.0, 0.0, 0.0, 0.0, 2.0]));
+        data.extend(words(&[1, 0]));
+        data.extend(be_floats(&[1.5, -2.0, 3.0]));
+        let mut plugins = be_chunk(0x11E, &words(&[0x100, 7, 0]));
+        plugins.extend(be_chunk(0x0253F2F6, &[1, 2, 3, 4]));
+        let mut payload = be_chunk(0x01, &data);
+        payload.extend(be_chunk(0x03, &plugins));
+        let file = be_chunk(0x0F, &payload);
+        assert_eq!(endian::Endian::detect(&file), endian::Endian::Big);
+
+        let (_, geo) = Chunk::parse(&file).unwrap();
+        assert_eq!(geo.header.version, RwVersion::GTA3_PC);
+        let geo_content = geo.content.as_geometry().unwrap();
+        assert_eq!(geo_content.surface_prop.unwrap().specular, 0.5);
+        assert_eq!(geo_content.morph_targets[0].sphere.radius, 2.0);
+        assert_eq!(geo_content.vertices()[0].as_arr(), [1.5, -2.0, 3.0]);
+        let hanim = geo.get_extension()[0].content.as_hanim().unwrap();
+        assert_eq!(hanim.id, 7);
+        // Chunks without big-endian support are kept raw
+        assert!(geo.get_extension()[1].content.as_section().is_some());
+        // and would end up between little-endian headers, so the tree isn't written
+        assert_eq!(geo.header.endian, endian::Endian::Big);
+        assert!(geo.to_bytes().is_err());
+
+        // Forcing the wrong byte order fails
+        let opts = ParseOptions::default().with_endian(endian::Endian::Little);
+        assert!(Chunk::parse_with(&file, &opts).is_err());
+        assert_eq!(
+            endian::Endian::detect(&chunk(0x0F, &[])),
+            endian::Endian::Little
+        );
+    }
+
+    #[test]
+    fn late_chunk_types() {
+        let mut adc = 5u32.to_le_bytes().to_vec();
+        adc.extend([0, 0, 1, 0, 1, 0, 0, 0]);
+        let mut ext = chunk(0x134, &adc);
+        ext.extend(chunk(0x131, &0x11003u32.to_le_bytes()));
+        let (_, ext) = Chunk::parse(&chunk(0x03, &ext)).unwrap();
+
+        let adc = ext.get_children()[0].content.as_adc().unwrap();
+        assert_eq!(adc.flags, [0, 0, 1, 0, 1]);
+        // Unparsed sections are still identified
+        let (ty, _) = ext.get_children()[1].content.as_section().unwrap();
+        assert_eq!(ty.known(), Some(ChunkType::PdsPLG));
+
+        let (_, reparsed) = Chunk::parse(&ext.to_bytes().unwrap()).unwrap();
+        assert_eq!(reparsed.get_children()[0].content.as_adc(), Some(adc));
+    }
+
+    #[test]
+    fn merge_texture_dictionaries() {
+        let image = tex::RgbaImage {
+            width: 4,
+            height: 4,
+            data: [255, 0, 0, 255].repeat(16),
+        };
+        let txd = |version, names: &[&str], device_id| {
+            let encoding = if version >= RwVersion::SA_PC {
+                tex::RasterEncoding::Dxt1
+            } else {
+                tex::RasterEncoding::Format8888
+            };
+            let header = ChunkHeader {
+                version,
+                build: RwBuild::UNSPECIFIED,
+                endian: endian::Endian::Little,
+                size: None,
+            };
+            let mut children: Vec<Chunk> = names
+                .iter()
+                .map(|name| Chunk {
+                    header,
+                    content: ChunkContent::Raster(RpRasterPC::encode(
+                        name, &image, encoding, false, version,
+                    )),
+                    children: Some(Vec::new()),
+                })
+                .collect();
+            children.push(Chunk {
+                header,
+                content: ChunkContent::Extension,
+                children: Some(Vec::new()),
+            });
+            Chunk {
+                header,
+                content: ChunkContent::TextureDictionary(RwTextureDictionary {
+                    num_textures: names.len() as u16,
+                    device_id,
+                }),
+                children: Some(children),
+            }
+        };
+        let txds = [
+            txd(RwVersion::VC_PC, &["road", "grass"], 1),
+            txd(RwVersion::SA_PC, &["Grass", "sand"], 2),
+        ];
+
+        let merged = Chunk::merge_texture_dictionaries(&txds, TextureConflict::KeepFirst).unwrap();
+        let names: Vec<_> = merged.textures().filter_map(|t| t.texture_name()).collect();
+        assert_eq!(names, ["road", "grass", "sand"]);
+        let dict = merged.content.as_texture_dictionary().unwrap();
+        assert_eq!((dict.num_textures, dict.device_id), (3, 1));
+        assert!(merged.iter().all(|c| c.header.version == RwVersion::VC_PC));
+        // The SA raster is moved to the D3D8 layout and still decodes after a round trip
+        let (_, reparsed) = Chunk::parse(&merged.to_bytes().unwrap()).unwrap();
+        let sand = reparsed.textures().nth(2).unwrap();
+        assert_eq!(sand.content.as_raster().unwrap().platform_id, PLATFORM_D3D8);
+        assert_eq!(sand.decode_texture().unwrap().data, image.data);
+        // and back to D3D9 when merged into an SA dictionary
+        let sa = Chunk::merge_texture_dictionaries(
+            &[txds[1].clone(), reparsed],
+            TextureConflict::KeepFirst,
+        )
+        .unwrap();
+        let road = sa.textures().nth(2).unwrap().content.as_raster().unwrap();
+        assert_eq!(road.d3d_format, tex::D3dFormat::A8R8G8B8);
+        let (_, reparsed) = Chunk::parse(&sa.to_bytes().unwrap()).unwrap();
+        for texture in reparsed.textures() {
+            assert_eq!(texture.decode_texture().unwrap().data, image.data);
+        }
+
+        let merged = Chunk::merge_texture_dictionaries(&txds, TextureConflict::KeepLast).unwrap();
+        let names: Vec<_> = merged.textures().filter_map(|t| t.texture_name()).collect();
+        assert_eq!(names, ["road", "Grass", "sand"]);
+        let opts = options::WriteOptions::default().with_texture_order(options::TextureOrder::Name);
+        let sorted = merged.to_bytes_with(&opts).unwrap();
+        assert_eq!(sorted, merged.to_bytes_with(&opts).unwrap());
+        let (_, sorted) = Chunk::parse(&sorted).unwrap();
+        let names: Vec<_> = sorted.textures().filter_map(|t| t.texture_name()).collect();
+        assert_eq!(names, ["Grass", "road", "sand"]);
+        assert!(matches!(
+            sorted.get_children().last().unwrap().content,
+            ChunkContent::Extension
+        ));
+
+        let lookup = tex::TexDictionary::new(&merged).unwrap();
+        assert_eq!(lookup.len(), 3);
+        assert_eq!(lookup.get("GRASS").unwrap().texture_name(), Some("Grass"));
+        assert!(lookup.contains("Sand") && !lookup.contains("wheel32"));
+        let names: Vec<_> = lookup.iter().filter_map(|t| t.texture_name()).collect();
+        assert_eq!(names, ["road", "Grass", "sand"]);
+
+        let duplicates = txd(RwVersion::VC_PC, &["wheel", "WHEEL"], 1);
+        let lookup = tex::TexDictionary::new(&duplicates).unwrap();
+        assert_eq!(lookup.get("Wheel").unwrap().texture_name(), Some("WHEEL"));
+        assert!(tex::TexDictionary::new(&duplicates.get_children()[0]).is_none());
+
+        assert!(Chunk::merge_texture_dictionaries(&txds, TextureConflict::Fail).is_err());
+        assert!(Chunk::merge_texture_dictionaries(&[], TextureConflict::Fail).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let (_, geo) = Chunk::parse(&geometry(&[[0.0; 3], [1.0; 3]], &[])).unwrap();
+        let json = serde_json::to_string(&geo).unwrap();
+        let decoded: Chunk = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.to_bytes().unwrap(), geo.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn splice_and_extract() {
+        let source = geometry(&[[0.0; 3]], &chunk(0x0253F2F9, &0u32.to_le_bytes()));
+        let (_, source) = Chunk::parse(&source).unwrap();
+        let (_, mut target) = Chunk::parse(&geometry(&[[0.0; 3]], &[])).unwrap();
+
+        let mut plugin = source.extract(&[0, 0]).unwrap();
+        assert_eq!(plugin.content.chunk_type(), ChunkType::NightVertexColors);
+        // Pretend the plugin came from a VC file
+        plugin.header.version = RwVersion::VC_PC;
+        target.splice(&[0, 0], plugin).unwrap();
+        assert_eq!(target.get_extension().len(), 1);
+        assert_eq!(
+            target.get_path(&[0, 0]).unwrap().header.version,
+            target.header.version
+        );
+
+        assert!(target.splice(&[0, 5], source.clone()).is_err());
+        assert!(target.remove(&[0, 0]).is_some());
+        assert!(target.get_extension().is_empty());
+    }
+
+    #[test]
+    fn truncated_plugin() {
+        let mut plugins = chunk(0x0253F2F9, &0u32.to_le_bytes());
+        plugins.extend(chunk(0x0253F2F6, &[0; 28]));
+        let mut file = geometry(&[[0.0; 3]], &plugins);
+        // Declare 8 more bytes for the specular plugin than the extension contains
+        let size_offset = file.len() - 28 - 8;
+        file[size_offset..size_offset + 4].copy_from_slice(&36u32.to_le_bytes());
+
+        let opts = ParseOptions::default();
+        let (_, geo) = Chunk::parse_with(&file, &opts).unwrap();
+        assert_eq!(geo.get_extension().len(), 2);
+        let diagnostics = opts.take_diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].offset, Some(size_offset as u64 - 4));
+    }
+
+    #[test]
+    fn broken_plugin() {
+        // The specular plugin is too short for its name, the morph plugin after it still parses
+        let mut plugins = chunk(0x0253F2F6, &[0; 8]);
+        plugins.extend(chunk(0x105, &0u32.to_le_bytes()));
+        let file = geometry(&[[0.0; 3]], &plugins);
+
+        let opts = ParseOptions::default();
+        let (_, geo) = Chunk::parse_with(&file, &opts).unwrap();
+        let extension = geo.get_extension();
+        assert_eq!(extension.len(), 2);
+        assert!(matches!(
+            &extension[0].content,
+            ChunkContent::Section((ChunkTypeId(0x0253F2F6), data)) if data.len() == 8
+        ));
+        assert!(extension[1].content.as_morph().is_some());
+        assert_eq!(opts.take_diagnostics().len(), 1);
+        assert_eq!(geo.to_bytes().unwrap(), file);
+
+        // Clamped to what's left and still too short
+        let mut file = geometry(&[[0.0; 3]], &chunk(0x0253F2F6, &[0; 8]));
+        let size_offset = file.len() - 8 - 8;
+        file[size_offset..size_offset + 4].copy_from_slice(&28u32.to_le_bytes());
+        let (_, geo) = Chunk::parse_with(&file, &opts).unwrap();
+        assert!(matches!(
+            &geo.get_extension()[0].content,
+            ChunkContent::Section((_, data)) if data.len() == 8
+        ));
+    }
 }
@@ -0,0 +1,69 @@
+use super::endian::Endian;
+use super::plugin::PluginRegistry;
+use super::RwVersion;
+use crate::diagnostics::{Diagnostic, Diagnostics};
+
+#[derive(Clone, Debug)]
+pub struct ParseOptions {
+    // Clamp extension plugins that declare more data than their parent has left
+    pub clamp_truncated_plugins: bool,
+    pub diagnostics: Diagnostics,
+    pub plugins: PluginRegistry,
+    // Byte order of every chunk, detected from each chunk header when None
+    pub endian: Option<Endian>,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            clamp_truncated_plugins: true,
+            diagnostics: Diagnostics::new(),
+            plugins: PluginRegistry::default(),
+            endian: None,
+        }
+    }
+}
+
+impl ParseOptions {
+    pub fn take_diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics.take()
+    }
+
+    // Parses chunks with the given id, which the crate doesn't know about, into ChunkContent::Custom
+    pub fn with_plugin<T, F>(mut self, id: u32, parser: F) -> Self
+    where
+        T: std::any::Any + Send + Sync,
+        F: Fn(&[u8], RwVersion) -> Option<T> + Send + Sync + 'static,
+    {
+        self.plugins.register(id, parser);
+        self
+    }
+
+    pub fn with_endian(mut self, endian: Endian) -> Self {
+        self.endian = Some(endian);
+        self
+    }
+}
+
+// Order the textures of a TextureDictionary are written in
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum TextureOrder {
+    #[default]
+    Stored,
+    // Case-insensitive, raw rasters of unparsed platforms and other chunks keep their place
+    Name,
+}
+
+// Options for Chunk::to_bytes_with. Padding, like that of names and strings, is always written
+// as zero bytes, so the same tree always gives the same file.
+#[derive(Clone, Debug, Default)]
+pub struct WriteOptions {
+    pub texture_order: TextureOrder,
+}
+
+impl WriteOptions {
+    pub fn with_texture_order(mut self, order: TextureOrder) -> Self {
+        self.texture_order = order;
+        self
+    }
+}
@@ -0,0 +1,108 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use super::{ChunkTypeId, RwVersion};
+
+type CustomParser =
+    Arc<dyn Fn(&[u8], RwVersion) -> Option<Arc<dyn Any + Send + Sync>> + Send + Sync>;
+
+// Parsers supplied by library users for chunk ids the crate doesn't know about
+#[derive(Clone, Default)]
+pub struct PluginRegistry {
+    parsers: HashMap<u32, CustomParser>,
+}
+
+impl PluginRegistry {
+    // Replaces any parser already registered for the id. Returning None keeps the chunk as a raw
+    // section.
+    pub fn register<T, F>(&mut self, id: u32, parser: F)
+    where
+        T: Any + Send + Sync,
+        F: Fn(&[u8], RwVersion) -> Option<T> + Send + Sync + 'static,
+    {
+        self.parsers.insert(
+            id,
+            Arc::new(move |data, version| {
+                parser(data, version).map(|v| Arc::new(v) as Arc<dyn Any + Send + Sync>)
+            }),
+        );
+    }
+
+    pub fn contains(&self, id: u32) -> bool {
+        self.parsers.contains_key(&id)
+    }
+
+    pub(crate) fn parse(&self, id: u32, data: &[u8], version: RwVersion) -> Option<CustomChunk> {
+        let value = self.parsers.get(&id)?(data, version)?;
+        Some(CustomChunk {
+            ty: ChunkTypeId(id),
+            data: data.to_vec(),
+            value,
+        })
+    }
+}
+
+impl fmt::Debug for PluginRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut ids: Vec<_> = self.parsers.keys().map(|id| ChunkTypeId(*id)).collect();
+        ids.sort_by_key(|id| id.0);
+        f.debug_set().entries(ids).finish()
+    }
+}
+
+// Output of a registered parser. The raw data is kept so the chunk can be written back.
+#[derive(Clone)]
+pub struct CustomChunk {
+    pub ty: ChunkTypeId,
+    pub data: Vec<u8>,
+    pub value: Arc<dyn Any + Send + Sync>,
+}
+
+impl CustomChunk {
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.value.downcast_ref()
+    }
+}
+
+impl fmt::Debug for CustomChunk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CustomChunk")
+            .field("ty", &self.ty)
+            .field("size", &self.data.len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bsf::fixtures::chunk;
+    use crate::bsf::options::ParseOptions;
+    use crate::bsf::{Chunk, ChunkContent};
+
+    #[test]
+    fn custom_plugins() {
+        #[derive(Debug, PartialEq)]
+        struct Magic(u32);
+
+        let plugins = chunk(0x0253F2AA, &[0xDE, 0xAD, 0xBE, 0xEF]);
+        let file = chunk(0x03, &[plugins, chunk(0x0253F2AB, &[1])].concat());
+        let opts = ParseOptions::default()
+            .with_plugin(0x0253F2AA, |data, _| {
+                Some(Magic(u32::from_le_bytes(data.try_into().ok()?)))
+            })
+            .with_plugin(0x0253F2AB, |_, _| None::<Magic>);
+        assert!(opts.plugins.contains(0x0253F2AB));
+
+        let (_, ext) = Chunk::parse_with(&file, &opts).unwrap();
+        let ChunkContent::Custom(custom) = &ext.get_children()[0].content else {
+            panic!("not a custom chunk");
+        };
+        assert_eq!(custom.downcast_ref::<Magic>(), Some(&Magic(0xEFBEADDE)));
+        assert!(custom.downcast_ref::<u32>().is_none());
+        // Parsers can decline, leaving the raw section
+        assert!(ext.get_children()[1].content.as_section().is_some());
+        assert_eq!(ext.to_bytes().unwrap(), file);
+    }
+}
@@ -0,0 +1,715 @@
+use nom::bytes::complete::take;
+use nom::number::complete::{le_u32, le_u64};
+use nom::IResult;
+
+use num_traits::FromPrimitive;
+
+use super::geo::{strip_triangles, BinMesh, MorphTarget, RpGeometry, RpTriangle, RwV3d};
+use super::tex::{
+    RasterFormat, RgbaImage, RwRGBA, RwTexCoords, TextureAddressingMode, TextureFilteringMode,
+};
+use super::RwVersion;
+
+pub const PLATFORM_PS2: u32 = 4;
+// Texture Natives use a FourCC instead of the platform id
+pub const PLATFORM_PS2_FOURCC: u32 = u32::from_le_bytes(*b"PS2\0");
+
+// Each level and the palette are preceded by GIF packets
+const RASTER_HAS_HEADERS: RasterFormat = RasterFormat::from_bits_retain(0x20000);
+const GIF_HEADER_SIZE: usize = 0x50;
+
+// VIF unpack formats used by the default PS2 pipelines, (vn << 2) | vl
+const UNPACK_V2_32: u8 = 0x4;
+const UNPACK_V2_16: u8 = 0x5;
+const UNPACK_V3_32: u8 = 0x8;
+const UNPACK_V3_8: u8 = 0xA;
+const UNPACK_V4_8: u8 = 0xE;
+
+// Vertex data of one BinMesh split, unpacked from its VIF stream. Each batch
+// holds its own copy of the vertices it uses, so batches are drawn separately.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ps2Mesh {
+    pub positions: Vec<[f32; 3]>,
+    pub tex_coords: Vec<[f32; 2]>,
+    pub colors: Vec<[u8; 4]>,
+    pub normals: Vec<[f32; 3]>,
+    pub batches: Vec<usize>, // Number of vertices in each batch
+}
+
+// Native Data PLG of PS2 geometry, one mesh per BinMesh split
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RpNativeDataPS2 {
+    pub meshes: Vec<Ps2Mesh>,
+    pub raw: Vec<u8>, // DMA chains as stored, written back as is
+}
+
+impl RpNativeDataPS2 {
+    pub fn parse(i: &[u8], _version: RwVersion, num_meshes: usize) -> IResult<&[u8], Self> {
+        let start = i;
+        let (i, _struct_header) = take(12usize)(i)?;
+        let (mut i, platform) = le_u32(i)?;
+        if platform != PLATFORM_PS2 {
+            return Err(nom::Err::Error(nom::error::Error::new(
+                i,
+                nom::error::ErrorKind::Verify,
+            )));
+        }
+
+        let mut meshes = Vec::with_capacity(num_meshes);
+        for _ in 0..num_meshes {
+            let size;
+            let data;
+            (i, size) = le_u32(i)?;
+            (i, _) = le_u32(i)?; // No pointers, ref tags are relative to data either way
+            (i, data) = take(size)(i)?;
+            let Some(stream) = dma_chain(data) else {
+                return Err(nom::Err::Error(nom::error::Error::new(
+                    data,
+                    nom::error::ErrorKind::Eof,
+                )));
+            };
+            meshes.push(unpack_mesh(&stream));
+        }
+
+        Ok((
+            i,
+            Self {
+                meshes,
+                raw: start.to_vec(),
+            },
+        ))
+    }
+
+    // Fills in the vertex arrays and triangles of native geometry
+    pub fn apply_to(&self, geo: &mut RpGeometry, bin_mesh: &BinMesh) {
+        let total: usize = self.meshes.iter().map(|m| m.positions.len()).sum();
+        if total > u16::MAX as usize + 1 {
+            return;
+        }
+        let has_uvs = self
+            .meshes
+            .iter()
+            .all(|m| m.tex_coords.len() == m.positions.len());
+        let has_colors = self
+            .meshes
+            .iter()
+            .all(|m| m.colors.len() == m.positions.len());
+        let has_normals = self
+            .meshes
+            .iter()
+            .all(|m| m.normals.len() == m.positions.len());
+
+        let mut vertices = Vec::new();
+        let mut tex_coords = Vec::new();
+        let mut prelit = Vec::new();
+        let mut normals = Vec::new();
+        let mut triangles = Vec::new();
+        for (mesh, (material_id, _)) in self.meshes.iter().zip(&bin_mesh.splits) {
+            let mut start = vertices.len();
+            for count in &mesh.batches {
+                let tris = if bin_mesh.is_tristrip() {
+                    strip_triangles(start, *count)
+                } else {
+                    (start..start + count - count % 3)
+                        .step_by(3)
+                        .map(|v| [v, v + 1, v + 2])
+                        .collect()
+                };
+                for [a, b, c] in tris {
+                    let pos = |v: usize| mesh.positions.get(v - vertices.len());
+                    // Strips are joined with degenerate triangles
+                    if pos(a) == pos(b) || pos(b) == pos(c) || pos(a) == pos(c) {
+                        continue;
+                    }
+                    triangles.push(RpTriangle {
+                        vertex1: a as u16,
+                        vertex2: b as u16,
+                        vertex3: c as u16,
+                        material_id: *material_id as u16,
+                    });
+                }
+                start += count;
+            }
+
+            vertices.extend(mesh.positions.iter().map(|p| RwV3d {
+                x: p[0],
+                y: p[1],
+                z: p[2],
+            }));
+            if has_uvs {
+                tex_coords.extend(
+                    mesh.tex_coords
+                        .iter()
+                        .map(|t| RwTexCoords { u: t[0], v: t[1] }),
+                );
+            }
+            if has_colors {
+                prelit.extend(mesh.colors.iter().map(|c| RwRGBA {
+                    r: c[0],
+                    g: c[1],
+                    b: c[2],
+                    a: c[3],
+                }));
+            }
+            if has_normals {
+                normals.extend(mesh.normals.iter().map(|n| RwV3d {
+                    x: n[0],
+                    y: n[1],
+                    z: n[2],
+                }));
+            }
+        }
+
+        geo.num_vertices = vertices.len() as u32;
+        geo.num_triangles = triangles.len() as u32;
+        geo.tex_coords = if has_uvs {
+            vec![tex_coords]
+        } else {
+            Vec::new()
+        };
+        geo.prelit = prelit;
+        if geo.morph_targets.is_empty() {
+            geo.morph_targets.push(MorphTarget::default());
+        }
+        geo.morph_targets[0].vertices = vertices;
+        geo.morph_targets[0].normals = normals;
+        geo.triangles = triangles;
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+// Follows the DMA tags in data and returns the VIF stream they would transfer.
+// Ref tags address quadwords relative to the start of data.
+fn dma_chain(data: &[u8]) -> Option<Vec<u8>> {
+    let mut vif = Vec::new();
+    let mut pos = 0;
+    // next tags could loop forever on corrupt data
+    for _ in 0..data.len() / 16 {
+        if pos + 16 > data.len() {
+            break;
+        }
+        let tag = read_u32(data, pos)?;
+        let addr = read_u32(data, pos + 4)? as usize & 0x7FFFFFFF;
+        let qwc = (tag & 0xFFFF) as usize;
+        let id = (tag >> 28) & 7;
+        vif.extend_from_slice(&data[pos + 8..pos + 16]);
+        let inline = data.get(pos + 16..pos + 16 + qwc * 16);
+        let referenced = data.get(addr * 16..(addr + qwc) * 16);
+        match id {
+            // cnt
+            1 => {
+                vif.extend_from_slice(inline?);
+                pos += 16 + qwc * 16;
+            }
+            // next
+            2 => {
+                vif.extend_from_slice(inline?);
+                pos = addr * 16;
+            }
+            // ref, refs
+            3 | 4 => {
+                vif.extend_from_slice(referenced?);
+                pos += 16;
+            }
+            // refe
+            0 => {
+                vif.extend_from_slice(referenced?);
+                break;
+            }
+            // ret, end
+            6 | 7 => {
+                vif.extend_from_slice(inline?);
+                break;
+            }
+            _ => return None,
+        }
+    }
+    Some(vif)
+}
+
+// Collects the vertex attributes unpacked by a VIF stream. Formats other than
+// the ones written by the default pipelines (e.g. skin weights) are skipped.
+fn unpack_mesh(vif: &[u8]) -> Ps2Mesh {
+    let mut mesh = Ps2Mesh::default();
+    let mut pos = 0;
+    while let Some(code) = read_u32(vif, pos) {
+        pos += 4;
+        let imm = (code & 0xFFFF) as usize;
+        let num = (code >> 16) & 0xFF;
+        let cmd = (code >> 24) as u8 & 0x7F;
+
+        if cmd & 0x60 == 0x60 {
+            let num = if num == 0 { 256 } else { num as usize };
+            let format = cmd & 0xF;
+            let (vn, vl) = ((format >> 2) as usize, format & 3);
+            let size = if vl == 3 {
+                num * 2
+            } else {
+                num * (vn + 1) * (4 >> vl)
+            };
+            let Some(data) = vif.get(pos..pos + size) else {
+                break;
+            };
+            pos += size.div_ceil(4) * 4;
+            let unsigned = imm & 0x4000 != 0;
+            unpack(&mut mesh, format, unsigned, num, data);
+            continue;
+        }
+
+        pos += match cmd {
+            0x20 => 4,                                               // STMASK
+            0x30 | 0x31 => 16,                                       // STROW, STCOL
+            0x4A => (if num == 0 { 256 } else { num as usize }) * 8, // MPG
+            0x50 | 0x51 => {
+                // DIRECT, DIRECTHL
+                let qwords = if imm == 0 { 0x10000 } else { imm };
+                qwords * 16
+            }
+            _ => 0,
+        };
+    }
+    mesh
+}
+
+fn unpack(mesh: &mut Ps2Mesh, format: u8, unsigned: bool, num: usize, data: &[u8]) {
+    let f32_at = |i: usize| f32::from_le_bytes(data[i * 4..i * 4 + 4].try_into().unwrap());
+    match format {
+        UNPACK_V3_32 => {
+            mesh.batches.push(num);
+            mesh.positions
+                .extend((0..num).map(|v| [f32_at(v * 3), f32_at(v * 3 + 1), f32_at(v * 3 + 2)]));
+        }
+        UNPACK_V2_32 => {
+            mesh.tex_coords
+                .extend((0..num).map(|v| [f32_at(v * 2), f32_at(v * 2 + 1)]));
+        }
+        // Compressed texture coordinates, 4.12 fixed point
+        UNPACK_V2_16 => {
+            let s16_at = |i: usize| i16::from_le_bytes([data[i * 2], data[i * 2 + 1]]) as f32;
+            mesh.tex_coords
+                .extend((0..num).map(|v| [s16_at(v * 2) / 4096.0, s16_at(v * 2 + 1) / 4096.0]));
+        }
+        UNPACK_V4_8 if unsigned => {
+            mesh.colors
+                .extend(data.chunks_exact(4).map(|c| [c[0], c[1], c[2], c[3]]));
+        }
+        UNPACK_V4_8 | UNPACK_V3_8 => {
+            let stride = if format == UNPACK_V4_8 { 4 } else { 3 };
+            mesh.normals.extend(data.chunks_exact(stride).map(|n| {
+                [
+                    n[0] as i8 as f32 / 127.0,
+                    n[1] as i8 as f32 / 127.0,
+                    n[2] as i8 as f32 / 127.0,
+                ]
+            }));
+        }
+        _ => {}
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RpRasterPS2 {
+    pub filtering: TextureFilteringMode,
+    pub address_u: TextureAddressingMode,
+    pub address_v: TextureAddressingMode,
+    pub name: String,
+    pub mask_name: String,
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+    pub raster_format: RasterFormat,
+    // GS registers the game uploads as is
+    pub tex0: u64,
+    pub tex1: u64,
+    pub miptbp1: u64,
+    pub miptbp2: u64,
+    pub gpu_size: u32,
+    pub sky_mipmap: u32,
+    pub pixels: Vec<u8>,
+    pub palette: Vec<u8>,
+    pub raw: Vec<u8>, // Struct chunks before the Extension, written back as is
+}
+
+// Payload of a Struct chunk, the header is only checked for its size
+fn struct_payload(i: &[u8]) -> IResult<&[u8], &[u8]> {
+    let (i, _ty) = le_u32(i)?;
+    let (i, size) = le_u32(i)?;
+    let (i, _lib_id) = le_u32(i)?;
+    take(size)(i)
+}
+
+fn string_payload(i: &[u8]) -> IResult<&[u8], String> {
+    let (i, data) = struct_payload(i)?;
+    Ok((
+        i,
+        String::from_utf8_lossy(data).trim_matches('\0').to_owned(),
+    ))
+}
+
+impl RpRasterPS2 {
+    // Payload of a Texture Native chunk, returns the trailing Extension chunk
+    pub fn parse(i: &[u8], _version: RwVersion) -> IResult<&[u8], Self> {
+        let start = i;
+        let (i, header) = struct_payload(i)?;
+        let (header, _platform) = le_u32(header)?;
+        let (_, flags) = le_u32(header)?;
+        let filtering = TextureFilteringMode::from_u32(flags & 0xFF)
+            .unwrap_or(TextureFilteringMode::FILTERNAFILTERMODE);
+        let address = |mode| {
+            TextureAddressingMode::from_u32(mode)
+                .unwrap_or(TextureAddressingMode::TEXTUREADDRESSNATEXTUREADDRESS)
+        };
+        let address_u = address((flags >> 8) & 0xF);
+        let address_v = address((flags >> 12) & 0xF);
+        let (i, name) = string_payload(i)?;
+        let (i, mask_name) = string_payload(i)?;
+
+        let (i, raster) = struct_payload(i)?;
+        let (raster, info) = struct_payload(raster)?;
+        let (_, data) = struct_payload(raster)?;
+        let (info, width) = le_u32(info)?;
+        let (info, height) = le_u32(info)?;
+        let (info, depth) = le_u32(info)?;
+        let (info, raster_format) = le_u32(info)?;
+        let raster_format = RasterFormat::from_bits_retain(raster_format);
+        let (info, tex0) = le_u64(info)?;
+        let (info, tex1) = le_u64(info)?;
+        let (info, miptbp1) = le_u64(info)?;
+        let (info, miptbp2) = le_u64(info)?;
+        let (info, pixel_size) = le_u32(info)?;
+        let (info, palette_size) = le_u32(info)?;
+        let (info, gpu_size) = le_u32(info)?;
+        let (_, sky_mipmap) = le_u32(info)?;
+        let (data, pixels) = take(pixel_size)(data)?;
+        let (_, palette) = take(palette_size)(data)?;
+
+        Ok((
+            i,
+            Self {
+                filtering,
+                address_u,
+                address_v,
+                name,
+                mask_name,
+                width,
+                height,
+                depth,
+                raster_format,
+                tex0,
+                tex1,
+                miptbp1,
+                miptbp2,
+                gpu_size,
+                sky_mipmap,
+                pixels: pixels.to_vec(),
+                palette: palette.to_vec(),
+                raw: start[..start.len() - i.len()].to_vec(),
+            },
+        ))
+    }
+
+    pub fn has_headers(&self) -> bool {
+        self.raster_format.contains(RASTER_HAS_HEADERS)
+    }
+
+    pub fn to_image(&self) -> Option<RgbaImage> {
+        Some(RgbaImage {
+            width: self.width,
+            height: self.height,
+            data: self.to_rgba()?,
+        })
+    }
+
+    // Base level as RGBA8, None for formats that can't be decoded
+    pub fn to_rgba(&self) -> Option<Vec<u8>> {
+        let (w, h) = (self.width as usize, self.height as usize);
+        let header = if self.has_headers() {
+            GIF_HEADER_SIZE
+        } else {
+            0
+        };
+        let level = self
+            .pixels
+            .get(header..header + w * h * self.depth as usize / 8)?;
+        // The transfer size in TRXREG differs from the texture size when the
+        // indices were uploaded swizzled as 32 bit pixels
+        let swizzled = header > 0 && read_u32(&self.pixels, 0x30)? as usize & 0xFFF != w;
+
+        match self.depth {
+            32 => Some(
+                level
+                    .chunks_exact(4)
+                    .flat_map(|c| [c[0], c[1], c[2], ps2_alpha(c[3])])
+                    .collect(),
+            ),
+            16 => Some(
+                level
+                    .chunks_exact(2)
+                    .flat_map(|c| {
+                        let p = u16::from_le_bytes([c[0], c[1]]);
+                        let channel = |shift: u16| ((p >> shift & 0x1F) as u32 * 255 / 31) as u8;
+                        [
+                            channel(0),
+                            channel(5),
+                            channel(10),
+                            if p & 0x8000 != 0 { 255 } else { 0 },
+                        ]
+                    })
+                    .collect(),
+            ),
+            8 if self.raster_format.contains(RasterFormat::PAL8) => {
+                let indices = if swizzled {
+                    ps2_unswizzle(level, w, h, 8)?
+                } else {
+                    level.to_vec()
+                };
+                let palette = self.palette_rgba(256)?;
+                Some(indices.iter().flat_map(|i| palette[*i as usize]).collect())
+            }
+            4 if self.raster_format.contains(RasterFormat::PAL4) => {
+                let palette = self.palette_rgba(16)?;
+                let indices = if swizzled {
+                    ps2_unswizzle(level, w, h, 4)?
+                } else {
+                    level.to_vec()
+                };
+                Some(
+                    indices
+                        .iter()
+                        .flat_map(|b| [b & 0xF, b >> 4])
+                        .flat_map(|i| palette[i as usize])
+                        .collect(),
+                )
+            }
+            _ => None,
+        }
+    }
+
+    fn palette_rgba(&self, len: usize) -> Option<Vec<[u8; 4]>> {
+        let header = if self.has_headers() {
+            GIF_HEADER_SIZE
+        } else {
+            0
+        };
+        let data = self.palette.get(header..header + len * 4)?;
+        let mut colors: Vec<[u8; 4]> = data
+            .chunks_exact(4)
+            .map(|c| [c[0], c[1], c[2], ps2_alpha(c[3])])
+            .collect();
+        // 256 color palettes are stored in CSM1 order, which swaps the middle two of every four blocks of 8
+        if len == 256 {
+            for i in 0..256 {
+                if i & 0x18 == 0x08 {
+                    colors.swap(i, i + 8);
+                }
+            }
+        }
+        Some(colors)
+    }
+}
+
+// The GS treats 0x80 as fully opaque
+fn ps2_alpha(a: u8) -> u8 {
+    (a as u32 * 255 / 128).min(255) as u8
+}
+
+// Reorders 4 or 8 bit indices that were uploaded as PSMCT32 back into rows. 4 bit indices are
+// packed two per byte, low nibble first, in both layouts. None for other depths.
+pub fn ps2_unswizzle(data: &[u8], width: usize, height: usize, depth: u8) -> Option<Vec<u8>> {
+    let mut out = vec![0; width * height * depth as usize / 8];
+    for y in 0..height {
+        for x in 0..width {
+            let linear = y * width + x;
+            match depth {
+                8 => out[linear] = data.get(swizzled8(x, y, width)).copied().unwrap_or(0),
+                4 => {
+                    let (offset, shift) = swizzled4(x, y, width, height);
+                    let index = data.get(offset).map_or(0, |b| b >> shift & 0xF);
+                    out[linear / 2] |= index << (linear % 2 * 4);
+                }
+                _ => return None,
+            }
+        }
+    }
+    Some(out)
+}
+
+// Inverse of ps2_unswizzle, for indices about to be uploaded as PSMCT32
+pub fn ps2_swizzle(data: &[u8], width: usize, height: usize, depth: u8) -> Option<Vec<u8>> {
+    let mut out = vec![0; width * height * depth as usize / 8];
+    for y in 0..height {
+        for x in 0..width {
+            let linear = y * width + x;
+            match depth {
+                8 => {
+                    let offset = swizzled8(x, y, width);
+                    if let (Some(out), Some(&index)) = (out.get_mut(offset), data.get(linear)) {
+                        *out = index;
+                    }
+                }
+                4 => {
+                    let (offset, shift) = swizzled4(x, y, width, height);
+                    let index = data
+                        .get(linear / 2)
+                        .map_or(0, |b| b >> (linear % 2 * 4) & 0xF);
+                    if let Some(out) = out.get_mut(offset) {
+                        *out |= index << shift;
+                    }
+                }
+                _ => return None,
+            }
+        }
+    }
+    Some(out)
+}
+
+// Byte holding the 8 bit index of (x, y)
+fn swizzled8(x: usize, y: usize, w: usize) -> usize {
+    let block = (y & !0xF) * w + (x & !0xF) * 2;
+    let swap = (((y + 2) >> 2) & 1) * 4;
+    let row = (((y & !3) >> 1) + (y & 1)) & 7;
+    let column = row * w * 2 + ((x + swap) & 7) * 4;
+    let byte = ((y >> 1) & 1) + ((x >> 2) & 2);
+    block + column + byte
+}
+
+// Byte and bit shift of the 4 bit index of (x, y), pages of 128x128 pixels are laid out in
+// columns
+fn swizzled4(x: usize, y: usize, w: usize, h: usize) -> (usize, u32) {
+    let pages_horz = w.div_ceil(128);
+    let pages_vert = h.div_ceil(128);
+    let page = (y / 128) * pages_horz + x / 128;
+    let page = (page / pages_vert) * 32 * h * 2 + (page % pages_vert) * 64 * 4;
+    let (x_in_page, y_in_page) = (x & 0x7F, y & 0x7F);
+    let block = ((x_in_page & !0x1F) >> 1) * h + (y_in_page & !0xF) * 2;
+    let swap = (((y + 2) >> 2) & 1) * 4;
+    let row = (((y & !3) >> 1) + (y & 1)) & 7;
+    let column = row * h * 2 + ((x + swap) & 7) * 4;
+    let byte = (x >> 3) & 3;
+    let shift = ((y >> 1) & 1) as u32 * 4;
+    (page + block + column + byte, shift)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsf::fixtures::{chunk, floats, geometry};
+    use crate::bsf::{tex, Chunk};
+
+    #[test]
+    fn ps2_native_geometry() {
+        let mut bin_mesh = Vec::new();
+        for word in [1u32, 1, 4, 4, 0] {
+            bin_mesh.extend(word.to_le_bytes());
+        }
+
+        // One cnt tag uploading a batch of 4 positions and colors, then an end tag
+        let mut vif = (0x68040000u32).to_le_bytes().to_vec();
+        vif.extend(floats(&[
+            0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0,
+        ]));
+        vif.extend(0x6E044000u32.to_le_bytes());
+        vif.extend([255; 16]);
+        vif.resize(80, 0);
+        let mut dma = (0x10000005u32).to_le_bytes().to_vec();
+        dma.extend([0; 12]);
+        dma.extend(vif);
+        dma.extend(0x70000000u32.to_le_bytes());
+        dma.extend([0; 12]);
+        let mut native = 4u32.to_le_bytes().to_vec();
+        native.extend((dma.len() as u32).to_le_bytes());
+        native.extend(0u32.to_le_bytes());
+        native.extend(dma);
+
+        let mut plugins = chunk(0x50E, &bin_mesh);
+        plugins.extend(chunk(0x510, &chunk(0x01, &native)));
+        let mut file = geometry(&[], &plugins);
+        file[24..28].copy_from_slice(&0x01000001u32.to_le_bytes());
+
+        let (_, parsed) = Chunk::parse(&file).unwrap();
+        let geo = parsed.content.as_geometry().unwrap();
+        assert_eq!(geo.num_vertices, 4);
+        assert_eq!(geo.vertices()[3].as_arr(), [1.0, 1.0, 0.0]);
+        assert_eq!(geo.prelit.len(), 4);
+        assert!(geo.normals().is_empty());
+        let triangles: Vec<_> = geo.triangles.iter().map(|t| t.as_arr()).collect();
+        assert_eq!(triangles, vec![[0, 1, 2], [2, 1, 3]]);
+
+        // The split sizes and native data are written back as parsed
+        let data = parsed.to_bytes().unwrap();
+        let (_, reparsed) = Chunk::parse(&data).unwrap();
+        let mesh = reparsed.get_extension()[0].content.as_bin_mesh().unwrap();
+        assert_eq!(mesh.native_counts, [4]);
+        assert_eq!(&data[data.len() - native.len()..], native);
+        let geo = reparsed.content.as_geometry().unwrap();
+        assert_eq!(geo.vertices()[3].as_arr(), [1.0, 1.0, 0.0]);
+        assert_eq!(geo.triangles.len(), 2);
+        assert_eq!(reparsed.to_bytes().unwrap(), data);
+    }
+
+    #[test]
+    fn ps2_raster() {
+        let mut header = PLATFORM_PS2_FOURCC.to_le_bytes().to_vec();
+        header.extend(0x1102u32.to_le_bytes());
+        let mut info = Vec::new();
+        for word in [2u32, 1, 8, 0x2000] {
+            info.extend(word.to_le_bytes());
+        }
+        info.extend([0; 32]);
+        for word in [2u32, 1024, 0, 0] {
+            info.extend(word.to_le_bytes());
+        }
+        // Index 8 lands in the second block of 8 colors, which CSM1 swaps with the third
+        let mut data = vec![1, 8];
+        for i in 0..256u32 {
+            data.extend([i as u8, 0, 0, 0x80]);
+        }
+        let mut raster = chunk(0x01, &info);
+        raster.extend(chunk(0x01, &data));
+
+        let mut payload = chunk(0x01, &header);
+        payload.extend(chunk(0x02, b"tex\0"));
+        payload.extend(chunk(0x02, b"\0"));
+        payload.extend(chunk(0x01, &raster));
+        payload.extend(chunk(0x03, &[]));
+        let file = chunk(0x15, &payload);
+        let (_, chunk) = Chunk::parse(&file).unwrap();
+
+        let raster = chunk.content.as_raster_ps2().unwrap();
+        assert_eq!(raster.name, "tex");
+        assert!(matches!(
+            (raster.address_u, raster.address_v),
+            (
+                tex::TextureAddressingMode::TEXTUREADDRESSWRAP,
+                tex::TextureAddressingMode::TEXTUREADDRESSWRAP
+            )
+        ));
+        assert_eq!(raster.to_rgba().unwrap(), vec![1, 0, 0, 255, 16, 0, 0, 255]);
+        assert_eq!(chunk.get_children().len(), 1);
+        assert_eq!(chunk.to_bytes().unwrap(), file);
+    }
+
+    #[test]
+    fn ps2_swizzle_round_trip() {
+        for (depth, width, height) in [(8, 16, 16), (8, 64, 32), (4, 32, 16), (4, 256, 128)] {
+            let data: Vec<u8> = (0..width * height * depth as usize / 8)
+                .map(|i| (i * 7 + i / 5) as u8)
+                .collect();
+            let swizzled = ps2_swizzle(&data, width, height, depth).unwrap();
+            assert_ne!(swizzled, data);
+            assert_eq!(
+                ps2_unswizzle(&swizzled, width, height, depth).unwrap(),
+                data,
+                "{depth} bit {width}x{height}"
+            );
+        }
+        assert!(ps2_swizzle(&[0; 8], 2, 2, 16).is_none());
+    }
+}
@@ -0,0 +1,319 @@
+use nom::bytes::complete::take;
+use nom::multi::count;
+use nom::number::complete::le_u8;
+use nom::IResult;
+use nom_derive::Nom;
+
+use super::endian::Endian;
+use super::{Chunk, ChunkContent, RwVersion};
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RpSkin {
+    pub num_bones: u8,
+    pub max_weights: u8,
+    pub used_bones: Vec<u8>,
+    pub bone_indices: Vec<[u8; 4]>,
+    pub weights: Vec<[f32; 4]>,
+    pub inverse_matrices: Vec<[[f32; 4]; 4]>, // Column-major, one per bone
+    pub matrix_flags: Vec<[u32; 4]>, // Fourth row of each stored matrix, which isn't a float
+    pub split_data: Vec<u8>,         // Bone limit, mesh and RLE data of newer files, kept as is
+}
+
+impl RpSkin {
+    pub fn parse(
+        i: &[u8],
+        version: RwVersion,
+        endian: Endian,
+        num_vertices: u32,
+    ) -> IResult<&[u8], Self> {
+        let (i, num_bones) = le_u8(i)?;
+        let (i, num_used_bones) = le_u8(i)?;
+        let (i, max_weights) = le_u8(i)?;
+        let (i, _pad) = le_u8(i)?;
+        let old_format = Self::old_format(version);
+
+        let (i, used_bones) = take(num_used_bones)(i)?;
+        let (i, bone_indices) = count(array4(le_u8), num_vertices as usize)(i)?;
+        let (mut i, weights) = count(array4(|i| endian.f32(i)), num_vertices as usize)(i)?;
+
+        let mut inverse_matrices = Vec::with_capacity(num_bones as usize);
+        let mut matrix_flags = Vec::with_capacity(num_bones as usize);
+        for _ in 0..num_bones {
+            if old_format {
+                (i, _) = take(4usize)(i)?;
+            }
+            let mat;
+            (i, mat) = array4(array4(|i| endian.f32(i)))(i)?;
+            // The fourth row holds matrix flags and padding
+            let [[rx, ry, rz, rw], [ux, uy, uz, uw], [ax, ay, az, aw], [px, py, pz, pw]] = mat;
+            matrix_flags.push([rw, uw, aw, pw].map(f32::to_bits));
+            inverse_matrices.push([
+                [rx, ry, rz, 0.0],
+                [ux, uy, uz, 0.0],
+                [ax, ay, az, 0.0],
+                [px, py, pz, 1.0],
+            ]);
+        }
+        let (i, split_data) = if old_format {
+            (i, Vec::new())
+        } else {
+            (&[] as &[u8], i.to_vec())
+        };
+
+        Ok((
+            i,
+            Self {
+                num_bones,
+                max_weights,
+                used_bones: used_bones.to_vec(),
+                bone_indices,
+                weights,
+                inverse_matrices,
+                matrix_flags,
+                split_data,
+            },
+        ))
+    }
+
+    // Files from before 3.4.0.3 pad each matrix with 0xDEADDEAD and have no split data. They
+    // usually don't list used bones either, but newer files can leave them out too.
+    fn old_format(version: RwVersion) -> bool {
+        version < RwVersion::VC_PC
+    }
+
+    pub fn write(&self, version: RwVersion) -> Vec<u8> {
+        let old_format = Self::old_format(version);
+        let mut data = vec![
+            self.num_bones,
+            self.used_bones.len() as u8,
+            self.max_weights,
+            0,
+        ];
+        data.extend(&self.used_bones);
+        data.extend(self.bone_indices.iter().flatten());
+        data.extend(self.weights.iter().flatten().flat_map(|w| w.to_le_bytes()));
+        for (n, mat) in self.inverse_matrices.iter().enumerate() {
+            if old_format {
+                data.extend(0xDEADDEADu32.to_le_bytes());
+            }
+            let flags = self.matrix_flags.get(n).copied().unwrap_or_default();
+            for (column, w) in mat.iter().zip(flags) {
+                data.extend(column[..3].iter().flat_map(|f| f.to_le_bytes()));
+                data.extend(w.to_le_bytes());
+            }
+        }
+        if !old_format {
+            data.extend(&self.split_data);
+        }
+        data
+    }
+}
+
+pub(crate) const HANIM_POP: u32 = 0x01;
+pub(crate) const HANIM_PUSH: u32 = 0x02;
+
+#[derive(Clone, Copy, Debug, Nom)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HAnimNode {
+    pub id: i32,
+    pub index: u32,
+    pub flags: u32,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RpHAnim {
+    pub version: u32,
+    pub id: i32,
+    pub flags: u32,
+    pub key_frame_size: u32,
+    pub nodes: Vec<HAnimNode>, // Only present on the root frame of the hierarchy
+}
+
+impl RpHAnim {
+    pub fn parse(i: &[u8], _version: RwVersion, endian: Endian) -> IResult<&[u8], Self> {
+        let (i, version) = endian.u32(i)?;
+        let (i, id) = endian.i32(i)?;
+        let (mut i, num_nodes) = endian.u32(i)?;
+
+        let mut flags = 0;
+        let mut key_frame_size = 0;
+        let mut nodes = Vec::new();
+        if num_nodes > 0 {
+            (i, flags) = endian.u32(i)?;
+            (i, key_frame_size) = endian.u32(i)?;
+            (i, nodes) = count(|i| endian.parse(i), num_nodes as usize)(i)?;
+        }
+
+        Ok((
+            i,
+            Self {
+                version,
+                id,
+                flags,
+                key_frame_size,
+                nodes,
+            },
+        ))
+    }
+
+    pub fn write(&self, _version: RwVersion) -> Vec<u8> {
+        let mut data = self.version.to_le_bytes().to_vec();
+        data.extend(self.id.to_le_bytes());
+        data.extend((self.nodes.len() as u32).to_le_bytes());
+        if !self.nodes.is_empty() {
+            data.extend(self.flags.to_le_bytes());
+            data.extend(self.key_frame_size.to_le_bytes());
+            for node in &self.nodes {
+                data.extend(node.id.to_le_bytes());
+                data.extend(node.index.to_le_bytes());
+                data.extend(node.flags.to_le_bytes());
+            }
+        }
+        data
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Bone {
+    pub id: i32,
+    pub flags: u32,
+    pub parent: Option<usize>,
+    pub frame: Option<usize>, // Index into the clump's frame list
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Skeleton {
+    pub bones: Vec<Bone>,
+}
+
+impl Skeleton {
+    // Builds the bone tree from the HAnim plugins of a FrameList chunk
+    pub fn from_frame_list(frame_list: &Chunk) -> Option<Skeleton> {
+        let plugins: Vec<Option<&RpHAnim>> = frame_list
+            .get_children()
+            .iter()
+            .filter(|e| matches!(e.content, ChunkContent::Extension))
+            .map(|ext| {
+                ext.get_children().iter().find_map(|e| match &e.content {
+                    ChunkContent::HAnim(hanim) => Some(hanim),
+                    _ => None,
+                })
+            })
+            .collect();
+        let root = plugins.iter().flatten().find(|h| !h.nodes.is_empty())?;
+
+        let mut bones = Vec::with_capacity(root.nodes.len());
+        let mut stack = Vec::new();
+        let mut parent = None;
+        for (i, node) in root.nodes.iter().enumerate() {
+            bones.push(Bone {
+                id: node.id,
+                flags: node.flags,
+                parent,
+                frame: plugins
+                    .iter()
+                    .position(|h| h.is_some_and(|h| h.id == node.id)),
+            });
+            if node.flags & HANIM_PUSH != 0 {
+                stack.push(parent);
+            }
+            parent = Some(i);
+            if node.flags & HANIM_POP != 0 {
+                parent = stack.pop().flatten();
+            }
+        }
+
+        Some(Skeleton { bones })
+    }
+
+    pub fn find_bone(&self, id: i32) -> Option<&Bone> {
+        self.bones.iter().find(|b| b.id == id)
+    }
+}
+
+fn array4<'a, O, F>(mut f: F) -> impl FnMut(&'a [u8]) -> IResult<&'a [u8], [O; 4]>
+where
+    O: Copy,
+    F: FnMut(&'a [u8]) -> IResult<&'a [u8], O>,
+{
+    move |i| {
+        let (i, a) = f(i)?;
+        let (i, b) = f(i)?;
+        let (i, c) = f(i)?;
+        let (i, d) = f(i)?;
+        Ok((i, [a, b, c, d]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsf::fixtures::{chunk, floats, geometry};
+
+    #[test]
+    fn skin() {
+        let mut data = vec![1, 0, 1, 0];
+        data.extend([0, 0, 0, 0, 0, 0, 0, 0]);
+        data.extend(floats(&[1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0]));
+        data.extend(0xDEADDEADu32.to_le_bytes());
+        data.extend(floats(&[1.0, 0.0, 0.0]));
+        data.extend(3u32.to_le_bytes());
+        data.extend(floats(&[0.0, 1.0, 0.0, 0.0]));
+        data.extend(floats(&[0.0, 0.0, 1.0, 0.0, 0.0, -1.0, 0.0, 0.0]));
+        let file = geometry(&[[0.0; 3], [0.0, 1.0, 0.0]], &chunk(0x116, &data));
+
+        let (_, geo) = Chunk::parse(&file).unwrap();
+        let ChunkContent::Skin(skin) = &geo.get_extension()[0].content else {
+            panic!("not a skin");
+        };
+        assert_eq!(skin.bone_indices, vec![[0; 4]; 2]);
+        assert_eq!(skin.weights[1], [1.0, 0.0, 0.0, 0.0]);
+        assert_eq!(skin.inverse_matrices[0][3], [0.0, -1.0, 0.0, 1.0]);
+        assert_eq!(skin.matrix_flags[0][0], 3);
+        assert_eq!(geo.to_bytes().unwrap(), file);
+
+        // Newer files without used bones still have split data and no matrix padding
+        let mut data = vec![1, 0, 1, 0];
+        data.extend([0; 4]);
+        data.extend(floats(&[1.0, 0.0, 0.0, 0.0]));
+        data.extend(floats(&[1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0]));
+        data.extend(floats(&[0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0]));
+        data.extend([0; 12]);
+        let (rest, skin) = RpSkin::parse(&data, RwVersion::SA_PC, Endian::Little, 1).unwrap();
+        assert!(rest.is_empty());
+        assert!(skin.used_bones.is_empty());
+        assert_eq!(skin.split_data, [0; 12]);
+        assert_eq!(skin.write(RwVersion::SA_PC), data);
+    }
+
+    #[test]
+    fn skeleton() {
+        let words = |w: &[u32]| -> Vec<u8> { w.iter().flat_map(|w| w.to_le_bytes()).collect() };
+        let mut data = 3u32.to_le_bytes().to_vec();
+        for parent in [-1i32, 0, 0] {
+            data.extend(floats(&[
+                1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0,
+            ]));
+            data.extend(parent.to_le_bytes());
+            data.extend(0u32.to_le_bytes());
+        }
+        let mut payload = chunk(0x01, &data);
+        // Root bone with two leaf children
+        let root = words(&[0x100, 1, 3, 0, 36, 1, 0, 0, 2, 1, 3, 3, 2, 1]);
+        payload.extend(chunk(0x03, &chunk(0x11E, &root)));
+        payload.extend(chunk(0x03, &chunk(0x11E, &words(&[0x100, 3, 0]))));
+        payload.extend(chunk(0x03, &chunk(0x11E, &words(&[0x100, 2, 0]))));
+        let file = chunk(0x0E, &payload);
+
+        let (_, frame_list) = Chunk::parse(&file).unwrap();
+        let skeleton = Skeleton::from_frame_list(&frame_list).unwrap();
+        let parents: Vec<_> = skeleton.bones.iter().map(|b| b.parent).collect();
+        assert_eq!(parents, vec![None, Some(0), Some(0)]);
+        assert_eq!(skeleton.find_bone(2).unwrap().frame, Some(2));
+        assert_eq!(skeleton.find_bone(3).unwrap().frame, Some(1));
+    }
+}
@@ -0,0 +1,144 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use anyhow::{anyhow, bail, Result};
+
+use super::endian::Endian;
+use super::options::ParseOptions;
+use super::{Chunk, ChunkHeader, ChunkTypeId};
+
+const HEADER_SIZE: u64 = 12;
+
+// Header and position of a chunk in a stream. The payload is only read when asked for, so
+// single entries can be pulled out of large files without loading them.
+#[derive(Clone, Copy, Debug)]
+pub struct LazyChunk {
+    pub ty: ChunkTypeId,
+    pub header: ChunkHeader,
+    pub offset: u64, // Start of the payload in the stream
+    pub size: u32,
+}
+
+impl LazyChunk {
+    // Reads the header at the current position and leaves the reader at the start of the payload
+    pub fn read_header<R: Read + Seek>(r: &mut R) -> Result<Self> {
+        let mut header = [0; HEADER_SIZE as usize];
+        r.read_exact(&mut header)?;
+        let endian = Endian::detect(&header);
+        let word = |n: usize| {
+            let bytes = header[n * 4..n * 4 + 4].try_into().unwrap();
+            match endian {
+                Endian::Little => u32::from_le_bytes(bytes),
+                Endian::Big => u32::from_be_bytes(bytes),
+            }
+        };
+        let (_, chunk_header) = ChunkHeader::parse(&header[8..], endian)
+            .map_err(|e| anyhow!("Invalid chunk header: {}", e))?;
+        Ok(Self {
+            ty: ChunkTypeId(word(0)),
+            header: chunk_header,
+            offset: r.stream_position()?,
+            size: word(1),
+        })
+    }
+
+    pub fn end(&self) -> u64 {
+        self.offset + self.size as u64
+    }
+
+    // Moves the reader past the payload, to where the next sibling starts
+    pub fn skip<R: Seek>(&self, r: &mut R) -> Result<()> {
+        r.seek(SeekFrom::Start(self.end()))?;
+        Ok(())
+    }
+
+    pub fn read_data<R: Read + Seek>(&self, r: &mut R) -> Result<Vec<u8>> {
+        r.seek(SeekFrom::Start(self.offset))?;
+        read_len(r, self.size as u64)
+    }
+
+    // Headers of the chunks in the payload, only meaningful for sections made of children such as
+    // a Clump or Texture Dictionary, see ChunkTypeId::has_children
+    pub fn children<R: Read + Seek>(&self, r: &mut R) -> Result<Vec<LazyChunk>> {
+        let mut children = Vec::new();
+        let mut pos = self.offset;
+        while pos < self.end() {
+            r.seek(SeekFrom::Start(pos))?;
+            let child = Self::read_header(r)?;
+            if child.end() > self.end() {
+                bail!("Chunk {:#x} at {} overruns its parent", child.ty.0, pos);
+            }
+            pos = child.end();
+            children.push(child);
+        }
+        Ok(children)
+    }
+
+    pub fn parse<R: Read + Seek>(&self, r: &mut R) -> Result<Chunk> {
+        self.parse_with(r, &ParseOptions::default())
+    }
+
+    // Reads the whole chunk and parses it with its children
+    pub fn parse_with<R: Read + Seek>(&self, r: &mut R, opts: &ParseOptions) -> Result<Chunk> {
+        r.seek(SeekFrom::Start(self.offset - HEADER_SIZE))?;
+        let data = read_len(r, HEADER_SIZE + self.size as u64)?;
+        let (_, chunk) =
+            Chunk::parse_with(&data, opts).map_err(|e| anyhow!("Invalid chunk: {}", e))?;
+        Ok(chunk)
+    }
+}
+
+// Reads len bytes without allocating them up front, the size comes from the file and a corrupt
+// one shouldn't allocate gigabytes before the read fails
+fn read_len<R: Read>(r: &mut R, len: u64) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    r.take(len).read_to_end(&mut data)?;
+    if (data.len() as u64) < len {
+        bail!(
+            "Chunk ends {} bytes past the stream",
+            len - data.len() as u64
+        );
+    }
+    Ok(data)
+}
+
+impl Chunk {
+    pub fn read_from<R: Read + Seek>(r: &mut R) -> Result<LazyChunk> {
+        LazyChunk::read_header(r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsf::fixtures::{chunk, texture};
+
+    #[test]
+    fn lazy_chunks() {
+        let mut payload = chunk(0x01, &[2, 0, 0, 0]);
+        payload.extend(texture("a"));
+        payload.extend(texture("b"));
+        let mut file = chunk(0x16, &payload);
+        file.extend(chunk(0x02, b"next"));
+        let mut r = std::io::Cursor::new(file);
+
+        let txd = Chunk::read_from(&mut r).unwrap();
+        assert_eq!((txd.ty, txd.offset), (ChunkTypeId(0x16), 12));
+        let children = txd.children(&mut r).unwrap();
+        assert_eq!(children.len(), 3);
+        assert_eq!(children[0].read_data(&mut r).unwrap(), vec![2, 0, 0, 0]);
+        let texture = children[2].parse(&mut r).unwrap();
+        assert_eq!(texture.get_children()[0].content.as_string().unwrap(), "b");
+
+        txd.skip(&mut r).unwrap();
+        let next = Chunk::read_from(&mut r).unwrap();
+        assert_eq!(next.read_data(&mut r).unwrap(), b"next");
+
+        // A size far past the end of the stream fails instead of allocating it
+        let mut file = chunk(0x02, b"next");
+        file[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut r = std::io::Cursor::new(file);
+        let string = Chunk::read_from(&mut r).unwrap();
+        assert!(string.read_data(&mut r).is_err());
+        assert!(string.parse(&mut r).is_err());
+    }
+}
@@ -1,14 +1,26 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use anyhow::{bail, Result};
+use bitflags::bitflags;
 use nom::{
     bytes,
     multi::count,
-    number::complete::{le_i32, le_u16, le_u32, le_u8},
+    number::complete::{le_f32, le_u16, le_u32, le_u8},
     IResult,
 };
 use nom_derive::{Nom, Parse};
 use num_derive::FromPrimitive;
 use num_traits::cast::FromPrimitive;
 
+use super::dxt::{self, DxtFormat};
+use super::endian::Endian;
+use super::error::RwError;
+use super::matfx::MatFXEffect;
+use super::{Chunk, ChunkContent, RwVersion};
+
 #[derive(Clone, Copy, Debug, Nom)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RwRGBA {
     pub r: u8,
     pub g: u8,
@@ -28,6 +40,7 @@ impl RwRGBA {
 }
 
 #[derive(Clone, Copy, Debug, Nom)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RwTexCoords {
     pub u: f32,
     pub v: f32,
@@ -40,42 +53,133 @@ impl RwTexCoords {
 }
 
 #[derive(Clone, Copy, Debug, Nom)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RpSurfProp {
     pub ambient: f32,
     pub specular: f32,
     pub diffuse: f32,
 }
 
+pub(crate) const DEFAULT_SURFACE_PROP: RpSurfProp = RpSurfProp {
+    ambient: 1.0,
+    specular: 1.0,
+    diffuse: 1.0,
+};
+
+impl RpSurfProp {
+    pub fn to_bytes(self) -> Vec<u8> {
+        [self.ambient, self.specular, self.diffuse]
+            .iter()
+            .flat_map(|f| f.to_le_bytes())
+            .collect()
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RpMaterial {
+    pub flags: u32, // Unused by the engine, kept for round trips
     pub color: RwRGBA,
+    pub unused: u32,
     pub surface_prop: Option<RpSurfProp>,
 }
 impl RpMaterial {
-    pub fn parse(i: &[u8], version: u32) -> IResult<&[u8], Self> {
-        let (i, _flags) = le_u32(i)?;
+    pub fn parse(i: &[u8], version: RwVersion, endian: Endian) -> IResult<&[u8], Self> {
+        let (i, flags) = endian.u32(i)?;
         let (i, color) = RwRGBA::parse_le(i)?;
-        let (i, _unused) = le_u32(i)?;
-        let (mut i, _is_textured) = le_u32(i)?;
+        let (i, unused) = endian.u32(i)?;
+        let (mut i, _is_textured) = endian.u32(i)?;
 
         let mut surface_prop = None;
-        if version > 0x30400 {
+        if version > RwVersion(0x30400) {
             let s;
-            (i, s) = RpSurfProp::parse_le(i)?;
+            (i, s) = endian.parse::<RpSurfProp, _>(i)?;
             surface_prop = Some(s);
         }
 
         Ok((
             i,
             Self {
+                flags,
                 color,
+                unused,
                 surface_prop,
             },
         ))
     }
+
+    // Whether the material is textured depends on its children, so the caller passes it in
+    pub fn write(&self, version: RwVersion, textured: bool) -> Vec<u8> {
+        let mut data = self.flags.to_le_bytes().to_vec();
+        let c = self.color;
+        data.extend([c.r, c.g, c.b, c.a]);
+        data.extend(self.unused.to_le_bytes());
+        data.extend((textured as u32).to_le_bytes());
+        if version > RwVersion(0x30400) {
+            data.extend(self.surface_prop.unwrap_or(DEFAULT_SURFACE_PROP).to_bytes());
+        }
+        data
+    }
+}
+
+// Rockstar extension used by SA vehicle materials
+#[derive(Clone, Copy, Debug, Nom)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RpReflectionMaterial {
+    pub env_map_scale: [f32; 2],
+    pub env_map_offset: [f32; 2],
+    pub intensity: f32,
+    pub env_texture: u32, // Runtime pointer, always 0 in files
+}
+
+impl RpReflectionMaterial {
+    pub fn parse(i: &[u8], _version: RwVersion) -> IResult<&[u8], Self> {
+        Self::parse_le(i)
+    }
+
+    pub fn write(&self, _version: RwVersion) -> Vec<u8> {
+        let mut data: Vec<u8> = [self.env_map_scale, self.env_map_offset]
+            .iter()
+            .flatten()
+            .chain([&self.intensity])
+            .flat_map(|f| f.to_le_bytes())
+            .collect();
+        data.extend(self.env_texture.to_le_bytes());
+        data
+    }
+}
+
+// Rockstar extension used by SA vehicle materials
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RpSpecularMaterial {
+    pub level: f32,
+    pub texture: String,
+}
+
+impl RpSpecularMaterial {
+    pub fn parse(i: &[u8], _version: RwVersion) -> IResult<&[u8], Self> {
+        let (i, level) = le_f32(i)?;
+        let (i, texture) = bytes::complete::take(24usize)(i)?;
+        let texture = String::from_utf8_lossy(texture)
+            .trim_matches('\0')
+            .to_string();
+
+        Ok((i, Self { level, texture }))
+    }
+
+    pub fn write(&self, _version: RwVersion) -> Vec<u8> {
+        let mut data = self.level.to_le_bytes().to_vec();
+        let mut texture = [0u8; 24];
+        let len = self.texture.len().min(23);
+        texture[..len].copy_from_slice(&self.texture.as_bytes()[..len]);
+        data.extend(texture);
+        data
+    }
 }
 
 #[derive(Clone, Copy, Debug, Nom, FromPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum TextureFilteringMode {
     FILTERNAFILTERMODE,     // filtering is disabled
@@ -88,6 +192,7 @@ pub enum TextureFilteringMode {
 }
 
 #[derive(Clone, Copy, Debug, Nom, FromPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum TextureAddressingMode {
     TEXTUREADDRESSNATEXTUREADDRESS, // no tiling
@@ -97,65 +202,308 @@ pub enum TextureAddressingMode {
     TEXTUREADDRESSBORDER,
 }
 
+// Some tools write invalid modes, they're read as the NA modes and reported by the parser, see
+// invalid_modes
+fn filtering_mode(value: u8) -> TextureFilteringMode {
+    TextureFilteringMode::from_u8(value).unwrap_or(TextureFilteringMode::FILTERNAFILTERMODE)
+}
+
+fn addressing_mode(value: u8) -> TextureAddressingMode {
+    TextureAddressingMode::from_u8(value)
+        .unwrap_or(TextureAddressingMode::TEXTUREADDRESSNATEXTUREADDRESS)
+}
+
+// Warning for the flags word of a Texture or Texture Native if its modes aren't valid
+pub(crate) fn invalid_modes(flags: u32) -> Option<String> {
+    let filtering = flags as u8;
+    let addressing = [(flags >> 8) as u8 & 0xF, (flags >> 12) as u8 & 0xF];
+    if TextureFilteringMode::from_u8(filtering).is_some()
+        && addressing
+            .iter()
+            .all(|&a| TextureAddressingMode::from_u8(a).is_some())
+    {
+        return None;
+    }
+    Some(format!(
+        "Invalid texture filtering mode {} or addressing modes {:?}, using the NA modes",
+        filtering, addressing
+    ))
+}
+
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RpMaterialList {
-    vec: Vec<u32>,
+    pub indices: Vec<u32>,        // Index into materials for every material id
+    pub materials: Vec<Material>, // Filled in from the Material children
+}
+
+// Material with the names of its texture resolved
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Material {
+    pub color: RwRGBA,
+    pub surface_prop: Option<RpSurfProp>,
+    pub diffuse_tex: Option<String>,
+    pub mask_tex: Option<String>,
+    pub uv_anims: Vec<(u32, String)>, // (texture slot, animation name) from the UV Anim PLG
+    pub uv_transform: bool,           // Whether MatFX applies the animated UV matrix
 }
 
 impl RpMaterialList {
-    pub fn parse(i: &[u8], _version: u32) -> IResult<&[u8], Self> {
-        let (i, num_mats) = le_u32(i)?;
-        let (i, mat_vec) = count(le_i32, num_mats as usize)(i)?;
-        let mut vec = Vec::with_capacity(num_mats as usize);
+    pub fn parse(i: &[u8], _version: RwVersion, endian: Endian) -> IResult<&[u8], Self> {
+        let (i, num_mats) = endian.u32(i)?;
+        let (i, mat_vec) = count(|i| endian.i32(i), num_mats as usize)(i)?;
+        let mut indices = Vec::with_capacity(num_mats as usize);
         let mut mat_count = 0;
         for val in mat_vec {
             if val != -1 {
-                vec.push(val as u32);
+                indices.push(val as u32);
             } else {
-                vec.push(mat_count);
+                indices.push(mat_count);
                 mat_count += 1;
             }
         }
 
-        Ok((i, Self { vec }))
+        Ok((
+            i,
+            Self {
+                indices,
+                materials: Vec::new(),
+            },
+        ))
+    }
+
+    pub fn resolve(&mut self, children: &[Chunk]) {
+        self.materials = children
+            .iter()
+            .filter_map(|c| {
+                let material = c.content.as_material()?;
+                let mut names = c
+                    .get_children()
+                    .iter()
+                    .find(|t| matches!(t.content, ChunkContent::Texture(_)))
+                    .into_iter()
+                    .flat_map(|t| t.get_children())
+                    .filter_map(|n| n.content.as_string())
+                    .map(|n| Some(n.clone()).filter(|n| !n.is_empty()));
+                let ext = c.get_extension();
+                Some(Material {
+                    color: material.color,
+                    surface_prop: material.surface_prop,
+                    diffuse_tex: names.next().flatten(),
+                    mask_tex: names.next().flatten(),
+                    uv_anims: ext
+                        .iter()
+                        .find_map(|e| e.content.as_uv_anim())
+                        .map(|uv| uv.anims.clone())
+                        .unwrap_or_default(),
+                    uv_transform: ext
+                        .iter()
+                        .filter_map(|e| e.content.as_matfx())
+                        .flat_map(|fx| fx.effects())
+                        .any(|fx| matches!(fx, MatFXEffect::UvTransform)),
+                })
+            })
+            .collect();
+    }
+
+    // List where every material is stored once, in order
+    pub fn new(num_materials: u32) -> Self {
+        Self {
+            indices: (0..num_materials).collect(),
+            materials: Vec::new(),
+        }
+    }
+
+    // Materials are written inline the first time their index comes up and referenced after that
+    pub fn write(&self, _version: RwVersion) -> Vec<u8> {
+        let mut data = (self.indices.len() as u32).to_le_bytes().to_vec();
+        let mut mat_count = 0;
+        for &index in &self.indices {
+            if index == mat_count {
+                data.extend((-1i32).to_le_bytes());
+                mat_count += 1;
+            } else {
+                data.extend(index.to_le_bytes());
+            }
+        }
+        data
     }
 
     pub fn get_index(&self, material_id: u32) -> u32 {
-        *self.vec.get(material_id as usize).unwrap_or(&0)
+        *self.indices.get(material_id as usize).unwrap_or(&0)
+    }
+
+    // Resolved material used by triangles with the given id
+    pub fn get(&self, material_id: u32) -> Option<&Material> {
+        self.materials.get(self.get_index(material_id) as usize)
+    }
+
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
     }
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RpTexture {
     pub filtering: TextureFilteringMode,
-    pub addressing: [TextureAddressingMode; 2],
+    pub address_u: TextureAddressingMode,
+    pub address_v: TextureAddressingMode,
     pub has_mip: bool,
 }
 
 impl RpTexture {
-    pub fn parse(i: &[u8], _version: u32) -> IResult<&[u8], Self> {
-        let (i, filtering) = TextureFilteringMode::parse_le(i)?;
-        let (i, addr) = le_u8(i)?;
-        let addr_h = TextureAddressingMode::from_u8((addr & 0b11110000) >> 4).unwrap();
-        let addr_l = TextureAddressingMode::from_u8(addr & 0b00001111).unwrap();
-        let addressing = [addr_h, addr_l];
-        let (i, has_mip) = le_u16(i)?;
-        let has_mip = has_mip != 0;
+    pub fn parse(i: &[u8], _version: RwVersion, endian: Endian) -> IResult<&[u8], Self, RwError> {
+        // A single word, with the filtering mode in the lowest byte
+        let (i, flags) = endian.u32(i)?;
+        let filtering = filtering_mode(flags as u8);
+        let address_u = addressing_mode((flags >> 8) as u8 & 0xF);
+        let address_v = addressing_mode((flags >> 12) as u8 & 0xF);
+        let has_mip = flags >> 16 != 0;
 
         Ok((
             i,
             Self {
                 filtering,
-                addressing,
+                address_u,
+                address_v,
                 has_mip,
             },
         ))
     }
+
+    pub fn write(&self, _version: RwVersion) -> Vec<u8> {
+        let addressing = (self.address_v as u8) << 4 | self.address_u as u8;
+        let mut data = vec![self.filtering as u8, addressing];
+        data.extend((self.has_mip as u16).to_le_bytes());
+        data
+    }
+}
+
+// Struct of a TextureDictionary, which is followed by num_textures Texture Natives
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RwTextureDictionary {
+    pub num_textures: u16,
+    pub device_id: u16, // Platform the rasters were made for, 0 in older files
 }
 
-#[derive(Debug, Nom, FromPrimitive)]
+impl RwTextureDictionary {
+    pub const DEVICE_D3D8: u16 = 1;
+    pub const DEVICE_D3D9: u16 = 2;
+    pub const DEVICE_GAMECUBE: u16 = 3;
+    pub const DEVICE_PS2: u16 = 6;
+    pub const DEVICE_XBOX: u16 = 8;
+
+    pub fn parse(i: &[u8], _version: RwVersion, endian: Endian) -> IResult<&[u8], Self, RwError> {
+        let (i, (num_textures, device_id)) = endian.parse(i)?;
+        Ok((
+            i,
+            Self {
+                num_textures,
+                device_id,
+            },
+        ))
+    }
+
+    pub fn write(&self, _version: RwVersion) -> Vec<u8> {
+        let mut data = self.num_textures.to_le_bytes().to_vec();
+        data.extend(self.device_id.to_le_bytes());
+        data
+    }
+}
+
+// TextureDictionary chunk indexed by texture name, the way materials refer to textures. Names
+// are case-insensitive, and like RW, which adds textures to the front of a dictionary as they're
+// read, the last of several textures with the same name is found.
+#[derive(Clone, Debug)]
+pub struct TexDictionary<'a> {
+    pub dictionary: &'a RwTextureDictionary,
+    textures: Vec<&'a Chunk>,
+    names: HashMap<String, usize>,
+}
+
+impl<'a> TexDictionary<'a> {
+    pub fn new(txd: &'a Chunk) -> Option<Self> {
+        let textures: Vec<&Chunk> = txd.textures().collect();
+        let names = textures
+            .iter()
+            .enumerate()
+            .filter_map(|(i, t)| Some((t.texture_name()?.to_ascii_lowercase(), i)))
+            .collect();
+        Some(Self {
+            dictionary: txd.content.as_texture_dictionary()?,
+            textures,
+            names,
+        })
+    }
+
+    // Texture Native with the name
+    pub fn get(&self, name: &str) -> Option<&'a Chunk> {
+        let index = self.names.get(&name.to_ascii_lowercase())?;
+        Some(self.textures[*index])
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.names.contains_key(&name.to_ascii_lowercase())
+    }
+
+    // Decoded texture with the brightness of its mask texture as alpha, the way III-era textures
+    // get their transparency. Masks of a different size are scaled to fit, textures without a
+    // mask in the dictionary are returned as they are.
+    pub fn decode_masked(&self, name: &str) -> Option<RgbaImage> {
+        let texture = self.get(name)?;
+        let mut image = texture.decode_texture()?;
+        let Some(mask) = texture
+            .mask_name()
+            .and_then(|mask| self.get(mask))
+            .and_then(|mask| mask.decode_texture())
+        else {
+            return Some(image);
+        };
+        let (w, h) = (image.width as usize, image.height as usize);
+        let (mw, mh) = (mask.width as usize, mask.height as usize);
+        for y in 0..h {
+            for x in 0..w {
+                let m = ((y * mh / h) * mw + x * mw / w) * 4;
+                let [r, g, b] = [0, 1, 2].map(|c| mask.data[m + c] as u32);
+                image.data[(y * w + x) * 4 + 3] = ((r * 299 + g * 587 + b * 114) / 1000) as u8;
+            }
+        }
+        Some(image)
+    }
+
+    // Texture Natives in stream order, including unnamed ones
+    pub fn iter(&self) -> impl Iterator<Item = &'a Chunk> + '_ {
+        self.textures.iter().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.textures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.textures.is_empty()
+    }
+}
+
+// What Chunk::merge_texture_dictionaries does with a texture whose name is already taken
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureConflict {
+    KeepFirst,
+    KeepLast,
+    Fail,
+}
+
+// Pixel format of a raster without the extension flags
+#[derive(Clone, Copy, Debug, PartialEq, Eq, FromPrimitive)]
 #[repr(u32)]
-pub enum RasterFormat {
+pub enum RasterBaseFormat {
     FormatDefault = 0x0000,
     Format1555 = 0x0100, //(1 bit alpha, RGB 5 bits each; also used for DXT1 with alpha)
     Format565 = 0x0200,  //(5 bits red, 6 bits green, 5 bits blue; also used for DXT1 without alpha)
@@ -164,22 +512,234 @@ pub enum RasterFormat {
     Format8888 = 0x0500, //(RGBA 8 bits each)
     Format888 = 0x0600,  //(RGB 8 bits each, D3DFMT_X8R8G8B8)
     Format555 = 0x0A00,  //(RGB 5 bits each - rare, use 565 instead, D3DFMT_X1R5G5B5)
+}
+
+bitflags! {
+    // Format word of a raster, a RasterBaseFormat in bits 8-11 combined with these flags
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct RasterFormat: u32 {
+        const AUTO_MIPMAP = 0x1000; // RW generates the mipmaps
+        const PAL8 = 0x2000; // 256 palette colors
+        const PAL4 = 0x4000; // 16 palette colors
+        const MIPMAP = 0x8000; // Mipmaps included
+    }
+}
+
+impl RasterFormat {
+    const BASE_FORMAT_MASK: u32 = 0x0F00;
+
+    pub fn from_base(base: RasterBaseFormat) -> Self {
+        Self::from_bits_retain(base as u32)
+    }
+
+    // None for base formats that aren't known
+    pub fn base_format(self) -> Option<RasterBaseFormat> {
+        RasterBaseFormat::from_u32(self.bits() & Self::BASE_FORMAT_MASK)
+    }
+
+    pub fn is_palettized(self) -> bool {
+        self.intersects(Self::PAL8 | Self::PAL4)
+    }
+
+    pub fn has_mipmaps(self) -> bool {
+        self.contains(Self::MIPMAP)
+    }
+}
+
+pub const PLATFORM_D3D8: u32 = 8; // GTA3 and VC
+pub const PLATFORM_D3D9: u32 = 9; // SA
+
+// D3DFORMAT of SA rasters, Unknown in III and VC which don't store it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum D3dFormat {
+    Unknown,
+    R8G8B8,
+    A8R8G8B8,
+    X8R8G8B8,
+    R5G6B5,
+    X1R5G5B5,
+    A1R5G5B5,
+    A4R4G4B4,
+    A8,
+    P8,
+    L8,
+    A8L8,
+    Dxt1,
+    Dxt2,
+    Dxt3,
+    Dxt4,
+    Dxt5,
+    Other(u32),
+}
+
+impl D3dFormat {
+    pub fn from_u32(value: u32) -> Self {
+        match value {
+            0 => Self::Unknown,
+            20 => Self::R8G8B8,
+            21 => Self::A8R8G8B8,
+            22 => Self::X8R8G8B8,
+            23 => Self::R5G6B5,
+            24 => Self::X1R5G5B5,
+            25 => Self::A1R5G5B5,
+            26 => Self::A4R4G4B4,
+            28 => Self::A8,
+            41 => Self::P8,
+            50 => Self::L8,
+            51 => Self::A8L8,
+            _ => match &value.to_le_bytes() {
+                b"DXT1" => Self::Dxt1,
+                b"DXT2" => Self::Dxt2,
+                b"DXT3" => Self::Dxt3,
+                b"DXT4" => Self::Dxt4,
+                b"DXT5" => Self::Dxt5,
+                _ => Self::Other(value),
+            },
+        }
+    }
+
+    pub fn to_u32(self) -> u32 {
+        let fourcc = |fourcc: &[u8; 4]| u32::from_le_bytes(*fourcc);
+        match self {
+            Self::Unknown => 0,
+            Self::R8G8B8 => 20,
+            Self::A8R8G8B8 => 21,
+            Self::X8R8G8B8 => 22,
+            Self::R5G6B5 => 23,
+            Self::X1R5G5B5 => 24,
+            Self::A1R5G5B5 => 25,
+            Self::A4R4G4B4 => 26,
+            Self::A8 => 28,
+            Self::P8 => 41,
+            Self::L8 => 50,
+            Self::A8L8 => 51,
+            Self::Dxt1 => fourcc(b"DXT1"),
+            Self::Dxt2 => fourcc(b"DXT2"),
+            Self::Dxt3 => fourcc(b"DXT3"),
+            Self::Dxt4 => fourcc(b"DXT4"),
+            Self::Dxt5 => fourcc(b"DXT5"),
+            Self::Other(value) => value,
+        }
+    }
+
+    pub fn dxt(self) -> Option<DxtFormat> {
+        match self {
+            Self::Dxt1 => Some(DxtFormat::Dxt1),
+            Self::Dxt2 | Self::Dxt3 => Some(DxtFormat::Dxt3),
+            Self::Dxt4 | Self::Dxt5 => Some(DxtFormat::Dxt5),
+            _ => None,
+        }
+    }
+
+    pub fn bits_per_pixel(self) -> Option<u32> {
+        match self {
+            Self::Dxt1 => Some(4),
+            Self::Dxt2 | Self::Dxt3 | Self::Dxt4 | Self::Dxt5 | Self::A8 | Self::P8 | Self::L8 => {
+                Some(8)
+            }
+            Self::R5G6B5 | Self::X1R5G5B5 | Self::A1R5G5B5 | Self::A4R4G4B4 | Self::A8L8 => {
+                Some(16)
+            }
+            Self::R8G8B8 => Some(24),
+            Self::A8R8G8B8 | Self::X8R8G8B8 => Some(32),
+            Self::Unknown | Self::Other(_) => None,
+        }
+    }
 
-    FormatExtAutoMipmap = 0x1000, //(RW generates mipmaps, see special section below)
-    FormatExtPal8 = 0x2000,       //(2^8 = 256 palette colors)
-    FormatExtPal4 = 0x4000,       //(2^4 = 16 palette colors)
-    FormatExtMipmap = 0x8000,     //(mipmaps included)
+    // Bytes per 4x4 block of compressed formats
+    pub fn block_size(self) -> Option<usize> {
+        self.dxt().map(DxtFormat::block_size)
+    }
+
+    // Bytes a level of the given size takes, None for unknown formats
+    pub fn level_size(self, width: u32, height: u32) -> Option<usize> {
+        match self.dxt() {
+            Some(dxt) => Some(dxt.level_size(width, height)),
+            None => Some(
+                (width as usize * height as usize * self.bits_per_pixel()? as usize).div_ceil(8),
+            ),
+        }
+    }
+}
+
+// Problem found by RpRasterPC::validate, sizes are in bytes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RasterIssue {
+    UnknownFormat(u32),
+    Depth {
+        expected: u8,
+        found: u8,
+    },
+    Palette {
+        expected: usize,
+        found: usize,
+    },
+    MissingLevel(usize),
+    LevelSize {
+        level: usize,
+        expected: usize,
+        found: usize,
+    },
+    Truncated {
+        level: usize,
+        missing: usize,
+    },
+    TrailingData(usize),
+}
+
+impl fmt::Display for RasterIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownFormat(format) => write!(f, "Unknown raster format {:#x}", format),
+            Self::Depth { expected, found } => {
+                write!(f, "Depth is {} but the format has {}", found, expected)
+            }
+            Self::Palette { expected, found } => write!(
+                f,
+                "Palette of {} bytes doesn't fit the {} bytes of data",
+                expected, found
+            ),
+            Self::MissingLevel(level) => write!(f, "Level {} is missing", level),
+            Self::LevelSize {
+                level,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Level {} has {} bytes instead of {}",
+                level, found, expected
+            ),
+            Self::Truncated { level, missing } => {
+                write!(f, "Level {} is missing its last {} bytes", level, missing)
+            }
+            Self::TrailingData(len) => write!(f, "{} bytes after the last level", len),
+        }
+    }
+}
+
+// Formats RpRasterPC::encode can produce
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RasterEncoding {
+    Format8888,
+    Format565,
+    Pal8, // 256 colors picked by median cut
+    Dxt1, // 1 bit alpha
+    Dxt5,
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RpRasterPC {
     pub platform_id: u32,
     pub filtering: TextureFilteringMode,
-    pub addressing: [TextureAddressingMode; 2],
+    pub address_u: TextureAddressingMode,
+    pub address_v: TextureAddressingMode,
     pub name: String,
     pub mask_name: String,
-    pub raster_format: u32,
-    pub d3d_format: u32,
+    pub raster_format: RasterFormat,
+    pub d3d_format: D3dFormat,
     pub width: u16,
     pub height: u16,
     pub depth: u8,
@@ -194,14 +754,19 @@ pub struct RpRasterPC {
 }
 
 impl RpRasterPC {
-    pub fn parse(i: &[u8], version: u32) -> IResult<&[u8], Self> {
+    pub fn parse(i: &[u8], version: RwVersion) -> IResult<&[u8], Self, RwError> {
+        let (i, mut raster) = Self::parse_header(i, version)?;
+        raster.data = i.to_vec();
+        Ok((&[], raster))
+    }
+
+    // Everything but the data, which is left in the returned input
+    pub(crate) fn parse_header(i: &[u8], version: RwVersion) -> IResult<&[u8], Self, RwError> {
         let (i, platform_id) = le_u32(i)?;
         let (i, lump) = le_u32(i)?;
-        let filtering = TextureFilteringMode::from_u8((lump >> 24) as u8).unwrap();
-        let addr = ((lump >> 16) & 0b000000011111111) as u16;
-        let addr_h = TextureAddressingMode::from_u8(((addr & 0b11110000) >> 4) as u8).unwrap();
-        let addr_l = TextureAddressingMode::from_u8((addr & 0b00001111) as u8).unwrap();
-        let addressing = [addr_h, addr_l];
+        let filtering = filtering_mode(lump as u8);
+        let address_u = addressing_mode((lump >> 8) as u8 & 0xF);
+        let address_v = addressing_mode((lump >> 12) as u8 & 0xF);
         let (i, name) = bytes::complete::take(32usize)(i)?;
         let name = String::from_utf8_lossy(name).trim_matches('\0').to_string();
         let (i, mask_name) = bytes::complete::take(32usize)(i)?;
@@ -209,16 +774,17 @@ impl RpRasterPC {
             .trim_matches('\0')
             .to_string();
         let (i, raster_format) = le_u32(i)?;
+        let raster_format = RasterFormat::from_bits_retain(raster_format);
 
         let mut has_alpha = false;
-        let mut d3d_format = 0;
+        let mut d3d_format = D3dFormat::Unknown;
         let (i, temp0) = le_u32(i)?;
-        if version < 0x36003 {
+        if version < RwVersion::SA_PC {
             // III & VC
             has_alpha = temp0 > 0;
         } else {
             // SA
-            d3d_format = temp0;
+            d3d_format = D3dFormat::from_u32(temp0);
         }
 
         let (i, width) = le_u16(i)?;
@@ -232,25 +798,24 @@ impl RpRasterPC {
         let mut auto_mipmaps = false;
         let mut compressed = false;
         let (i, temp0) = le_u8(i)?;
-        if version < 0x36003 {
+        if version < RwVersion::SA_PC {
             // III & VC
             compression = temp0;
         } else {
             // SA
-            has_alpha = ((temp0 >> 7) & 1) > 0;
-            cube_texture = ((temp0 >> 6) & 1) > 0;
-            auto_mipmaps = ((temp0 >> 5) & 1) > 0;
-            compressed = ((temp0 >> 4) & 1) > 0;
+            has_alpha = temp0 & 1 > 0;
+            cube_texture = temp0 & 2 > 0;
+            auto_mipmaps = temp0 & 4 > 0;
+            compressed = temp0 & 8 > 0;
         }
 
-        let data = i.to_vec();
-
         Ok((
-            &[],
+            i,
             RpRasterPC {
                 platform_id,
                 filtering,
-                addressing,
+                address_u,
+                address_v,
                 name,
                 mask_name,
                 raster_format,
@@ -265,11 +830,1240 @@ impl RpRasterPC {
                 cube_texture,
                 auto_mipmaps,
                 compressed,
-                data,
+                data: Vec::new(),
+            },
+        ))
+    }
+
+    // Palette in front of the levels, RGBA per entry. PAL4 palettes are usually padded to 32
+    // entries like librw writes them, so the other size is only used if the level doesn't fit.
+    pub fn palette(&self) -> Option<&[u8]> {
+        self.palette_in(&self.data)
+    }
+
+    // palette and mip_levels of the raster with the data given separately
+    pub(crate) fn palette_in<'d>(&self, data: &'d [u8]) -> Option<&'d [u8]> {
+        let entries: &[usize] = if self.raster_format.contains(RasterFormat::PAL8) {
+            &[256]
+        } else if self.raster_format.contains(RasterFormat::PAL4) {
+            &[32, 16]
+        } else {
+            return None;
+        };
+        let pixels = self.width as usize * self.height as usize;
+        entries.iter().map(|n| n * 4).find_map(|size| {
+            let level = data.get(size..size + 4)?;
+            let level = u32::from_le_bytes(level.try_into().unwrap()) as usize;
+            (level == pixels || level == pixels.div_ceil(2)).then(|| &data[..size])
+        })
+    }
+
+    // Block compression, from the FourCC in SA or the compression field in III and VC
+    pub fn dxt_format(&self) -> Option<DxtFormat> {
+        self.d3d_format.dxt().or(match self.compression {
+            1 => Some(DxtFormat::Dxt1),
+            2 | 3 => Some(DxtFormat::Dxt3),
+            4 | 5 => Some(DxtFormat::Dxt5),
+            _ => None,
+        })
+    }
+
+    // Bytes a level of the given size takes, DXT levels are padded to whole blocks
+    pub fn level_size(&self, width: u32, height: u32) -> usize {
+        match self.dxt_format() {
+            Some(format) => format.level_size(width, height),
+            None => (width as usize * height as usize * self.depth as usize).div_ceil(8),
+        }
+    }
+
+    // (width, height, data) of each level, largest first. Levels are stored after the palette
+    // with their size in front, which is trusted over level_size as some tools pad them.
+    pub fn mip_levels(&self) -> impl Iterator<Item = (u32, u32, &[u8])> {
+        self.mip_levels_in(&self.data)
+    }
+
+    pub(crate) fn mip_levels_in<'d>(
+        &self,
+        data: &'d [u8],
+    ) -> impl Iterator<Item = (u32, u32, &'d [u8])> + 'd {
+        let mut i = &data[self.palette_in(data).map_or(0, |p| p.len())..];
+        let (width, height) = (self.width as u32, self.height as u32);
+        (0..self.num_levels.max(1) as u32).map_while(move |level| {
+            let size = u32::from_le_bytes(i.get(..4)?.try_into().unwrap()) as usize;
+            let data = i.get(4..4 + size)?;
+            i = &i[4 + size..];
+            let shift = |size: u32| size.checked_shr(level).unwrap_or(0).max(1);
+            Some((shift(width), shift(height), data))
+        })
+    }
+
+    // .dds file with the block data of a DXT compressed raster as it is stored, None for other
+    // formats
+    pub fn to_dds(&self) -> Option<Vec<u8>> {
+        let dxt = self.dxt_format()?;
+        let fourcc = match self.d3d_format.dxt() {
+            Some(_) => self.d3d_format,
+            None => match dxt {
+                DxtFormat::Dxt1 => D3dFormat::Dxt1,
+                DxtFormat::Dxt3 => D3dFormat::Dxt3,
+                DxtFormat::Dxt5 => D3dFormat::Dxt5,
             },
+        };
+        let levels: Vec<&[u8]> = self.mip_levels().map(|(_, _, data)| data).collect();
+        let mut dds = dxt::dds_header(
+            fourcc.to_u32(),
+            self.width as u32,
+            self.height as u32,
+            levels.len() as u32,
+            levels.first()?.len() as u32,
+        );
+        for level in levels {
+            dds.extend(level);
+        }
+        Some(dds)
+    }
+
+    // .ktx2 file of the raster, DXT levels are kept as BC blocks and other formats are expanded
+    // to RGBA. None for formats that can't be decoded.
+    #[cfg(feature = "ktx2")]
+    pub fn to_ktx2(&self) -> Option<Vec<u8>> {
+        use super::ktx2::{self, Ktx2Format};
+
+        let (format, levels) = match self.dxt_format() {
+            Some(dxt) => (
+                Ktx2Format::Bc(dxt),
+                self.mip_levels()
+                    .map(|(_, _, data)| data.to_vec())
+                    .collect(),
+            ),
+            None => {
+                let levels: Option<Vec<_>> = (0..self.mip_levels().count())
+                    .map(|level| Some(self.decode_level(level)?.data))
+                    .collect();
+                (Ktx2Format::Rgba8, levels?)
+            }
+        };
+        let levels: Vec<&[u8]> = levels.iter().map(Vec::as_slice).collect();
+        if levels.is_empty() {
+            return None;
+        }
+        Some(ktx2::write(
+            format,
+            self.width as u32,
+            self.height as u32,
+            &levels,
         ))
     }
+
+    // Checks that the data holds the palette and num_levels levels of the sizes the format and
+    // dimensions call for, and nothing after them
+    pub fn validate(&self) -> Result<(), Vec<RasterIssue>> {
+        let mut issues = Vec::new();
+        let dxt = self.dxt_format();
+        let expected_depth = match (dxt, self.raster_format.base_format()) {
+            _ if self.raster_format.contains(RasterFormat::PAL8) => Some(8),
+            _ if self.raster_format.contains(RasterFormat::PAL4) => Some(4),
+            (Some(_), _) => None, // Varies between tools
+            (None, Some(RasterBaseFormat::FormatLum8)) => Some(8),
+            (None, Some(RasterBaseFormat::Format8888 | RasterBaseFormat::Format888)) => Some(32),
+            (None, Some(RasterBaseFormat::FormatDefault)) | (None, None) => {
+                issues.push(RasterIssue::UnknownFormat(self.raster_format.bits()));
+                return Err(issues);
+            }
+            (None, Some(_)) => Some(16),
+        };
+        if let Some(depth) = expected_depth.filter(|&d| d != self.depth) {
+            issues.push(RasterIssue::Depth {
+                expected: depth,
+                found: self.depth,
+            });
+            return Err(issues);
+        }
+
+        let mut i = &self.data[..];
+        if self.raster_format.is_palettized() {
+            // palette() can't tell the PAL4 size apart when the first level is broken too
+            let size = match self.palette() {
+                Some(palette) => palette.len(),
+                None if self.depth == 8 => 256 * 4,
+                None => 32 * 4,
+            };
+            if size > i.len() {
+                issues.push(RasterIssue::Palette {
+                    expected: size,
+                    found: i.len(),
+                });
+                return Err(issues);
+            }
+            i = &i[size..];
+        }
+        for level in 0..self.num_levels.max(1) as usize {
+            // Corrupt rasters may claim more levels than a u32 can be shifted by
+            let width = (self.width as u32)
+                .checked_shr(level as u32)
+                .unwrap_or(0)
+                .max(1);
+            let height = (self.height as u32)
+                .checked_shr(level as u32)
+                .unwrap_or(0)
+                .max(1);
+            let Some(size) = i.get(..4) else {
+                issues.push(RasterIssue::MissingLevel(level));
+                return Err(issues);
+            };
+            let size = u32::from_le_bytes(size.try_into().unwrap()) as usize;
+            i = &i[4..];
+            let expected = self.level_size(width, height);
+            // PAL4 indices can also be stored one per byte
+            let unpacked = self.depth == 4 && size == (width * height) as usize;
+            if size != expected && !unpacked {
+                issues.push(RasterIssue::LevelSize {
+                    level,
+                    expected,
+                    found: size,
+                });
+            }
+            if size > i.len() {
+                issues.push(RasterIssue::Truncated {
+                    level,
+                    missing: size - i.len(),
+                });
+                return Err(issues);
+            }
+            i = &i[size..];
+        }
+        if !i.is_empty() {
+            issues.push(RasterIssue::TrailingData(i.len()));
+        }
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(issues)
+        }
+    }
+
+    // Base level expanded to RGBA
+    pub fn decode(&self) -> Option<RgbaImage> {
+        self.decode_level(0)
+    }
+
+    // Alpha of the decoded base level, has_alpha should be set unless it's opaque
+    pub fn analyze_alpha(&self) -> Option<AlphaUsage> {
+        Some(self.decode()?.alpha_usage())
+    }
+
+    // Level expanded to RGBA, None for formats that can't be decoded
+    pub fn decode_level(&self, level: usize) -> Option<RgbaImage> {
+        let (width, height, data) = self.mip_levels().nth(level)?;
+        let pixels = (width * height) as usize;
+
+        let rgba = if let Some(format) = self.dxt_format() {
+            dxt::decode(format, width, height, data)?
+        } else if !self.raster_format.is_palettized() {
+            decode_direct(self.raster_format.base_format()?, data, pixels)?
+        } else {
+            let palette = self.palette()?;
+            // PAL4 indices are stored one per byte or packed two per byte, low nibble first
+            let indices: Vec<usize> = if data.len() >= pixels {
+                data[..pixels].iter().map(|&i| i as usize).collect()
+            } else {
+                data.iter()
+                    .flat_map(|&b| [b & 0xF, b >> 4])
+                    .take(pixels)
+                    .map(|i| i as usize)
+                    .collect()
+            };
+            let mut rgba = Vec::with_capacity(pixels * 4);
+            for index in indices {
+                rgba.extend_from_slice(palette.get(index * 4..index * 4 + 4)?);
+            }
+            rgba
+        };
+        Some(RgbaImage {
+            width,
+            height,
+            data: rgba,
+        })
+    }
+
+    // Raster for the image in the given format, D3D9 for SA and D3D8 for earlier versions.
+    // Mipmaps are generated down to 1x1 by averaging.
+    pub fn encode(
+        name: &str,
+        image: &RgbaImage,
+        encoding: RasterEncoding,
+        mipmaps: bool,
+        version: RwVersion,
+    ) -> Self {
+        let mut levels = vec![image.clone()];
+        while mipmaps && levels.last().is_some_and(|l| l.width > 1 || l.height > 1) {
+            let level = levels.last().unwrap().downsample();
+            levels.push(level);
+        }
+        let sa = version >= RwVersion::SA_PC;
+        let has_alpha =
+            encoding != RasterEncoding::Format565 && image.alpha_usage() != AlphaUsage::Opaque;
+        let (format, depth, d3d_format) = match encoding {
+            RasterEncoding::Format8888 => (RasterBaseFormat::Format8888, 32, D3dFormat::A8R8G8B8),
+            RasterEncoding::Format565 => (RasterBaseFormat::Format565, 16, D3dFormat::R5G6B5),
+            RasterEncoding::Pal8 => (RasterBaseFormat::Format8888, 8, D3dFormat::P8),
+            RasterEncoding::Dxt1 if has_alpha => {
+                (RasterBaseFormat::Format1555, 16, D3dFormat::Dxt1)
+            }
+            RasterEncoding::Dxt1 => (RasterBaseFormat::Format565, 16, D3dFormat::Dxt1),
+            RasterEncoding::Dxt5 => (RasterBaseFormat::Format4444, 16, D3dFormat::Dxt5),
+        };
+        let dxt = d3d_format.dxt();
+        let mut raster_format = RasterFormat::from_base(format);
+        if levels.len() > 1 {
+            raster_format |= RasterFormat::MIPMAP;
+        }
+
+        let mut data = Vec::new();
+        let palette = (encoding == RasterEncoding::Pal8).then(|| median_cut(&image.data, 256));
+        if let Some(palette) = &palette {
+            raster_format |= RasterFormat::PAL8;
+            data.extend(palette.iter().flatten());
+            data.resize(256 * 4, 0);
+        }
+        for level in &levels {
+            let pixels = level.data.chunks_exact(4);
+            let bytes: Vec<u8> = match (encoding, dxt, &palette) {
+                (_, Some(dxt), _) => dxt::encode(dxt, level.width, level.height, &level.data),
+                (_, _, Some(palette)) => pixels
+                    .map(|p| nearest(palette, [p[0], p[1], p[2], p[3]]))
+                    .collect(),
+                (RasterEncoding::Format565, _, _) => pixels
+                    .flat_map(|p| {
+                        let c =
+                            (p[0] as u16 >> 3) << 11 | (p[1] as u16 >> 2) << 5 | p[2] as u16 >> 3;
+                        c.to_le_bytes()
+                    })
+                    .collect(),
+                _ => pixels.flat_map(|p| [p[2], p[1], p[0], p[3]]).collect(),
+            };
+            data.extend((bytes.len() as u32).to_le_bytes());
+            data.extend(bytes);
+        }
+
+        let compression = match dxt {
+            Some(DxtFormat::Dxt1) if !sa => 1,
+            Some(_) if !sa => 5,
+            _ => 0,
+        };
+        RpRasterPC {
+            platform_id: if sa { PLATFORM_D3D9 } else { PLATFORM_D3D8 },
+            filtering: if levels.len() > 1 {
+                TextureFilteringMode::FILTERLINEARMIPLINEAR
+            } else {
+                TextureFilteringMode::FILTERLINEAR
+            },
+            address_u: TextureAddressingMode::TEXTUREADDRESSWRAP,
+            address_v: TextureAddressingMode::TEXTUREADDRESSWRAP,
+            name: name.to_string(),
+            mask_name: String::new(),
+            raster_format,
+            d3d_format: if sa { d3d_format } else { D3dFormat::Unknown },
+            width: image.width as u16,
+            height: image.height as u16,
+            depth,
+            num_levels: levels.len() as u8,
+            raster_type: 4, // Texture
+            compression,
+            has_alpha,
+            cube_texture: false,
+            auto_mipmaps: false,
+            compressed: sa && dxt.is_some(),
+            data,
+        }
+    }
+
+    pub fn write(&self, version: RwVersion) -> Vec<u8> {
+        let mut data = self.platform_id.to_le_bytes().to_vec();
+        let addressing = (self.address_u as u32) << 8 | (self.address_v as u32) << 12;
+        data.extend((self.filtering as u32 | addressing).to_le_bytes());
+        for name in [&self.name, &self.mask_name] {
+            let mut name = name.as_bytes().to_vec();
+            name.resize(32, 0);
+            data.extend(name);
+        }
+        data.extend(self.raster_format.bits().to_le_bytes());
+        if version < RwVersion::SA_PC {
+            data.extend((self.has_alpha as u32).to_le_bytes());
+        } else {
+            data.extend(self.d3d_format.to_u32().to_le_bytes());
+        }
+        data.extend(self.width.to_le_bytes());
+        data.extend(self.height.to_le_bytes());
+        data.extend([self.depth, self.num_levels, self.raster_type]);
+        if version < RwVersion::SA_PC {
+            data.push(self.compression);
+        } else {
+            data.push(
+                self.has_alpha as u8
+                    | (self.cube_texture as u8) << 1
+                    | (self.auto_mipmaps as u8) << 2
+                    | (self.compressed as u8) << 3,
+            );
+        }
+        data.extend(&self.data);
+        data
+    }
+
+    // Moves the format between the D3D8 layout of III and VC, with a compression number, and
+    // the D3D9 layout of SA, with a D3D format. The pixel data is the same in both.
+    pub fn convert(&self, version: RwVersion) -> Result<Self> {
+        let mut raster = self.clone();
+        let sa = version >= RwVersion::SA_PC;
+        if sa == (self.platform_id == PLATFORM_D3D9) {
+            return Ok(raster);
+        }
+        if sa {
+            raster.platform_id = PLATFORM_D3D9;
+            raster.d3d_format = match (self.compression, self.raster_format.base_format()) {
+                (1, _) => D3dFormat::Dxt1,
+                (2, _) => D3dFormat::Dxt2,
+                (3, _) => D3dFormat::Dxt3,
+                (4, _) => D3dFormat::Dxt4,
+                (5, _) => D3dFormat::Dxt5,
+                (0, _) if self.raster_format.is_palettized() => D3dFormat::P8,
+                (0, Some(RasterBaseFormat::Format8888)) => D3dFormat::A8R8G8B8,
+                (0, Some(RasterBaseFormat::Format888)) => D3dFormat::X8R8G8B8,
+                (0, Some(RasterBaseFormat::Format565)) => D3dFormat::R5G6B5,
+                (0, Some(RasterBaseFormat::Format555)) => D3dFormat::X1R5G5B5,
+                (0, Some(RasterBaseFormat::Format1555)) => D3dFormat::A1R5G5B5,
+                (0, Some(RasterBaseFormat::Format4444)) => D3dFormat::A4R4G4B4,
+                (0, Some(RasterBaseFormat::FormatLum8)) => D3dFormat::L8,
+                _ => bail!("Raster {} has no D3D9 format", self.name),
+            };
+            raster.compressed = self.compression != 0;
+            raster.compression = 0;
+        } else {
+            raster.platform_id = PLATFORM_D3D8;
+            raster.compression = match self.d3d_format {
+                D3dFormat::Dxt1 => 1,
+                D3dFormat::Dxt2 => 2,
+                D3dFormat::Dxt3 => 3,
+                D3dFormat::Dxt4 => 4,
+                D3dFormat::Dxt5 => 5,
+                // Other formats are described by the raster format alone
+                _ if self
+                    .raster_format
+                    .base_format()
+                    .is_some_and(|f| f != RasterBaseFormat::FormatDefault) =>
+                {
+                    0
+                }
+                format => bail!("Raster {} uses {format:?}, which D3D8 lacks", self.name),
+            };
+            raster.d3d_format = D3dFormat::Unknown;
+            raster.cube_texture = false;
+            raster.auto_mipmaps = false;
+            raster.compressed = false;
+        }
+        Ok(raster)
+    }
+}
+
+// Unpalettised D3D formats, 16 and 32 bit ones are stored as little endian ARGB words
+fn decode_direct(format: RasterBaseFormat, data: &[u8], pixels: usize) -> Option<Vec<u8>> {
+    let expand = |v: u16, bits: u32| {
+        let max = (1 << bits) - 1;
+        ((v as u32 & max) * 255 / max) as u8
+    };
+    let rgba = match format {
+        RasterBaseFormat::Format8888 | RasterBaseFormat::Format888 => {
+            let opaque = matches!(format, RasterBaseFormat::Format888);
+            data.get(..pixels * 4)?
+                .chunks_exact(4)
+                .flat_map(|p| [p[2], p[1], p[0], if opaque { 255 } else { p[3] }])
+                .collect()
+        }
+        RasterBaseFormat::FormatLum8 => data
+            .get(..pixels)?
+            .iter()
+            .flat_map(|&l| [l, l, l, 255])
+            .collect(),
+        RasterBaseFormat::Format1555
+        | RasterBaseFormat::Format565
+        | RasterBaseFormat::Format4444
+        | RasterBaseFormat::Format555 => data
+            .get(..pixels * 2)?
+            .chunks_exact(2)
+            .flat_map(|p| {
+                let v = u16::from_le_bytes([p[0], p[1]]);
+                match format {
+                    RasterBaseFormat::Format565 => {
+                        [expand(v >> 11, 5), expand(v >> 5, 6), expand(v, 5), 255]
+                    }
+                    RasterBaseFormat::Format4444 => [
+                        expand(v >> 8, 4),
+                        expand(v >> 4, 4),
+                        expand(v, 4),
+                        expand(v >> 12, 4),
+                    ],
+                    RasterBaseFormat::Format1555 => [
+                        expand(v >> 10, 5),
+                        expand(v >> 5, 5),
+                        expand(v, 5),
+                        expand(v >> 15, 1),
+                    ],
+                    _ => [expand(v >> 10, 5), expand(v >> 5, 5), expand(v, 5), 255],
+                }
+            })
+            .collect(),
+        _ => return None,
+    };
+    Some(rgba)
+}
+
+// Palette of up to colors entries, splitting the box with the widest channel at its median
+fn median_cut(rgba: &[u8], colors: usize) -> Vec<[u8; 4]> {
+    let pixels: Vec<[u8; 4]> = rgba
+        .chunks_exact(4)
+        .map(|p| [p[0], p[1], p[2], p[3]])
+        .collect();
+    let range = |b: &[[u8; 4]], c: usize| {
+        let (min, max) = b
+            .iter()
+            .fold((255, 0), |(lo, hi), p| (p[c].min(lo), p[c].max(hi)));
+        max.saturating_sub(min)
+    };
+    let mut boxes = vec![pixels];
+    while boxes.len() < colors {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.len() > 1)
+            .map(|(i, b)| (i, (0..4).map(|c| (range(b, c), c)).max().unwrap()))
+            .max_by_key(|&(_, (r, _))| r);
+        let Some((i, (r, channel))) = widest else {
+            break;
+        };
+        if r == 0 {
+            break;
+        }
+        let mut b = boxes.swap_remove(i);
+        b.sort_unstable_by_key(|p| p[channel]);
+        let upper = b.split_off(b.len() / 2);
+        boxes.push(b);
+        boxes.push(upper);
+    }
+    boxes
+        .iter()
+        .filter(|b| !b.is_empty())
+        .map(|b| {
+            let mut sum = [0usize; 4];
+            for p in b {
+                for c in 0..4 {
+                    sum[c] += p[c] as usize;
+                }
+            }
+            sum.map(|s| (s / b.len()) as u8)
+        })
+        .collect()
+}
+
+fn nearest(palette: &[[u8; 4]], color: [u8; 4]) -> u8 {
+    let distance = |p: &[u8; 4]| -> u32 {
+        (0..4)
+            .map(|c| (p[c] as i32 - color[c] as i32).pow(2) as u32)
+            .sum()
+    };
+    (0..palette.len())
+        .min_by_key(|&i| distance(&palette[i]))
+        .unwrap_or(0) as u8
 }
 
 #[derive(Clone, Copy, Debug, Nom)]
 pub struct RpRasterPalette<const N: usize>(pub [RwRGBA; N]);
+
+// How a raster uses alpha, deciding between 565, 1555 and 8888 or DXT1 and DXT5
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlphaUsage {
+    Opaque,
+    OneBit, // Only fully transparent and fully opaque pixels
+    Gradient,
+}
+
+// Decoded raster level, 4 bytes per pixel in RGBA order
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl RgbaImage {
+    // Hash of the decoded pixels, equal for rasters that look the same regardless of how they're stored
+    pub fn checksum(&self) -> u32 {
+        let mut bytes = self.width.to_le_bytes().to_vec();
+        bytes.extend(self.height.to_le_bytes());
+        bytes.extend(&self.data);
+        crate::img::crc32(&bytes)
+    }
+
+    // Root mean square error per channel in 0..=255, None if the sizes differ
+    pub fn rmse(&self, other: &RgbaImage) -> Option<[f64; 4]> {
+        if (self.width, self.height) != (other.width, other.height)
+            || self.data.len() != other.data.len()
+        {
+            return None;
+        }
+        let mut sums = [0f64; 4];
+        for (a, b) in self.data.chunks_exact(4).zip(other.data.chunks_exact(4)) {
+            for c in 0..4 {
+                let d = a[c] as f64 - b[c] as f64;
+                sums[c] += d * d;
+            }
+        }
+        let pixels = (self.data.len() / 4).max(1) as f64;
+        Some(sums.map(|s| (s / pixels).sqrt()))
+    }
+
+    pub fn alpha_usage(&self) -> AlphaUsage {
+        let mut usage = AlphaUsage::Opaque;
+        for a in self.data.iter().skip(3).step_by(4) {
+            match a {
+                255 => {}
+                0 => usage = AlphaUsage::OneBit,
+                _ => return AlphaUsage::Gradient,
+            }
+        }
+        usage
+    }
+
+    // Next mip level, each pixel averaging up to 2x2 pixels of this one
+    pub fn downsample(&self) -> RgbaImage {
+        let (w, h) = (self.width as usize, self.height as usize);
+        let (width, height) = ((w / 2).max(1), (h / 2).max(1));
+        let mut data = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            for x in 0..width {
+                let xs = (x * 2..(x * 2 + 2).min(w)).collect::<Vec<_>>();
+                let ys = (y * 2..(y * 2 + 2).min(h)).collect::<Vec<_>>();
+                for c in 0..4 {
+                    let mut sum = 0;
+                    for &sy in &ys {
+                        for &sx in &xs {
+                            sum += self.data[(sy * w + sx) * 4 + c] as usize;
+                        }
+                    }
+                    data.push((sum / (xs.len() * ys.len())) as u8);
+                }
+            }
+        }
+        RgbaImage {
+            width: width as u32,
+            height: height as u32,
+            data,
+        }
+    }
+
+    // Surrounded by a pixel of color on every side, so sampling the result clamped to its edge
+    // gives color outside the original like TEXTUREADDRESSBORDER does. UVs have to be moved onto
+    // the inner pixels.
+    pub fn with_border(&self, color: [u8; 4]) -> RgbaImage {
+        let (w, h) = (self.width as usize, self.height as usize);
+        let mut data = color.repeat(w + 2);
+        for row in self.data.chunks_exact(w * 4).take(h) {
+            data.extend(color);
+            data.extend(row);
+            data.extend(color);
+        }
+        data.extend(color.repeat(w + 2));
+        RgbaImage {
+            width: self.width + 2,
+            height: self.height + 2,
+            data,
+        }
+    }
+
+    #[cfg(feature = "image")]
+    pub fn to_image(&self) -> image::RgbaImage {
+        image::RgbaImage::from_raw(self.width, self.height, self.data.clone())
+            .expect("RGBA data matches the image size")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsf::fixtures::{chunk, floats, texture};
+    use crate::bsf::options::ParseOptions;
+    use crate::bsf::{builder, endian, ChunkHeader, ChunkType, ChunkTypeId, RwBuild};
+    use crate::scene::{IntoSceneGraph, SceneAddressMode, SceneSampler};
+
+    #[test]
+    fn texture_dictionary() {
+        let mut payload = chunk(0x01, &[2, 0, 1, 0]);
+        // OpenGL rasters are kept as sections
+        for name in [b"a", b"b"] {
+            let mut raster = 2u32.to_le_bytes().to_vec();
+            raster.extend(name);
+            payload.extend(chunk(0x15, &chunk(0x01, &raster)));
+        }
+        payload.extend(chunk(0x03, &[]));
+        let file = chunk(0x16, &payload);
+
+        let (_, txd) = Chunk::parse(&file).unwrap();
+        let dict = txd.content.as_texture_dictionary().unwrap();
+        assert_eq!(dict.num_textures, 2);
+        assert_eq!(dict.device_id, RwTextureDictionary::DEVICE_D3D8);
+        let textures: Vec<_> = txd.textures().collect();
+        assert_eq!(textures.len(), 2);
+        assert_eq!(
+            textures[1].content.as_section().unwrap().1.last(),
+            Some(&b'b')
+        );
+        assert_eq!(txd.to_bytes().unwrap(), file);
+    }
+
+    #[test]
+    fn masked_texture() {
+        let header = ChunkHeader {
+            version: RwVersion::GTA3_PC,
+            build: RwBuild::UNSPECIFIED,
+            endian: endian::Endian::Little,
+            size: None,
+        };
+        let raster = |name: &str, mask: &str, width, pixel: [u8; 4]| {
+            let image = RgbaImage {
+                width,
+                height: width,
+                data: pixel.repeat((width * width) as usize),
+            };
+            let mut raster = RpRasterPC::encode(
+                name,
+                &image,
+                RasterEncoding::Format8888,
+                false,
+                header.version,
+            );
+            raster.mask_name = mask.to_string();
+            Chunk {
+                header,
+                content: ChunkContent::Raster(raster),
+                children: Some(Vec::new()),
+            }
+        };
+        let txd = Chunk {
+            header,
+            content: ChunkContent::TextureDictionary(RwTextureDictionary {
+                num_textures: 3,
+                device_id: 0,
+            }),
+            children: Some(vec![
+                raster("tree", "treem", 2, [10, 20, 30, 255]),
+                raster("treem", "", 1, [100, 100, 100, 255]),
+                raster("bush", "missing", 1, [1, 2, 3, 255]),
+            ]),
+        };
+
+        let lookup = TexDictionary::new(&txd).unwrap();
+        let tree = lookup.decode_masked("Tree").unwrap();
+        assert_eq!(tree.data, [10, 20, 30, 100].repeat(4));
+        assert_eq!(lookup.decode_masked("bush").unwrap().data, [1, 2, 3, 255]);
+        assert!(lookup.decode_masked("rock").is_none());
+    }
+
+    #[test]
+    fn texture_sampler() {
+        // V is addressed with a border, U is clamped
+        let file = chunk(0x01, &[2, 0x43, 0, 0]);
+        let (_, data) = Chunk::parse(&file).unwrap();
+        let (_, parsed) = RpTexture::parse(
+            data.content.as_struct().unwrap(),
+            RwVersion::VC_PC,
+            Endian::Little,
+        )
+        .unwrap();
+
+        let mut geometry = builder::GeometryBuilder::new();
+        geometry.vertices(vec![[0.0; 3]; 3]);
+        let material = geometry.material([255; 4], Some("decal"));
+        geometry.triangle([0, 1, 2], material);
+        let mut clump = builder::ClumpBuilder::new(RwVersion::VC_PC);
+        let frame = clump.frame(None, None, [0.0; 3]);
+        let geometry = clump.geometry(geometry).unwrap();
+        clump.atomic(frame, geometry);
+        let mut clump = clump.build();
+        fn set_addressing(chunk: &mut Chunk, texture: &RpTexture) {
+            if let ChunkContent::Texture(t) = &mut chunk.content {
+                (t.address_u, t.address_v) = (texture.address_u, texture.address_v);
+            }
+            for child in chunk.children.iter_mut().flatten() {
+                set_addressing(child, texture);
+            }
+        }
+        set_addressing(&mut clump, &parsed);
+
+        let scene = clump.to_scene_graph();
+        let sampler = scene.textures[0].sampler;
+        assert_eq!(sampler.address_u, SceneAddressMode::ClampToEdge);
+        assert_eq!(sampler.address_v, SceneAddressMode::ClampToBorder);
+        assert_eq!(sampler.border_color, [0.0; 4]);
+    }
+
+    #[test]
+    fn material_list() {
+        let material = |color: [u8; 4], tex_name: Option<&str>| {
+            let mut data = 0u32.to_le_bytes().to_vec();
+            data.extend(color);
+            data.extend(0u32.to_le_bytes());
+            data.extend((tex_name.is_some() as u32).to_le_bytes());
+            data.extend(floats(&[1.0, 0.5, 1.0]));
+            let mut payload = chunk(0x01, &data);
+            payload.extend(tex_name.map(texture).unwrap_or_default());
+            chunk(0x07, &payload)
+        };
+        let mut data = 3u32.to_le_bytes().to_vec();
+        for index in [-1i32, -1, 0] {
+            data.extend(index.to_le_bytes());
+        }
+        let mut payload = chunk(0x01, &data);
+        payload.extend(material([255, 0, 0, 255], Some("crate")));
+        payload.extend(material([0, 0, 255, 128], None));
+        let file = chunk(0x08, &payload);
+
+        let (_, list) = Chunk::parse(&file).unwrap();
+        let list = list.content.as_material_list().unwrap();
+        assert_eq!(list.indices, vec![0, 1, 0]);
+        assert_eq!(list.materials.len(), 2);
+        let first = list.get(2).unwrap();
+        assert_eq!(first.diffuse_tex.as_deref(), Some("crate"));
+        assert_eq!(first.mask_tex, None);
+        assert_eq!(first.surface_prop.unwrap().specular, 0.5);
+        let second = list.get(1).unwrap();
+        assert_eq!((second.color.b, second.color.a), (255, 128));
+        assert!(second.diffuse_tex.is_none());
+    }
+
+    #[test]
+    fn material_uv_anims() {
+        let mut data = 0x10u32.to_le_bytes().to_vec();
+        data.extend([255; 4]);
+        data.extend(7u32.to_le_bytes());
+        data.extend([0; 4]);
+        data.extend(floats(&[1.0, 1.0, 1.0]));
+        let mut uv_anim = 1u32.to_le_bytes().to_vec();
+        let mut name = b"waterfall".to_vec();
+        name.resize(32, 0);
+        uv_anim.extend(name);
+        let mut ext = chunk(0x135, &chunk(0x01, &uv_anim));
+        let matfx: Vec<u8> = [5u32, 5, 0].iter().flat_map(|w| w.to_le_bytes()).collect();
+        ext.extend(chunk(0x120, &matfx));
+        let mut material = chunk(0x01, &data);
+        material.extend(chunk(0x03, &ext));
+        let mut payload = chunk(0x01, &[1, 0, 0, 0, 255, 255, 255, 255]);
+        payload.extend(chunk(0x07, &material));
+
+        let file = chunk(0x08, &payload);
+        let (_, list) = Chunk::parse(&file).unwrap();
+        let material = list.content.as_material_list().unwrap().get(0).unwrap();
+        assert_eq!(material.uv_anims, vec![(0, "waterfall".to_owned())]);
+        assert!(material.uv_transform);
+        // Material flags, UV animation and effects plugins are written back unchanged
+        assert_eq!(list.to_bytes().unwrap(), file);
+    }
+
+    #[test]
+    fn specular_material() {
+        let mut data = 0.75f32.to_le_bytes().to_vec();
+        let mut name = b"vehiclespecdot64".to_vec();
+        name.resize(24, 0);
+        data.extend(name);
+        let file = chunk(0x0253F2F6, &data);
+
+        let (_, spec) = Chunk::parse(&file).unwrap();
+        let spec = spec.content.as_specular_material().unwrap();
+        assert_eq!(spec.level, 0.75);
+        assert_eq!(spec.texture, "vehiclespecdot64");
+    }
+
+    #[test]
+    fn raster_format() {
+        let format = RasterFormat::from_bits_retain(0xA500);
+        assert_eq!(format.base_format(), Some(RasterBaseFormat::Format8888));
+        assert!(format.is_palettized() && format.has_mipmaps());
+        assert!(!RasterFormat::from_base(RasterBaseFormat::Format565).is_palettized());
+        assert_eq!(RasterFormat::from_bits_retain(0x0700).base_format(), None);
+    }
+
+    #[test]
+    fn d3d_format() {
+        let dxt3 = u32::from_le_bytes(*b"DXT3");
+        for value in [0, 21, 23, 41, dxt3, 0x1234] {
+            assert_eq!(D3dFormat::from_u32(value).to_u32(), value);
+        }
+        assert_eq!(D3dFormat::from_u32(dxt3), D3dFormat::Dxt3);
+        assert_eq!(D3dFormat::from_u32(0x1234), D3dFormat::Other(0x1234));
+        assert_eq!(D3dFormat::Dxt1.bits_per_pixel(), Some(4));
+        assert_eq!(D3dFormat::Dxt1.block_size(), Some(8));
+        assert_eq!(D3dFormat::Dxt4.block_size(), Some(16));
+        assert_eq!(D3dFormat::A8R8G8B8.block_size(), None);
+        assert_eq!(D3dFormat::Dxt5.level_size(2, 2), Some(16));
+        assert_eq!(D3dFormat::R5G6B5.level_size(4, 2), Some(16));
+        assert_eq!(D3dFormat::Other(0x1234).level_size(4, 2), None);
+    }
+
+    #[test]
+    fn alpha_usage() {
+        let image = |alpha: &[u8]| RgbaImage {
+            width: alpha.len() as u32,
+            height: 1,
+            data: alpha.iter().flat_map(|&a| [0, 0, 0, a]).collect(),
+        };
+        assert_eq!(image(&[255, 255]).alpha_usage(), AlphaUsage::Opaque);
+        assert_eq!(image(&[255, 0]).alpha_usage(), AlphaUsage::OneBit);
+        assert_eq!(image(&[0, 128, 255]).alpha_usage(), AlphaUsage::Gradient);
+    }
+
+    #[test]
+    fn image_checksum_and_rmse() {
+        let a = RgbaImage {
+            width: 2,
+            height: 1,
+            data: vec![0, 0, 0, 255, 10, 10, 10, 255],
+        };
+        let mut b = a.clone();
+        assert_eq!(a.checksum(), b.checksum());
+        assert_eq!(a.rmse(&b), Some([0.0; 4]));
+
+        b.data[4] = 14;
+        assert_ne!(a.checksum(), b.checksum());
+        let rmse = a.rmse(&b).unwrap();
+        assert!((rmse[0] - 8f64.sqrt()).abs() < 1e-9);
+        assert_eq!(rmse[1..], [0.0; 3]);
+
+        b.width = 1;
+        assert_eq!(a.rmse(&b), None);
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn image_conversion() {
+        let image = RgbaImage {
+            width: 2,
+            height: 1,
+            data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+        }
+        .to_image();
+        assert_eq!(image.dimensions(), (2, 1));
+        assert_eq!(image.get_pixel(1, 0).0, [5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn palettised_pc_raster() {
+        let raster = |format: u32, depth: u8, palette: usize, level: &[u8]| {
+            let mut data = PLATFORM_D3D8.to_le_bytes().to_vec();
+            data.extend(0x1102u32.to_le_bytes());
+            data.extend([0; 64]);
+            data.extend(format.to_le_bytes());
+            data.extend(1u32.to_le_bytes());
+            data.extend(2u16.to_le_bytes());
+            data.extend(2u16.to_le_bytes());
+            data.extend([depth, 1, 4, 0]);
+            for entry in 0..palette {
+                data.extend([entry as u8, 0, 0, 255 - entry as u8]);
+            }
+            data.extend((level.len() as u32).to_le_bytes());
+            data.extend(level);
+            let (_, chunk) = Chunk::parse(&chunk(0x15, &chunk(0x01, &data))).unwrap();
+            chunk.content.as_raster().unwrap().decode().unwrap()
+        };
+
+        let image = raster(0x2500, 8, 256, &[3, 0, 255, 7]);
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(image.data[..8], [3, 0, 0, 252, 0, 0, 0, 255]);
+        assert_eq!(image.data[8..], [255, 0, 0, 0, 7, 0, 0, 248]);
+
+        let reds =
+            |image: RgbaImage| -> Vec<u8> { image.data.iter().step_by(4).copied().collect() };
+        assert_eq!(reds(raster(0x4500, 4, 32, &[0x21, 0x43])), vec![1, 2, 3, 4]);
+        assert_eq!(reds(raster(0x4500, 4, 16, &[0x21, 0x43])), vec![1, 2, 3, 4]);
+        assert_eq!(reds(raster(0x4500, 4, 16, &[5, 6, 7, 8])), vec![5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn direct_pc_raster() {
+        let raster = |format: u32, depth: u8, level: &[u8]| {
+            let mut data = PLATFORM_D3D8.to_le_bytes().to_vec();
+            data.extend(0x1102u32.to_le_bytes());
+            data.extend([0; 64]);
+            data.extend(format.to_le_bytes());
+            data.extend(0u32.to_le_bytes());
+            data.extend(2u16.to_le_bytes());
+            data.extend(1u16.to_le_bytes());
+            data.extend([depth, 1, 4, 0]);
+            data.extend((level.len() as u32).to_le_bytes());
+            data.extend(level);
+            let (_, chunk) = Chunk::parse(&chunk(0x15, &chunk(0x01, &data))).unwrap();
+            chunk.content.as_raster().unwrap().decode().unwrap().data
+        };
+        let words =
+            |words: [u16; 2]| -> Vec<u8> { words.iter().flat_map(|w| w.to_le_bytes()).collect() };
+
+        assert_eq!(
+            raster(0x0200, 16, &words([0xF800, 0x07E0])),
+            [255, 0, 0, 255, 0, 255, 0, 255]
+        );
+        assert_eq!(
+            raster(0x0100, 16, &words([0x801F, 0x7C00])),
+            [0, 0, 255, 255, 255, 0, 0, 0]
+        );
+        assert_eq!(
+            raster(0x0300, 16, &words([0xF0F0, 0x80FF])),
+            [0, 255, 0, 255, 0, 255, 255, 136]
+        );
+        assert_eq!(
+            raster(0x0400, 8, &[0, 200]),
+            [0, 0, 0, 255, 200, 200, 200, 255]
+        );
+        assert_eq!(
+            raster(0x0600, 32, &[1, 2, 3, 0, 4, 5, 6, 0]),
+            [3, 2, 1, 255, 6, 5, 4, 255]
+        );
+    }
+
+    #[test]
+    fn validate_pc_raster() {
+        let image = RgbaImage {
+            width: 4,
+            height: 4,
+            data: vec![0; 64],
+        };
+        let encode = |encoding| RpRasterPC::encode("tex", &image, encoding, true, RwVersion::SA_PC);
+
+        let mut raster = encode(RasterEncoding::Dxt1);
+        raster.data.push(0);
+        assert_eq!(raster.validate(), Err(vec![RasterIssue::TrailingData(1)]));
+        raster.data.truncate(raster.data.len() - 3);
+        assert_eq!(
+            raster.validate(),
+            Err(vec![RasterIssue::Truncated {
+                level: 2,
+                missing: 2
+            }])
+        );
+        raster.num_levels = 4;
+        raster.data = encode(RasterEncoding::Dxt1).data;
+        assert_eq!(raster.validate(), Err(vec![RasterIssue::MissingLevel(3)]));
+        // More levels than the dimensions allow, all of them 1x1
+        raster.num_levels = 40;
+        for _ in 3..40 {
+            raster.data.extend(8u32.to_le_bytes());
+            raster.data.extend([0; 8]);
+        }
+        assert_eq!(raster.validate(), Ok(()));
+
+        let mut raster = encode(RasterEncoding::Format565);
+        raster.data[..4].copy_from_slice(&30u32.to_le_bytes());
+        let issues = raster.validate().unwrap_err();
+        assert_eq!(
+            issues[0],
+            RasterIssue::LevelSize {
+                level: 0,
+                expected: 32,
+                found: 30
+            }
+        );
+        assert_eq!(issues[0].to_string(), "Level 0 has 30 bytes instead of 32");
+
+        let mut raster = encode(RasterEncoding::Pal8);
+        raster.data.truncate(100);
+        assert_eq!(
+            raster.validate(),
+            Err(vec![RasterIssue::Palette {
+                expected: 1024,
+                found: 100
+            }])
+        );
+        raster.depth = 4;
+        assert_eq!(
+            raster.validate(),
+            Err(vec![RasterIssue::Depth {
+                expected: 8,
+                found: 4
+            }])
+        );
+    }
+
+    #[test]
+    fn mobile_raster() {
+        // War Drum OpenGL rasters aren't decoded, they stay as they are
+        let mut data = 11u32.to_le_bytes().to_vec();
+        data.extend([0; 80]);
+        let file = chunk(0x15, &[chunk(0x01, &data), chunk(0x03, &[])].concat());
+        let (_, raster) = Chunk::parse(&file).unwrap();
+        assert!(raster.content.as_raster().is_none());
+        assert!(matches!(
+            &raster.content,
+            ChunkContent::Section((ChunkTypeId(0x15), _))
+        ));
+        assert_eq!(raster.to_bytes().unwrap(), file);
+    }
+
+    #[test]
+    fn raster_addressing() {
+        let image = RgbaImage {
+            width: 1,
+            height: 1,
+            data: vec![255; 4],
+        };
+        let mut raster = RpRasterPC::encode(
+            "tex",
+            &image,
+            RasterEncoding::Format8888,
+            false,
+            RwVersion::VC_PC,
+        );
+        raster.address_u = TextureAddressingMode::TEXTUREADDRESSCLAMP;
+        raster.address_v = TextureAddressingMode::TEXTUREADDRESSMIRROR;
+        let data = raster.write(RwVersion::VC_PC);
+        // U in bits 8-11 of the flags, V in bits 12-15
+        assert_eq!(data[5], 0x23);
+
+        let (_, raster) = RpRasterPC::parse(&data, RwVersion::VC_PC).unwrap();
+        let sampler = SceneSampler::from_addressing(raster.address_u, raster.address_v);
+        assert_eq!(sampler.address_u, SceneAddressMode::ClampToEdge);
+        assert_eq!(sampler.address_v, SceneAddressMode::Mirror);
+    }
+
+    #[test]
+    fn border_expansion() {
+        let image = RgbaImage {
+            width: 2,
+            height: 1,
+            data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+        };
+        let bordered = image.with_border([0; 4]);
+        assert_eq!((bordered.width, bordered.height), (4, 3));
+        assert_eq!(bordered.data[..16], [0; 16]);
+        assert_eq!(
+            bordered.data[16..32],
+            [0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 0, 0, 0, 0]
+        );
+        assert_eq!(bordered.data[32..], [0; 16]);
+    }
+
+    #[test]
+    fn encode_pc_raster() {
+        let mut data = Vec::new();
+        for y in 0..8u8 {
+            for x in 0..8u8 {
+                data.extend([x * 16, y * 16, 128, if x < 4 { 255 } else { 0 }]);
+            }
+        }
+        let image = RgbaImage {
+            width: 8,
+            height: 8,
+            data,
+        };
+        let (mut opaque, mut punched) = (image.clone(), image.clone());
+        for p in opaque.data.chunks_exact_mut(4) {
+            p[3] = 255;
+        }
+        // DXT1 decodes transparent texels as black
+        for p in punched.data.chunks_exact_mut(4).filter(|p| p[3] == 0) {
+            p.copy_from_slice(&[0; 4]);
+        }
+
+        for version in [RwVersion::GTA3_PC, RwVersion::SA_PC] {
+            for (encoding, source, max_error) in [
+                (RasterEncoding::Format8888, &image, 0.0),
+                (RasterEncoding::Format565, &opaque, 4.0),
+                (RasterEncoding::Pal8, &image, 0.0),
+                (RasterEncoding::Dxt1, &punched, 16.0),
+                (RasterEncoding::Dxt5, &image, 16.0),
+            ] {
+                let raster = RpRasterPC::encode("tex", source, encoding, true, version);
+                let file = Chunk {
+                    header: ChunkHeader {
+                        version,
+                        build: RwBuild::UNSPECIFIED,
+                        endian: endian::Endian::Little,
+                        size: None,
+                    },
+                    content: ChunkContent::Raster(raster),
+                    children: Some(Vec::new()),
+                }
+                .to_bytes()
+                .unwrap();
+                let (_, chunk) = Chunk::parse(&file).unwrap();
+
+                let raster = chunk.content.as_raster().unwrap();
+                assert_eq!(raster.name, "tex");
+                assert_eq!(raster.num_levels, 4);
+                if version == RwVersion::SA_PC {
+                    let d3d = raster.d3d_format;
+                    assert_eq!(d3d.level_size(8, 8), Some(raster.level_size(8, 8)));
+                    // Alpha in bit 0 and compression in bit 3, like librw reads them
+                    let dxt = matches!(encoding, RasterEncoding::Dxt1 | RasterEncoding::Dxt5);
+                    assert_eq!(file[111], raster.has_alpha as u8 | (dxt as u8) << 3);
+                }
+                assert_eq!(raster.has_alpha, encoding != RasterEncoding::Format565);
+                let alpha = match encoding {
+                    RasterEncoding::Format565 => AlphaUsage::Opaque,
+                    _ => AlphaUsage::OneBit,
+                };
+                assert_eq!(raster.analyze_alpha(), Some(alpha));
+                let rmse = raster.decode().unwrap().rmse(source).unwrap();
+                assert!(
+                    rmse.iter().all(|&e| e <= max_error),
+                    "{encoding:?} {rmse:?}"
+                );
+                assert_eq!(raster.validate(), Ok(()));
+                let mip = raster.decode_level(3).unwrap();
+                assert_eq!((mip.width, mip.height), (1, 1));
+
+                let dds = raster.to_dds();
+                match encoding {
+                    RasterEncoding::Dxt1 | RasterEncoding::Dxt5 => {
+                        let dds = dds.unwrap();
+                        let fourcc = if encoding == RasterEncoding::Dxt1 {
+                            b"DXT1"
+                        } else {
+                            b"DXT5"
+                        };
+                        assert_eq!(&dds[..4], b"DDS ");
+                        assert_eq!(&dds[84..88], fourcc);
+                        assert_eq!(dds[28], 4); // Mipmap count
+                        let levels: Vec<u8> = raster
+                            .mip_levels()
+                            .flat_map(|(_, _, l)| l.to_vec())
+                            .collect();
+                        assert_eq!(dds[128..], levels);
+                    }
+                    _ => assert!(dds.is_none()),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_texture() {
+        let file = chunk(0x06, &chunk(0x01, &[9, 0x19, 0, 0]));
+        let opts = ParseOptions::default();
+        let (_, parsed) = Chunk::parse_with(&file, &opts).unwrap();
+        let texture = parsed.content.as_texture().unwrap();
+        assert!(matches!(
+            texture.filtering,
+            TextureFilteringMode::FILTERNAFILTERMODE
+        ));
+        assert!(matches!(
+            (texture.address_u, texture.address_v),
+            (
+                TextureAddressingMode::TEXTUREADDRESSNATEXTUREADDRESS,
+                TextureAddressingMode::TEXTUREADDRESSWRAP
+            )
+        ));
+        let diagnostics = opts.take_diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].offset, Some(24));
+        assert_eq!(
+            Chunk::parse(&chunk(0x06, &[])).unwrap_err(),
+            nom::Err::Error(RwError::MissingStruct(ChunkType::Texture.into()))
+        );
+    }
+}
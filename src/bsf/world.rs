@@ -0,0 +1,484 @@
+use nom::multi::count;
+use nom::number::complete::{le_f32, le_i8, le_u32};
+use nom::IResult;
+use nom_derive::{Nom, Parse};
+
+use super::geo::{num_tex_sets, GeometryFlags, RwV3d};
+use super::tex::{RpSurfProp, RwRGBA, RwTexCoords, DEFAULT_SURFACE_PROP};
+use super::{Chunk, ChunkContent, RwVersion};
+
+#[derive(Clone, Debug, Default, Nom)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RwBBox {
+    pub sup: RwV3d, // Maximum corner
+    pub inf: RwV3d, // Minimum corner
+}
+
+// Static level geometry of a .bsp file. The children are the material list shared by all
+// sectors and the root of the sector tree, a PlaneSection or, for tiny worlds, an AtomicSection.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RpWorld {
+    pub root_is_world_sector: bool,
+    pub inverse_origin: RwV3d,
+    pub surface_prop: Option<RpSurfProp>,
+    pub num_triangles: u32,
+    pub num_vertices: u32,
+    pub num_plane_sectors: u32,
+    pub num_atomic_sectors: u32,
+    pub col_sector_size: u32,
+    pub flags: GeometryFlags, // Same bits as a geometry, applying to every atomic sector
+    pub bounding_box: RwBBox,
+}
+
+impl RpWorld {
+    pub fn parse(i: &[u8], version: RwVersion) -> IResult<&[u8], Self> {
+        let (i, root_is_world_sector) = le_u32(i)?;
+        let (mut i, inverse_origin) = RwV3d::parse_le(i)?;
+        let mut surface_prop = None;
+        if version < RwVersion(0x34000) {
+            let s;
+            (i, s) = RpSurfProp::parse_le(i)?;
+            surface_prop = Some(s);
+        }
+        let (i, num_triangles) = le_u32(i)?;
+        let (i, num_vertices) = le_u32(i)?;
+        let (i, num_plane_sectors) = le_u32(i)?;
+        let (i, num_atomic_sectors) = le_u32(i)?;
+        let (i, col_sector_size) = le_u32(i)?;
+        let (i, format) = le_u32(i)?;
+        let (i, bounding_box) = RwBBox::parse_le(i)?;
+
+        Ok((
+            i,
+            Self {
+                root_is_world_sector: root_is_world_sector != 0,
+                inverse_origin,
+                surface_prop,
+                num_triangles,
+                num_vertices,
+                num_plane_sectors,
+                num_atomic_sectors,
+                col_sector_size,
+                flags: GeometryFlags::from_bits_retain(format),
+                bounding_box,
+            },
+        ))
+    }
+
+    pub fn write(&self, version: RwVersion) -> Vec<u8> {
+        let mut data = (self.root_is_world_sector as u32).to_le_bytes().to_vec();
+        data.extend(self.inverse_origin.to_bytes());
+        if version < RwVersion(0x34000) {
+            data.extend(self.surface_prop.unwrap_or(DEFAULT_SURFACE_PROP).to_bytes());
+        }
+        for word in [
+            self.num_triangles,
+            self.num_vertices,
+            self.num_plane_sectors,
+            self.num_atomic_sectors,
+            self.col_sector_size,
+            self.flags.bits(),
+        ] {
+            data.extend(word.to_le_bytes());
+        }
+        data.extend(self.bounding_box.sup.to_bytes());
+        data.extend(self.bounding_box.inf.to_bytes());
+        data
+    }
+}
+
+// Vertex data of the sectors depends on the world format, so it's decoded once the whole tree
+// has been parsed
+pub(crate) fn decode_sectors(world: &RpWorld, children: &mut [Chunk]) {
+    for child in children {
+        if let ChunkContent::AtomicSection(sector) = &mut child.content {
+            sector.decode(world.flags);
+        }
+        if let Some(children) = &mut child.children {
+            decode_sectors(world, children);
+        }
+    }
+}
+
+// Node of the BSP tree, splitting space at value along one axis. The two children are the
+// sectors on the left (below value) and right side, in that order.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RpPlaneSector {
+    pub plane_type: u32, // Byte offset of the axis in a vector, 0, 4 or 8 for x, y and z
+    pub value: f32,
+    pub left_is_atomic: bool,
+    pub right_is_atomic: bool,
+    pub left_value: f32, // The sectors may overlap, these are their actual extents along the axis
+    pub right_value: f32,
+}
+
+impl RpPlaneSector {
+    pub fn parse(i: &[u8], _version: RwVersion) -> IResult<&[u8], Self> {
+        let (i, plane_type) = le_u32(i)?;
+        let (i, value) = le_f32(i)?;
+        let (i, left_is_atomic) = le_u32(i)?;
+        let (i, right_is_atomic) = le_u32(i)?;
+        let (i, left_value) = le_f32(i)?;
+        let (i, right_value) = le_f32(i)?;
+        Ok((
+            i,
+            Self {
+                plane_type,
+                value,
+                left_is_atomic: left_is_atomic != 0,
+                right_is_atomic: right_is_atomic != 0,
+                left_value,
+                right_value,
+            },
+        ))
+    }
+
+    // Index of the split axis, 0 for x
+    pub fn axis(&self) -> usize {
+        (self.plane_type / 4) as usize
+    }
+
+    pub fn write(&self, _version: RwVersion) -> Vec<u8> {
+        let mut data = self.plane_type.to_le_bytes().to_vec();
+        data.extend(self.value.to_le_bytes());
+        data.extend((self.left_is_atomic as u32).to_le_bytes());
+        data.extend((self.right_is_atomic as u32).to_le_bytes());
+        data.extend(self.left_value.to_le_bytes());
+        data.extend(self.right_value.to_le_bytes());
+        data
+    }
+}
+
+#[derive(Clone, Copy, Debug, Nom)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RpPolygon {
+    pub material_id: u16, // Relative to RpAtomicSector::mat_list_window_base
+    pub vertex1: u16,
+    pub vertex2: u16,
+    pub vertex3: u16,
+}
+
+impl RpPolygon {
+    pub fn as_arr(self) -> [u16; 3] {
+        [self.vertex1, self.vertex2, self.vertex3]
+    }
+}
+
+// Leaf of the BSP tree holding the geometry inside its box
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RpAtomicSector {
+    pub mat_list_window_base: u32,
+    pub num_triangles: u32,
+    pub num_vertices: u32,
+    pub inf: RwV3d,
+    pub sup: RwV3d,
+    pub coll_sector_present: u32,
+    pub unused: u32,
+    pub vertices: Vec<RwV3d>,
+    pub normals: Vec<RwV3d>, // Stored as signed bytes
+    pub prelit: Vec<RwRGBA>,
+    pub tex_coords: Vec<Vec<RwTexCoords>>,
+    pub triangles: Vec<RpPolygon>,
+    pub data: Vec<u8>, // Vertex data not decoded yet, empty for sectors inside a World
+}
+
+impl RpAtomicSector {
+    pub fn parse(i: &[u8], _version: RwVersion) -> IResult<&[u8], Self> {
+        let (i, mat_list_window_base) = le_u32(i)?;
+        let (i, num_triangles) = le_u32(i)?;
+        let (i, num_vertices) = le_u32(i)?;
+        let (i, inf) = RwV3d::parse_le(i)?;
+        let (i, sup) = RwV3d::parse_le(i)?;
+        let (i, coll_sector_present) = le_u32(i)?;
+        let (i, unused) = le_u32(i)?;
+
+        Ok((
+            &[],
+            Self {
+                mat_list_window_base,
+                num_triangles,
+                num_vertices,
+                inf,
+                sup,
+                coll_sector_present,
+                unused,
+                vertices: Vec::new(),
+                normals: Vec::new(),
+                prelit: Vec::new(),
+                tex_coords: Vec::new(),
+                triangles: Vec::new(),
+                data: i.to_vec(),
+            },
+        ))
+    }
+
+    // Sectors that fail to decode keep their raw data
+    pub fn decode(&mut self, flags: GeometryFlags) {
+        if flags.contains(GeometryFlags::NATIVE) || self.data.is_empty() {
+            return;
+        }
+        if let Ok((_, ())) = self.decode_data(flags) {
+            self.data.clear();
+        }
+    }
+
+    fn decode_data(&mut self, flags: GeometryFlags) -> IResult<&[u8], ()> {
+        let num_vertices = self.num_vertices as usize;
+        let i = &self.data[..];
+        let (mut i, vertices) = count(RwV3d::parse_le, num_vertices)(i)?;
+        let mut normals = Vec::new();
+        if flags.contains(GeometryFlags::NORMALS) {
+            (i, normals) = count(parse_normal, num_vertices)(i)?;
+        }
+        let mut prelit = Vec::new();
+        if flags.contains(GeometryFlags::PRELIT) {
+            (i, prelit) = count(RwRGBA::parse_le, num_vertices)(i)?;
+        }
+        let (i, tex_coords) = count(
+            count(RwTexCoords::parse_le, num_vertices),
+            num_tex_sets(flags) as usize,
+        )(i)?;
+        let (i, triangles) = count(RpPolygon::parse_le, self.num_triangles as usize)(i)?;
+
+        self.vertices = vertices;
+        self.normals = normals;
+        self.prelit = prelit;
+        self.tex_coords = tex_coords;
+        self.triangles = triangles;
+        Ok((i, ()))
+    }
+
+    pub fn write(&self, _version: RwVersion) -> Vec<u8> {
+        let mut data = Vec::new();
+        for word in [
+            self.mat_list_window_base,
+            self.num_triangles,
+            self.num_vertices,
+        ] {
+            data.extend(word.to_le_bytes());
+        }
+        data.extend(self.inf.to_bytes());
+        data.extend(self.sup.to_bytes());
+        data.extend(self.coll_sector_present.to_le_bytes());
+        data.extend(self.unused.to_le_bytes());
+        if !self.data.is_empty() {
+            data.extend(&self.data);
+            return data;
+        }
+        for v in &self.vertices {
+            data.extend(v.to_bytes());
+        }
+        for n in &self.normals {
+            let [x, y, z] = n
+                .as_arr()
+                .map(|c| (c * 128.0).round().clamp(-128.0, 127.0) as i8);
+            data.extend([x as u8, y as u8, z as u8, 0]);
+        }
+        for c in &self.prelit {
+            data.extend([c.r, c.g, c.b, c.a]);
+        }
+        for t in self.tex_coords.iter().flatten() {
+            data.extend(t.u.to_le_bytes());
+            data.extend(t.v.to_le_bytes());
+        }
+        for t in &self.triangles {
+            for word in [t.material_id, t.vertex1, t.vertex2, t.vertex3] {
+                data.extend(word.to_le_bytes());
+            }
+        }
+        data
+    }
+}
+
+// Three signed bytes and a padding byte
+fn parse_normal(i: &[u8]) -> IResult<&[u8], RwV3d> {
+    let (i, x) = le_i8(i)?;
+    let (i, y) = le_i8(i)?;
+    let (i, z) = le_i8(i)?;
+    let (i, _) = le_i8(i)?;
+    Ok((i, RwV3d::from_arr([x, y, z].map(|c| c as f32 / 128.0))))
+}
+
+// Sector tree of a World chunk, for finding the atomic sectors in a region without walking all
+// of them. Results are Atomic Section chunks, whose extensions hold the bin meshes.
+#[derive(Clone, Copy, Debug)]
+pub struct SectorTree<'a> {
+    pub world: &'a RpWorld,
+    root: &'a Chunk,
+}
+
+impl<'a> SectorTree<'a> {
+    pub fn new(world: &'a Chunk) -> Option<Self> {
+        Some(Self {
+            world: world.content.as_world()?,
+            root: sector_children(world).next()?,
+        })
+    }
+
+    pub fn sectors(&self) -> Vec<&'a Chunk> {
+        self.query(|_, _| true)
+    }
+
+    // Sectors overlapping the box from min to max
+    pub fn intersect_box(&self, min: [f32; 3], max: [f32; 3]) -> Vec<&'a Chunk> {
+        self.query(|inf, sup| (0..3).all(|c| inf[c] <= max[c] && sup[c] >= min[c]))
+    }
+
+    // Sectors at least partly inside all planes, given as [a, b, c, d] with the inside where
+    // a * x + b * y + c * z + d >= 0
+    pub fn intersect_frustum(&self, planes: &[[f32; 4]]) -> Vec<&'a Chunk> {
+        self.query(|inf, sup| {
+            planes.iter().all(|p| {
+                // Corner furthest along the plane normal
+                let corner = [0, 1, 2].map(|c| if p[c] >= 0.0 { sup[c] } else { inf[c] });
+                p[0] * corner[0] + p[1] * corner[1] + p[2] * corner[2] + p[3] >= 0.0
+            })
+        })
+    }
+
+    // Sectors for which test holds, skipping subtrees whose bounds fail it. test gets the
+    // minimum and maximum corner of a box.
+    pub fn query(&self, mut test: impl FnMut([f32; 3], [f32; 3]) -> bool) -> Vec<&'a Chunk> {
+        let bbox = &self.world.bounding_box;
+        let mut out = Vec::new();
+        visit(
+            self.root,
+            bbox.inf.as_arr(),
+            bbox.sup.as_arr(),
+            &mut test,
+            &mut out,
+        );
+        out
+    }
+}
+
+fn sector_children(chunk: &Chunk) -> impl Iterator<Item = &Chunk> {
+    chunk.get_children().iter().filter(|c| {
+        matches!(
+            c.content,
+            ChunkContent::PlaneSection(_) | ChunkContent::AtomicSection(_)
+        )
+    })
+}
+
+fn visit<'a>(
+    chunk: &'a Chunk,
+    inf: [f32; 3],
+    sup: [f32; 3],
+    test: &mut impl FnMut([f32; 3], [f32; 3]) -> bool,
+    out: &mut Vec<&'a Chunk>,
+) {
+    match &chunk.content {
+        ChunkContent::AtomicSection(sector) if test(sector.inf.as_arr(), sector.sup.as_arr()) => {
+            out.push(chunk);
+        }
+        ChunkContent::PlaneSection(plane) if test(inf, sup) => {
+            let axis = plane.axis().min(2);
+            let mut children = sector_children(chunk);
+            if let Some(left) = children.next() {
+                let mut left_sup = sup;
+                left_sup[axis] = plane.left_value;
+                visit(left, inf, left_sup, test, out);
+            }
+            if let Some(right) = children.next() {
+                let mut right_inf = inf;
+                right_inf[axis] = plane.right_value;
+                visit(right, right_inf, sup, test, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsf::fixtures::{chunk, floats, geometry};
+    use crate::bsf::geo;
+
+    fn atomic_sector(x: f32) -> Vec<u8> {
+        let mut data = Vec::new();
+        for word in [0u32, 1, 3] {
+            data.extend(word.to_le_bytes());
+        }
+        data.extend(floats(&[x, 0.0, 0.0, x + 1.0, 1.0, 0.0]));
+        // Collision sector present, and an unused word that isn't always zero
+        data.extend([1, 0, 0, 0, 0xCD, 0xCD, 0xCD, 0xCD]);
+        data.extend(floats(&[x, 0.0, 0.0, x + 1.0, 0.0, 0.0, x, 1.0, 0.0]));
+        data.extend([0, 0, 64, 0].repeat(3));
+        data.extend([255, 0, 0, 255].repeat(3));
+        data.extend(floats(&[0.0, 0.0, 1.0, 0.0, 0.0, 1.0]));
+        data.extend([0u16, 0, 1, 2].iter().flat_map(|w| w.to_le_bytes()));
+        let mut payload = chunk(0x01, &data);
+        payload.extend(chunk(0x03, &[]));
+        chunk(0x09, &payload)
+    }
+
+    fn world_file() -> Vec<u8> {
+        let mut data = vec![0; 4];
+        data.extend(floats(&[0.0, 0.0, 0.0, 1.0, 1.0, 1.0]));
+        for word in [2u32, 6, 1, 2, 0, 0x1E] {
+            data.extend(word.to_le_bytes());
+        }
+        data.extend(floats(&[2.0, 1.0, 0.0, 0.0, 0.0, 0.0]));
+        let mut plane = Vec::new();
+        plane.extend(0u32.to_le_bytes());
+        plane.extend(1f32.to_le_bytes());
+        plane.extend([1, 0, 0, 0, 1, 0, 0, 0]);
+        plane.extend(floats(&[1.0, 1.0]));
+        let mut plane = chunk(0x01, &plane);
+        plane.extend(atomic_sector(0.0));
+        plane.extend(atomic_sector(1.0));
+        let mut payload = chunk(0x01, &data);
+        payload.extend(chunk(0x08, &chunk(0x01, &[0; 4])));
+        payload.extend(chunk(0x0A, &plane));
+        payload.extend(chunk(0x03, &[]));
+        chunk(0x0B, &payload)
+    }
+
+    #[test]
+    fn world() {
+        let file = world_file();
+        let (_, world) = Chunk::parse(&file).unwrap();
+        let flags = world.content.as_world().unwrap().flags;
+        assert!(flags.contains(geo::GeometryFlags::PRELIT | geo::GeometryFlags::NORMALS));
+        let plane = &world.get_children()[1];
+        assert_eq!(plane.content.as_plane_section().unwrap().axis(), 0);
+        let sector = plane.get_children()[1].content.as_atomic_section().unwrap();
+        assert!(sector.data.is_empty());
+        assert_eq!((sector.coll_sector_present, sector.unused), (1, 0xCDCDCDCD));
+        assert_eq!(sector.vertices[1].as_arr(), [2.0, 0.0, 0.0]);
+        assert_eq!(sector.normals[0].as_arr(), [0.0, 0.0, 0.5]);
+        assert_eq!(sector.prelit[2].r, 255);
+        assert_eq!(sector.tex_coords[0][1].as_arr(), [1.0, 0.0]);
+        assert_eq!(sector.triangles[0].as_arr(), [0, 1, 2]);
+        assert_eq!(world.to_bytes().unwrap(), file);
+    }
+
+    #[test]
+    fn sector_queries() {
+        let (_, world) = Chunk::parse(&world_file()).unwrap();
+        let tree = SectorTree::new(&world).unwrap();
+        let min_x = |sectors: Vec<&Chunk>| -> Vec<f32> {
+            sectors
+                .iter()
+                .map(|c| c.content.as_atomic_section().unwrap().inf.x)
+                .collect()
+        };
+        assert_eq!(min_x(tree.sectors()), [0.0, 1.0]);
+        assert_eq!(
+            min_x(tree.intersect_box([1.5, 0.0, 0.0], [3.0, 1.0, 1.0])),
+            [1.0]
+        );
+        assert!(tree.intersect_box([-2.0; 3], [-1.0; 3]).is_empty());
+        // Everything with x <= 0.5
+        assert_eq!(
+            min_x(tree.intersect_frustum(&[[-1.0, 0.0, 0.0, 0.5]])),
+            [0.0]
+        );
+        assert!(SectorTree::new(&Chunk::parse(&geometry(&[], &[])).unwrap().1).is_none());
+    }
+}
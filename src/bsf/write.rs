@@ -0,0 +1,190 @@
+use anyhow::{bail, Result};
+
+use super::endian::Endian;
+use super::geo::RpGeometry;
+use super::options::{TextureOrder, WriteOptions};
+use super::{Chunk, ChunkContent, ChunkType};
+
+impl Chunk {
+    // Serializes the chunk and its children. Sizes are recomputed, Section and Struct payloads
+    // that weren't parsed are written back unchanged.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        self.to_bytes_with(&WriteOptions::default())
+    }
+
+    pub fn to_bytes_with(&self, opts: &WriteOptions) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        self.write(&mut out, None, opts)?;
+        Ok(out)
+    }
+
+    // geo is the Geometry being written, whose plugins keep part of their data in it
+    fn write(
+        &self,
+        out: &mut Vec<u8>,
+        geo: Option<&RpGeometry>,
+        opts: &WriteOptions,
+    ) -> Result<()> {
+        // Raw payloads of big-endian chunks would be written as they are, between
+        // little-endian headers
+        if self.header.endian == Endian::Big {
+            bail!("Writing big-endian chunks isn't supported");
+        }
+        let version = self.header.version;
+        let start = begin(out, self.content.chunk_type().0, self);
+
+        let payload = match &self.content {
+            ChunkContent::Section((_, data)) | ChunkContent::Struct(data) => {
+                Payload::Raw(data.clone())
+            }
+            ChunkContent::Custom(custom) => Payload::Raw(custom.data.clone()),
+            ChunkContent::String(s) => {
+                // Null terminated and padded to 4 bytes
+                let mut data = s.as_bytes().to_vec();
+                data.resize(data.len() + 4 - s.len() % 4, 0);
+                Payload::Raw(data)
+            }
+            ChunkContent::FrameName(s) => Payload::Raw(s.as_bytes().to_vec()),
+            ChunkContent::Extension
+            | ChunkContent::Camera
+            | ChunkContent::Clump
+            | ChunkContent::GeometryList => Payload::Children,
+            ChunkContent::TextureDictionary(txd) => Payload::Struct(txd.write(version)),
+            ChunkContent::Texture(texture) => Payload::Struct(texture.write(version)),
+            ChunkContent::Raster(raster) => Payload::Struct(raster.write(version)),
+            ChunkContent::Material(material) => {
+                let textured = self
+                    .get_children()
+                    .iter()
+                    .any(|c| matches!(c.content, ChunkContent::Texture(_)));
+                Payload::Struct(material.write(version, textured))
+            }
+            ChunkContent::MaterialList(list) => Payload::Struct(list.write(version)),
+            ChunkContent::FrameList(list) => Payload::Struct(list.write(version)),
+            ChunkContent::Geometry(geometry) => Payload::Struct(geometry.write(version)),
+            ChunkContent::Atomic(atomic) => Payload::Struct(atomic.write(version)),
+            ChunkContent::BinMesh(bin_mesh) => Payload::Raw(bin_mesh.write(version)),
+            ChunkContent::NightVertexColors => match geo {
+                Some(geo) => Payload::Raw(geo.write_night_prelit()),
+                None => bail!("Night vertex colors outside of a geometry"),
+            },
+            ChunkContent::Morph(morph) => Payload::Raw(morph.write(version)),
+            ChunkContent::Skin(skin) => Payload::Raw(skin.write(version)),
+            ChunkContent::HAnim(hanim) => Payload::Raw(hanim.write(version)),
+            ChunkContent::ReflectionMaterial(m) => Payload::Raw(m.write(version)),
+            ChunkContent::SpecularMaterial(m) => Payload::Raw(m.write(version)),
+            ChunkContent::RightToRender(r) => Payload::Raw(r.write(version)),
+            ChunkContent::PipelineSet(p) => Payload::Raw(p.write(version)),
+            ChunkContent::Adc(adc) => Payload::Raw(adc.write(version)),
+            ChunkContent::Animation(anim) => Payload::Raw(anim.write(version)),
+            ChunkContent::DeltaMorph(dmorph) => Payload::Raw(dmorph.write(version)),
+            ChunkContent::Breakable(breakable) => Payload::Raw(breakable.write(version)),
+            ChunkContent::World(world) => Payload::Struct(world.write(version)),
+            ChunkContent::PlaneSection(plane) => Payload::Struct(plane.write(version)),
+            ChunkContent::AtomicSection(sector) => Payload::Struct(sector.write(version)),
+            ChunkContent::CollisionModel(col) => Payload::Raw(col.data.clone()),
+            ChunkContent::MatFX(matfx) => Payload::Raw(matfx.write(version)?),
+            ChunkContent::UVAnimDictionary(dict) => Payload::Struct(dict.write(version)),
+            ChunkContent::UVAnim(anim) => Payload::Struct(anim.write(version)),
+            // Platform specific data without a typed writer goes back as it was read
+            ChunkContent::RasterPS2(raster) => Payload::Raw(raster.raw.clone()),
+            ChunkContent::RasterXbox(raster) => Payload::Struct(raster.raw.clone()),
+            ChunkContent::NativeDataPS2(native) => Payload::Raw(native.raw.clone()),
+        };
+
+        match payload {
+            Payload::Raw(data) => out.extend(data),
+            Payload::Struct(data) => {
+                let struct_start = begin(out, ChunkType::Struct as u32, self);
+                out.extend(data);
+                end(out, struct_start);
+            }
+            Payload::Children => {}
+        }
+        let geo = match &self.content {
+            ChunkContent::Geometry(geometry) => Some(geometry),
+            _ => geo,
+        };
+        let mut children: Vec<&Chunk> = self.get_children().iter().collect();
+        if matches!(self.content, ChunkContent::TextureDictionary(_))
+            && opts.texture_order == TextureOrder::Name
+        {
+            sort_textures(&mut children);
+        }
+        for child in children {
+            child.write(out, geo, opts)?;
+        }
+        end(out, start);
+        Ok(())
+    }
+}
+
+// What comes before the children of a chunk
+enum Payload {
+    Raw(Vec<u8>),
+    Struct(Vec<u8>), // Wrapped in a Struct chunk
+    Children,
+}
+
+// Writes a header with a placeholder size and returns where it starts
+fn begin(out: &mut Vec<u8>, ty: u32, chunk: &Chunk) -> usize {
+    let start = out.len();
+    let header = chunk.header;
+    out.extend(ty.to_le_bytes());
+    out.extend(0u32.to_le_bytes());
+    out.extend(header.version.to_lib_id(header.build).to_le_bytes());
+    start
+}
+
+fn end(out: &mut [u8], start: usize) {
+    let size = (out.len() - start - 12) as u32;
+    out[start + 4..start + 8].copy_from_slice(&size.to_le_bytes());
+}
+
+// Sorts the named rasters among the children by name, everything else stays where it is
+fn sort_textures(children: &mut [&Chunk]) {
+    let slots: Vec<usize> = (0..children.len())
+        .filter(|&i| children[i].texture_name().is_some())
+        .collect();
+    let mut textures: Vec<&Chunk> = slots.iter().map(|&i| children[i]).collect();
+    textures.sort_by_cached_key(|t| t.texture_name().unwrap_or_default().to_ascii_lowercase());
+    for (slot, texture) in slots.into_iter().zip(textures) {
+        children[slot] = texture;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsf::fixtures::{chunk, geometry};
+
+    #[test]
+    fn unknown_chunk_passthrough() {
+        let mut plugins = chunk(0x0253F2F9, &[1, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8]);
+        plugins.extend(chunk(0x0253F2AA, &[0xDE, 0xAD, 0xBE, 0xEF, 1]));
+        let geometry = geometry(&[[0.0; 3], [1.0; 3]], &plugins);
+        let unknown = chunk(0x0BADF00D, b"opaque");
+        let mut payload = unknown.clone();
+        payload.extend(geometry.clone());
+        let file = chunk(0x1A, &payload);
+
+        // Parsing and writing without changes reproduces the input
+        let (_, mut list) = Chunk::parse(&file).unwrap();
+        assert_eq!(list.to_bytes().unwrap(), file);
+
+        let geo = list.get_path_mut(&[1]).unwrap();
+        let ChunkContent::Geometry(geo) = &mut geo.content else {
+            panic!("not a geometry");
+        };
+        geo.morph_targets[0].vertices[1].x = 2.0;
+        let written = list.to_bytes().unwrap();
+        assert_eq!(written.len(), file.len());
+        assert_eq!(written[12..12 + unknown.len()], unknown);
+        assert!(written.ends_with(&chunk(0x0253F2AA, &[0xDE, 0xAD, 0xBE, 0xEF, 1])));
+
+        let (_, list) = Chunk::parse(&written).unwrap();
+        let geo = list.get_children()[1].content.as_geometry().unwrap();
+        assert_eq!(geo.vertices()[1].as_arr(), [2.0, 1.0, 1.0]);
+        assert_eq!(geo.night_prelit.len(), 2);
+    }
+}
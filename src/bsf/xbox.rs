@@ -0,0 +1,212 @@
+use nom::bytes::complete::take;
+use nom::number::complete::{le_u16, le_u32, le_u8};
+use nom::IResult;
+use num_traits::FromPrimitive;
+
+use super::tex::{
+    RasterBaseFormat, RasterFormat, RgbaImage, TextureAddressingMode, TextureFilteringMode,
+};
+use super::RwVersion;
+
+pub const PLATFORM_XBOX: u32 = 5;
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RpRasterXbox {
+    pub filtering: TextureFilteringMode,
+    pub address_u: TextureAddressingMode,
+    pub address_v: TextureAddressingMode,
+    pub name: String,
+    pub mask_name: String,
+    pub raster_format: RasterFormat,
+    pub has_alpha: bool,
+    pub width: u16,
+    pub height: u16,
+    pub depth: u8,
+    pub num_levels: u8,
+    pub raster_type: u8,
+    pub compression: u8, // 0 for swizzled uncompressed surfaces, 0xC-0x10 for DXT1-DXT5
+    pub palette: Vec<u8>,
+    pub data: Vec<u8>, // All levels, largest first
+    pub raw: Vec<u8>,  // Struct payload, written back as is
+}
+
+impl RpRasterXbox {
+    pub fn parse(i: &[u8], _version: RwVersion) -> IResult<&[u8], Self> {
+        let start = i;
+        let (i, _platform_id) = le_u32(i)?;
+        let (i, flags) = le_u32(i)?;
+        let filtering = TextureFilteringMode::from_u32(flags & 0xFF)
+            .unwrap_or(TextureFilteringMode::FILTERNAFILTERMODE);
+        let address = |mode| {
+            TextureAddressingMode::from_u32(mode)
+                .unwrap_or(TextureAddressingMode::TEXTUREADDRESSNATEXTUREADDRESS)
+        };
+        let address_u = address((flags >> 8) & 0xF);
+        let address_v = address((flags >> 12) & 0xF);
+        let (i, name) = take(32usize)(i)?;
+        let name = String::from_utf8_lossy(name).trim_matches('\0').to_string();
+        let (i, mask_name) = take(32usize)(i)?;
+        let mask_name = String::from_utf8_lossy(mask_name)
+            .trim_matches('\0')
+            .to_string();
+        let (i, raster_format) = le_u32(i)?;
+        let raster_format = RasterFormat::from_bits_retain(raster_format);
+        let (i, has_alpha) = le_u16(i)?;
+        let (i, _unknown) = le_u16(i)?;
+        let (i, width) = le_u16(i)?;
+        let (i, height) = le_u16(i)?;
+        let (i, depth) = le_u8(i)?;
+        let (i, num_levels) = le_u8(i)?;
+        let (i, raster_type) = le_u8(i)?;
+        let (i, compression) = le_u8(i)?;
+        let (i, size) = le_u32(i)?;
+
+        let palette_size: usize = if raster_format.contains(RasterFormat::PAL8) {
+            256 * 4
+        } else if raster_format.contains(RasterFormat::PAL4) {
+            16 * 4
+        } else {
+            0
+        };
+        let (i, palette) = take(palette_size)(i)?;
+        let (i, data) = take(size)(i)?;
+
+        Ok((
+            i,
+            Self {
+                filtering,
+                address_u,
+                address_v,
+                name,
+                mask_name,
+                raster_format,
+                has_alpha: has_alpha != 0,
+                width,
+                height,
+                depth,
+                num_levels,
+                raster_type,
+                compression,
+                palette: palette.to_vec(),
+                data: data.to_vec(),
+                raw: start.to_vec(),
+            },
+        ))
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.compression != 0
+    }
+
+    // Base level of uncompressed 32 bit and 8 bit palettised rasters, which are stored swizzled
+    pub fn to_image(&self) -> Option<RgbaImage> {
+        if self.is_compressed() {
+            return None;
+        }
+        let (w, h) = (self.width as usize, self.height as usize);
+        let mut data = vec![0; w * h * 4];
+        let mut write = |x: usize, y: usize, bgra: &[u8]| {
+            let o = (y * w + x) * 4;
+            data[o..o + 4].copy_from_slice(&[bgra[2], bgra[1], bgra[0], bgra[3]]);
+        };
+
+        let base = self.raster_format.base_format();
+        match (self.depth, base) {
+            (8, _) if self.raster_format.contains(RasterFormat::PAL8) => {
+                let level = self.data.get(..w * h)?;
+                for y in 0..h {
+                    for x in 0..w {
+                        let index = level[swizzle(x, y, w, h)] as usize;
+                        write(x, y, self.palette.get(index * 4..index * 4 + 4)?);
+                    }
+                }
+            }
+            (32, Some(RasterBaseFormat::Format8888 | RasterBaseFormat::Format888)) => {
+                let level = self.data.get(..w * h * 4)?;
+                let opaque = base == Some(RasterBaseFormat::Format888);
+                for y in 0..h {
+                    for x in 0..w {
+                        let o = swizzle(x, y, w, h) * 4;
+                        let mut bgra = [level[o], level[o + 1], level[o + 2], level[o + 3]];
+                        if opaque {
+                            bgra[3] = 255;
+                        }
+                        write(x, y, &bgra);
+                    }
+                }
+            }
+            _ => return None,
+        }
+
+        Some(RgbaImage {
+            width: self.width as u32,
+            height: self.height as u32,
+            data,
+        })
+    }
+}
+
+// Index of pixel (x, y) in a Morton ordered surface, x and y bits are
+// interleaved until the smaller dimension runs out
+fn swizzle(x: usize, y: usize, w: usize, h: usize) -> usize {
+    let mut offset = 0;
+    let mut bit = 0;
+    let (mut x_mask, mut y_mask) = (1, 1);
+    while x_mask < w || y_mask < h {
+        if x_mask < w {
+            if x & x_mask != 0 {
+                offset |= 1 << bit;
+            }
+            bit += 1;
+            x_mask <<= 1;
+        }
+        if y_mask < h {
+            if y & y_mask != 0 {
+                offset |= 1 << bit;
+            }
+            bit += 1;
+            y_mask <<= 1;
+        }
+    }
+    offset
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsf::fixtures::chunk;
+    use crate::bsf::{tex, Chunk};
+
+    #[test]
+    fn xbox_raster() {
+        let mut data = PLATFORM_XBOX.to_le_bytes().to_vec();
+        data.extend(0x1102u32.to_le_bytes());
+        let mut name = [0; 32];
+        name[..3].copy_from_slice(b"tex");
+        data.extend(name);
+        data.extend([0; 32]);
+        data.extend(0x0500u32.to_le_bytes());
+        data.extend([1, 0, 0, 0]);
+        data.extend(4u16.to_le_bytes());
+        data.extend(2u16.to_le_bytes());
+        data.extend([32, 1, 4, 0]);
+        data.extend(32u32.to_le_bytes());
+        // BGRA texels in Morton order, (2, 0) is stored at index 4
+        for texel in 0..8u8 {
+            data.extend([0, 0, texel, 255]);
+        }
+        let file = chunk(0x15, &chunk(0x01, &data));
+        let (_, chunk) = Chunk::parse(&file).unwrap();
+
+        let raster = chunk.content.as_raster_xbox().unwrap();
+        assert_eq!(raster.name, "tex");
+        assert!(raster.has_alpha && !raster.is_compressed());
+        let base = raster.raster_format.base_format();
+        assert_eq!(base, Some(tex::RasterBaseFormat::Format8888));
+        let image = raster.to_image().unwrap();
+        let reds: Vec<u8> = image.data.chunks_exact(4).map(|p| p[0]).collect();
+        assert_eq!(reds, vec![0, 1, 4, 5, 2, 3, 6, 7]);
+        assert_eq!(chunk.to_bytes().unwrap(), file);
+    }
+}
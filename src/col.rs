@@ -0,0 +1,305 @@
+use nom::bytes::complete::take;
+use nom::multi::count;
+use nom::number::complete::{le_f32, le_i16, le_u16, le_u32, le_u8};
+use nom::IResult;
+
+// Surface properties of a collision face
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ColSurface {
+    pub material: u8,
+    pub flag: u8,
+    pub brightness: u8,
+    pub light: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ColFace {
+    pub indices: [u32; 3],
+    pub surface: ColSurface,
+}
+
+// Triangle mesh of a collision model. Spheres, boxes and shadow meshes aren't kept.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ColModel {
+    pub version: u8, // 1 for COLL, otherwise the digit of COL2/COL3/COL4
+    pub name: String,
+    pub model_id: u16,
+    pub vertices: Vec<[f32; 3]>,
+    pub faces: Vec<ColFace>,
+}
+
+type ColMesh = (Vec<[f32; 3]>, Vec<ColFace>);
+
+fn vec3(i: &[u8]) -> IResult<&[u8], [f32; 3]> {
+    let (i, x) = le_f32(i)?;
+    let (i, y) = le_f32(i)?;
+    let (i, z) = le_f32(i)?;
+    Ok((i, [x, y, z]))
+}
+
+fn surface(i: &[u8]) -> IResult<&[u8], ColSurface> {
+    let (i, material) = le_u8(i)?;
+    let (i, flag) = le_u8(i)?;
+    let (i, brightness) = le_u8(i)?;
+    let (i, light) = le_u8(i)?;
+    Ok((
+        i,
+        ColSurface {
+            material,
+            flag,
+            brightness,
+            light,
+        },
+    ))
+}
+
+fn tag_error(i: &[u8]) -> nom::Err<nom::error::Error<&[u8]>> {
+    nom::Err::Error(nom::error::Error::new(i, nom::error::ErrorKind::Tag))
+}
+
+impl ColModel {
+    // Parses one model and returns the data following it, COL files contain several in a row
+    pub fn parse(i: &[u8]) -> IResult<&[u8], Self> {
+        let start = i;
+        let (i, fourcc) = take(4usize)(i)?;
+        let version = match fourcc {
+            b"COLL" => 1,
+            [b'C', b'O', b'L', v @ b'2'..=b'4'] => v - b'0',
+            _ => return Err(tag_error(i)),
+        };
+        let (i, size) = le_u32(i)?;
+        // Offsets in COL2+ are relative to the end of the FourCC
+        let (rest, body) = take(size)(i)?;
+        let (i, name) = take(22usize)(body)?;
+        let name =
+            String::from_utf8_lossy(name.split(|c| *c == 0).next().unwrap_or(&[])).to_string();
+        let (i, model_id) = le_u16(i)?;
+
+        let (vertices, faces) = if version == 1 {
+            Self::parse_col1_mesh(i)?.1
+        } else {
+            Self::parse_col2_mesh(i, &start[4..4 + 4 + size as usize])?
+        };
+
+        Ok((
+            rest,
+            Self {
+                version,
+                name,
+                model_id,
+                vertices,
+                faces,
+            },
+        ))
+    }
+
+    pub fn parse_file(mut i: &[u8]) -> IResult<&[u8], Vec<Self>> {
+        let mut models = Vec::new();
+        while i.len() >= 8 {
+            let model;
+            (i, model) = Self::parse(i)?;
+            models.push(model);
+        }
+        Ok((i, models))
+    }
+
+    fn parse_col1_mesh(i: &[u8]) -> IResult<&[u8], ColMesh> {
+        let (i, _bounds) = take(40usize)(i)?;
+        let (i, num_spheres) = le_u32(i)?;
+        let (i, _spheres) = take(num_spheres as usize * 20)(i)?;
+        let (i, _num_unknown) = le_u32(i)?;
+        let (i, num_boxes) = le_u32(i)?;
+        let (i, _boxes) = take(num_boxes as usize * 28)(i)?;
+        let (i, num_vertices) = le_u32(i)?;
+        let (i, vertices) = count(vec3, num_vertices as usize)(i)?;
+        let (i, num_faces) = le_u32(i)?;
+        let (i, faces) = count(
+            |i| {
+                let (i, a) = le_u32(i)?;
+                let (i, b) = le_u32(i)?;
+                let (i, c) = le_u32(i)?;
+                let (i, surface) = surface(i)?;
+                Ok((
+                    i,
+                    ColFace {
+                        indices: [a, b, c],
+                        surface,
+                    },
+                ))
+            },
+            num_faces as usize,
+        )(i)?;
+        Ok((i, (vertices, faces)))
+    }
+
+    // base starts right after the FourCC, where offsets are counted from
+    fn parse_col2_mesh<'a>(
+        i: &'a [u8],
+        base: &'a [u8],
+    ) -> Result<ColMesh, nom::Err<nom::error::Error<&'a [u8]>>> {
+        let (i, _bounds) = take(40usize)(i)?;
+        let (i, _num_spheres) = le_u16(i)?;
+        let (i, _num_boxes) = le_u16(i)?;
+        let (i, num_faces) = le_u16(i)?;
+        let (i, _num_lines) = le_u8(i)?;
+        let (i, _pad) = le_u8(i)?;
+        let (i, _flags) = le_u32(i)?;
+        let (i, _off_spheres) = le_u32(i)?;
+        let (i, _off_boxes) = le_u32(i)?;
+        let (i, _off_lines) = le_u32(i)?;
+        let (i, off_vertices) = le_u32(i)?;
+        let (_, off_faces) = le_u32(i)?;
+
+        let at = |offset: u32| base.get(offset as usize..).ok_or_else(|| tag_error(base));
+        let (_, faces) = count(
+            |i| {
+                let (i, a) = le_u16(i)?;
+                let (i, b) = le_u16(i)?;
+                let (i, c) = le_u16(i)?;
+                let (i, material) = le_u8(i)?;
+                let (i, light) = le_u8(i)?;
+                Ok((
+                    i,
+                    ColFace {
+                        indices: [a as u32, b as u32, c as u32],
+                        surface: ColSurface {
+                            material,
+                            light,
+                            ..Default::default()
+                        },
+                    },
+                ))
+            },
+            num_faces as usize,
+        )(at(off_faces)?)?;
+
+        // The vertex count isn't stored, only as many as the faces use are read
+        let num_vertices = faces
+            .iter()
+            .flat_map(|f| f.indices)
+            .max()
+            .map_or(0, |m| m as usize + 1);
+        let (_, vertices) = count(
+            |i| {
+                let (i, x) = le_i16(i)?;
+                let (i, y) = le_i16(i)?;
+                let (i, z) = le_i16(i)?;
+                Ok((i, [x, y, z].map(|c| c as f32 / 128.0)))
+            },
+            num_vertices,
+        )(at(off_vertices)?)?;
+
+        Ok((vertices, faces))
+    }
+
+    // Writes the model as COL1, which stores uncompressed vertices and needs no offsets
+    pub fn write_col1(&self) -> Vec<u8> {
+        let mut min = [f32::MAX; 3];
+        let mut max = [f32::MIN; 3];
+        for v in &self.vertices {
+            for c in 0..3 {
+                min[c] = min[c].min(v[c]);
+                max[c] = max[c].max(v[c]);
+            }
+        }
+        if self.vertices.is_empty() {
+            (min, max) = ([0.0; 3], [0.0; 3]);
+        }
+        let center = [0, 1, 2].map(|c| (min[c] + max[c]) / 2.0);
+        let radius = self
+            .vertices
+            .iter()
+            .map(|v| {
+                (0..3)
+                    .map(|c| (v[c] - center[c]).powi(2))
+                    .sum::<f32>()
+                    .sqrt()
+            })
+            .fold(0.0, f32::max);
+
+        let mut body = Vec::new();
+        let mut name = [0u8; 22];
+        let len = self.name.len().min(21);
+        name[..len].copy_from_slice(&self.name.as_bytes()[..len]);
+        body.extend(name);
+        body.extend(self.model_id.to_le_bytes());
+        body.extend(radius.to_le_bytes());
+        for v in [center, min, max] {
+            body.extend(v.iter().flat_map(|c| c.to_le_bytes()));
+        }
+        // No spheres, unknown entries or boxes
+        body.extend([0; 12]);
+        body.extend((self.vertices.len() as u32).to_le_bytes());
+        for v in &self.vertices {
+            body.extend(v.iter().flat_map(|c| c.to_le_bytes()));
+        }
+        body.extend((self.faces.len() as u32).to_le_bytes());
+        for f in &self.faces {
+            body.extend(f.indices.iter().flat_map(|i| i.to_le_bytes()));
+            let s = f.surface;
+            body.extend([s.material, s.flag, s.brightness, s.light]);
+        }
+
+        let mut data = b"COLL".to_vec();
+        data.extend((body.len() as u32).to_le_bytes());
+        data.extend(body);
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn col1_round_trip() {
+        let model = ColModel {
+            version: 1,
+            name: "barrier".into(),
+            model_id: 1422,
+            vertices: vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 2.0, 0.5]],
+            faces: vec![ColFace {
+                indices: [0, 1, 2],
+                surface: ColSurface {
+                    material: 4,
+                    ..Default::default()
+                },
+            }],
+        };
+        let mut data = model.write_col1();
+        data.extend(model.write_col1());
+
+        let (rest, models) = ColModel::parse_file(&data).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(models, vec![model.clone(), model]);
+    }
+
+    #[test]
+    fn col3_mesh() {
+        let mut body = vec![0; 22];
+        body.extend(7u16.to_le_bytes());
+        body.extend([0; 40]);
+        body.extend([0, 0, 0, 0, 1, 0, 0, 0]);
+        // Offsets count from the size field, past the flags and five offsets written here
+        let header_end = 4 + body.len() as u32 + 24;
+        for offset in [0, 0, 0, 0, header_end, header_end + 18] {
+            body.extend(offset.to_le_bytes());
+        }
+        for v in [[0i16, 0, 0], [128, 0, 0], [0, 256, -64]] {
+            body.extend(v.iter().flat_map(|c| c.to_le_bytes()));
+        }
+        body.extend([0, 0, 1, 0, 2, 0, 9, 0]);
+        let mut data = b"COL3".to_vec();
+        data.extend((body.len() as u32).to_le_bytes());
+        data.extend(body);
+
+        let (_, model) = ColModel::parse(&data).unwrap();
+        assert_eq!((model.version, model.model_id), (3, 7));
+        assert_eq!(model.vertices[2], [0.0, 2.0, -0.5]);
+        assert_eq!(model.faces[0].indices, [0, 1, 2]);
+        assert_eq!(model.faces[0].surface.material, 9);
+    }
+}
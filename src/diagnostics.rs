@@ -0,0 +1,91 @@
+use std::cell::{Cell, RefCell};
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning, // Data was recovered or skipped, the result may be incomplete
+    Error,
+}
+
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub module: &'static str, // "bsf", "img", ...
+    pub offset: Option<u64>,  // Byte offset into the parsed file, if known
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} [{}]", self.severity, self.module)?;
+        if let Some(offset) = self.offset {
+            write!(f, " at {:#x}", offset)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Collects problems found while parsing so tools can report them in one place.
+/// Reporting only needs a shared reference, so one collector can be passed through nested parsers.
+#[derive(Clone, Debug, Default)]
+pub struct Diagnostics {
+    entries: RefCell<Vec<Diagnostic>>,
+    // Start of the outermost buffer being parsed, used to turn slices into offsets
+    origin: Cell<Option<usize>>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn report(
+        &self,
+        severity: Severity,
+        module: &'static str,
+        offset: Option<u64>,
+        message: String,
+    ) {
+        self.entries.borrow_mut().push(Diagnostic {
+            severity,
+            module,
+            offset,
+            message,
+        });
+    }
+
+    pub fn warn(&self, module: &'static str, offset: Option<u64>, message: String) {
+        self.report(Severity::Warning, module, offset, message);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.borrow().is_empty()
+    }
+
+    pub fn take(&self) -> Vec<Diagnostic> {
+        self.entries.take()
+    }
+
+    // Runs f with i as the buffer offsets are relative to, unless an outer call already set one
+    pub(crate) fn with_origin<T>(&self, i: &[u8], f: impl FnOnce() -> T) -> T {
+        let outermost = self.origin.get().is_none();
+        if outermost {
+            self.origin.set(Some(i.as_ptr() as usize));
+        }
+        let res = f();
+        if outermost {
+            self.origin.set(None);
+        }
+        res
+    }
+
+    pub(crate) fn offset_of(&self, i: &[u8]) -> Option<u64> {
+        let origin = self.origin.get()?;
+        (i.as_ptr() as usize).checked_sub(origin).map(|o| o as u64)
+    }
+}
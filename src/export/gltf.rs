@@ -0,0 +1,428 @@
+use serde_json::{json, Map, Value};
+
+use crate::scene::{SceneAddressMode, SceneGraph, SceneMesh, SceneSampler};
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const UNSIGNED_SHORT: u32 = 5123;
+const UNSIGNED_INT: u32 = 5125;
+const FLOAT: u32 = 5126;
+
+// Name of the node that holds the RenderWare roots
+pub const ROOT_NAME: &str = "rw_root";
+
+// RenderWare is Z-up, glTF is Y-up
+const Z_UP_TO_Y_UP: [[f32; 4]; 4] = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 0.0, -1.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+// glTF document with its single binary buffer. Textures are referenced as <name>.png next to the
+// file, extracting them from the TXD is up to the caller.
+#[derive(Clone, Debug)]
+pub struct Gltf {
+    pub json: Value,
+    pub buffer: Vec<u8>,
+}
+
+impl Gltf {
+    // .gltf file with the buffer embedded as a data URI
+    pub fn to_gltf(&self) -> String {
+        let mut json = self.json.clone();
+        json["buffers"][0]["uri"] = format!(
+            "data:application/octet-stream;base64,{}",
+            base64(&self.buffer)
+        )
+        .into();
+        json.to_string()
+    }
+
+    pub fn to_glb(&self) -> Vec<u8> {
+        let mut json = self.json.to_string().into_bytes();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        let mut buffer = self.buffer.clone();
+        buffer.resize(buffer.len().next_multiple_of(4), 0);
+
+        let total = 12 + 8 + json.len() + 8 + buffer.len();
+        let mut data = b"glTF".to_vec();
+        data.extend(2u32.to_le_bytes());
+        data.extend((total as u32).to_le_bytes());
+        data.extend((json.len() as u32).to_le_bytes());
+        data.extend(b"JSON");
+        data.extend(json);
+        data.extend((buffer.len() as u32).to_le_bytes());
+        data.extend(b"BIN\0");
+        data.extend(buffer);
+        data
+    }
+}
+
+#[derive(Default)]
+struct Builder {
+    buffer: Vec<u8>,
+    views: Vec<Value>,
+    accessors: Vec<Value>,
+}
+
+impl Builder {
+    fn view(&mut self, data: &[u8], target: Option<u32>) -> usize {
+        self.buffer.resize(self.buffer.len().next_multiple_of(4), 0);
+        let mut view = json!({
+            "buffer": 0,
+            "byteOffset": self.buffer.len(),
+            "byteLength": data.len(),
+        });
+        if let Some(target) = target {
+            view["target"] = target.into();
+        }
+        self.buffer.extend(data);
+        self.views.push(view);
+        self.views.len() - 1
+    }
+
+    fn floats<const N: usize>(&mut self, values: &[[f32; N]], ty: &str, bounds: bool) -> usize {
+        let data: Vec<u8> = values
+            .iter()
+            .flatten()
+            .flat_map(|f| f.to_le_bytes())
+            .collect();
+        let view = self.view(&data, Some(ARRAY_BUFFER));
+        let mut accessor = json!({
+            "bufferView": view,
+            "componentType": FLOAT,
+            "count": values.len(),
+            "type": ty,
+        });
+        // Required for positions
+        if bounds {
+            let mut min = [f32::MAX; N];
+            let mut max = [f32::MIN; N];
+            for v in values {
+                for c in 0..N {
+                    min[c] = min[c].min(v[c]);
+                    max[c] = max[c].max(v[c]);
+                }
+            }
+            accessor["min"] = json!(min.as_slice());
+            accessor["max"] = json!(max.as_slice());
+        }
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    fn joints(&mut self, values: &[[u16; 4]]) -> usize {
+        let data: Vec<u8> = values
+            .iter()
+            .flatten()
+            .flat_map(|j| j.to_le_bytes())
+            .collect();
+        let view = self.view(&data, Some(ARRAY_BUFFER));
+        self.accessors.push(json!({
+            "bufferView": view,
+            "componentType": UNSIGNED_SHORT,
+            "count": values.len(),
+            "type": "VEC4",
+        }));
+        self.accessors.len() - 1
+    }
+
+    fn indices(&mut self, values: &[u32]) -> usize {
+        let data: Vec<u8> = values.iter().flat_map(|i| i.to_le_bytes()).collect();
+        let view = self.view(&data, Some(ELEMENT_ARRAY_BUFFER));
+        self.accessors.push(json!({
+            "bufferView": view,
+            "componentType": UNSIGNED_INT,
+            "count": values.len(),
+            "type": "SCALAR",
+        }));
+        self.accessors.len() - 1
+    }
+
+    fn matrices(&mut self, values: &[[[f32; 4]; 4]]) -> usize {
+        let data: Vec<u8> = values
+            .iter()
+            .flatten()
+            .flatten()
+            .flat_map(|f| f.to_le_bytes())
+            .collect();
+        let view = self.view(&data, None);
+        self.accessors.push(json!({
+            "bufferView": view,
+            "componentType": FLOAT,
+            "count": values.len(),
+            "type": "MAT4",
+        }));
+        self.accessors.len() - 1
+    }
+
+    fn mesh(&mut self, mesh: &SceneMesh) -> Value {
+        let mut attributes = Map::new();
+        attributes.insert(
+            "POSITION".into(),
+            self.floats(&mesh.positions, "VEC3", true).into(),
+        );
+        let n = mesh.positions.len();
+        if mesh.normals.len() == n {
+            attributes.insert(
+                "NORMAL".into(),
+                self.floats(&mesh.normals, "VEC3", false).into(),
+            );
+        }
+        if mesh.uvs.len() == n {
+            attributes.insert(
+                "TEXCOORD_0".into(),
+                self.floats(&mesh.uvs, "VEC2", false).into(),
+            );
+        }
+        if mesh.colors.len() == n {
+            attributes.insert(
+                "COLOR_0".into(),
+                self.floats(&mesh.colors, "VEC4", false).into(),
+            );
+        }
+        if mesh.skin.is_some() && mesh.joints.len() == n && mesh.weights.len() == n {
+            attributes.insert("JOINTS_0".into(), self.joints(&mesh.joints).into());
+            attributes.insert(
+                "WEIGHTS_0".into(),
+                self.floats(&mesh.weights, "VEC4", false).into(),
+            );
+        }
+
+        // Targets store offsets from the base positions
+        let targets: Vec<Value> = mesh
+            .morph_targets
+            .iter()
+            .filter(|t| t.len() == n)
+            .map(|target| {
+                let deltas: Vec<[f32; 3]> = target
+                    .iter()
+                    .zip(&mesh.positions)
+                    .map(|(t, p)| [t[0] - p[0], t[1] - p[1], t[2] - p[2]])
+                    .collect();
+                json!({ "POSITION": self.floats(&deltas, "VEC3", true) })
+            })
+            .collect();
+
+        let primitives: Vec<Value> = mesh
+            .primitives
+            .iter()
+            .filter(|p| !p.indices.is_empty())
+            .map(|prim| {
+                let mut primitive = json!({
+                    "attributes": attributes,
+                    "indices": self.indices(&prim.indices),
+                });
+                if let Some(material) = prim.material {
+                    primitive["material"] = material.into();
+                }
+                if !targets.is_empty() {
+                    primitive["targets"] = json!(targets);
+                }
+                primitive
+            })
+            .collect();
+        let mut mesh = json!({ "primitives": primitives });
+        // The base positions are shown until a weight is animated
+        if !targets.is_empty() {
+            mesh["weights"] = json!(vec![0.0; targets.len()]);
+        }
+        mesh
+    }
+}
+
+fn wrap_mode(mode: SceneAddressMode) -> u32 {
+    match mode {
+        SceneAddressMode::Repeat => 10497,
+        SceneAddressMode::Mirror => 33648,
+        // glTF has no border color
+        SceneAddressMode::ClampToEdge | SceneAddressMode::ClampToBorder => 33071,
+    }
+}
+
+fn sampler(sampler: &SceneSampler) -> Value {
+    json!({
+        "wrapS": wrap_mode(sampler.address_u),
+        "wrapT": wrap_mode(sampler.address_v),
+    })
+}
+
+pub fn export(scene: &SceneGraph) -> Gltf {
+    let mut builder = Builder::default();
+    let meshes: Vec<Value> = scene.meshes.iter().map(|m| builder.mesh(m)).collect();
+
+    let mut nodes: Vec<Value> = scene
+        .nodes
+        .iter()
+        .map(|node| {
+            let mut value = json!({ "matrix": node.transform.concat() });
+            if let Some(name) = &node.name {
+                value["name"] = name.as_str().into();
+            }
+            if !node.children.is_empty() {
+                value["children"] = json!(node.children);
+            }
+            if let Some(mesh) = node.mesh {
+                value["mesh"] = mesh.into();
+                if let Some(skin) = scene.meshes[mesh].skin {
+                    value["skin"] = skin.into();
+                }
+            }
+            value
+        })
+        .collect();
+    let roots: Vec<usize> = scene.roots().collect();
+    nodes.push(json!({
+        "name": ROOT_NAME,
+        "matrix": Z_UP_TO_Y_UP.concat(),
+        "children": roots,
+    }));
+
+    let skins: Vec<Value> = scene
+        .skins
+        .iter()
+        .map(|skin| {
+            json!({
+                "joints": skin.joints,
+                "inverseBindMatrices": builder.matrices(&skin.inverse_bind_matrices),
+            })
+        })
+        .collect();
+
+    let materials: Vec<Value> = scene
+        .materials
+        .iter()
+        .map(|material| {
+            let mut pbr = json!({
+                "baseColorFactor": material.color,
+                "metallicFactor": 0.0,
+                "roughnessFactor": 1.0,
+            });
+            if let Some(texture) = material.texture {
+                pbr["baseColorTexture"] = json!({ "index": texture });
+            }
+            let mut value = json!({ "pbrMetallicRoughness": pbr });
+            if scene.is_transparent(material) {
+                value["alphaMode"] = "BLEND".into();
+            }
+            value
+        })
+        .collect();
+    let images: Vec<Value> = scene
+        .textures
+        .iter()
+        .map(|t| json!({ "uri": format!("{}.png", t.name), "name": t.name }))
+        .collect();
+    let samplers: Vec<Value> = scene.textures.iter().map(|t| sampler(&t.sampler)).collect();
+    let textures: Vec<Value> = (0..scene.textures.len())
+        .map(|i| json!({ "source": i, "sampler": i }))
+        .collect();
+
+    let mut json = json!({
+        "asset": { "version": "2.0", "generator": "rw-rs" },
+        "scene": 0,
+        "scenes": [{ "nodes": [nodes.len() - 1] }],
+        "nodes": nodes,
+        "meshes": meshes,
+        "materials": materials,
+        "textures": textures,
+        "images": images,
+        "samplers": samplers,
+        "skins": skins,
+        "accessors": builder.accessors,
+        "bufferViews": builder.views,
+        "buffers": [{ "byteLength": builder.buffer.len() }],
+    });
+    // Empty arrays aren't allowed at the top level
+    if let Value::Object(map) = &mut json {
+        map.retain(|_, v| v.as_array().map_or(true, |a| !a.is_empty()));
+    }
+    Gltf {
+        json,
+        buffer: builder.buffer,
+    }
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for k in 0..4 {
+            if k <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * k) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsf::builder::{ClumpBuilder, GeometryBuilder};
+    use crate::bsf::RwVersion;
+    use crate::scene::IntoSceneGraph;
+
+    #[test]
+    fn export_clump() {
+        let mut geometry = GeometryBuilder::new();
+        geometry
+            .vertices(vec![[0.0; 3], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]])
+            .tex_coords(vec![[0.0; 2]; 3]);
+        let material = geometry.material([255; 4], Some("crate"));
+        geometry.triangle([0, 1, 2], material);
+        let mut clump = ClumpBuilder::new(RwVersion::VC_PC);
+        let frame = clump.frame(Some("crate"), None, [0.0; 3]);
+        let geometry = clump.geometry(geometry).unwrap();
+        clump.atomic(frame, geometry);
+
+        let clump = clump.build();
+        let gltf = export(&clump.to_scene_graph());
+        let json = &gltf.json;
+        assert_eq!(json["nodes"][0]["name"], "crate");
+        assert_eq!(json["nodes"][0]["mesh"], 0);
+        assert_eq!(json["scenes"][0]["nodes"][0], 1);
+        assert_eq!(json["images"][0]["uri"], "crate.png");
+        // Textured materials without a mask are opaque
+        assert!(json["materials"][0].get("alphaMode").is_none());
+        let position = &json["accessors"][0];
+        assert_eq!(position["max"], json!([1.0, 1.0, 0.0]));
+        assert!(json.get("skins").is_none());
+        assert_eq!(gltf.buffer.len(), 3 * 12 + 3 * 8 + 3 * 4);
+
+        let glb = gltf.to_glb();
+        assert_eq!(&glb[..4], b"glTF");
+        assert_eq!(
+            u32::from_le_bytes(glb[8..12].try_into().unwrap()) as usize,
+            glb.len()
+        );
+        assert!(gltf
+            .to_gltf()
+            .contains("data:application/octet-stream;base64,"));
+        assert!(json["meshes"][0].get("weights").is_none());
+
+        let mut scene = clump.to_scene_graph();
+        scene.meshes[0]
+            .morph_targets
+            .push(vec![[0.0, 0.0, 1.0], [1.0, 0.0, 1.0], [0.0, 1.0, 1.0]]);
+        let json = export(&scene).json;
+        assert_eq!(json["meshes"][0]["weights"], json!([0.0]));
+        assert!(json["meshes"][0]["primitives"][0]["targets"][0]["POSITION"].is_u64());
+    }
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(base64(b"rw"), "cnc=");
+        assert_eq!(base64(b"rw-rs"), "cnctcnM=");
+        assert_eq!(base64(b"dff"), "ZGZm");
+    }
+}
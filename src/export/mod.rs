@@ -0,0 +1,3 @@
+#[cfg(feature = "gltf")]
+pub mod gltf;
+pub mod obj;
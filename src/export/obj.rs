@@ -0,0 +1,118 @@
+use std::io::{self, Write};
+
+use crate::bsf::geo::RpGeometry;
+use crate::bsf::tex::RpMaterialList;
+use crate::bsf::Chunk;
+
+// Groups are named after the material id of their triangles, matching the names written by
+// write_mtl
+pub fn write_obj<W: Write>(w: &mut W, geo: &RpGeometry, mtllib: Option<&str>) -> io::Result<()> {
+    if let Some(mtllib) = mtllib {
+        writeln!(w, "mtllib {}", mtllib)?;
+    }
+    for v in geo.vertices() {
+        writeln!(w, "v {} {} {}", v.x, v.y, v.z)?;
+    }
+    let has_normals = !geo.normals().is_empty();
+    for n in geo.normals() {
+        writeln!(w, "vn {} {} {}", n.x, n.y, n.z)?;
+    }
+    // OBJ puts the V origin at the bottom
+    let tex_coords = geo.tex_coords.first().filter(|t| !t.is_empty());
+    for uv in tex_coords.into_iter().flatten() {
+        writeln!(w, "vt {} {}", uv.u, 1.0 - uv.v)?;
+    }
+
+    let mut material_ids: Vec<u16> = geo.triangles.iter().map(|t| t.material_id).collect();
+    material_ids.sort_unstable();
+    material_ids.dedup();
+    for id in material_ids {
+        writeln!(w, "g material_{}", id)?;
+        writeln!(w, "usemtl material_{}", id)?;
+        for tri in geo.triangles.iter().filter(|t| t.material_id == id) {
+            write!(w, "f")?;
+            for i in tri.as_arr() {
+                // Indices are 1-based
+                let i = i as u32 + 1;
+                match (tex_coords.is_some(), has_normals) {
+                    (true, true) => write!(w, " {i}/{i}/{i}")?,
+                    (true, false) => write!(w, " {i}/{i}")?,
+                    (false, true) => write!(w, " {i}//{i}")?,
+                    (false, false) => write!(w, " {i}")?,
+                }
+            }
+            writeln!(w)?;
+        }
+    }
+    Ok(())
+}
+
+// Writes one material per material id. Textures are referenced as <name>.png.
+pub fn write_mtl<W: Write>(w: &mut W, list: &RpMaterialList) -> io::Result<()> {
+    for id in 0..list.len() as u32 {
+        let Some(material) = list.get(id) else {
+            continue;
+        };
+        let [r, g, b, a] = material.color.as_rgba_arr();
+        writeln!(w, "newmtl material_{}", id)?;
+        writeln!(w, "Kd {} {} {}", r, g, b)?;
+        writeln!(w, "d {}", a)?;
+        if let Some(name) = &material.diffuse_tex {
+            writeln!(w, "map_Kd {}.png", name)?;
+        }
+        writeln!(w)?;
+    }
+    Ok(())
+}
+
+// Writes both files for a Geometry chunk, returning (obj, mtl)
+pub fn export_geometry(geometry: &Chunk, mtllib: &str) -> Option<(String, String)> {
+    let geo = geometry.content.as_geometry()?;
+    let mut obj = Vec::new();
+    write_obj(&mut obj, geo, Some(mtllib)).ok()?;
+    let mut mtl = Vec::new();
+    if let Some(list) = geometry
+        .get_children()
+        .iter()
+        .find_map(|c| c.content.as_material_list())
+    {
+        write_mtl(&mut mtl, list).ok()?;
+    }
+    Some((String::from_utf8(obj).ok()?, String::from_utf8(mtl).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsf::builder::GeometryBuilder;
+    use crate::bsf::RwVersion;
+
+    #[test]
+    fn export_obj() {
+        let mut geometry = GeometryBuilder::new();
+        geometry
+            .vertices(vec![
+                [0.0; 3],
+                [1.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [1.0, 1.0, 0.0],
+            ])
+            .tex_coords(vec![[0.0, 0.25]; 4]);
+        let textured = geometry.material([255, 0, 0, 255], Some("crate"));
+        let plain = geometry.material([0, 0, 255, 128], None);
+        geometry.triangle([0, 1, 2], textured);
+        geometry.triangle([1, 3, 2], plain);
+        let chunk = geometry.build(RwVersion::VC_PC).unwrap();
+
+        let (obj, mtl) = export_geometry(&chunk, "crate.mtl").unwrap();
+        let lines: Vec<&str> = obj.lines().collect();
+        assert_eq!(lines[0], "mtllib crate.mtl");
+        assert_eq!(lines.iter().filter(|l| l.starts_with("v ")).count(), 4);
+        assert!(lines.contains(&"vt 0 0.75"));
+        assert!(lines.contains(&"usemtl material_1"));
+        assert!(lines.contains(&"f 2/2 4/4 3/3"));
+
+        assert!(mtl.contains("newmtl material_0\nKd 1 0 0\nd 1\nmap_Kd crate.png\n"));
+        assert!(mtl.contains("newmtl material_1\nKd 0 0 1\n"));
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Game {
+    GTA3,
+    VC,
+    SA,
+}
+
+// IDE sections whose lines start with "id, model name"
+const MODEL_SECTIONS: [&str; 8] = [
+    "objs", "tobj", "anim", "peds", "cars", "weap", "hier", "tanm",
+];
+
+/// Model id to model name tables per game, filled from IDE files.
+#[derive(Clone, Debug, Default)]
+pub struct ModelIdMap {
+    tables: HashMap<Game, HashMap<u32, String>>,
+}
+
+impl ModelIdMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load_ide_file(&mut self, game: Game, path: &Path) -> Result<()> {
+        let bytes = fs::read(path)?;
+        self.load_ide(game, &String::from_utf8_lossy(&bytes));
+        Ok(())
+    }
+
+    pub fn load_ide(&mut self, game: Game, text: &str) {
+        let table = self.tables.entry(game).or_default();
+        let mut in_model_section = false;
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.eq_ignore_ascii_case("end") {
+                in_model_section = false;
+                continue;
+            }
+            if !line.contains(',') && !line.contains(' ') {
+                in_model_section = MODEL_SECTIONS.iter().any(|s| s.eq_ignore_ascii_case(line));
+                continue;
+            }
+            if !in_model_section {
+                continue;
+            }
+
+            let mut fields = line.split(',').map(str::trim);
+            let id = fields.next().and_then(|f| f.parse::<u32>().ok());
+            let name = fields.next().filter(|f| !f.is_empty());
+            if let (Some(id), Some(name)) = (id, name) {
+                table.insert(id, name.to_ascii_lowercase());
+            }
+        }
+    }
+
+    pub fn name(&self, game: Game, id: u32) -> Option<&str> {
+        self.tables.get(&game)?.get(&id).map(|s| s.as_str())
+    }
+
+    pub fn id(&self, game: Game, name: &str) -> Option<u32> {
+        let name = name.to_ascii_lowercase();
+        self.tables
+            .get(&game)?
+            .iter()
+            .find_map(|(id, n)| (*n == name).then_some(*id))
+    }
+
+    pub fn len(&self, game: Game) -> usize {
+        self.tables.get(&game).map_or(0, |t| t.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_ide() {
+        let mut map = ModelIdMap::new();
+        map.load_ide(
+            Game::VC,
+            "# comment\nobjs\n300, LODwash, generic, 1, 300, 0\nend\n\
+             cars\n130, Landstal, landstal, car, LANDSTAL, LANDSTK, null, normal, 10, 7, 0\nend\n\
+             path\n0, 300, LODwash\nend\n",
+        );
+        assert_eq!(map.name(Game::VC, 130), Some("landstal"));
+        assert_eq!(map.id(Game::VC, "LODWASH"), Some(300));
+        assert_eq!(map.len(Game::VC), 2);
+        assert_eq!(map.name(Game::SA, 130), None);
+    }
+}
@@ -1,10 +1,13 @@
 use std::collections::HashMap;
 use std::ffi::CString;
+use std::fmt;
 use std::fs::File;
 use std::io::Read;
 use std::io::Seek;
 use std::io::SeekFrom;
+use std::io::Write;
 use std::path::Path;
+use std::sync::Mutex;
 
 use anyhow::bail;
 use binrw::until_eof;
@@ -12,75 +15,913 @@ use binrw::BinRead;
 
 use anyhow::Result;
 
+use crate::bsf::RwVersion;
+use crate::diagnostics::Diagnostics;
+
 pub trait ReadSeek: Read + Seek + Send + Sync {}
 impl<T: Read + Seek + Send + Sync> ReadSeek for T {}
 
+// Reads only need a shared reference, so an archive can be shared between threads for parallel
+// loading. They take turns on the underlying reader, each seek and read happening under its lock,
+// except for archives opened from a slice which are read without locking. Changes need exclusive
+// access.
 pub struct Img<'a> {
+    version: ImgVersion,
     entries: HashMap<String, DirEnt>,
-    img_reader: Box<dyn ReadSeek + 'a>,
+    directory: Vec<DirEnt>, // As stored, including hidden duplicates
+    pending: HashMap<String, (String, Vec<u8>)>, // Added or replaced data by name, until rebuilt
+    img_reader: Mutex<Box<dyn ReadSeek + 'a>>,
+    mapped: Option<&'a [u8]>, // Whole archive when it is already in memory
 }
 impl<'a> Img<'a> {
     pub fn new(path: &Path) -> Result<Img<'a>> {
-        if !path.extension().map_or(false, |x| x == "img") {
+        Self::new_with(path, &Diagnostics::new())
+    }
+
+    pub fn new_with(path: &Path, diagnostics: &Diagnostics) -> Result<Img<'a>> {
+        if path.extension().map_or(true, |x| x != "img") {
             bail!("File does not end in .img")
         }
         let img_file = File::open(path)?;
         let dir_path = path.with_extension("dir");
         if let Ok(mut dir_file) = File::open(dir_path) {
-            return Img::from_v1(img_file, &mut dir_file);
+            Img::from_v1_with(img_file, &mut dir_file, diagnostics)
         } else {
-            return Img::from_v2(img_file);
+            Img::from_v2_with(img_file, diagnostics)
         }
     }
 
-    pub fn from_v1<R, S>(img_reader: R, mut dir_reader: S) -> Result<Img<'a>>
+    pub fn from_v1<R, S>(img_reader: R, dir_reader: S) -> Result<Img<'a>>
     where
         R: ReadSeek + 'a,
         S: ReadSeek,
     {
-        let mut map = HashMap::new();
-        {
-            let list = DirList::read(&mut dir_reader)?;
-            for entry in list.entries {
-                map.insert(
-                    entry
-                        .name
-                        .clone()
-                        .into_string()
-                        .unwrap()
-                        .to_ascii_lowercase(),
-                    entry,
-                );
-            }
-        }
+        Self::from_v1_with(img_reader, dir_reader, &Diagnostics::new())
+    }
+
+    pub fn from_v1_with<R, S>(
+        img_reader: R,
+        mut dir_reader: S,
+        diagnostics: &Diagnostics,
+    ) -> Result<Img<'a>>
+    where
+        R: ReadSeek + 'a,
+        S: ReadSeek,
+    {
+        let list = DirList::read(&mut dir_reader)?;
+        // Offsets are into the .dir file
+        let entries = index_entries(list.entries.clone(), 0, diagnostics);
         Ok(Img {
-            entries: map,
-            img_reader: Box::new(img_reader),
+            version: ImgVersion::V1,
+            entries,
+            directory: list.entries,
+            pending: HashMap::new(),
+            img_reader: Mutex::new(Box::new(img_reader)),
+            mapped: None,
         })
     }
 
-    pub fn from_v2<R>(mut _img_reader: R) -> Result<Img<'a>>
+    pub fn from_v2<R>(img_reader: R) -> Result<Img<'a>>
+    where
+        R: ReadSeek + 'a,
+    {
+        Self::from_v2_with(img_reader, &Diagnostics::new())
+    }
+
+    // San Andreas archives hold their directory after an 8 byte header at the start of the .img
+    pub fn from_v2_with<R>(mut img_reader: R, diagnostics: &Diagnostics) -> Result<Img<'a>>
     where
-        R: ReadSeek,
+        R: ReadSeek + 'a,
     {
-        unimplemented!("V2 .IMG files (San Andreas) not yet supported")
+        let header = V2Header::read(&mut img_reader)?;
+        if &header.magic != b"VER2" {
+            bail!("Not a version 2 IMG, magic is {:?}", header.magic)
+        }
+        let directory: Vec<_> = header
+            .entries
+            .into_iter()
+            .map(|entry| DirEnt {
+                offset: entry.offset,
+                // The archive size is always 0 in the shipped archives, the streaming size is used
+                size: if entry.archive_size != 0 {
+                    entry.archive_size as u32
+                } else {
+                    entry.streaming_size as u32
+                },
+                name: entry.name,
+            })
+            .collect();
+        let entries = index_entries(directory.clone(), 8, diagnostics);
+        Ok(Img {
+            version: ImgVersion::V2,
+            entries,
+            directory,
+            pending: HashMap::new(),
+            img_reader: Mutex::new(Box::new(img_reader)),
+            mapped: None,
+        })
+    }
+
+    pub fn from_slice(img: &'a [u8], dir: Option<&[u8]>) -> Result<Img<'a>> {
+        Self::from_slice_with(img, dir, &Diagnostics::new())
+    }
+
+    // Archive held in memory, typically a memory mapped file. The .dir is only given for v1.
+    // Entries can then be borrowed with get_slice instead of being copied.
+    pub fn from_slice_with(
+        img: &'a [u8],
+        dir: Option<&[u8]>,
+        diagnostics: &Diagnostics,
+    ) -> Result<Img<'a>> {
+        let img_reader = std::io::Cursor::new(img);
+        let mut res = match dir {
+            Some(dir) => Self::from_v1_with(img_reader, std::io::Cursor::new(dir), diagnostics)?,
+            None => Self::from_v2_with(img_reader, diagnostics)?,
+        };
+        res.mapped = Some(img);
+        Ok(res)
+    }
+
+    // Archive read straight from a memory mapped .img, the .dir next to it is read like in new
+    #[cfg(all(feature = "mmap", unix))]
+    pub fn open_mmap(map: &'a Mmap) -> Result<Img<'a>> {
+        Self::open_mmap_with(map, &Diagnostics::new())
+    }
+
+    #[cfg(all(feature = "mmap", unix))]
+    pub fn open_mmap_with(map: &'a Mmap, diagnostics: &Diagnostics) -> Result<Img<'a>> {
+        let dir = std::fs::read(map.path().with_extension("dir")).ok();
+        Self::from_slice_with(map, dir.as_deref(), diagnostics)
+    }
+
+    pub fn version(&self) -> ImgVersion {
+        self.version
+    }
+
+    // Lowercased names of all entries, in no particular order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        let entries = &self.entries;
+        let added = self.pending.keys().filter(|n| !entries.contains_key(*n));
+        entries.keys().chain(added).map(|n| n.as_str())
+    }
+
+    // Name, offset and size in sectors of the stored entries, in archive order. Entries added
+    // since opening are missing and replaced ones are at their old place until rebuilt.
+    pub fn entries(&self) -> impl Iterator<Item = (&str, u32, u32)> {
+        let mut entries: Vec<_> = self
+            .entries
+            .iter()
+            .map(|(name, e)| (name.as_str(), e.offset, e.size))
+            .collect();
+        entries.sort_by_key(|&(name, offset, _)| (offset, name));
+        entries.into_iter()
     }
 
+    pub fn len(&self) -> usize {
+        self.names().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+        self.entries.contains_key(&name) || self.pending.contains_key(&name)
+    }
+
+    // Location of the stored data, entries added since the archive was opened have none
     pub fn get_entry(&self, name: &str) -> Option<DirEnt> {
-        return self.entries.get(name).cloned();
+        self.entries.get(name).cloned()
+    }
+
+    // Data of an entry, without the padding to whole sectors if its length can be measured, see
+    // content_len
+    pub fn get_file(&self, name: &str) -> Option<Vec<u8>> {
+        let mut res = Vec::new();
+        self.open_entry(name)?.read_to_end(&mut res).ok()?;
+        Some(res)
+    }
+
+    // Stored entries are read as whole sectors, pending data is returned as given
+    pub fn get_file_padded(&self, name: &str) -> Option<Vec<u8>> {
+        let mut res = Vec::new();
+        self.open_entry_padded(name)?.read_to_end(&mut res).ok()?;
+        Some(res)
+    }
+
+    // Reader limited to the data of an entry, for parsing large entries without copying them
+    pub fn open_entry(&self, name: &str) -> Option<EntryReader<'_>> {
+        let mut reader = self.open_entry_padded(name)?;
+        reader.len = content_len(&mut reader).ok()?;
+        reader.pos = 0;
+        Some(reader)
+    }
+
+    // Byte offset of a stored entry in the archive, None for entries that are only pending
+    pub fn entry_offset(&self, name: &str) -> Option<u64> {
+        let entry = self.entries.get(&name.to_ascii_lowercase())?;
+        Some(entry.offset as u64 * SECTOR_SIZE as u64)
+    }
+
+    // Length of the data of an entry as get_file returns it
+    pub fn entry_len(&self, name: &str) -> Option<u64> {
+        Some(self.open_entry(name)?.len)
+    }
+
+    pub fn open_entry_padded(&self, name: &str) -> Option<EntryReader<'_>> {
+        let name = name.to_ascii_lowercase();
+        if let Some((_, data)) = self.pending.get(&name) {
+            return Some(EntryReader {
+                inner: EntrySource::Memory(data),
+                start: 0,
+                len: data.len() as u64,
+                pos: 0,
+            });
+        }
+        let entry = self.entries.get(&name)?;
+        Some(EntryReader {
+            start: entry.offset as u64 * SECTOR_SIZE as u64,
+            len: entry.size as u64 * SECTOR_SIZE as u64,
+            pos: 0,
+            inner: match self.mapped {
+                Some(mapped) => EntrySource::Memory(mapped),
+                None => EntrySource::Shared(&self.img_reader),
+            },
+        })
+    }
+
+    // Data of an entry in an archive opened from a slice. None for other archives, entries with
+    // pending changes and entries past the end of the data.
+    pub fn get_slice(&self, name: &str) -> Option<&'a [u8]> {
+        let name = name.to_ascii_lowercase();
+        if self.pending.contains_key(&name) {
+            return None;
+        }
+        let entry = self.entries.get(&name)?;
+        let start = entry.offset as usize * SECTOR_SIZE;
+        let padded = self
+            .mapped?
+            .get(start..start + entry.size as usize * SECTOR_SIZE)?;
+        let len = content_len(&mut std::io::Cursor::new(padded)).ok()?;
+        Some(&padded[..len as usize])
+    }
+
+    // Checks the layout of the archive as stored, pending changes are not considered
+    pub fn verify(&self) -> Result<(), Vec<ImgIssue>> {
+        let mut issues = Vec::new();
+        let len = match self.mapped {
+            Some(mapped) => mapped.len() as u64,
+            None => {
+                let mut reader = self.img_reader.lock().unwrap_or_else(|e| e.into_inner());
+                reader.seek(SeekFrom::End(0)).unwrap_or(0)
+            }
+        };
+        // The last sector may be cut short
+        let num_sectors = len.div_ceil(SECTOR_SIZE as u64);
+
+        let mut seen = std::collections::HashSet::new();
+        for entry in &self.directory {
+            let name = entry.name.to_string_lossy().to_ascii_lowercase();
+            if !seen.insert(name.clone()) {
+                issues.push(ImgIssue::Duplicate(name));
+            }
+        }
+
+        let mut sorted: Vec<&DirEnt> = self.directory.iter().filter(|e| e.size > 0).collect();
+        sorted.sort_by_key(|e| (e.offset, e.size));
+        let start = match self.version {
+            ImgVersion::V1 => 0,
+            ImgVersion::V2 => (8 + self.directory.len() as u64 * 32).div_ceil(SECTOR_SIZE as u64),
+        };
+        let name = |e: &DirEnt| e.name.to_string_lossy().into_owned();
+        let (mut end, mut last): (u64, Option<String>) = (start, None);
+        for entry in sorted {
+            let (offset, entry_end) =
+                (entry.offset as u64, entry.offset as u64 + entry.size as u64);
+            if entry_end > num_sectors {
+                issues.push(ImgIssue::PastEnd {
+                    name: name(entry),
+                    missing: entry_end - num_sectors,
+                });
+            }
+            if offset < start {
+                issues.push(ImgIssue::InDirectory(name(entry)));
+            } else if offset < end {
+                // Set by whichever entry reaches furthest so far
+                let last = last.clone().unwrap_or_default();
+                issues.push(ImgIssue::Overlap(last, name(entry)));
+            } else if offset > end {
+                issues.push(ImgIssue::Gap {
+                    offset: end,
+                    size: offset - end,
+                });
+            }
+            if entry_end > end {
+                (end, last) = (entry_end, Some(name(entry)));
+            }
+        }
+        if num_sectors > end {
+            issues.push(ImgIssue::Gap {
+                offset: end,
+                size: num_sectors - end,
+            });
+        }
+
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(issues)
+        }
+    }
+
+    // Adds a new entry, kept in memory until the archive is rebuilt
+    pub fn insert(&mut self, name: &str, data: Vec<u8>) -> Result<()> {
+        entry_name(name)?;
+        if self.contains(name) {
+            bail!("Entry {} already exists", name)
+        }
+        self.pending
+            .insert(name.to_ascii_lowercase(), (name.to_owned(), data));
+        Ok(())
+    }
+
+    // Swaps the data of an existing entry, which keeps its place and name
+    pub fn replace(&mut self, name: &str, data: Vec<u8>) -> Result<()> {
+        let key = name.to_ascii_lowercase();
+        let name = match (self.entries.get(&key), self.pending.get(&key)) {
+            (_, Some((name, _))) => name.clone(),
+            (Some(entry), None) => entry.name.to_string_lossy().into_owned(),
+            (None, None) => bail!("Entry {} does not exist", name),
+        };
+        self.pending.insert(key, (name, data));
+        Ok(())
+    }
+
+    // False if there was no such entry
+    pub fn remove(&mut self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+        let stored = self.entries.remove(&name).is_some();
+        self.pending.remove(&name).is_some() || stored
+    }
+
+    // Writes the archive with all changes applied and no gaps, in stored order. v1 archives need
+    // a writer for the .dir file.
+    pub fn rebuild<W, D>(&self, img_writer: W, dir_writer: Option<D>) -> Result<()>
+    where
+        W: Write,
+        D: Write,
+    {
+        self.rebuild_ordered(img_writer, dir_writer, EntryOrder::Stored)
+    }
+
+    pub fn rebuild_ordered<W, D>(
+        &self,
+        mut img_writer: W,
+        dir_writer: Option<D>,
+        entry_order: EntryOrder,
+    ) -> Result<()>
+    where
+        W: Write,
+        D: Write,
+    {
+        let mut stored: Vec<_> = self.entries.iter().collect();
+        stored.sort_by_key(|(_, entry)| entry.offset);
+        let mut order: Vec<(String, String)> = stored
+            .into_iter()
+            .map(|(key, entry)| (key.clone(), entry.name.to_string_lossy().into_owned()))
+            .collect();
+        let mut added: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|(key, _)| !self.entries.contains_key(*key))
+            .map(|(key, (name, _))| (key.clone(), name.clone()))
+            .collect();
+        added.sort();
+        order.extend(added);
+        if entry_order == EntryOrder::Name {
+            order.sort();
+        }
+
+        let layout: Vec<(&str, u32)> = order
+            .iter()
+            .map(|(key, name)| {
+                let size = match self.pending.get(key) {
+                    Some((_, data)) => data.len().div_ceil(SECTOR_SIZE) as u32,
+                    None => self.entries[key].size,
+                };
+                (name.as_str(), size)
+            })
+            .collect();
+        let directory = directory(self.version, &layout)?;
+        match (self.version, dir_writer) {
+            (ImgVersion::V1, Some(mut dir_writer)) => {
+                dir_writer.write_all(&directory)?;
+                dir_writer.flush()?;
+            }
+            (ImgVersion::V1, None) => bail!("Version 1 IMG needs a .dir writer"),
+            (ImgVersion::V2, _) => write_padded(&mut img_writer, &directory)?,
+        }
+
+        for (key, _) in order {
+            let Some(data) = self.get_file(&key) else {
+                bail!("Entry {} could not be read", key)
+            };
+            write_padded(&mut img_writer, &data)?;
+        }
+        img_writer.flush()?;
+        Ok(())
+    }
+
+    // Rebuilds into files next to path, then moves them over the originals and reopens them
+    pub fn save(&mut self, path: &Path) -> Result<()> {
+        self.compact(path, EntryOrder::Stored)
     }
 
-    pub fn get_file(&mut self, name: &str) -> Option<Vec<u8>> {
-        if let Some(entry) = self.get_entry(&name.to_ascii_lowercase()) {
-            self.img_reader
-                .seek(SeekFrom::Start(entry.offset as u64 * 2048))
-                .unwrap();
-            let mut res = vec![0; entry.size as usize * 2048];
-            self.img_reader.read_exact(&mut res).unwrap();
-            return Some(res);
+    // Saves the archive without the space left by removed and replaced entries
+    pub fn compact(&mut self, path: &Path, entry_order: EntryOrder) -> Result<()> {
+        if path.extension().map_or(true, |x| x != "img") {
+            bail!("File does not end in .img")
+        }
+        let img_tmp = path.with_extension("img.tmp");
+        let dir_tmp = path.with_extension("dir.tmp");
+        {
+            let img_file = std::io::BufWriter::new(File::create(&img_tmp)?);
+            let dir_file = match self.version {
+                ImgVersion::V1 => Some(std::io::BufWriter::new(File::create(&dir_tmp)?)),
+                ImgVersion::V2 => None,
+            };
+            self.rebuild_ordered(img_file, dir_file, entry_order)?;
+        }
+        std::fs::rename(&img_tmp, path)?;
+        if self.version == ImgVersion::V1 {
+            std::fs::rename(&dir_tmp, path.with_extension("dir"))?;
+        }
+        *self = Img::new(path)?;
+        Ok(())
+    }
+}
+
+// Maps lowercased names to entries, later duplicates replace earlier ones. Entries are 32 bytes
+// each, starting at base in the directory's file.
+fn index_entries(
+    entries: impl IntoIterator<Item = DirEnt>,
+    base: u64,
+    diagnostics: &Diagnostics,
+) -> HashMap<String, DirEnt> {
+    let mut map = HashMap::new();
+    for (index, entry) in entries.into_iter().enumerate() {
+        let offset = Some(base + index as u64 * 32);
+        let name = entry.name.to_string_lossy();
+        if name.contains('\u{FFFD}') {
+            diagnostics.warn(
+                "img",
+                offset,
+                format!("Entry name {:?} is not valid UTF-8", name),
+            );
+        }
+        let name = name.to_ascii_lowercase();
+        if map.contains_key(&name) {
+            diagnostics.warn(
+                "img",
+                offset,
+                format!("Duplicate entry {}, the earlier one is hidden", name),
+            );
         }
-        None
+        map.insert(name, entry);
     }
+    map
+}
+
+const SECTOR_SIZE: usize = 2048;
+
+// Read-only memory mapping of a whole file, for Img::open_mmap. The archives are only read, but
+// the mapping isn't protected against the file being changed by another process meanwhile.
+#[cfg(all(feature = "mmap", unix))]
+pub struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+    path: std::path::PathBuf,
+}
+
+#[cfg(all(feature = "mmap", unix))]
+impl Mmap {
+    pub fn open(path: &Path) -> Result<Self> {
+        use std::os::unix::io::AsRawFd;
+
+        let file = File::open(path)?;
+        let len = usize::try_from(file.metadata()?.len())?;
+        // Empty mappings aren't allowed, an empty file is mapped as an empty slice
+        let ptr = if len == 0 {
+            std::ptr::null_mut()
+        } else {
+            // SAFETY: a new private read-only mapping of an open file, checked for failure below
+            let ptr = unsafe {
+                libc::mmap(
+                    std::ptr::null_mut(),
+                    len,
+                    libc::PROT_READ,
+                    libc::MAP_PRIVATE,
+                    file.as_raw_fd(),
+                    0,
+                )
+            };
+            if ptr == libc::MAP_FAILED {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            ptr
+        };
+        Ok(Self {
+            ptr,
+            len,
+            path: path.to_owned(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(all(feature = "mmap", unix))]
+impl std::ops::Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        // SAFETY: the mapping is len bytes long and lives until drop
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+#[cfg(all(feature = "mmap", unix))]
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            // SAFETY: ptr and len are those of the mapping created in open
+            unsafe { libc::munmap(self.ptr, self.len) };
+        }
+    }
+}
+
+// The mapping is read-only, so it can be read from any thread
+#[cfg(all(feature = "mmap", unix))]
+unsafe impl Send for Mmap {}
+#[cfg(all(feature = "mmap", unix))]
+unsafe impl Sync for Mmap {}
+
+// Problem found by Img::verify, offsets and sizes are in sectors
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ImgIssue {
+    Duplicate(String),
+    Overlap(String, String),
+    InDirectory(String), // v2 entry overlapping the header
+    PastEnd { name: String, missing: u64 },
+    Gap { offset: u64, size: u64 },
+}
+
+impl fmt::Display for ImgIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Duplicate(name) => write!(f, "Entry {} is listed more than once", name),
+            Self::Overlap(first, second) => write!(f, "Entries {} and {} overlap", first, second),
+            Self::InDirectory(name) => write!(f, "Entry {} overlaps the directory", name),
+            Self::PastEnd { name, missing } => write!(
+                f,
+                "Entry {} extends {} sectors past the end of the archive",
+                name, missing
+            ),
+            Self::Gap { offset, size } => {
+                write!(f, "{} unused sectors at sector {}", size, offset)
+            }
+        }
+    }
+}
+
+// Positions are relative to the start of the entry, reads stop at its end
+pub struct EntryReader<'r> {
+    inner: EntrySource<'r>,
+    start: u64,
+    len: u64,
+    pos: u64,
+}
+
+impl EntryReader<'_> {
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Read for EntryReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let remaining = self.len.saturating_sub(self.pos);
+        let max = buf.len().min(remaining as usize);
+        if max == 0 {
+            return Ok(0);
+        }
+        let pos = self.start + self.pos;
+        let n = match self.inner {
+            EntrySource::Shared(reader) => reader.read_at(pos, &mut buf[..max])?,
+            EntrySource::Memory(data) => data.read_at(pos, &mut buf[..max])?,
+        };
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for EntryReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(d) => self.len.checked_add_signed(d),
+            SeekFrom::Current(d) => self.pos.checked_add_signed(d),
+        };
+        let Some(pos) = pos else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Seek before the start of the entry",
+            ));
+        };
+        self.pos = pos;
+        Ok(pos)
+    }
+}
+
+enum EntrySource<'r> {
+    Shared(&'r (dyn ReadAt + 'r)),
+    Memory(&'r [u8]),
+}
+
+trait ReadAt: Send + Sync {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> std::io::Result<usize>;
+}
+
+impl ReadAt for Mutex<Box<dyn ReadSeek + '_>> {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        // Every read seeks first, so a reader left behind by a panic is still usable
+        let mut reader = self.lock().unwrap_or_else(|e| e.into_inner());
+        reader.seek(SeekFrom::Start(pos))?;
+        reader.read(buf)
+    }
+}
+
+impl ReadAt for [u8] {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        let rest = self.get(pos as usize..).unwrap_or_default();
+        let n = buf.len().min(rest.len());
+        buf[..n].copy_from_slice(&rest[..n]);
+        Ok(n)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EntryOrder {
+    // Order of the existing archive, added entries last sorted by name. Keeps entries the game
+    // streams together close to each other.
+    #[default]
+    Stored,
+    Name,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImgVersion {
+    V1, // III and VC, separate .dir file
+    V2, // San Andreas
+}
+
+// Writes a v1 archive, entries are stored in the given order, each padded to whole sectors
+pub fn write_v1<'b, W, D>(
+    entries: impl IntoIterator<Item = (&'b str, &'b [u8])>,
+    mut img_writer: W,
+    mut dir_writer: D,
+) -> Result<()>
+where
+    W: Write,
+    D: Write,
+{
+    let entries: Vec<_> = entries.into_iter().collect();
+    dir_writer.write_all(&directory(ImgVersion::V1, &sectors(&entries))?)?;
+    for (_, data) in &entries {
+        write_padded(&mut img_writer, data)?;
+    }
+    Ok(())
+}
+
+// Writes a v2 archive, the directory is padded to whole sectors before the first entry
+pub fn write_v2<'b, W>(
+    entries: impl IntoIterator<Item = (&'b str, &'b [u8])>,
+    mut img_writer: W,
+) -> Result<()>
+where
+    W: Write,
+{
+    let entries: Vec<_> = entries.into_iter().collect();
+    write_padded(
+        &mut img_writer,
+        &directory(ImgVersion::V2, &sectors(&entries))?,
+    )?;
+    for (_, data) in &entries {
+        write_padded(&mut img_writer, data)?;
+    }
+    Ok(())
+}
+
+fn sectors<'b>(entries: &[(&'b str, &[u8])]) -> Vec<(&'b str, u32)> {
+    entries
+        .iter()
+        .map(|(name, data)| (*name, data.len().div_ceil(SECTOR_SIZE) as u32))
+        .collect()
+}
+
+// Directory of entries stored back to back in the given order, sizes are in sectors. For v2 this
+// is the header, which is followed by the first entry at the next sector.
+fn directory(version: ImgVersion, entries: &[(&str, u32)]) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut offset = 0;
+    if version == ImgVersion::V2 {
+        data.extend(b"VER2");
+        data.extend((entries.len() as u32).to_le_bytes());
+        offset = (8 + entries.len() * 32).div_ceil(SECTOR_SIZE) as u32;
+    }
+    for &(name, size) in entries {
+        data.extend(offset.to_le_bytes());
+        match version {
+            ImgVersion::V1 => data.extend(size.to_le_bytes()),
+            ImgVersion::V2 => {
+                let Ok(size) = u16::try_from(size) else {
+                    bail!("Entry {} is too large for a version 2 IMG", name)
+                };
+                data.extend(size.to_le_bytes());
+                data.extend(0u16.to_le_bytes());
+            }
+        }
+        data.extend(entry_name(name)?);
+        offset += size;
+    }
+    Ok(data)
+}
+
+// Creates the .img at path, and the .dir next to it for v1
+pub fn create<'b>(
+    path: &Path,
+    version: ImgVersion,
+    entries: impl IntoIterator<Item = (&'b str, &'b [u8])>,
+) -> Result<()> {
+    if path.extension().map_or(true, |x| x != "img") {
+        bail!("File does not end in .img")
+    }
+    let img_file = std::io::BufWriter::new(File::create(path)?);
+    match version {
+        ImgVersion::V1 => {
+            let dir_file = std::io::BufWriter::new(File::create(path.with_extension("dir"))?);
+            write_v1(entries, img_file, dir_file)
+        }
+        ImgVersion::V2 => write_v2(entries, img_file),
+    }
+}
+
+// Packs files into an archive, each stored under its file name
+pub fn create_from_files(path: &Path, version: ImgVersion, files: &[&Path]) -> Result<()> {
+    let mut entries = Vec::new();
+    for file in files {
+        let Some(name) = file.file_name().and_then(|n| n.to_str()) else {
+            bail!("{} has no valid file name", file.display())
+        };
+        entries.push((name, std::fs::read(file)?));
+    }
+    create(
+        path,
+        version,
+        entries.iter().map(|(name, data)| (*name, data.as_slice())),
+    )
+}
+
+// Length of an entry without its sector padding, which neither directory format records.
+// RenderWare and collision files are measured by walking their top-level headers. Anything else
+// keeps the padding, as zero bytes at its end may be part of the data.
+pub fn content_len<R: Read + Seek>(reader: &mut R) -> std::io::Result<u64> {
+    let padded = reader.seek(SeekFrom::End(0))?;
+    let mut pos = 0;
+    while pos + 12 <= padded {
+        reader.seek(SeekFrom::Start(pos))?;
+        let mut header = [0; 12];
+        reader.read_exact(&mut header)?;
+        let word = |i: usize| u32::from_le_bytes(header[i..i + 4].try_into().unwrap()) as u64;
+        let len = match &header[..4] {
+            b"COLL" | b"COL2" | b"COL3" | b"COL4" => 8 + word(4),
+            _ if header == [0; 12] => break,
+            _ if !RwVersion::from_lib_id(word(8) as u32).games().is_empty() => 12 + word(4),
+            _ => return Ok(padded),
+        };
+        if pos + len > padded {
+            return Ok(padded);
+        }
+        pos += len;
+    }
+    // Whatever follows the last header has to be padding
+    if pos > 0 && padded - pos < SECTOR_SIZE as u64 {
+        reader.seek(SeekFrom::Start(pos))?;
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest)?;
+        if rest.iter().all(|&b| b == 0) {
+            return Ok(pos);
+        }
+    }
+    Ok(padded)
+}
+
+// Null padded to 24 bytes, the last one is reserved for the terminator
+fn entry_name(name: &str) -> Result<[u8; 24]> {
+    if name.len() > 23 || !name.is_ascii() || name.contains('\0') {
+        bail!("Entry name {:?} must be ASCII and at most 23 bytes", name)
+    }
+    let mut raw = [0; 24];
+    raw[..name.len()].copy_from_slice(name.as_bytes());
+    Ok(raw)
+}
+
+fn write_padded<W: Write>(writer: &mut W, data: &[u8]) -> Result<()> {
+    writer.write_all(data)?;
+    let padding = data.len().next_multiple_of(SECTOR_SIZE) - data.len();
+    writer.write_all(&vec![0; padding])?;
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+pub struct DiffEntry {
+    pub name: String,
+    pub size: u64,
+    pub checksum: u32,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ImgDiff {
+    pub added: Vec<DiffEntry>,
+    pub removed: Vec<DiffEntry>,
+    pub changed: Vec<(DiffEntry, DiffEntry)>, // (old, new)
+}
+
+impl ImgDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+// Compares two archives entry by entry, names are matched case-insensitively
+pub fn diff(img_a: &Img, img_b: &Img) -> Result<ImgDiff> {
+    let mut res = ImgDiff::default();
+
+    let mut names_a: Vec<String> = img_a.names().map(str::to_owned).collect();
+    names_a.sort();
+    for name in names_a {
+        let old = diff_entry(img_a, &name)?;
+        if img_b.contains(&name) {
+            let new = diff_entry(img_b, &name)?;
+            if old.size != new.size || old.checksum != new.checksum {
+                res.changed.push((old, new));
+            }
+        } else {
+            res.removed.push(old);
+        }
+    }
+
+    let mut names_b: Vec<String> = img_b.names().map(str::to_owned).collect();
+    names_b.sort();
+    for name in names_b {
+        if !img_a.contains(&name) {
+            res.added.push(diff_entry(img_b, &name)?);
+        }
+    }
+
+    Ok(res)
+}
+
+fn diff_entry(img: &Img, name: &str) -> Result<DiffEntry> {
+    let Some(data) = img.get_file(name) else {
+        bail!("Entry {} could not be read", name)
+    };
+    Ok(DiffEntry {
+        name: name.to_owned(),
+        size: data.len() as u64,
+        checksum: crc32(&data),
+    })
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB88320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
 }
 
 #[derive(BinRead)]
@@ -91,6 +932,7 @@ pub struct DirList {
 }
 
 #[derive(BinRead, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[brw(little)]
 pub struct DirEnt {
     pub offset: u32,
@@ -99,6 +941,26 @@ pub struct DirEnt {
     pub name: CString,
 }
 
+#[derive(BinRead)]
+#[brw(little)]
+pub struct V2Header {
+    pub magic: [u8; 4],
+    pub num_entries: u32,
+    #[br(count = num_entries)]
+    pub entries: Vec<V2DirEnt>,
+}
+
+// Sizes are in 2048 byte sectors like the offset
+#[derive(BinRead, Clone)]
+#[brw(little)]
+pub struct V2DirEnt {
+    pub offset: u32,
+    pub streaming_size: u16,
+    pub archive_size: u16,
+    #[brw(map = |x: [u8; 24]| CString::new(x.split(|x| *x == b'\0').next().unwrap()).unwrap())]
+    pub name: CString,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -108,4 +970,366 @@ mod tests {
         let _list = Img::new(Path::new("/mnt/winstor/Games/GTAIII/models/gta3.img"))?;
         Ok(())
     }
+
+    #[test]
+    fn duplicate_entries() -> Result<()> {
+        let mut dir = Vec::new();
+        for (offset, name) in [(0u32, "a.dff"), (1, "A.DFF")] {
+            dir.extend(offset.to_le_bytes());
+            dir.extend(1u32.to_le_bytes());
+            let mut raw = [0u8; 24];
+            raw[..name.len()].copy_from_slice(name.as_bytes());
+            dir.extend(raw);
+        }
+        let diagnostics = Diagnostics::new();
+        let img = Img::from_v1_with(
+            std::io::Cursor::new(vec![0; 4096]),
+            std::io::Cursor::new(dir),
+            &diagnostics,
+        )?;
+        assert_eq!(img.get_entry("a.dff").map(|e| e.offset), Some(1));
+        let diagnostics = diagnostics.take();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].offset, Some(32));
+        Ok(())
+    }
+
+    #[test]
+    fn version_2() -> Result<()> {
+        let mut data = b"VER2".to_vec();
+        data.extend(2u32.to_le_bytes());
+        for (offset, size, name) in [(1u32, 1u16, "a.dff"), (2, 2, "B.TXD")] {
+            data.extend(offset.to_le_bytes());
+            data.extend(size.to_le_bytes());
+            data.extend(0u16.to_le_bytes());
+            let mut raw = [0u8; 24];
+            raw[..name.len()].copy_from_slice(name.as_bytes());
+            data.extend(raw);
+        }
+        data.resize(2048 * 4, 0);
+        data[2048 * 2..2048 * 4].fill(7);
+
+        let img = Img::from_v2(std::io::Cursor::new(data))?;
+        let mut names: Vec<_> = img.names().collect();
+        names.sort();
+        assert_eq!(names, ["a.dff", "b.txd"]);
+        assert_eq!(img.get_entry("a.dff").map(|e| e.size), Some(1));
+        assert_eq!(img.get_file("b.txd"), Some(vec![7; 4096]));
+
+        assert!(Img::from_v2(std::io::Cursor::new(vec![0; 2048])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn write_archives() -> Result<()> {
+        let big = vec![3; 3000];
+        let entries = [("a.dff", &b"abc"[..]), ("B.txd", &big[..])];
+
+        let (mut img, mut dir) = (Vec::new(), Vec::new());
+        write_v1(entries, &mut img, &mut dir)?;
+        assert_eq!((img.len(), dir.len()), (2048 * 3, 64));
+        let v1 = Img::from_v1(std::io::Cursor::new(img), std::io::Cursor::new(dir))?;
+        assert_eq!(
+            v1.get_entry("b.txd").map(|e| (e.offset, e.size)),
+            Some((1, 2))
+        );
+        // Data that isn't RenderWare or collision keeps its padding
+        assert_eq!(v1.get_file("a.dff"), Some(padded(b"abc")));
+        assert_eq!(v1.entry_len("a.dff"), Some(2048));
+        assert_eq!(v1.get_file_padded("a.dff").map(|f| f.len()), Some(2048));
+
+        let mut img = Vec::new();
+        write_v2(entries, &mut img)?;
+        assert_eq!(img.len(), 2048 * 4);
+        let v2 = Img::from_v2(std::io::Cursor::new(img))?;
+        assert_eq!(
+            v2.get_entry("a.dff").map(|e| (e.offset, e.size)),
+            Some((1, 1))
+        );
+        assert_eq!(v2.get_file("b.txd").unwrap()[..3000], big);
+
+        let long = [("a_very_long_model_name.dff", &b""[..])];
+        assert!(write_v2(long, Vec::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn edit_entries() -> Result<()> {
+        let entries = [("a.dff", &[1; 3000][..]), ("B.txd", &[2; 10][..])];
+        let mut img = Vec::new();
+        write_v2(entries, &mut img)?;
+        let mut img = Img::from_v2(std::io::Cursor::new(img))?;
+
+        img.insert("c.col", vec![3; 5])?;
+        assert!(img.insert("C.COL", vec![]).is_err());
+        img.replace("b.TXD", vec![4; 20])?;
+        assert!(img.replace("d.dff", vec![]).is_err());
+        assert!(img.remove("a.dff"));
+        assert!(!img.remove("a.dff"));
+        assert_eq!(img.get_file("b.txd"), Some(vec![4; 20]));
+
+        let mut rebuilt = Vec::new();
+        img.rebuild(&mut rebuilt, None::<Vec<u8>>)?;
+        assert_eq!(rebuilt.len(), 2048 * 3);
+        let img = Img::from_v2(std::io::Cursor::new(rebuilt))?;
+        let mut names: Vec<_> = img.names().collect();
+        names.sort();
+        assert_eq!(names, ["b.txd", "c.col"]);
+        assert_eq!(img.len(), 2);
+        let entries: Vec<_> = img.entries().collect();
+        assert_eq!(entries, [("b.txd", 1, 1), ("c.col", 2, 1)]);
+        // Original case is kept, the replaced entry stays first
+        assert_eq!(img.get_entry("b.txd").map(|e| e.offset), Some(1));
+        assert_eq!(img.get_entry("b.txd").unwrap().name.to_str()?, "B.txd");
+        assert_eq!(img.get_file("c.col"), Some(padded(&[3; 5])));
+
+        // Version 1 archives can't be rebuilt without a .dir
+        let v1 = Img::from_v1(std::io::Cursor::new(vec![]), std::io::Cursor::new(vec![]))?;
+        assert!(v1.is_empty());
+        assert!(v1.rebuild(Vec::new(), None::<Vec<u8>>).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn borrowed_entries() -> Result<()> {
+        let entries = [("a.dff", &[1; 3000][..]), ("b.txd", &[2; 10][..])];
+        let (mut img, mut dir) = (Vec::new(), Vec::new());
+        write_v1(entries, &mut img, &mut dir)?;
+
+        let mut v1 = Img::from_slice(&img, Some(&dir))?;
+        let slice = v1.get_slice("A.DFF").unwrap();
+        assert_eq!(slice.as_ptr(), img.as_ptr());
+        assert_eq!(slice.len(), 4096);
+        assert_eq!(v1.get_file("b.txd").as_deref(), v1.get_slice("b.txd"));
+        v1.replace("b.txd", vec![])?;
+        assert!(v1.get_slice("b.txd").is_none());
+
+        let mut v2 = Vec::new();
+        write_v2(entries, &mut v2)?;
+        let v2 = Img::from_slice(&v2[..2048 * 3], None)?;
+        assert_eq!(v2.get_slice("a.dff").map(|s| s[0]), Some(1));
+        // Truncated data
+        assert!(v2.get_slice("b.txd").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn entry_reader() -> Result<()> {
+        let data: Vec<u8> = (0..3000).map(|n| n as u8).collect();
+        let entries = [("a.dff", &[1; 10][..]), ("b.dff", &data[..])];
+        let (mut img, mut dir) = (Vec::new(), Vec::new());
+        write_v1(entries, &mut img, &mut dir)?;
+        let mut img = Img::from_v1(std::io::Cursor::new(img), std::io::Cursor::new(dir))?;
+
+        let mut reader = img.open_entry("B.DFF").unwrap();
+        assert_eq!(reader.len(), 4096);
+        let mut buf = [0; 4];
+        reader.seek(SeekFrom::Start(1000))?;
+        reader.read_exact(&mut buf)?;
+        assert_eq!(buf, data[1000..1004]);
+        assert_eq!(reader.stream_position()?, 1004);
+        // Reads stop at the end of the entry, not the archive
+        reader.seek(SeekFrom::End(-2))?;
+        assert_eq!(reader.read(&mut buf)?, 2);
+        assert_eq!(reader.read(&mut buf)?, 0);
+        assert!(reader.seek(SeekFrom::Current(-5000)).is_err());
+
+        img.insert("c.dff", vec![5; 3])?;
+        let mut pending = Vec::new();
+        img.open_entry("c.dff").unwrap().read_to_end(&mut pending)?;
+        assert_eq!(pending, [5; 3]);
+        assert!(img.open_entry("d.dff").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn exact_sizes() -> Result<()> {
+        // Trailing zeros that belong to the data are kept
+        let mut dff = chunk_bytes(0x10, &[1, 0, 0, 0]);
+        dff.extend(chunk_bytes(0x2B, &[]));
+        let mut col = b"COLL".to_vec();
+        col.extend(4u32.to_le_bytes());
+        col.extend([0; 4]);
+        let mut two_models = col.clone();
+        two_models.extend(&col);
+        let cases = [
+            (dff.clone(), dff.len()),
+            (two_models, 24),
+            // Other formats can't be measured and keep their padding
+            (vec![9, 9, 0, 0], 2048),
+            (b"bnry".to_vec(), 2048),
+        ];
+        for (data, len) in cases {
+            let mut padded = data.clone();
+            padded.resize(2048, 0);
+            assert_eq!(content_len(&mut std::io::Cursor::new(padded))?, len as u64);
+        }
+        Ok(())
+    }
+
+    fn padded(data: &[u8]) -> Vec<u8> {
+        let mut data = data.to_vec();
+        data.resize(data.len().next_multiple_of(SECTOR_SIZE), 0);
+        data
+    }
+
+    fn chunk_bytes(ty: u32, payload: &[u8]) -> Vec<u8> {
+        let mut data = ty.to_le_bytes().to_vec();
+        data.extend((payload.len() as u32).to_le_bytes());
+        data.extend(0x1803FFFFu32.to_le_bytes());
+        data.extend(payload);
+        data
+    }
+
+    #[test]
+    fn shared_reads() -> Result<()> {
+        let files: Vec<(String, Vec<u8>)> = (0..8u8)
+            .map(|n| (format!("{}.dat", n), vec![n + 1; 3000]))
+            .collect();
+        let entries = files.iter().map(|(name, data)| (name.as_str(), &data[..]));
+        let mut data = Vec::new();
+        write_v2(entries, &mut data)?;
+        let img = Img::from_v2(std::io::Cursor::new(data))?;
+
+        let mut readers: Vec<_> = (0..2)
+            .map(|n| img.open_entry(&files[n].0).unwrap())
+            .collect();
+        let mut buf = [0; 2];
+        readers[0].read_exact(&mut buf[..1])?;
+        readers[1].read_exact(&mut buf[1..])?;
+        assert_eq!(buf, [1, 2]);
+
+        std::thread::scope(|scope| {
+            for (name, data) in &files {
+                let img = &img;
+                scope.spawn(move || assert_eq!(img.get_file(name), Some(padded(data))));
+            }
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn verify_layout() -> Result<()> {
+        let mut dir = Vec::new();
+        for (offset, size, name) in [
+            (0u32, 2u32, "a.dff"),
+            (1, 1, "b.dff"),
+            (4, 1, "A.DFF"),
+            (5, 0, "empty.dff"),
+            (5, 3, "c.dff"),
+        ] {
+            dir.extend(offset.to_le_bytes());
+            dir.extend(size.to_le_bytes());
+            dir.extend(entry_name(name)?);
+        }
+        let img = Img::from_v1(
+            std::io::Cursor::new(vec![0; 2048 * 7]),
+            std::io::Cursor::new(dir),
+        )?;
+        let issues = img.verify().unwrap_err();
+        assert_eq!(
+            issues,
+            [
+                ImgIssue::Duplicate("a.dff".to_owned()),
+                ImgIssue::Overlap("a.dff".to_owned(), "b.dff".to_owned()),
+                ImgIssue::Gap { offset: 2, size: 2 },
+                ImgIssue::PastEnd {
+                    name: "c.dff".to_owned(),
+                    missing: 1
+                },
+            ]
+        );
+
+        let mut data = Vec::new();
+        write_v2([("a.dff", &[1; 3000][..]), ("b.dff", &[2; 10])], &mut data)?;
+        let mut img = Img::from_v2(std::io::Cursor::new(data))?;
+        img.verify().unwrap();
+        img.remove("a.dff");
+        // Removed entries still take their space until rebuilt
+        img.verify().unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn compact_archive() -> Result<()> {
+        let root = std::env::temp_dir().join(format!("rw-rs-img-{}", std::process::id()));
+        std::fs::create_dir_all(&root)?;
+        let path = root.join("test.img");
+        let entries = [
+            ("b.dff", &[1; 3000][..]),
+            ("c.dff", &[2; 10]),
+            ("a.dff", &[3; 10]),
+        ];
+        create(&path, ImgVersion::V1, entries)?;
+
+        let mut img = Img::new(&path)?;
+        img.remove("c.dff");
+        img.compact(&path, EntryOrder::Name)?;
+        let entries: Vec<_> = img.entries().collect();
+        assert_eq!(entries, [("a.dff", 0, 1), ("b.dff", 1, 2)]);
+        img.verify().unwrap();
+        assert_eq!(std::fs::metadata(&path)?.len(), 2048 * 3);
+        assert_eq!(img.get_file("b.dff"), Some(padded(&[1; 3000])));
+
+        std::fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+
+    #[cfg(all(feature = "mmap", unix))]
+    #[test]
+    fn mapped_archive() -> Result<()> {
+        let root = std::env::temp_dir().join(format!("rw-rs-mmap-{}", std::process::id()));
+        std::fs::create_dir_all(&root)?;
+        let path = root.join("test.img");
+        create(&path, ImgVersion::V1, [("a.dff", &[1; 3000][..])])?;
+
+        let map = Mmap::open(&path)?;
+        let img = Img::open_mmap(&map)?;
+        let slice = img.get_slice("a.dff").unwrap();
+        assert_eq!(slice.as_ptr(), map.as_ptr());
+        assert_eq!(slice[..3000], [1; 3000]);
+
+        std::fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+
+    #[test]
+    fn diff_archives() -> Result<()> {
+        let (mut a, mut b) = (Vec::new(), Vec::new());
+        write_v2([("a.dff", &[1; 10][..]), ("b.txd", &[2; 10][..])], &mut a)?;
+        write_v2(
+            [
+                ("A.DFF", &[1; 10][..]),
+                ("b.txd", &[3; 10][..]),
+                ("c.col", &[4; 5][..]),
+            ],
+            &mut b,
+        )?;
+        let a = Img::from_v2(std::io::Cursor::new(a))?;
+        let mut b = Img::from_v2(std::io::Cursor::new(b))?;
+
+        let res = diff(&a, &b)?;
+        let names = |entries: &[DiffEntry]| -> Vec<String> {
+            entries.iter().map(|e| e.name.clone()).collect()
+        };
+        assert_eq!(names(&res.added), ["c.col"]);
+        assert!(res.removed.is_empty());
+        assert_eq!(res.changed.len(), 1);
+        let (old, new) = &res.changed[0];
+        assert_eq!((old.name.as_str(), new.name.as_str()), ("b.txd", "b.txd"));
+        assert_ne!(old.checksum, new.checksum);
+        assert_eq!(old.checksum, crc32(&padded(&[2; 10])));
+
+        b.remove("a.dff");
+        let res = diff(&a, &b)?;
+        assert_eq!(names(&res.removed), ["a.dff"]);
+        assert_eq!(res.removed[0].size, 2048);
+        assert!(diff(&a, &a)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
 }
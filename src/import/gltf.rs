@@ -0,0 +1,738 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::Value;
+
+use crate::bsf::builder::{ClumpBuilder, GeometryBuilder};
+use crate::bsf::{Chunk, RwVersion};
+use crate::export::gltf::ROOT_NAME;
+
+// Column-major like glTF
+type Mat4 = [[f32; 4]; 4];
+
+const IDENTITY: Mat4 = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+// glTF is Y-up, RenderWare is Z-up
+const Y_UP_TO_Z_UP: Mat4 = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, -1.0, 0.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+const Z_UP_TO_Y_UP: Mat4 = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 0.0, -1.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+const MAX_ZERO_ELEMENTS: usize = 0x10000;
+
+// Builds a Clump from a .gltf or .glb file. Buffers referenced by URI are loaded relative to the
+// file.
+pub fn import_file(path: &Path, version: RwVersion) -> Result<Chunk> {
+    let data = fs::read(path)?;
+    Document::load(&data, path.parent())?.to_clump(version)
+}
+
+// Builds a Clump from a .glb or a .gltf whose buffers are embedded as data URIs
+pub fn import(data: &[u8], version: RwVersion) -> Result<Chunk> {
+    Document::load(data, None)?.to_clump(version)
+}
+
+struct Document {
+    json: Value,
+    buffers: Vec<Vec<u8>>,
+}
+
+impl Document {
+    fn load(data: &[u8], base: Option<&Path>) -> Result<Self> {
+        let (json, mut bin) = if data.starts_with(b"glTF") {
+            let (json, bin) = split_glb(data)?;
+            (serde_json::from_slice::<Value>(json)?, bin)
+        } else {
+            (serde_json::from_slice::<Value>(data)?, None)
+        };
+
+        let mut buffers = Vec::new();
+        for buffer in array(&json, "buffers") {
+            let data = match buffer["uri"].as_str() {
+                Some(uri) if uri.starts_with("data:") => {
+                    let (_, encoded) = uri
+                        .split_once(";base64,")
+                        .ok_or_else(|| anyhow!("Unsupported data URI"))?;
+                    decode_base64(encoded)?
+                }
+                Some(uri) => {
+                    let base = base.ok_or_else(|| anyhow!("External buffer {}", uri))?;
+                    fs::read(base.join(uri)).with_context(|| format!("Reading {}", uri))?
+                }
+                // The first buffer without a URI is the GLB binary chunk
+                None => bin
+                    .take()
+                    .ok_or_else(|| anyhow!("Buffer without data"))?
+                    .to_vec(),
+            };
+            buffers.push(data);
+        }
+        Ok(Self { json, buffers })
+    }
+
+    // Values of an accessor with the number of components per element. Normalized integers are
+    // mapped to 0..1.
+    fn accessor(&self, index: usize) -> Result<(Vec<f32>, usize)> {
+        let accessor = &self.json["accessors"][index];
+        let count = usize_field(accessor, "count").ok_or_else(|| anyhow!("Invalid accessor"))?;
+        let components = match accessor["type"].as_str() {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") => 4,
+            Some("MAT4") => 16,
+            ty => bail!("Unsupported accessor type {:?}", ty),
+        };
+        let component_type = accessor["componentType"].as_u64().unwrap_or(0);
+        let size = match component_type {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            5125 | 5126 => 4,
+            ty => bail!("Unsupported component type {}", ty),
+        };
+        let normalized = accessor["normalized"].as_bool().unwrap_or(false);
+
+        // Accessors without a buffer view are all zeros. Geometries index vertices with u16, so
+        // larger ones can't be used and only waste memory.
+        let Some(view) = usize_field(accessor, "bufferView") else {
+            if count > MAX_ZERO_ELEMENTS {
+                bail!("Accessor {} has too many elements", index);
+            }
+            return Ok((vec![0.0; count * components], components));
+        };
+        let view = &self.json["bufferViews"][view];
+        let buffer = usize_field(view, "buffer")
+            .and_then(|b| self.buffers.get(b))
+            .ok_or_else(|| anyhow!("Invalid buffer view"))?;
+        let view_offset = usize_field(view, "byteOffset").unwrap_or(0);
+        let view_len = usize_field(view, "byteLength")
+            .unwrap_or_else(|| buffer.len().saturating_sub(view_offset));
+        let offset = view_offset + usize_field(accessor, "byteOffset").unwrap_or(0);
+        let stride = usize_field(view, "byteStride").unwrap_or(components * size);
+
+        // The count comes from the file, the elements have to fit in the view before it's used
+        // to size anything
+        let end = count
+            .checked_sub(1)
+            .and_then(|last| last.checked_mul(stride))
+            .and_then(|last| last.checked_add(offset + components * size));
+        match end {
+            Some(end) if end > view_offset + view_len || end > buffer.len() => {
+                bail!("Accessor {} out of bounds", index)
+            }
+            None if count > 0 => bail!("Accessor {} out of bounds", index),
+            _ => {}
+        }
+
+        let mut values = Vec::with_capacity(count * components);
+        for element in 0..count {
+            for c in 0..components {
+                let start = offset + element * stride + c * size;
+                let bytes = buffer
+                    .get(start..start + size)
+                    .ok_or_else(|| anyhow!("Accessor {} out of bounds", index))?;
+                let value = match component_type {
+                    5120 => bytes[0] as i8 as f32 / if normalized { 127.0 } else { 1.0 },
+                    5121 => bytes[0] as f32 / if normalized { 255.0 } else { 1.0 },
+                    5122 => {
+                        i16::from_le_bytes([bytes[0], bytes[1]]) as f32
+                            / if normalized { 32767.0 } else { 1.0 }
+                    }
+                    5123 => {
+                        u16::from_le_bytes([bytes[0], bytes[1]]) as f32
+                            / if normalized { 65535.0 } else { 1.0 }
+                    }
+                    5125 => u32::from_le_bytes(bytes.try_into()?) as f32,
+                    _ => f32::from_le_bytes(bytes.try_into()?),
+                };
+                values.push(value);
+            }
+        }
+        Ok((values, components))
+    }
+
+    fn vectors<const N: usize>(&self, index: usize) -> Result<Vec<[f32; N]>> {
+        let (values, components) = self.accessor(index)?;
+        Ok(values
+            .chunks_exact(components)
+            .map(|v| std::array::from_fn(|c| v.get(c).copied().unwrap_or(1.0)))
+            .collect())
+    }
+
+    fn to_clump(&self, version: RwVersion) -> Result<Chunk> {
+        let nodes = array(&self.json, "nodes");
+        let scene = usize_field(&self.json, "scene").unwrap_or(0);
+        let roots: Vec<usize> = match self.json["scenes"][scene]["nodes"].as_array() {
+            Some(roots) => roots
+                .iter()
+                .filter_map(|n| n.as_u64())
+                .map(|n| n as usize)
+                .collect(),
+            // Without scenes every node that isn't a child is a root
+            None => (0..nodes.len())
+                .filter(|&i| {
+                    !nodes.iter().any(|n| {
+                        array(n, "children")
+                            .iter()
+                            .any(|c| c.as_u64() == Some(i as u64))
+                    })
+                })
+                .collect(),
+        };
+
+        let mut clump = ClumpBuilder::new(version);
+        let mut frames: Vec<Option<usize>> = vec![None; nodes.len()];
+        // Nodes can only have one parent, so a node seen twice means a cycle
+        let mut visited = vec![false; nodes.len()];
+        // (node, parent frame, transform applied before the node's own)
+        let mut stack: Vec<(usize, Option<usize>, Mat4)> = roots
+            .iter()
+            .rev()
+            .map(|&r| (r, None, Y_UP_TO_Z_UP))
+            .collect();
+        while let Some((index, parent, pre)) = stack.pop() {
+            let node = nodes
+                .get(index)
+                .ok_or_else(|| anyhow!("Invalid node {}", index))?;
+            if std::mem::replace(&mut visited[index], true) {
+                bail!("Node {} is part of a cycle or has several parents", index);
+            }
+            let matrix = mul(&pre, &node_matrix(node));
+            let children = array(node, "children").iter().filter_map(|c| c.as_u64());
+
+            // Our own exporter wraps the RenderWare roots in a node doing the axis conversion
+            if parent.is_none() && node["name"] == ROOT_NAME && node.get("mesh").is_none() {
+                stack.extend(children.rev().map(|c| (c as usize, None, matrix)));
+                continue;
+            }
+
+            let frame = clump.frame(
+                node["name"].as_str(),
+                parent,
+                [matrix[3][0], matrix[3][1], matrix[3][2]],
+            );
+            clump.rotate(
+                frame,
+                [0, 1, 2].map(|c| [matrix[c][0], matrix[c][1], matrix[c][2]]),
+            );
+            frames[index] = Some(frame);
+            stack.extend(children.rev().map(|c| (c as usize, Some(frame), IDENTITY)));
+        }
+
+        // A clump only has one hierarchy, so only the first skin is kept
+        let skin = match self.json["skins"].get(0) {
+            Some(skin) => Some(self.skin(skin, &frames, &mut clump)?),
+            None => None,
+        };
+
+        let mut geometries = HashMap::new();
+        for (index, node) in nodes.iter().enumerate() {
+            let (Some(mesh), Some(frame)) = (usize_field(node, "mesh"), frames[index]) else {
+                continue;
+            };
+            let skinned = usize_field(node, "skin") == Some(0);
+            let geometry = match geometries.get(&(mesh, skinned)) {
+                Some(&geometry) => geometry,
+                None => {
+                    let skin = skin.as_ref().filter(|_| skinned);
+                    let builder = self
+                        .geometry(&self.json["meshes"][mesh], skin)
+                        .with_context(|| format!("Mesh {}", mesh))?;
+                    let geometry = clump.geometry(builder)?;
+                    geometries.insert((mesh, skinned), geometry);
+                    geometry
+                }
+            };
+            clump.atomic(frame, geometry);
+        }
+        Ok(clump.build())
+    }
+
+    fn skin(
+        &self,
+        skin: &Value,
+        frames: &[Option<usize>],
+        clump: &mut ClumpBuilder,
+    ) -> Result<Skin> {
+        let joints = array(skin, "joints")
+            .iter()
+            .map(|j| {
+                j.as_u64()
+                    .and_then(|j| frames.get(j as usize).copied().flatten())
+                    .ok_or_else(|| anyhow!("Joint outside of the scene"))
+            })
+            .collect::<Result<Vec<usize>>>()?;
+        if joints.len() > 256 {
+            bail!("Skins are limited to 256 bones");
+        }
+        let matrices: Vec<[f32; 16]> = match usize_field(skin, "inverseBindMatrices") {
+            Some(accessor) => self.vectors(accessor)?,
+            None => vec![IDENTITY.concat().try_into().unwrap(); joints.len()],
+        };
+
+        let bone_indices = clump.skeleton(&joints);
+        let mut inverse_matrices = vec![IDENTITY; joints.len()];
+        for (joint, m) in matrices.iter().enumerate().take(joints.len()) {
+            let m: Mat4 = std::array::from_fn(|c| std::array::from_fn(|r| m[c * 4 + r]));
+            // The joints now sit below the axis conversion
+            inverse_matrices[bone_indices[joint]] = mul(&m, &Z_UP_TO_Y_UP);
+        }
+        Ok(Skin {
+            bone_indices,
+            inverse_matrices,
+        })
+    }
+
+    fn geometry(&self, mesh: &Value, skin: Option<&Skin>) -> Result<GeometryBuilder> {
+        let mut primitives = Vec::new();
+        for primitive in array(mesh, "primitives") {
+            // Only triangle lists
+            if primitive["mode"].as_u64().unwrap_or(4) != 4 {
+                continue;
+            }
+            let attributes = &primitive["attributes"];
+            let attribute = |name: &str| usize_field(attributes, name);
+            let positions: Vec<[f32; 3]> = self.vectors(
+                attribute("POSITION").ok_or_else(|| anyhow!("Primitive without positions"))?,
+            )?;
+            let indices = match usize_field(primitive, "indices") {
+                Some(accessor) => self
+                    .accessor(accessor)?
+                    .0
+                    .iter()
+                    .map(|&i| i as u32)
+                    .collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+            let (joints, weights) = match (skin, attribute("JOINTS_0"), attribute("WEIGHTS_0")) {
+                (Some(skin), Some(joints), Some(weights)) => {
+                    let joints: Vec<[f32; 4]> = self.vectors(joints)?;
+                    let joints = joints
+                        .iter()
+                        .map(|j| j.map(|j| *skin.bone_indices.get(j as usize).unwrap_or(&0) as u8))
+                        .collect();
+                    (Some(joints), Some(self.vectors(weights)?))
+                }
+                _ => (None, None),
+            };
+            primitives.push(Primitive {
+                normals: attribute("NORMAL").map(|a| self.vectors(a)).transpose()?,
+                tex_coords: attribute("TEXCOORD_0")
+                    .map(|a| self.vectors(a))
+                    .transpose()?,
+                colors: attribute("COLOR_0").map(|a| self.vectors(a)).transpose()?,
+                joints,
+                weights,
+                positions,
+                indices,
+                material: usize_field(primitive, "material"),
+            });
+        }
+
+        let num_vertices: usize = primitives.iter().map(|p| p.positions.len()).sum();
+        if num_vertices > u16::MAX as usize + 1 {
+            bail!("{} vertices don't fit 16-bit indices", num_vertices);
+        }
+
+        // Attributes missing from some primitives get defaults
+        let mut vertices = Vec::with_capacity(num_vertices);
+        let mut normals = Vec::new();
+        let mut tex_coords = Vec::new();
+        let mut colors = Vec::new();
+        let mut joints = Vec::new();
+        let mut weights = Vec::new();
+        let any = |f: fn(&Primitive) -> bool| primitives.iter().any(f);
+        let (has_normals, has_tex_coords, has_colors, has_skin) = (
+            any(|p| p.normals.is_some()),
+            any(|p| p.tex_coords.is_some()),
+            any(|p| p.colors.is_some()),
+            any(|p| p.joints.is_some()),
+        );
+        for p in &primitives {
+            let n = p.positions.len();
+            vertices.extend(&p.positions);
+            if has_normals {
+                normals.extend(p.normals.clone().unwrap_or_else(|| vec![[0.0; 3]; n]));
+            }
+            if has_tex_coords {
+                tex_coords.extend(p.tex_coords.clone().unwrap_or_else(|| vec![[0.0; 2]; n]));
+            }
+            if has_colors {
+                let c = p.colors.clone().unwrap_or_else(|| vec![[1.0; 4]; n]);
+                colors.extend(c.iter().map(|c| c.map(|c| (c * 255.0).round() as u8)));
+            }
+            if has_skin {
+                joints.extend(p.joints.clone().unwrap_or_else(|| vec![[0; 4]; n]));
+                weights.extend(
+                    p.weights
+                        .clone()
+                        .unwrap_or_else(|| vec![[1.0, 0.0, 0.0, 0.0]; n]),
+                );
+            }
+        }
+
+        let mut builder = GeometryBuilder::new();
+        builder
+            .vertices(vertices)
+            .normals(normals)
+            .tex_coords(tex_coords)
+            .colors(colors);
+        let mut materials = HashMap::new();
+        let mut base = 0;
+        for (index, p) in primitives.iter().enumerate() {
+            let material = match materials.get(&p.material) {
+                Some(&id) => id,
+                None => {
+                    let (color, texture) = self.material(p.material);
+                    let id = builder.material(color, texture.as_deref());
+                    materials.insert(p.material, id);
+                    id
+                }
+            };
+            if let Some(&i) = p.indices.iter().find(|&&i| i as usize >= p.positions.len()) {
+                bail!("Primitive {} index {} out of range", index, i);
+            }
+            for tri in p.indices.chunks_exact(3) {
+                builder.triangle([0, 1, 2].map(|v| (base + tri[v]) as u16), material);
+            }
+            base += p.positions.len() as u32;
+        }
+        if let Some(skin) = skin.filter(|_| has_skin) {
+            builder.skin(joints, weights, skin.inverse_matrices.clone());
+        }
+        Ok(builder)
+    }
+
+    // Base color and texture name. Images are named after their file without the extension.
+    fn material(&self, index: Option<usize>) -> ([u8; 4], Option<String>) {
+        let Some(material) = index.map(|m| &self.json["materials"][m]) else {
+            return ([255; 4], None);
+        };
+        let pbr = &material["pbrMetallicRoughness"];
+        let factor: Vec<f64> = array(pbr, "baseColorFactor")
+            .iter()
+            .filter_map(|f| f.as_f64())
+            .collect();
+        let color =
+            std::array::from_fn(|c| (factor.get(c).copied().unwrap_or(1.0) * 255.0).round() as u8);
+
+        let image = usize_field(&pbr["baseColorTexture"], "index")
+            .and_then(|t| usize_field(&self.json["textures"][t], "source"))
+            .map(|i| &self.json["images"][i]);
+        let texture = image.and_then(|image| {
+            let name = image["name"].as_str().or_else(|| {
+                image["uri"]
+                    .as_str()
+                    .filter(|uri| !uri.starts_with("data:"))
+            })?;
+            let stem = Path::new(name).file_stem()?.to_str()?;
+            Some(stem.to_owned())
+        });
+        (color, texture)
+    }
+}
+
+struct Skin {
+    bone_indices: Vec<usize>, // Bone index of every joint
+    inverse_matrices: Vec<Mat4>,
+}
+
+struct Primitive {
+    positions: Vec<[f32; 3]>,
+    normals: Option<Vec<[f32; 3]>>,
+    tex_coords: Option<Vec<[f32; 2]>>,
+    colors: Option<Vec<[f32; 4]>>,
+    joints: Option<Vec<[u8; 4]>>,
+    weights: Option<Vec<[f32; 4]>>,
+    indices: Vec<u32>,
+    material: Option<usize>,
+}
+
+fn split_glb(data: &[u8]) -> Result<(&[u8], Option<&[u8]>)> {
+    let u32_at = |offset: usize| -> Result<usize> {
+        let bytes = data
+            .get(offset..offset + 4)
+            .ok_or_else(|| anyhow!("Truncated GLB"))?;
+        Ok(u32::from_le_bytes(bytes.try_into()?) as usize)
+    };
+    let mut offset = 12;
+    let mut json = None;
+    let mut bin = None;
+    while offset + 8 <= data.len() {
+        let (length, ty) = (u32_at(offset)?, u32_at(offset + 4)?);
+        let chunk = data
+            .get(offset + 8..offset + 8 + length)
+            .ok_or_else(|| anyhow!("Truncated GLB"))?;
+        match &(ty as u32).to_le_bytes() {
+            b"JSON" => json = Some(chunk),
+            b"BIN\0" => bin = Some(chunk),
+            _ => {}
+        }
+        offset += 8 + length;
+    }
+    Ok((json.ok_or_else(|| anyhow!("GLB without JSON"))?, bin))
+}
+
+fn node_matrix(node: &Value) -> Mat4 {
+    let floats = |key: &str| -> Vec<f32> {
+        array(node, key)
+            .iter()
+            .filter_map(|f| f.as_f64())
+            .map(|f| f as f32)
+            .collect()
+    };
+    let matrix = floats("matrix");
+    if matrix.len() == 16 {
+        return std::array::from_fn(|c| std::array::from_fn(|r| matrix[c * 4 + r]));
+    }
+
+    let t = Some(floats("translation")).filter(|t| t.len() == 3);
+    let t = t.unwrap_or_else(|| vec![0.0; 3]);
+    let q = Some(floats("rotation")).filter(|q| q.len() == 4);
+    let [x, y, z, w] = q.map_or([0.0, 0.0, 0.0, 1.0], |q| [q[0], q[1], q[2], q[3]]);
+    let s = Some(floats("scale")).filter(|s| s.len() == 3);
+    let s = s.unwrap_or_else(|| vec![1.0; 3]);
+    [
+        [
+            (1.0 - 2.0 * (y * y + z * z)) * s[0],
+            2.0 * (x * y + w * z) * s[0],
+            2.0 * (x * z - w * y) * s[0],
+            0.0,
+        ],
+        [
+            2.0 * (x * y - w * z) * s[1],
+            (1.0 - 2.0 * (x * x + z * z)) * s[1],
+            2.0 * (y * z + w * x) * s[1],
+            0.0,
+        ],
+        [
+            2.0 * (x * z + w * y) * s[2],
+            2.0 * (y * z - w * x) * s[2],
+            (1.0 - 2.0 * (x * x + y * y)) * s[2],
+            0.0,
+        ],
+        [t[0], t[1], t[2], 1.0],
+    ]
+}
+
+fn mul(a: &Mat4, b: &Mat4) -> Mat4 {
+    std::array::from_fn(|c| std::array::from_fn(|r| (0..4).map(|k| a[k][r] * b[c][k]).sum()))
+}
+
+fn array<'a>(value: &'a Value, key: &str) -> &'a [Value] {
+    value[key].as_array().map_or(&[], |a| a.as_slice())
+}
+
+fn usize_field(value: &Value, key: &str) -> Option<usize> {
+    value[key].as_u64().map(|v| v as usize)
+}
+
+fn decode_base64(encoded: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(encoded.len() / 4 * 3);
+    let mut bits = 0u32;
+    let mut num_bits = 0;
+    for c in encoded.bytes().take_while(|&c| c != b'=') {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => bail!("Invalid base64"),
+        };
+        bits = bits << 6 | value as u32;
+        num_bits += 6;
+        if num_bits >= 8 {
+            num_bits -= 8;
+            out.push((bits >> num_bits) as u8);
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsf::ChunkContent;
+    use crate::export::gltf::export;
+    use crate::scene::IntoSceneGraph;
+
+    #[test]
+    fn gltf_round_trip() {
+        let mut geometry = GeometryBuilder::new();
+        geometry
+            .vertices(vec![[0.0; 3], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]])
+            .normals(vec![[0.0, -1.0, 0.0]; 3])
+            .tex_coords(vec![[0.5, 0.5]; 3]);
+        let material = geometry.material([255, 128, 0, 255], Some("crate"));
+        geometry.triangle([0, 1, 2], material);
+        let mut clump = ClumpBuilder::new(RwVersion::VC_PC);
+        let root = clump.frame(Some("root"), None, [0.0; 3]);
+        let child = clump.frame(Some("child"), Some(root), [0.0, 0.0, 2.0]);
+        let geometry = clump.geometry(geometry).unwrap();
+        clump.atomic(child, geometry);
+
+        let glb = export(&clump.build().to_scene_graph()).to_glb();
+        let chunk = import(&glb, RwVersion::SA_PC).unwrap();
+        assert_eq!(chunk.header.version, RwVersion::SA_PC);
+        let frame_list = chunk
+            .iter()
+            .find_map(|c| c.content.as_frame_list())
+            .unwrap();
+        assert_eq!(frame_list.frames.len(), 2);
+        assert_eq!(frame_list.frames[1].parent, 0);
+        assert_eq!(frame_list.frames[1].position.z, 2.0);
+        assert_eq!(frame_list.frames[1].name.as_deref(), Some("child"));
+
+        let geo = chunk.iter().find_map(|c| c.content.as_geometry()).unwrap();
+        assert_eq!(geo.num_vertices, 3);
+        assert_eq!(geo.vertices()[2].z, 1.0);
+        assert_eq!(geo.normals()[0].y, -1.0);
+        assert_eq!(geo.tex_coords[0][0].u, 0.5);
+        let mat = chunk.iter().find_map(|c| c.content.as_material()).unwrap();
+        assert_eq!(mat.color.g, 128);
+        assert!(chunk
+            .iter()
+            .any(|c| matches!(&c.content, ChunkContent::String(s) if s == "crate")));
+        chunk.to_bytes().unwrap();
+    }
+
+    #[test]
+    fn import_skin() {
+        let positions: Vec<u8> = [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]
+            .iter()
+            .flat_map(|f| f.to_le_bytes())
+            .collect();
+        let joints: Vec<u8> = [0u8, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0].to_vec();
+        let weights: Vec<u8> = [[1.0f32, 0.0, 0.0, 0.0]; 3]
+            .iter()
+            .flatten()
+            .flat_map(|f| f.to_le_bytes())
+            .collect();
+        let buffer = [positions, joints, weights].concat();
+        let json = serde_json::json!({
+            "asset": { "version": "2.0" },
+            "scenes": [{ "nodes": [0, 1] }],
+            "nodes": [
+                { "name": "mesh", "mesh": 0, "skin": 0 },
+                { "name": "hip", "children": [2] },
+                { "name": "leg", "translation": [0.0, 1.0, 0.0] },
+            ],
+            "meshes": [{ "primitives": [{
+                "attributes": { "POSITION": 0, "JOINTS_0": 1, "WEIGHTS_0": 2 },
+            }] }],
+            "skins": [{ "joints": [2, 1] }],
+            "accessors": [
+                { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3" },
+                { "bufferView": 1, "componentType": 5121, "count": 3, "type": "VEC4" },
+                { "bufferView": 2, "componentType": 5126, "count": 3, "type": "VEC4" },
+            ],
+            "bufferViews": [
+                { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+                { "buffer": 0, "byteOffset": 36, "byteLength": 12 },
+                { "buffer": 0, "byteOffset": 48, "byteLength": 48 },
+            ],
+            "buffers": [{ "byteLength": buffer.len() }],
+        });
+        let glb = crate::export::gltf::Gltf { json, buffer }.to_glb();
+
+        let chunk = import(&glb, RwVersion::SA_PC).unwrap();
+        let frame_list = chunk
+            .iter()
+            .find_map(|c| c.content.as_frame_list())
+            .unwrap();
+        // Roots are rotated so that their Y axis points up
+        assert_eq!(frame_list.frames[1].rotation[1].z, 1.0);
+        assert_eq!(frame_list.frames[2].position.y, 1.0);
+
+        let skin = chunk.iter().find_map(|c| c.content.as_skin()).unwrap();
+        assert_eq!(skin.num_bones, 2);
+        // The hip comes first in the hierarchy, so joint 0 (the leg) becomes bone 1
+        assert_eq!(skin.bone_indices[0], [1, 1, 1, 1]);
+        assert_eq!(skin.bone_indices[1], [0, 1, 1, 1]);
+        assert_eq!(skin.used_bones, vec![0, 1]);
+
+        let hanims: Vec<_> = chunk.iter().filter_map(|c| c.content.as_hanim()).collect();
+        assert_eq!(hanims.len(), 2);
+        assert_eq!(hanims[0].nodes.len(), 2);
+
+        // The written skin parses back
+        let data = chunk.to_bytes().unwrap();
+        let (_, parsed) = Chunk::parse(&data).unwrap();
+        let parsed = parsed.iter().find_map(|c| c.content.as_skin()).unwrap();
+        assert_eq!(parsed.bone_indices, skin.bone_indices);
+        assert_eq!(parsed.inverse_matrices, skin.inverse_matrices);
+    }
+
+    #[test]
+    fn import_invalid() {
+        let glb = |json| {
+            crate::export::gltf::Gltf {
+                json,
+                buffer: vec![0; 36],
+            }
+            .to_glb()
+        };
+
+        let cycle = glb(serde_json::json!({
+            "asset": { "version": "2.0" },
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [{ "children": [1] }, { "children": [0] }],
+        }));
+        assert!(import(&cycle, RwVersion::SA_PC).is_err());
+
+        // The count claims far more vertices than the view holds
+        let count = glb(serde_json::json!({
+            "asset": { "version": "2.0" },
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [{ "mesh": 0 }],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 } }] }],
+            "accessors": [
+                { "bufferView": 0, "componentType": 5126, "count": 1u64 << 40, "type": "VEC3" },
+            ],
+            "bufferViews": [{ "buffer": 0, "byteOffset": 0, "byteLength": 36 }],
+            "buffers": [{ "byteLength": 36 }],
+        }));
+        let error = import(&count, RwVersion::SA_PC).unwrap_err();
+        assert!(format!("{:#}", error).contains("out of bounds"));
+
+        // The last index is past the primitive's three vertices
+        let mut buffer = vec![0; 36];
+        buffer.extend([0u32, 1, 65537].iter().flat_map(|i| i.to_le_bytes()));
+        let index = crate::export::gltf::Gltf {
+            json: serde_json::json!({
+                "asset": { "version": "2.0" },
+                "scenes": [{ "nodes": [0] }],
+                "nodes": [{ "mesh": 0 }],
+                "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "indices": 1 }] }],
+                "accessors": [
+                    { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3" },
+                    { "bufferView": 1, "componentType": 5125, "count": 3, "type": "SCALAR" },
+                ],
+                "bufferViews": [
+                    { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+                    { "buffer": 0, "byteOffset": 36, "byteLength": 12 },
+                ],
+                "buffers": [{ "byteLength": 48 }],
+            }),
+            buffer,
+        }
+        .to_glb();
+        let error = import(&index, RwVersion::SA_PC).unwrap_err();
+        assert!(format!("{:#}", error).contains("Primitive 0 index 65537 out of range"));
+    }
+}
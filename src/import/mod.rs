@@ -0,0 +1,2 @@
+#[cfg(feature = "gltf")]
+pub mod gltf;
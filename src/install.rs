@@ -0,0 +1,216 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+
+use crate::bsf::tex::TexDictionary;
+use crate::bsf::Chunk;
+use crate::diagnostics::Diagnostics;
+use crate::ide::{Game, ModelIdMap};
+use crate::img::Img;
+
+/// Entry point to an installed game: the IDE definitions and IMG archives listed in its dat files.
+pub struct GameInstall {
+    pub root: PathBuf,
+    pub game: Game,
+    pub ids: ModelIdMap,
+    pub diagnostics: Diagnostics,
+    imgs: Vec<Img<'static>>,
+}
+
+impl GameInstall {
+    pub fn open(root: &Path, game: Game) -> Result<Self> {
+        let mut install = Self {
+            root: root.to_owned(),
+            game,
+            ids: ModelIdMap::new(),
+            diagnostics: Diagnostics::new(),
+            imgs: Vec::new(),
+        };
+
+        let main_dat = match game {
+            Game::VC => "data/gta_vc.dat",
+            Game::GTA3 | Game::SA => "data/gta.dat",
+        };
+        let mut img_paths = vec!["models/gta3.img".to_owned()];
+        for dat in ["data/default.dat", main_dat] {
+            let Some(path) = resolve(root, dat) else {
+                install.warn(format!("{} not found", dat));
+                continue;
+            };
+            let text = String::from_utf8_lossy(&fs::read(path)?).into_owned();
+            for line in text.lines() {
+                let line = line.split('#').next().unwrap_or("").trim();
+                let Some((keyword, arg)) = line.split_once(char::is_whitespace) else {
+                    continue;
+                };
+                let arg = arg.trim();
+                match keyword.to_ascii_uppercase().as_str() {
+                    "IDE" => install.load_ide(arg)?,
+                    "IMG" | "CDIMAGE" => img_paths.push(arg.to_owned()),
+                    _ => {}
+                }
+            }
+        }
+
+        for path in img_paths {
+            install.open_img(&path);
+        }
+        Ok(install)
+    }
+
+    fn warn(&self, message: String) {
+        self.diagnostics.warn("install", None, message);
+    }
+
+    fn load_ide(&mut self, path: &str) -> Result<()> {
+        match resolve(&self.root, path) {
+            Some(path) => self.ids.load_ide_file(self.game, &path),
+            None => {
+                self.warn(format!("IDE {} not found", path));
+                Ok(())
+            }
+        }
+    }
+
+    fn open_img(&mut self, path: &str) {
+        let Some(path) = resolve(&self.root, path) else {
+            self.warn(format!("IMG {} not found", path));
+            return;
+        };
+        // Version 2 archives (SA) have no .dir file
+        let dir_name = path.with_extension("dir");
+        let dir_name = dir_name.file_name().unwrap_or_default().to_string_lossy();
+        let dir = path.parent().and_then(|p| resolve(p, &dir_name));
+        let img = File::open(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|img| match dir {
+                Some(dir) => Img::from_v1_with(img, File::open(dir)?, &self.diagnostics),
+                None => Img::from_v2_with(img, &self.diagnostics),
+            });
+        match img {
+            Ok(img) => self.imgs.push(img),
+            Err(e) => self.warn(format!("Can't open {}: {}", path.display(), e)),
+        }
+    }
+
+    // Raw file from the first archive containing it
+    pub fn file(&self, name: &str) -> Option<Vec<u8>> {
+        self.imgs.iter().find_map(|img| img.get_file(name))
+    }
+
+    pub fn model(&self, name: &str) -> Result<Option<Chunk>> {
+        self.parse_file(&format!("{}.dff", name))
+    }
+
+    pub fn model_by_id(&self, id: u32) -> Result<Option<Chunk>> {
+        let Some(name) = self.ids.name(self.game, id).map(str::to_owned) else {
+            return Ok(None);
+        };
+        self.model(&name)
+    }
+
+    pub fn texture_dictionary(&self, name: &str) -> Result<Option<Chunk>> {
+        self.parse_file(&format!("{}.txd", name))
+    }
+
+    // Texture Native from a texture dictionary, names are matched case-insensitively
+    pub fn texture(&self, txd: &str, name: &str) -> Result<Option<Chunk>> {
+        let Some(txd) = self.texture_dictionary(txd)? else {
+            return Ok(None);
+        };
+        Ok(TexDictionary::new(&txd).and_then(|t| t.get(name).cloned()))
+    }
+
+    fn parse_file(&self, name: &str) -> Result<Option<Chunk>> {
+        let Some(data) = self.file(name) else {
+            return Ok(None);
+        };
+        let (_, chunk) = Chunk::parse(&data).map_err(|e| anyhow!("{}: {}", name, e))?;
+        Ok(Some(chunk))
+    }
+}
+
+// Finds path below root, matching each component case-insensitively since dat
+// files are written for Windows
+fn resolve(root: &Path, path: &str) -> Option<PathBuf> {
+    let mut res = root.to_owned();
+    for component in path.split(['/', '\\']).filter(|c| !c.is_empty()) {
+        let exact = res.join(component);
+        if exact.exists() {
+            res = exact;
+            continue;
+        }
+        res = fs::read_dir(&res)
+            .ok()?
+            .filter_map(|e| e.ok())
+            .find(|e| {
+                e.file_name()
+                    .to_string_lossy()
+                    .eq_ignore_ascii_case(component)
+            })?
+            .path();
+    }
+    Some(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsf::builder;
+    use crate::bsf::tex::{RasterEncoding, RgbaImage};
+    use crate::bsf::RwVersion;
+
+    #[test]
+    fn open_install() -> Result<()> {
+        let root = std::env::temp_dir().join(format!("rw-rs-install-{}", std::process::id()));
+        fs::create_dir_all(root.join("DATA"))?;
+        fs::create_dir_all(root.join("models"))?;
+        fs::write(root.join("DATA/gta.dat"), "# comment\nIDE DATA\\OBJS.IDE\n")?;
+        fs::write(
+            root.join("DATA/OBJS.IDE"),
+            "objs\n1412, barrier, generic, 1, 100, 0\nend\n",
+        )?;
+
+        // A Clump without children, stored in the first sector
+        let mut dff = 0x10u32.to_le_bytes().to_vec();
+        dff.extend(0u32.to_le_bytes());
+        dff.extend(0x0401FFFFu32.to_le_bytes());
+        dff.resize(2048, 0);
+        // And a texture dictionary in the second one
+        let image = RgbaImage {
+            width: 1,
+            height: 1,
+            data: vec![255; 4],
+        };
+        let txd = builder::texture_dictionary(
+            RwVersion::GTA3_PC,
+            &[("Barrier", &image)],
+            RasterEncoding::Format8888,
+            false,
+        );
+        let mut img = [dff, txd.to_bytes()?].concat();
+        img.resize(2048 * 2, 0);
+        fs::write(root.join("models/GTA3.IMG"), img)?;
+        let mut dir = Vec::new();
+        for (offset, file) in [(0u32, &b"BARRIER.DFF"[..]), (1, b"GENERIC.TXD")] {
+            dir.extend([offset.to_le_bytes(), 1u32.to_le_bytes()].concat());
+            let mut name = [0u8; 24];
+            name[..file.len()].copy_from_slice(file);
+            dir.extend(name);
+        }
+        fs::write(root.join("models/GTA3.DIR"), dir)?;
+
+        let install = GameInstall::open(&root, Game::GTA3)?;
+        fs::remove_dir_all(&root)?;
+        assert_eq!(install.ids.id(Game::GTA3, "barrier"), Some(1412));
+        assert!(install.model_by_id(1412)?.is_some());
+        assert!(install.model("missing")?.is_none());
+        let texture = install.texture("generic", "barrier")?.unwrap();
+        assert_eq!(texture.texture_name(), Some("Barrier"));
+        assert!(install.texture("generic", "missing")?.is_none());
+        // default.dat is missing
+        assert_eq!(install.diagnostics.len(), 1);
+        Ok(())
+    }
+}
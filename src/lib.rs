@@ -1,2 +1,12 @@
+#[cfg(feature = "bevy")]
+pub mod bevy;
 pub mod bsf;
+pub mod col;
+pub mod diagnostics;
+pub mod export;
+pub mod ide;
 pub mod img;
+pub mod import;
+pub mod install;
+pub mod query;
+pub mod scene;
@@ -0,0 +1,144 @@
+use anyhow::{bail, Result};
+
+use crate::bsf::{Chunk, ChunkContent, ChunkTypeId, RwVersion};
+use crate::img::Img;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Query {
+    UsesPlugin(ChunkTypeId),
+    Version(RwVersion),
+    // Textures named by materials or stored in a dictionary, compared case-insensitively
+    ReferencesTexture(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QueryMatch {
+    pub archive: usize, // Index into the archives passed to search
+    pub name: String,
+    pub offset: u64, // Byte offset of the entry in its archive
+}
+
+impl Query {
+    pub fn matches(&self, chunk: &Chunk) -> bool {
+        match self {
+            Self::Version(version) => chunk.header.version == *version,
+            Self::UsesPlugin(ty) => any_chunk(chunk, &|c| c.content.chunk_type() == *ty),
+            Self::ReferencesTexture(name) => any_chunk(chunk, &|c| {
+                texture_names(c).any(|n| n.eq_ignore_ascii_case(name))
+            }),
+        }
+    }
+}
+
+// Parses every stored DFF and TXD in the archives, entries that fail to parse never match. Fails
+// if an entry can't be read.
+pub fn search(archives: &[Img], query: &Query) -> Result<Vec<QueryMatch>> {
+    let mut res = Vec::new();
+    for (archive, img) in archives.iter().enumerate() {
+        let mut names: Vec<String> = img
+            .names()
+            .filter(|n| n.ends_with(".dff") || n.ends_with(".txd"))
+            .map(str::to_owned)
+            .collect();
+        names.sort();
+        for name in names {
+            let Some(offset) = img.entry_offset(&name) else {
+                continue;
+            };
+            let Some(data) = img.get_file(&name) else {
+                bail!("Entry {} could not be read", name)
+            };
+            if Chunk::parse(&data).is_ok_and(|(_, chunk)| query.matches(&chunk)) {
+                res.push(QueryMatch {
+                    archive,
+                    name,
+                    offset,
+                });
+            }
+        }
+    }
+    Ok(res)
+}
+
+// Also looks inside the textures held by MatFX effects, which aren't children
+fn any_chunk(chunk: &Chunk, f: &dyn Fn(&Chunk) -> bool) -> bool {
+    chunk.iter().any(|c| {
+        f(c) || c.content.as_matfx().is_some_and(|matfx| {
+            matfx
+                .effects()
+                .iter()
+                .flat_map(|e| e.textures())
+                .any(|t| any_chunk(t, f))
+        })
+    })
+}
+
+fn texture_names(chunk: &Chunk) -> Box<dyn Iterator<Item = &str> + '_> {
+    match &chunk.content {
+        ChunkContent::Texture(_) => Box::new(
+            chunk
+                .get_children()
+                .iter()
+                .filter_map(|c| c.content.as_string())
+                .map(|s| s.as_str())
+                .filter(|s| !s.is_empty()),
+        ),
+        ChunkContent::Raster(raster) => Box::new(std::iter::once(raster.name.as_str())),
+        ChunkContent::RasterPS2(raster) => Box::new(std::iter::once(raster.name.as_str())),
+        ChunkContent::RasterXbox(raster) => Box::new(std::iter::once(raster.name.as_str())),
+        ChunkContent::SpecularMaterial(spec) => Box::new(std::iter::once(spec.texture.as_str())),
+        _ => Box::new(std::iter::empty()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsf::fixtures::chunk;
+    use crate::bsf::ChunkType;
+
+    #[test]
+    fn matches() {
+        let mut payload = chunk(0x01, &[0, 0x11, 0, 0]);
+        payload.extend(chunk(0x02, b"wheel\0\0\0"));
+        payload.extend(chunk(0x02, b"\0\0\0\0"));
+        let (_, texture) = Chunk::parse(&chunk(0x06, &payload)).unwrap();
+
+        assert!(Query::ReferencesTexture("WHEEL".into()).matches(&texture));
+        assert!(!Query::ReferencesTexture("tyre".into()).matches(&texture));
+        assert!(Query::Version(RwVersion::GTA3_PC).matches(&texture));
+        assert!(!Query::UsesPlugin(ChunkType::SkinPLG.into()).matches(&texture));
+    }
+
+    #[test]
+    fn search_archives() -> Result<()> {
+        let mut payload = chunk(0x01, &[0, 0x11, 0, 0]);
+        payload.extend(chunk(0x02, b"wheel\0\0\0"));
+        payload.extend(chunk(0x02, b"\0\0\0\0"));
+        let texture = chunk(0x06, &payload);
+        let entries = [
+            ("a.dff", &chunk(0x10, &[])[..]),
+            ("b.dff", &texture[..]),
+            ("wheel.txt", &b"wheel"[..]),
+        ];
+        let mut data = Vec::new();
+        crate::img::write_v2(entries, &mut data)?;
+        let img = Img::from_slice(&data, None)?;
+
+        let query = Query::ReferencesTexture("wheel".into());
+        let found = search(&[img], &query)?;
+        assert_eq!(
+            found,
+            [QueryMatch {
+                archive: 0,
+                name: "b.dff".into(),
+                offset: 2 * 2048,
+            }]
+        );
+
+        // Truncated after the directory and the first entry
+        let img = Img::from_slice(&data[..2 * 2048], None)?;
+        assert!(search(&[img], &query).is_err());
+        Ok(())
+    }
+}
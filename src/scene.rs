@@ -0,0 +1,357 @@
+use crate::bsf::geo::RpGeometry;
+use crate::bsf::skin::Skeleton;
+use crate::bsf::tex::{RpMaterialList, TextureAddressingMode};
+use crate::bsf::{Chunk, ChunkContent};
+
+/// Engine-neutral description of a model, meant to be consumed by engine/exporter backends.
+#[derive(Clone, Debug, Default)]
+pub struct SceneGraph {
+    pub nodes: Vec<SceneNode>,
+    pub meshes: Vec<SceneMesh>,
+    pub materials: Vec<SceneMaterial>,
+    pub textures: Vec<SceneTexture>,
+    pub skins: Vec<SceneSkin>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SceneNode {
+    pub name: Option<String>,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    // Column-major 4x4 matrix relative to the parent node
+    pub transform: [[f32; 4]; 4],
+    pub mesh: Option<usize>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SceneMesh {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 2]>,
+    pub colors: Vec<[f32; 4]>,
+    // Up to four joints per vertex, indexing SceneSkin::joints
+    pub joints: Vec<[u16; 4]>,
+    pub weights: Vec<[f32; 4]>,
+    pub skin: Option<usize>,
+    // Positions of the morph targets after the base one
+    pub morph_targets: Vec<Vec<[f32; 3]>>,
+    pub primitives: Vec<ScenePrimitive>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SceneSkin {
+    pub joints: Vec<usize>, // Node of each bone
+    pub inverse_bind_matrices: Vec<[[f32; 4]; 4]>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ScenePrimitive {
+    // Triangle list indices into the vertex arrays of the parent mesh
+    pub indices: Vec<u32>,
+    pub material: Option<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SceneMaterial {
+    pub color: [f32; 4],
+    pub texture: Option<usize>,
+    pub specular_level: Option<f32>,
+    pub specular_texture: Option<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SceneTexture {
+    pub name: String,
+    pub mask_name: Option<String>,
+    pub sampler: SceneSampler,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SceneAddressMode {
+    #[default]
+    Repeat,
+    Mirror,
+    ClampToEdge,
+    // Samples outside the texture return SceneSampler::border_color
+    ClampToBorder,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SceneSampler {
+    pub address_u: SceneAddressMode,
+    pub address_v: SceneAddressMode,
+    pub border_color: [f32; 4],
+}
+
+// Streams don't store a border color, D3D and the PS2 GS both default to transparent black
+pub const BORDER_COLOR: [f32; 4] = [0.0; 4];
+
+impl Default for SceneSampler {
+    fn default() -> Self {
+        Self {
+            address_u: SceneAddressMode::Repeat,
+            address_v: SceneAddressMode::Repeat,
+            border_color: BORDER_COLOR,
+        }
+    }
+}
+
+impl SceneSampler {
+    pub fn from_addressing(
+        address_u: TextureAddressingMode,
+        address_v: TextureAddressingMode,
+    ) -> Self {
+        Self {
+            address_u: address_u.into(),
+            address_v: address_v.into(),
+            border_color: BORDER_COLOR,
+        }
+    }
+}
+
+impl From<TextureAddressingMode> for SceneAddressMode {
+    fn from(mode: TextureAddressingMode) -> Self {
+        match mode {
+            TextureAddressingMode::TEXTUREADDRESSNATEXTUREADDRESS
+            | TextureAddressingMode::TEXTUREADDRESSWRAP => Self::Repeat,
+            TextureAddressingMode::TEXTUREADDRESSMIRROR => Self::Mirror,
+            TextureAddressingMode::TEXTUREADDRESSCLAMP => Self::ClampToEdge,
+            TextureAddressingMode::TEXTUREADDRESSBORDER => Self::ClampToBorder,
+        }
+    }
+}
+
+pub const IDENTITY: [[f32; 4]; 4] = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+/// Conversion of parsed data into a [`SceneGraph`].
+pub trait IntoSceneGraph {
+    fn to_scene_graph(&self) -> SceneGraph;
+}
+
+impl IntoSceneGraph for Chunk {
+    fn to_scene_graph(&self) -> SceneGraph {
+        let mut scene = SceneGraph::default();
+
+        let geometries = self
+            .get_children()
+            .iter()
+            .find(|e| matches!(e.content, ChunkContent::GeometryList))
+            .map(|e| e.get_children())
+            .unwrap_or(&[]);
+        let mut skins = Vec::new();
+        for geometry_chunk in geometries {
+            if let ChunkContent::Geometry(geo) = &geometry_chunk.content {
+                let mesh = scene.add_geometry(geo, geometry_chunk);
+                let skin = geometry_chunk
+                    .get_extension()
+                    .iter()
+                    .find_map(|e| e.content.as_skin());
+                skins.push((scene.meshes.len(), skin));
+                scene.meshes.push(mesh);
+            }
+        }
+
+        let frames = self
+            .get_children()
+            .iter()
+            .find_map(|e| e.content.as_frame_list());
+        for frame in frames.map_or(&[][..], |f| &f.frames) {
+            let parent = frame.parent_index().filter(|p| *p < scene.nodes.len());
+            let node = scene.add_node(parent, None);
+            scene.nodes[node].name = frame.name.clone();
+            scene.nodes[node].transform = frame.matrix();
+        }
+
+        // Skin bones are in HAnim order, which the skeleton maps to frames and so to nodes
+        let skeleton = self
+            .get_children()
+            .iter()
+            .find(|e| matches!(e.content, ChunkContent::FrameList(_)))
+            .and_then(Skeleton::from_frame_list);
+        for (mesh, skin) in skins {
+            let (Some(skin), Some(skeleton)) = (skin, &skeleton) else {
+                continue;
+            };
+            let joints = (0..skin.num_bones as usize)
+                .map(|b| skeleton.bones.get(b).and_then(|b| b.frame).unwrap_or(0))
+                .collect();
+            scene.skins.push(SceneSkin {
+                joints,
+                inverse_bind_matrices: skin.inverse_matrices.clone(),
+            });
+            let mesh = &mut scene.meshes[mesh];
+            mesh.joints = skin.bone_indices.iter().map(|j| j.map(u16::from)).collect();
+            mesh.weights = skin.weights.clone();
+            mesh.skin = Some(scene.skins.len() - 1);
+        }
+
+        for child in self.get_children() {
+            if let ChunkContent::Atomic(atomic) = &child.content {
+                let mesh = atomic.geometry_index as usize;
+                let mesh = (mesh < scene.meshes.len()).then_some(mesh);
+                match scene.nodes.get_mut(atomic.frame_index as usize) {
+                    Some(node) if node.mesh.is_none() => node.mesh = mesh,
+                    Some(_) => {
+                        scene.add_node(Some(atomic.frame_index as usize), mesh);
+                    }
+                    None => {
+                        scene.add_node(None, mesh);
+                    }
+                }
+            }
+        }
+
+        // Loose geometry without atomics still gets a node so it isn't lost
+        if scene.nodes.iter().all(|n| n.mesh.is_none()) {
+            for mesh in 0..scene.meshes.len() {
+                scene.add_node(None, Some(mesh));
+            }
+        }
+
+        scene
+    }
+}
+
+impl SceneGraph {
+    // Whether the material needs blending, either from its color or a texture with an alpha mask.
+    // Textures alone are opaque, most of them don't use their alpha channel.
+    pub fn is_transparent(&self, material: &SceneMaterial) -> bool {
+        material.color[3] < 1.0
+            || material
+                .texture
+                .is_some_and(|t| self.textures[t].mask_name.is_some())
+    }
+
+    pub fn roots(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.nodes.len()).filter(|i| self.nodes[*i].parent.is_none())
+    }
+
+    fn add_node(&mut self, parent: Option<usize>, mesh: Option<usize>) -> usize {
+        let index = self.nodes.len();
+        self.nodes.push(SceneNode {
+            name: None,
+            parent,
+            children: Vec::new(),
+            transform: IDENTITY,
+            mesh,
+        });
+        if let Some(parent) = parent {
+            self.nodes[parent].children.push(index);
+        }
+        index
+    }
+
+    fn add_texture(&mut self, name: &str, mask_name: &str, sampler: SceneSampler) -> usize {
+        if let Some(index) = self
+            .textures
+            .iter()
+            .position(|t| t.name.eq_ignore_ascii_case(name) && t.sampler == sampler)
+        {
+            return index;
+        }
+        self.textures.push(SceneTexture {
+            name: name.to_owned(),
+            mask_name: (!mask_name.is_empty()).then(|| mask_name.to_owned()),
+            sampler,
+        });
+        self.textures.len() - 1
+    }
+
+    fn add_materials(&mut self, list: &RpMaterialList, chunk: &Chunk) -> Vec<usize> {
+        let mut indices = Vec::new();
+        for material_chunk in chunk.get_children() {
+            if let ChunkContent::Material(mat) = &material_chunk.content {
+                let texture = material_chunk
+                    .get_children()
+                    .iter()
+                    .find(|e| matches!(e.content, ChunkContent::Texture(_)))
+                    .and_then(|tex| {
+                        let sampler = tex
+                            .content
+                            .as_texture()
+                            .map(|t| SceneSampler::from_addressing(t.address_u, t.address_v))
+                            .unwrap_or_default();
+                        let mut names =
+                            tex.get_children().iter().filter_map(|e| match &e.content {
+                                ChunkContent::String(s) => Some(s.as_str()),
+                                _ => None,
+                            });
+                        let name = names.next()?;
+                        let mask_name = names.next().unwrap_or("");
+                        Some(self.add_texture(name, mask_name, sampler))
+                    });
+                let specular = material_chunk
+                    .get_extension()
+                    .iter()
+                    .find_map(|e| e.content.as_specular_material());
+                let specular_texture = specular
+                    .filter(|spec| !spec.texture.is_empty())
+                    .map(|spec| self.add_texture(&spec.texture, "", SceneSampler::default()));
+                self.materials.push(SceneMaterial {
+                    color: mat.color.as_rgba_arr(),
+                    texture,
+                    specular_level: specular.map(|spec| spec.level),
+                    specular_texture,
+                });
+                indices.push(self.materials.len() - 1);
+            }
+        }
+        (0..list.len() as u32)
+            .map(|id| {
+                indices
+                    .get(list.get_index(id) as usize)
+                    .copied()
+                    .unwrap_or(0)
+            })
+            .collect()
+    }
+
+    fn add_geometry(&mut self, geo: &RpGeometry, chunk: &Chunk) -> SceneMesh {
+        let materials = chunk
+            .get_children()
+            .iter()
+            .find_map(|e| match &e.content {
+                ChunkContent::MaterialList(list) => Some(self.add_materials(list, e)),
+                _ => None,
+            })
+            .unwrap_or_default();
+
+        let bin_mesh = chunk
+            .get_extension()
+            .iter()
+            .find_map(|e| e.content.as_bin_mesh());
+        let mut primitives: Vec<ScenePrimitive> = Vec::new();
+        for (material_id, indices) in geo.index_buffers(bin_mesh) {
+            let material = materials.get(material_id as usize).copied();
+            match primitives.iter_mut().find(|p| p.material == material) {
+                Some(prim) => prim.indices.extend(indices),
+                None => primitives.push(ScenePrimitive { indices, material }),
+            }
+        }
+
+        SceneMesh {
+            positions: geo.vertices().iter().map(|v| v.as_arr()).collect(),
+            normals: geo.normals().iter().map(|v| v.as_arr()).collect(),
+            uvs: geo
+                .tex_coords
+                .first()
+                .map(|set| set.iter().map(|t| t.as_arr()).collect())
+                .unwrap_or_default(),
+            colors: geo.prelit.iter().map(|c| c.as_rgba_arr()).collect(),
+            morph_targets: geo
+                .morph_targets
+                .iter()
+                .skip(1)
+                .filter(|m| !m.vertices.is_empty())
+                .map(|m| m.vertices.iter().map(|v| v.as_arr()).collect())
+                .collect(),
+            primitives,
+            ..Default::default()
+        }
+    }
+}